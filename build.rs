@@ -0,0 +1,159 @@
+//! Reads the scene files under scenes/ with the files they include,
+//! reference and load, so a malformed scene or a missing asset fails the
+//! build, and lists the files of each for `embed_scene!` to build in.
+
+use serde_json::Value;
+use std::path::{Component, Path, PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=scenes");
+    let root = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let mut scenes = Vec::new();
+    find_scenes(&root, Path::new("scenes"), &mut scenes);
+    scenes.sort();
+
+    let mut arms = String::new();
+    for scene in &scenes {
+        let mut files = vec![scene.clone()];
+        if let Err(e) = collect(&root, scene, &mut files) {
+            eprintln!("{}: {}", scene.display(), e);
+            std::process::exit(1);
+        }
+        arms += &format!("    ({:?}) => {{ &[\n", slashed(scene));
+        for file in &files {
+            arms += &format!(
+                "        ({:?}, include_bytes!({:?}) as &[u8]),\n",
+                slashed(file),
+                root.join(file).to_string_lossy()
+            );
+        }
+        arms += "    ] };\n";
+    }
+    // scenes of other crates are built in on their own, relative to theirs
+    let code = format!(
+        "#[doc(hidden)]\n#[macro_export]\nmacro_rules! __scene_files {{\n{}    \
+         ($scene:literal) => {{ &[($scene, include_bytes!(concat!(env!(\"CARGO_MANIFEST_DIR\"), \
+         \"/\", $scene)) as &[u8])] }};\n}}\n",
+        arms
+    );
+    let out = PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("scene_files.rs");
+    std::fs::write(out, code).unwrap();
+}
+
+fn is_scene(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("json") | Some("toml") | Some("ron")
+    )
+}
+
+/// Adds the scene files in `dir`, relative to `root`, and its subdirectories.
+fn find_scenes(root: &Path, dir: &Path, scenes: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(root.join(dir)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let path = dir.join(entry.file_name());
+        if entry.path().is_dir() {
+            find_scenes(root, &path, scenes);
+        } else if is_scene(&path) {
+            scenes.push(path);
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<Value, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&text).map_err(|e| e.to_string()),
+        Some("toml") => toml::from_str(&text).map_err(|e| e.to_string()),
+        _ => ron::from_str(&text).map_err(|e| e.to_string()),
+    }
+}
+
+/// Adds the files `scene` reads, and those they read in turn, to `files`:
+/// every `include`, `file`, `map` of a displaced mesh and `background`
+/// naming a file, found where the loader looks for them.
+fn collect(root: &Path, scene: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let value = read(&root.join(scene))?;
+    let dir = scene.parent().unwrap_or_else(|| Path::new(""));
+    let mut search = vec![dir.to_path_buf()];
+    if let Some(list) = value["search_path"].as_array() {
+        search.extend(list.iter().filter_map(Value::as_str).map(|d| dir.join(d)));
+    }
+    let mut names = Vec::new();
+    assets(&value, &mut names);
+    match &value["include"] {
+        Value::String(file) => names.push((file.clone(), true)),
+        Value::Array(list) => names.extend(
+            list.iter()
+                .filter_map(Value::as_str)
+                .map(|f| (f.to_string(), true)),
+        ),
+        _ => {}
+    }
+    for (name, required) in names {
+        let name = name.replace('\\', "/");
+        let found = search
+            .iter()
+            .map(|d| normalize(&d.join(&name)))
+            .find(|p| root.join(p).is_file());
+        let path = match found {
+            Some(path) => path,
+            None if required => return Err(format!("{} not found", name)),
+            // the name of a sky
+            None => continue,
+        };
+        if files.contains(&path) {
+            continue;
+        }
+        files.push(path.clone());
+        if is_scene(&path) {
+            collect(root, &path, files).map_err(|e| format!("{}: {}", name, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// The asset names in `value`, each with whether it must name a file.
+fn assets(value: &Value, names: &mut Vec<(String, bool)>) {
+    match value {
+        Value::Object(map) => {
+            let displaced = map.get("type").and_then(Value::as_str) == Some("displaced");
+            for (key, v) in map {
+                match (key.as_str(), v) {
+                    ("file", Value::String(file)) => names.push((file.clone(), true)),
+                    ("map", Value::String(file)) if displaced => names.push((file.clone(), true)),
+                    ("background", Value::String(sky)) => names.push((sky.clone(), false)),
+                    _ => assets(v, names),
+                }
+            }
+        }
+        Value::Array(list) => list.iter().for_each(|v| assets(v, names)),
+        _ => {}
+    }
+}
+
+/// `path` with `.` and `dir/..` left out, as `embed::normalize` does.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(normal.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                normal.pop();
+            }
+            c => normal.push(c),
+        }
+    }
+    normal
+}
+
+/// `path` as the crate-relative string scenes are registered at.
+fn slashed(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
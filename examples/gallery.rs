@@ -0,0 +1,12 @@
+//! The gallery scene as a program of its own: the scene files and the model
+//! they use are built in, so it renders from any directory. Further options
+//! are passed on, e.g.
+//! `cargo run --release --example gallery -- --samples 16 > image.ppm`.
+
+fn main() {
+    let scene = raytracer::embed_scene!("scenes/gallery.toml");
+    let mut args: Vec<String> = std::env::args().take(1).collect();
+    args.extend(["--scene-file".to_string(), scene.to_string()]);
+    args.extend(std::env::args().skip(1));
+    raytracer::cli::run(args);
+}
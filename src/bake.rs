@@ -0,0 +1,142 @@
+//! Baking lighting and surface data into texture maps.
+
+use crate::hittable::Hittable;
+use crate::ray::{PathInfo, Ray};
+use crate::scene::Scene;
+use crate::vec3::Vector3;
+use rayon::prelude::*;
+
+/// What `--bake` writes into the object's UV layout.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum BakeMap {
+    #[default]
+    Lightmap,
+    /// Ambient occlusion.
+    Ao,
+    Curvature,
+}
+
+impl std::str::FromStr for BakeMap {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lightmap" => Ok(BakeMap::Lightmap),
+            "ao" => Ok(BakeMap::Ao),
+            "curvature" => Ok(BakeMap::Curvature),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Bakes the irradiance over the UV layout of `object` into a `size`x`size`
+/// texture, top row (v = 1) first. Each texel holds the sum of `samples`
+/// cosine-weighted radiance estimates, i.e. irradiance / pi once averaged.
+pub(crate) fn bake_lightmap(
+    scene: &Scene,
+    object: &dyn Hittable,
+    size: u32,
+    samples: usize,
+    max_depth: usize,
+) -> Vec<Vector3> {
+    (0..size)
+        .into_par_iter()
+        .flat_map(|y| {
+            (0..size)
+                .map(|x| {
+                    let u = (x as f64 + 0.5) / size as f64;
+                    let v = 1.0 - (y as f64 + 0.5) / size as f64;
+                    let (p, normal) = match object.surface_at(u, v) {
+                        Some(s) => s,
+                        None => return Vector3(0.0, 0.0, 0.0),
+                    };
+                    let direct = scene.direct_irradiance(&p, &normal) / std::f64::consts::PI;
+                    let irradiance = (0..samples)
+                        .map(|_| {
+                            let mut direction = normal + Vector3::random_unit_vector();
+                            if direction.near_zero() {
+                                direction = normal;
+                            }
+                            let r = Ray {
+                                origin: p,
+                                direction,
+                                time: 0.0,
+                            };
+                            direct + r.ray_color(scene, max_depth, None, &mut PathInfo::default())
+                        })
+                        .fold(Vector3(0.0, 0.0, 0.0), |acc, x| acc + x);
+                    scene.color_space.to_linear_srgb(irradiance)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Bakes ambient occlusion over the UV layout of `object`, laid out like
+/// `bake_lightmap`: the fraction of `samples` cosine-weighted rays per texel
+/// that travel `distance` without hitting anything.
+pub(crate) fn bake_ao(
+    scene: &Scene,
+    object: &dyn Hittable,
+    size: u32,
+    samples: usize,
+    distance: f64,
+) -> Vec<Vector3> {
+    (0..size * size)
+        .into_par_iter()
+        .map(|i| {
+            let u = ((i % size) as f64 + 0.5) / size as f64;
+            let v = 1.0 - ((i / size) as f64 + 0.5) / size as f64;
+            let (p, normal) = match object.surface_at(u, v) {
+                Some(s) => s,
+                None => return Vector3(0.0, 0.0, 0.0),
+            };
+            let open = (0..samples)
+                .filter(|_| {
+                    let mut direction = normal + Vector3::random_unit_vector();
+                    if direction.near_zero() {
+                        direction = normal;
+                    }
+                    scene.visible(&p, &direction.normalize(), distance)
+                })
+                .count();
+            let ao = open as f64 / samples as f64;
+            Vector3(ao, ao, ao)
+        })
+        .collect()
+}
+
+/// Bakes the mean curvature over the UV layout of `object`, laid out like
+/// `bake_lightmap`, from finite differences of its normals. Flat surfaces are
+/// mid gray, convex ones lighter and concave ones darker; a sphere of radius 1
+/// comes out at 0.88.
+pub(crate) fn bake_curvature(object: &dyn Hittable, size: u32) -> Vec<Vector3> {
+    let step = 0.5 / size as f64;
+    (0..size * size)
+        .into_par_iter()
+        .map(|i| {
+            let u = ((i % size) as f64 + 0.5) / size as f64;
+            let v = 1.0 - ((i / size) as f64 + 0.5) / size as f64;
+            // normal change per distance moved, along u and along v
+            let along = |(u0, v0): (f64, f64), (u1, v1): (f64, f64)| {
+                let ((p0, n0), (p1, n1)) = (object.surface_at(u0, v0)?, object.surface_at(u1, v1)?);
+                let dp = p1 - p0;
+                let length_squared = dp.dot(&dp);
+                (length_squared > 1e-18).then(|| (n1 - n0).dot(&dp) / length_squared)
+            };
+            let estimates: Vec<f64> = [
+                along((u - step, v), (u + step, v)),
+                along((u, v - step), (u, v + step)),
+            ]
+            .iter()
+            .flatten()
+            .copied()
+            .collect();
+            if estimates.is_empty() {
+                return Vector3(0.5, 0.5, 0.5);
+            }
+            let k = estimates.iter().sum::<f64>() / estimates.len() as f64;
+            let c = 0.5 + 0.5 * k.tanh();
+            Vector3(c, c, c)
+        })
+        .collect()
+}
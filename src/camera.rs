@@ -0,0 +1,533 @@
+//! Cameras, their lenses and camera paths.
+
+use crate::image::read_ppm;
+use crate::light::LUMENS_PER_WATT;
+use crate::ray::Ray;
+use crate::vec3::{random, Vector3};
+
+/// One frame of an imported camera path.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct CameraKey {
+    frame: f64,
+    pub(crate) position: Vector3,
+    pub(crate) look_at: Vector3,
+    /// Vertical field of view in degrees.
+    pub(crate) vfov: f64,
+    /// Lens diameter, if the path sets it.
+    pub(crate) aperture: Option<f64>,
+}
+
+/// Reads a camera path, sorted by frame, from CSV with lines
+/// `frame,x,y,z,look_x,look_y,look_z,vfov[,aperture]` or from JSON holding
+/// an array of `{"frame", "position": [x, y, z], "look_at": [x, y, z],
+/// "fov"}` objects with an optional `"aperture"`.
+pub(crate) fn load_camera_path(path: &str) -> Result<Vec<CameraKey>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut keys = if path.ends_with(".json") {
+        let json: serde_json::Value = serde_json::from_str(&text).map_err(|e| e.to_string())?;
+        let vector = |v: &serde_json::Value| -> Option<Vector3> {
+            match v.as_array()?.as_slice() {
+                [x, y, z] => Some(Vector3(x.as_f64()?, y.as_f64()?, z.as_f64()?)),
+                _ => None,
+            }
+        };
+        let key = |key: &serde_json::Value| -> Option<CameraKey> {
+            Some(CameraKey {
+                frame: key["frame"].as_f64()?,
+                position: vector(&key["position"])?,
+                look_at: vector(&key["look_at"])?,
+                vfov: key["fov"].as_f64()?,
+                aperture: match key.get("aperture") {
+                    Some(aperture) => Some(aperture.as_f64()?),
+                    None => None,
+                },
+            })
+        };
+        json.as_array()
+            .ok_or("expected an array of frames")?
+            .iter()
+            .enumerate()
+            .map(|(n, k)| key(k).ok_or(format!("frame {} is incomplete", n)))
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        let mut keys = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let c: Vec<f64> = match line.split(',').map(|c| c.trim().parse()).collect() {
+                Ok(c) => c,
+                // a header naming the columns
+                Err(_) if keys.is_empty() => continue,
+                Err(_) => return Err(format!("line {} is not numeric", n + 1)),
+            };
+            let (aperture, c) = match c.len() {
+                9 => (Some(c[8]), &c[..8]),
+                _ => (None, &c[..]),
+            };
+            match *c {
+                [frame, x, y, z, lx, ly, lz, vfov] => keys.push(CameraKey {
+                    frame,
+                    position: Vector3(x, y, z),
+                    look_at: Vector3(lx, ly, lz),
+                    vfov,
+                    aperture,
+                }),
+                _ => return Err(format!("line {} does not have 8 or 9 columns", n + 1)),
+            }
+        }
+        keys
+    };
+    if keys.is_empty() {
+        return Err("the camera path has no frames".to_string());
+    }
+    keys.sort_by(|a, b| a.frame.partial_cmp(&b.frame).unwrap());
+    Ok(keys)
+}
+
+/// The camera at `frame`, interpolated linearly between the surrounding keys
+/// and held before the first and after the last one.
+pub(crate) fn camera_at(keys: &[CameraKey], frame: f64) -> CameraKey {
+    let next = keys.partition_point(|k| k.frame < frame);
+    if next == 0 {
+        return keys[0];
+    }
+    if next == keys.len() {
+        return keys[keys.len() - 1];
+    }
+    let (a, b) = (keys[next - 1], keys[next]);
+    let t = (frame - a.frame) / (b.frame - a.frame);
+    CameraKey {
+        frame,
+        position: a.position * (1.0 - t) + b.position * t,
+        look_at: a.look_at * (1.0 - t) + b.look_at * t,
+        vfov: a.vfov * (1.0 - t) + b.vfov * t,
+        aperture: match (a.aperture, b.aperture) {
+            (Some(a), Some(b)) => Some(a * (1.0 - t) + b * t),
+            (a, b) => a.or(b),
+        },
+    }
+}
+
+/// A grayscale image giving the shape of the aperture, sampled in proportion
+/// to its brightness.
+pub(crate) struct ApertureMask {
+    width: usize,
+    height: usize,
+    /// Running sum of the pixel values, top row first.
+    cdf: Vec<f64>,
+}
+
+impl ApertureMask {
+    pub(crate) fn load(path: &str) -> std::io::Result<Self> {
+        let (width, height, pixels) = read_ppm(path)?;
+        let mut total = 0.0;
+        let cdf: Vec<f64> = pixels
+            .iter()
+            .map(|c| {
+                total += 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+                total
+            })
+            .collect();
+        if total <= 0.0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "the aperture image is black",
+            ));
+        }
+        Ok(Self { width, height, cdf })
+    }
+
+    /// A random point in [-1, 1]², distributed like the image's brightness.
+    fn sample(&self) -> (f64, f64) {
+        let target = random() * self.cdf.last().unwrap();
+        let i = self
+            .cdf
+            .partition_point(|c| *c <= target)
+            .min(self.cdf.len() - 1);
+        let x = ((i % self.width) as f64 + random()) / self.width as f64;
+        let y = ((i / self.width) as f64 + random()) / self.height as f64;
+        (2.0 * x - 1.0, 1.0 - 2.0 * y)
+    }
+}
+
+/// How far open the shutter is over the exposure: a trapezoid taking the
+/// fractions `opening` and `closing` of it to open and close fully, like
+/// a real shutter. Both zero make the ideal box shutter.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ShutterCurve {
+    pub(crate) opening: f64,
+    pub(crate) closing: f64,
+}
+
+impl ShutterCurve {
+    /// `None` unless both fractions are at least 0 and add up to at most 1.
+    pub(crate) fn new(opening: f64, closing: f64) -> Option<Self> {
+        (opening >= 0.0 && closing >= 0.0 && opening + closing <= 1.0)
+            .then_some(Self { opening, closing })
+    }
+
+    /// The fraction of the exposure at which uniform `x` in [0, 1] falls
+    /// when moments are picked in proportion to how far open the shutter is.
+    fn sample(&self, x: f64) -> f64 {
+        let (a, b) = (self.opening, self.closing);
+        let area = 1.0 - (a + b) / 2.0;
+        let y = x * area;
+        if y < a / 2.0 {
+            (2.0 * a * y).sqrt()
+        } else if y < area - b / 2.0 {
+            y + a / 2.0
+        } else {
+            1.0 - (2.0 * b * (area - y)).sqrt()
+        }
+    }
+}
+
+impl std::str::FromStr for ShutterCurve {
+    type Err = ();
+    /// Parses `opening,closing`, see `ShutterCurve::new`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (opening, closing) = s.split_once(',').ok_or(())?;
+        ShutterCurve::new(
+            opening.parse().map_err(|_| ())?,
+            closing.parse().map_err(|_| ())?,
+        )
+        .ok_or(())
+    }
+}
+
+/// How the camera maps image positions to ray directions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    /// The full sphere around the camera: longitude across, latitude up.
+    Equirectangular,
+    /// The full sphere projected from the nadir, the "little planet" look,
+    /// showing `fov` degrees across the image height.
+    Stereographic { fov: f64 },
+}
+
+impl std::str::FromStr for Projection {
+    type Err = ();
+    /// Parses `perspective`, `equirectangular` or `stereographic[:fov]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "perspective" => Ok(Projection::Perspective),
+            None if s == "equirectangular" => Ok(Projection::Equirectangular),
+            None if s == "stereographic" => Ok(Projection::Stereographic { fov: 240.0 }),
+            Some(("stereographic", fov)) => match fov.parse() {
+                Ok(fov) if fov > 0.0 && fov < 360.0 => Ok(Projection::Stereographic { fov }),
+                _ => Err(()),
+            },
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Camera {
+    pub(crate) origin: Vector3,
+    lower_left: Vector3,
+    pub(crate) horizontal: Vector3,
+    vertical: Vector3,
+    u: Vector3,
+    v: Vector3,
+    lens_radius: f64,
+    /// Shape of the aperture, a disk if `None`. The image is stretched over
+    /// the square around the lens.
+    pub(crate) aperture_mask: Option<std::sync::Arc<ApertureMask>>,
+    /// Projections other than perspective have no defocus blur.
+    pub(crate) projection: Projection,
+    /// Rays sample times between the shutter opening and closing.
+    pub(crate) shutter: (f64, f64),
+    pub(crate) shutter_curve: ShutterCurve,
+    /// Fraction of the exposure a rolling shutter takes to reach the bottom
+    /// row from the top one, each row exposed for the rest; 0 exposes all
+    /// rows at once.
+    pub(crate) rolling_shutter: f64,
+}
+
+impl Camera {
+    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
+        if self.projection != Projection::Perspective {
+            return self.center_ray(s, t);
+        }
+        let rd = match &self.aperture_mask {
+            Some(mask) => {
+                let (x, y) = mask.sample();
+                Vector3(x, y, 0.0)
+            }
+            None => Vector3::random_in_unit_disk(),
+        } * self.lens_radius;
+        let offset = self.u * rd.x() + self.v * rd.y();
+        Ray {
+            origin: self.origin + offset,
+            direction: self.lower_left + self.horizontal * s + self.vertical * t
+                - self.origin
+                - offset,
+            time: self.time_at(t),
+        }
+    }
+
+    pub fn new(
+        lookfrom: Vector3,
+        lookat: Vector3,
+        vup: Vector3,
+        vfov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+        focus_dist: f64,
+    ) -> Self {
+        let theta = vfov.to_radians();
+        let h = (theta / 2.0).tan();
+        let viewport_height = 2.0 * h;
+        let viewport_width = aspect_ratio * viewport_height;
+
+        let w = (lookfrom - lookat).normalize();
+        let u = vup.cross(&w).normalize();
+        let v = w.cross(&u);
+
+        let origin = lookfrom;
+        //let focal_length = 1.0;
+
+        let horizontal = u * viewport_width * focus_dist;
+        let vertical = v * viewport_height * focus_dist;
+        let lower_left = origin - horizontal / 2.0 - vertical / 2.0 - w * focus_dist;
+        let lens_radius = aperture / 2.0;
+        Self {
+            origin,
+            lower_left,
+            horizontal,
+            vertical,
+            u,
+            v,
+            lens_radius,
+            aperture_mask: None,
+            projection: Projection::Perspective,
+            shutter: (0.0, 0.0),
+            shutter_curve: ShutterCurve::default(),
+            rolling_shutter: 0.0,
+        }
+    }
+
+    /// The ray through the center of the lens, without defocus blur.
+    pub fn center_ray(&self, s: f64, t: f64) -> Ray {
+        Ray {
+            origin: self.origin,
+            direction: self.direction(s, t),
+            time: self.time_at(t),
+        }
+    }
+
+    /// A random moment while the shutter is open over some row.
+    pub(crate) fn time(&self) -> f64 {
+        // without a rolling shutter every row is the same
+        let row = if self.rolling_shutter > 0.0 {
+            random()
+        } else {
+            0.0
+        };
+        self.time_at(row)
+    }
+
+    /// A random moment while the shutter is open over the row at `t`, from
+    /// 0 at the bottom to 1 at the top, likelier the further open it is.
+    fn time_at(&self, t: f64) -> f64 {
+        let (open, close) = self.shutter;
+        let readout = self.rolling_shutter;
+        let start = readout * (1.0 - t.clamp(0.0, 1.0));
+        open + (start + self.shutter_curve.sample(random()) * (1.0 - readout)) * (close - open)
+    }
+
+    /// Direction of the ray through image position `(s, t)`, both in [0, 1]
+    /// from the lower left corner, for the camera's projection.
+    fn direction(&self, s: f64, t: f64) -> Vector3 {
+        use std::f64::consts::PI;
+        let forward = self.v.cross(&self.u);
+        // a direction from spherical angles around the camera's axes
+        let spherical = |axis: Vector3, side: Vector3, up: Vector3, polar: f64, azimuth: f64| {
+            axis * polar.cos() + (side * azimuth.cos() + up * azimuth.sin()) * polar.sin()
+        };
+        match self.projection {
+            Projection::Perspective => {
+                self.lower_left + self.horizontal * s + self.vertical * t - self.origin
+            }
+            Projection::Equirectangular => {
+                let longitude = (s - 0.5) * 2.0 * PI;
+                let latitude = (t - 0.5) * PI;
+                spherical(self.v, forward, self.u, PI / 2.0 - latitude, longitude)
+            }
+            Projection::Stereographic { fov } => {
+                // the image's vertical edges lie fov / 2 away from the nadir
+                let aspect = self.horizontal.length() / self.vertical.length();
+                let scale = 2.0 * (fov.to_radians() / 4.0).tan();
+                let (x, y) = ((2.0 * s - 1.0) * aspect * scale, (2.0 * t - 1.0) * scale);
+                let polar = 2.0 * (x.hypot(y) / 2.0).atan();
+                // up in the image is where the camera looks, flattened
+                let down = Vector3(0.0, -1.0, 0.0);
+                let ahead = (forward - down * forward.dot(&down)).normalize();
+                let side = ahead.cross(&down);
+                spherical(down, side, ahead, polar, y.atan2(x))
+            }
+        }
+    }
+
+    /// Where a ray from the lens center along `direction` crosses the image,
+    /// as the `(s, t)` that `center_ray` takes, or `None` behind the camera.
+    pub fn project(&self, direction: &Vector3) -> Option<(f64, f64)> {
+        if self.projection != Projection::Perspective {
+            return None;
+        }
+        let forward = self.lower_left + (self.horizontal + self.vertical) / 2.0 - self.origin;
+        let along = direction.dot(&forward);
+        if along <= 0.0 {
+            return None;
+        }
+        let p = self.origin + *direction * (forward.dot(&forward) / along) - self.lower_left;
+        Some((
+            p.dot(&self.horizontal) / self.horizontal.dot(&self.horizontal),
+            p.dot(&self.vertical) / self.vertical.dot(&self.vertical),
+        ))
+    }
+
+    /// Diameter, in pixels of an image `height` pixels high, of the circle
+    /// that a point `distance` ahead along the view axis is blurred into;
+    /// negative in front of the focal plane.
+    pub(crate) fn blur(&self, distance: f64, height: u32) -> f64 {
+        if self.projection != Projection::Perspective {
+            return 0.0;
+        }
+        let focus =
+            (self.lower_left + (self.horizontal + self.vertical) / 2.0 - self.origin).length();
+        let pixel = self.vertical.length() / height as f64;
+        let defocus = if distance.is_infinite() {
+            1.0
+        } else {
+            (distance - focus) / distance
+        };
+        2.0 * self.lens_radius * defocus / pixel
+    }
+
+    /// The nearest and farthest distances along the view axis blurred into
+    /// circles less than `pixels` across in an image `height` pixels high.
+    pub(crate) fn depth_of_field(&self, pixels: f64, height: u32) -> (f64, f64) {
+        let aperture = 2.0 * self.lens_radius;
+        if self.projection != Projection::Perspective || aperture == 0.0 {
+            return (0.0, f64::INFINITY);
+        }
+        let focus =
+            (self.lower_left + (self.horizontal + self.vertical) / 2.0 - self.origin).length();
+        let limit = pixels * self.vertical.length() / height as f64;
+        let far = if limit < aperture {
+            focus * aperture / (aperture - limit)
+        } else {
+            f64::INFINITY
+        };
+        (focus * aperture / (aperture + limit), far)
+    }
+
+    /// This camera moved sideways by `offset`, keeping the window on the focus
+    /// plane fixed so that a stereo pair converges at the focus distance.
+    pub fn eye(&self, offset: f64) -> Self {
+        Self {
+            origin: self.origin + self.u * offset,
+            ..self.clone()
+        }
+    }
+}
+
+// impl Default for Camera {
+//     fn default() -> Self {
+//         let aspect_ratio = 16.0 / 9.0;
+//         let viewport_height = 2.0;
+//         let viewport_width = aspect_ratio * viewport_height;
+//         let focal_length = 1.0;
+
+//         let horizontal = Vector3(viewport_width, 0.0, 0.0);
+//         let vertical = Vector3(0.0, viewport_height, 0.0);
+//         let origin = Vector3(0.0, 0.0, 0.0);
+//         Self {
+//             origin,
+//             lower_left: origin
+//                 - horizontal / 2.0
+//                 - vertical / 2.0
+//                 - Vector3(0.0, 0.0, focal_length),
+//             horizontal,
+//             vertical,
+//         }
+//     }
+// }
+
+/// Height of a full-frame sensor in meters, which with the field of view
+/// gives the focal length of the physical camera.
+const SENSOR_HEIGHT: f64 = 0.024;
+
+/// The settings of a real camera, exposing the image in physical units and
+/// opening the lens to match the f-stop.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PhysicalCamera {
+    pub(crate) f_stop: f64,
+    /// Shutter speed in seconds.
+    pub(crate) shutter: f64,
+    pub(crate) iso: f64,
+}
+
+impl PhysicalCamera {
+    /// Exposure value at ISO 100.
+    pub(crate) fn ev100(&self) -> f64 {
+        (self.f_stop * self.f_stop / self.shutter * 100.0 / self.iso).log2()
+    }
+
+    /// Scale from render units to image values, so that a luminance of
+    /// 1.2 * 2^EV100 cd/m² just saturates the sensor, after ISO 12232.
+    pub(crate) fn exposure(&self) -> f64 {
+        LUMENS_PER_WATT / (1.2 * self.ev100().exp2())
+    }
+
+    /// Diameter of the entrance pupil in scene units of `meters`, for a lens
+    /// covering `vfov` degrees of the sensor height.
+    pub(crate) fn aperture(&self, vfov: f64, meters: f64) -> f64 {
+        let focal_length = SENSOR_HEIGHT / (2.0 * (vfov.to_radians() / 2.0).tan());
+        focal_length / self.f_stop / meters
+    }
+}
+
+/// A camera placement; named ones come from `--camera`.
+#[derive(Clone, Debug)]
+pub struct CameraView {
+    pub(crate) name: Option<String>,
+    pub(crate) from: Vector3,
+    pub(crate) at: Vector3,
+    /// Vertical field of view in degrees.
+    pub(crate) vfov: f64,
+    pub(crate) aperture: f64,
+    pub(crate) focus: f64,
+}
+
+impl std::str::FromStr for CameraView {
+    type Err = ();
+    /// Parses `name:x,y,z:look_x,look_y,look_z[:vfov]`, focused on the look-at
+    /// point.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let (name, from, at, vfov) = match parts[..] {
+            [name, from, at] => (name, from, at, 20.0),
+            [name, from, at, vfov] => (name, from, at, vfov.parse().map_err(|_| ())?),
+            _ => return Err(()),
+        };
+        let (from, at): (Vector3, Vector3) = (from.parse()?, at.parse()?);
+        if name.is_empty() {
+            return Err(());
+        }
+        Ok(CameraView {
+            name: Some(name.to_string()),
+            from,
+            at,
+            vfov,
+            aperture: 0.1,
+            focus: (at - from).length(),
+        })
+    }
+}
@@ -0,0 +1,2880 @@
+//! The command line interface of the `raytracer` binary.
+
+use crate::bake::{bake_ao, bake_curvature, bake_lightmap, BakeMap};
+use crate::camera::{
+    camera_at, load_camera_path, ApertureMask, Camera, CameraView, PhysicalCamera, Projection,
+    ShutterCurve,
+};
+use crate::color::{id_color, srgb_encode, ColorSpace, Colormap};
+use crate::diagnostics::{self, log_eprintln};
+use crate::embed;
+use crate::gltf::export_gltf;
+use crate::hittable::bvh::SCALAR_TRAVERSAL;
+use crate::hittable::{Hittable, Visibility};
+use crate::image::{
+    compose_stereo, crop_pixels, read_checkpoint, read_ppm, read_tile_placement, write_checkpoint,
+    write_exr, write_image, write_ppm, write_preview, Checkpoint, ImageFormat, Stereo, RGB,
+};
+use crate::irradiance_cache::IrradianceCache;
+use crate::lidar::{lidar_scan, LidarPattern};
+use crate::light::{Light, SceneUnit, LUMENS_PER_WATT};
+use crate::material::Material;
+use crate::medium::{HomogeneousMedium, PhaseFunction};
+use crate::photon::Sppm;
+use crate::post::{
+    apply_lut, auto_exposure, bloom, denoise, draw_bounds, false_color, film_grain, focus_blur,
+    lens_flare, tone_map, BoundsOverlay, Lut, ToneMap,
+};
+use crate::ray::{PathEvent, Ray, Termination};
+use crate::render::{
+    cost_heatmap, dump_paths, error_metrics, print_path_stats, r2_jitter, render, render_pass,
+    write_path_dump, Adaptive, Aov, FalseColor, HeatmapMetric, Image, PixelCost, Progress,
+    RenderMode, RenderSettings, Sampler, Settings, ShadowCatcher,
+};
+use crate::scene::{RenderLayer, Scene, ScenePreset};
+use crate::scene_file::{self, SceneSettings};
+use crate::server::serve;
+use crate::sky::{Fog, Sky};
+use crate::texture::ImageTexture;
+use crate::vec3::{derive_seed, random, reseed, Vector3};
+use crate::window::PreviewWindow;
+use rayon::prelude::*;
+use std::io::{IsTerminal, Write};
+
+/// The scene selected by the options, from a scene file or a preset, with the
+/// settings the file gives.
+fn load_scene(options: &Options) -> (Scene, SceneSettings) {
+    // the random scene is drawn from the seed too
+    if let Some(seed) = options.seed {
+        reseed(seed);
+    }
+    let (mut scene, settings) = match &options.scene_file {
+        Some(path) => Scene::from_file(path, options).unwrap_or_else(|e| {
+            eprintln!("could not load scene {}: {}", path, e);
+            std::process::exit(1);
+        }),
+        None => (Scene::from_options(options), SceneSettings::default()),
+    };
+    for (object, visibility) in &options.visibility {
+        scene
+            .set_visibility(object, *visibility)
+            .unwrap_or_else(|e| {
+                eprintln!("--visibility: {}", e);
+                std::process::exit(1);
+            });
+    }
+    for (object, weight) in &options.importance {
+        scene.set_importance(object, *weight).unwrap_or_else(|e| {
+            eprintln!("--importance: {}", e);
+            std::process::exit(1);
+        });
+    }
+    for (light, objects) in &options.light_links {
+        let (objects, exclude) = match objects.strip_prefix('!') {
+            Some(objects) => (objects, true),
+            None => (objects.as_str(), false),
+        };
+        let objects: Vec<&str> = objects.split(',').collect();
+        scene
+            .link_light(*light, &objects, exclude)
+            .unwrap_or_else(|e| {
+                eprintln!("--light-link: {}", e);
+                std::process::exit(1);
+            });
+    }
+    (scene, settings)
+}
+
+#[derive(Default, Clone)]
+pub(crate) struct Options {
+    pub(crate) reference: Option<String>,
+    pub(crate) report_every: Option<usize>,
+    pub(crate) mode: RenderMode,
+    pub(crate) heatmap: Option<HeatmapMetric>,
+    pub(crate) heatmap_file: Option<String>,
+    pub(crate) false_color: Option<FalseColor>,
+    pub(crate) colormap: Colormap,
+    pub(crate) false_color_range: Option<(f64, f64)>,
+    pub(crate) aovs: Vec<Aov>,
+    pub(crate) aov_file: Option<String>,
+    pub(crate) passes: Option<String>,
+    pub(crate) light_groups: Option<String>,
+    pub(crate) shadow_catcher: bool,
+    pub(crate) path_stats: bool,
+    pub(crate) adaptive: Option<f64>,
+    pub(crate) sampler: Option<Sampler>,
+    pub(crate) median_of_means: Option<usize>,
+    pub(crate) min_samples: Option<usize>,
+    pub(crate) dump_region: Option<(u32, u32, u32, u32)>,
+    pub(crate) dump_file: Option<String>,
+    pub(crate) bake: Option<String>,
+    pub(crate) bake_size: Option<u32>,
+    pub(crate) bake_file: Option<String>,
+    pub(crate) bake_map: Option<BakeMap>,
+    pub(crate) ao_distance: Option<f64>,
+    pub(crate) irradiance_cache: Option<f64>,
+    pub(crate) sppm: Option<usize>,
+    pub(crate) photon_radius: Option<f64>,
+    pub(crate) lights: Vec<Light>,
+    pub(crate) medium: Option<HomogeneousMedium>,
+    pub(crate) phase: Option<PhaseFunction>,
+    pub(crate) sky: Option<Sky>,
+    pub(crate) aerial_perspective: Option<f64>,
+    pub(crate) scene: ScenePreset,
+    pub(crate) ball_material: Option<Material>,
+    pub(crate) contact_sheet: Option<String>,
+    pub(crate) sheet_range: Option<(f64, f64, usize)>,
+    pub(crate) time: f64,
+    pub(crate) draft: bool,
+    pub(crate) progressive: bool,
+    /// Report progress even when stderr is not a terminal.
+    pub(crate) progress: bool,
+    pub(crate) jitter_preview: bool,
+    pub(crate) seed: Option<u64>,
+    pub(crate) window: bool,
+    /// Blur diameter, in pixels, below which the window's focus overlay
+    /// counts a surface as sharp; given, the overlay starts on.
+    pub(crate) focus_overlay: Option<f64>,
+    /// Write the scene to this glTF file instead of rendering it.
+    pub(crate) export_gltf: Option<String>,
+    pub(crate) preview_file: Option<String>,
+    pub(crate) checkpoint: Option<String>,
+    pub(crate) checkpoint_every: Option<f64>,
+    pub(crate) resume: bool,
+    /// Where bundles for reproducing a failed render go, see `diagnostics`;
+    /// none are written without.
+    pub(crate) diagnostics: Option<String>,
+    /// How many pixels may be NaN or infinite before the render counts as failed.
+    pub(crate) nan_threshold: Option<usize>,
+    pub(crate) stereo: Option<Stereo>,
+    pub(crate) eye_separation: Option<f64>,
+    pub(crate) crop: Option<[f64; 4]>,
+    pub(crate) crop_only: bool,
+    pub(crate) pick: Option<(u32, u32)>,
+    pub(crate) debug_pixel: Option<(u32, u32)>,
+    /// The boxes outlined by `--show-bounds`, or `--bounds` for objects.
+    pub(crate) bounds: Option<BoundsOverlay>,
+    pub(crate) aperture_image: Option<String>,
+    pub(crate) camera_path: Option<String>,
+    pub(crate) frame: f64,
+    pub(crate) frames: Option<(u32, u32)>,
+    /// Frames per second of `--frames`, advancing the scene's time.
+    pub(crate) fps: Option<f64>,
+    pub(crate) sky_rotation: f64,
+    pub(crate) sky_intensity: Option<f64>,
+    pub(crate) backdrop: Option<Sky>,
+    pub(crate) plate: Option<&'static ImageTexture>,
+    pub(crate) fog: Option<Fog>,
+    pub(crate) fog_color: Option<Vector3>,
+    pub(crate) denoise: bool,
+    pub(crate) auto_exposure: Option<f64>,
+    pub(crate) exposure_clip: Option<(f64, f64)>,
+    pub(crate) exposure: f64,
+    pub(crate) tone_map: ToneMap,
+    pub(crate) bloom: Option<(f64, f64)>,
+    pub(crate) lens_flare: Option<f64>,
+    pub(crate) film_grain: Option<f64>,
+    pub(crate) lut: Option<String>,
+    pub(crate) color_space: Option<ColorSpace>,
+    pub(crate) max_diffuse: Option<usize>,
+    pub(crate) units: Option<SceneUnit>,
+    pub(crate) lidar: Option<String>,
+    pub(crate) cameras: Vec<CameraView>,
+    pub(crate) projection: Option<Projection>,
+    pub(crate) layers: Vec<RenderLayer>,
+    pub(crate) visibility: Vec<(String, Visibility)>,
+    pub(crate) importance: Vec<(String, f64)>,
+    /// Light IDs with the objects they light, or with `!` the ones they don't.
+    pub(crate) light_links: Vec<(usize, String)>,
+    pub(crate) scene_file: Option<String>,
+    /// Directories to look for scene assets in.
+    pub(crate) asset_paths: Vec<std::path::PathBuf>,
+    pub(crate) camera_selection: Option<Vec<String>>,
+    pub(crate) lidar_pattern: Option<LidarPattern>,
+    /// Full beam divergence in milliradians.
+    pub(crate) beam_divergence: Option<f64>,
+    pub(crate) range_noise: Option<f64>,
+    pub(crate) max_glossy: Option<usize>,
+    pub(crate) max_transmission: Option<usize>,
+    /// Dielectric surfaces a shadow ray passes through, see `Scene::transparent_shadows`.
+    pub(crate) transparent_shadows: Option<usize>,
+    pub(crate) grain_color: bool,
+    pub(crate) tiles: Option<(u32, u32)>,
+    pub(crate) tile: u32,
+    pub(crate) tile_overlap: Option<u32>,
+    pub(crate) output: Option<String>,
+    pub(crate) format: Option<ImageFormat>,
+    pub(crate) width: Option<u32>,
+    pub(crate) height: Option<u32>,
+    pub(crate) samples: Option<usize>,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) roulette: Option<usize>,
+    pub(crate) split: Option<usize>,
+    pub(crate) look_from: Option<Vector3>,
+    pub(crate) look_at: Option<Vector3>,
+    pub(crate) vfov: Option<f64>,
+    pub(crate) aperture: Option<f64>,
+    pub(crate) shutter: Option<(f64, f64)>,
+    pub(crate) shutter_curve: Option<ShutterCurve>,
+    /// Readout time of a rolling shutter as a fraction of the exposure.
+    pub(crate) rolling_shutter: Option<f64>,
+    /// Whether moving objects blur while the shutter is open, overriding
+    /// the scene file.
+    pub(crate) motion_blur: Option<bool>,
+    pub(crate) focus: Option<f64>,
+    pub(crate) f_stop: Option<f64>,
+    /// Shutter speed in seconds.
+    pub(crate) shutter_speed: Option<f64>,
+    pub(crate) iso: Option<f64>,
+}
+
+impl Options {
+    /// The image format given by `--format`, or else by the extension of
+    /// `--output`, PPM by default.
+    fn output_format(&self) -> ImageFormat {
+        self.format
+            .or_else(|| self.output.as_deref().and_then(ImageFormat::from_path))
+            .unwrap_or_default()
+    }
+
+    /// The physical camera of `--f-stop`, at 1/125 s and ISO 100 unless
+    /// given.
+    pub(crate) fn physical_camera(&self) -> Option<PhysicalCamera> {
+        Some(PhysicalCamera {
+            f_stop: self.f_stop?,
+            shutter: self.shutter_speed.unwrap_or(1.0 / 125.0),
+            iso: self.iso.unwrap_or(100.0),
+        })
+    }
+
+    fn parse(mut args: impl Iterator<Item = String>) -> Self {
+        let mut options = Self::default();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next().unwrap_or_else(|| {
+                    eprintln!("missing value for {}", arg);
+                    std::process::exit(1);
+                })
+            };
+            match arg.as_str() {
+                "--reference" => options.reference = Some(value()),
+                "--report-every" => options.report_every = Some(parse_positive(&arg, &value())),
+                "--mode" => options.mode = parse_arg(&arg, &value()),
+                "--heatmap" => options.heatmap = Some(parse_arg(&arg, &value())),
+                "--heatmap-file" => options.heatmap_file = Some(value()),
+                "--false-color" => options.false_color = Some(parse_arg(&arg, &value())),
+                "--colormap" => options.colormap = parse_arg(&arg, &value()),
+                "--false-color-range" => {
+                    let value = value();
+                    let c: Vec<f64> = value.split(',').map(|c| parse_arg(&arg, c)).collect();
+                    options.false_color_range = Some(match c[..] {
+                        [low, high] if low < high => (low, high),
+                        _ => {
+                            eprintln!("--false-color-range expects low,high values");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                "--aov" => {
+                    options.aovs = value().split(',').map(|aov| parse_arg(&arg, aov)).collect()
+                }
+                "--aov-file" => options.aov_file = Some(value()),
+                "--passes" => options.passes = Some(value()),
+                "--light-groups" => options.light_groups = Some(value()),
+                "--shadow-catcher" => options.shadow_catcher = true,
+                "--path-stats" => options.path_stats = true,
+                "--adaptive" => options.adaptive = Some(parse_positive(&arg, &value())),
+                "--sampler" => options.sampler = Some(parse_arg(&arg, &value())),
+                "--min-samples" => options.min_samples = Some(parse_positive(&arg, &value())),
+                "--median-of-means" => {
+                    options.median_of_means = Some(parse_positive(&arg, &value()))
+                }
+                "--dump-paths" => {
+                    let value = value();
+                    let c: Vec<u32> = value.split(',').map(|c| parse_arg(&arg, c)).collect();
+                    options.dump_region = Some(match c[..] {
+                        [x, y] => (x, y, x + 1, y + 1),
+                        [x0, y0, x1, y1] => (x0, y0, x1, y1),
+                        _ => {
+                            eprintln!("--dump-paths expects x,y or x0,y0,x1,y1");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                "--dump-file" => options.dump_file = Some(value()),
+                "--crop" => {
+                    let value = value();
+                    let c: Vec<f64> = value.split(',').map(|c| parse_arg(&arg, c)).collect();
+                    options.crop = Some(match c[..] {
+                        [x0, y0, x1, y1] => [x0, y0, x1, y1],
+                        _ => {
+                            eprintln!("--crop expects x0,y0,x1,y1");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                "--crop-only" => options.crop_only = true,
+                "--show-bounds" => options.bounds = Some(parse_arg(&arg, &value())),
+                // short for --show-bounds objects
+                "--bounds" => options.bounds = Some(BoundsOverlay::Objects),
+                "--aperture-image" => options.aperture_image = Some(value()),
+                "--camera-path" => options.camera_path = Some(value()),
+                "--sky-rotation" => options.sky_rotation = parse_arg(&arg, &value()),
+                "--sky-intensity" => options.sky_intensity = Some(parse_arg(&arg, &value())),
+                "--backdrop" => options.backdrop = Some(parse_sky(&arg, &value())),
+                "--plate" => {
+                    let path = value();
+                    options.plate = Some(ImageTexture::load(&path).unwrap_or_else(|e| {
+                        eprintln!("could not read background plate {}: {}", path, e);
+                        std::process::exit(1);
+                    }))
+                }
+                "--texture-cache-mb" => ImageTexture::set_cache_budget(
+                    (parse_positive::<f64>(&arg, &value()) * 1048576.0) as usize,
+                ),
+                "--fog" => options.fog = Some(parse_arg(&arg, &value())),
+                "--fog-color" => options.fog_color = Some(parse_arg(&arg, &value())),
+                "--denoise" => options.denoise = true,
+                "--auto-exposure" => options.auto_exposure = Some(parse_arg(&arg, &value())),
+                "--lens-flare" => options.lens_flare = Some(parse_arg(&arg, &value())),
+                "--film-grain" => options.film_grain = Some(parse_arg(&arg, &value())),
+                "--grain-color" => options.grain_color = true,
+                "--lut" => options.lut = Some(value()),
+                "--exposure" => options.exposure = parse_arg(&arg, &value()),
+                "--tone-map" => options.tone_map = parse_arg(&arg, &value()),
+                "--color-space" => options.color_space = Some(parse_arg(&arg, &value())),
+                "--bloom" => {
+                    let value = value();
+                    let c: Vec<f64> = value.split(',').map(|c| parse_arg(&arg, c)).collect();
+                    options.bloom = Some(match c[..] {
+                        [threshold] => (threshold, 0.1),
+                        [threshold, strength] => (threshold, strength),
+                        _ => {
+                            eprintln!("--bloom expects threshold[,strength]");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                "--shutter" => {
+                    let value = value();
+                    let c: Vec<f64> = value.split(',').map(|c| parse_arg(&arg, c)).collect();
+                    options.shutter = Some(match c[..] {
+                        [open, close] if open <= close => (open, close),
+                        _ => {
+                            eprintln!("--shutter expects open,close times");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                "--shutter-curve" => options.shutter_curve = Some(parse_arg(&arg, &value())),
+                "--rolling-shutter" => {
+                    let readout: f64 = parse_arg(&arg, &value());
+                    if !(0.0..=1.0).contains(&readout) {
+                        eprintln!(
+                            "--rolling-shutter expects a fraction of the exposure from 0 to 1"
+                        );
+                        std::process::exit(1);
+                    }
+                    options.rolling_shutter = Some(readout);
+                }
+                "--motion-blur" => {
+                    options.motion_blur = Some(match value().as_str() {
+                        "on" => true,
+                        "off" => false,
+                        _ => {
+                            eprintln!("--motion-blur expects on or off");
+                            std::process::exit(1);
+                        }
+                    })
+                }
+                "--f-stop" => options.f_stop = Some(parse_positive(&arg, &value())),
+                "--shutter-speed" => {
+                    let value = value();
+                    // also as a fraction, 1/125
+                    let seconds = match value.split_once('/') {
+                        Some((a, b)) => {
+                            parse_positive::<f64>(&arg, a) / parse_positive::<f64>(&arg, b)
+                        }
+                        None => parse_positive(&arg, &value),
+                    };
+                    options.shutter_speed = Some(seconds);
+                }
+                "--iso" => options.iso = Some(parse_positive(&arg, &value())),
+                "--exposure-clip" => {
+                    let value = value();
+                    let c: Vec<f64> = value.split(',').map(|c| parse_arg(&arg, c)).collect();
+                    options.exposure_clip = Some(match c[..] {
+                        [low, high] if low < high => (low / 100.0, high / 100.0),
+                        _ => {
+                            eprintln!("--exposure-clip expects low,high percentiles");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                "--frame" => options.frame = parse_arg(&arg, &value()),
+                "--frames" => {
+                    let value = value();
+                    options.frames = Some(match value.split_once(',') {
+                        Some((first, last)) => (parse_arg(&arg, first), parse_arg(&arg, last)),
+                        None => {
+                            eprintln!("--frames expects first,last");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                "--fps" => options.fps = Some(parse_positive(&arg, &value())),
+                "--pick" => {
+                    let value = value();
+                    let c: Vec<u32> = value.split(',').map(|c| parse_arg(&arg, c)).collect();
+                    options.pick = Some(match c[..] {
+                        [x, y] => (x, y),
+                        _ => {
+                            eprintln!("--pick expects x,y");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                "--debug-pixel" => {
+                    let value = value();
+                    let c: Vec<u32> = value.split(',').map(|c| parse_arg(&arg, c)).collect();
+                    options.debug_pixel = Some(match c[..] {
+                        [x, y] => (x, y),
+                        _ => {
+                            eprintln!("--debug-pixel expects x,y");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                "--tiles" => {
+                    let value = value();
+                    let c: Vec<u32> = value.split('x').map(|c| parse_arg(&arg, c)).collect();
+                    options.tiles = Some(match c[..] {
+                        [columns, rows] if columns > 0 && rows > 0 => (columns, rows),
+                        _ => {
+                            eprintln!("--tiles expects COLUMNSxROWS");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                "--tile" => options.tile = parse_arg(&arg, &value()),
+                "--tile-overlap" => options.tile_overlap = Some(parse_arg(&arg, &value())),
+                "--bake" => options.bake = Some(value()),
+                "--bake-size" => options.bake_size = Some(parse_arg(&arg, &value())),
+                "--bake-file" => options.bake_file = Some(value()),
+                "--bake-map" => options.bake_map = Some(parse_arg(&arg, &value())),
+                "--ao-distance" => options.ao_distance = Some(parse_arg(&arg, &value())),
+                "--phase" => options.phase = Some(parse_arg(&arg, &value())),
+                "--scene" => {
+                    // a path with a scene file extension is a scene file
+                    let value = value();
+                    match value.rsplit_once('.') {
+                        Some((_, "json" | "toml" | "ron")) => options.scene_file = Some(value),
+                        _ => options.scene = parse_arg(&arg, &value),
+                    }
+                }
+                "--ball-material" => options.ball_material = Some(parse_arg(&arg, &value())),
+                "--contact-sheet" => {
+                    let template = value();
+                    if !template.contains("{}") {
+                        eprintln!("--contact-sheet expects a material with {{}} for the value");
+                        std::process::exit(1);
+                    }
+                    options.contact_sheet = Some(template);
+                }
+                "--sheet-range" => {
+                    let value = value();
+                    let c: Vec<&str> = value.split(',').collect();
+                    options.sheet_range = Some(match c[..] {
+                        [from, to, count] => (
+                            parse_arg(&arg, from),
+                            parse_arg(&arg, to),
+                            parse_positive(&arg, count),
+                        ),
+                        _ => {
+                            eprintln!("--sheet-range expects from,to,count");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                "--width" => options.width = Some(parse_positive(&arg, &value())),
+                "--height" => options.height = Some(parse_positive(&arg, &value())),
+                "--spp" | "--samples" => options.samples = Some(parse_positive(&arg, &value())),
+                "--depth" | "--max-depth" => {
+                    options.max_depth = Some(parse_positive(&arg, &value()))
+                }
+                "--roulette" => options.roulette = Some(parse_positive(&arg, &value())),
+                "--split" => {
+                    let split: usize = parse_arg(&arg, &value());
+                    if split < 2 {
+                        eprintln!("--split must be at least 2");
+                        std::process::exit(1);
+                    }
+                    options.split = Some(split);
+                }
+                "--look-from" => options.look_from = Some(parse_arg(&arg, &value())),
+                "--look-at" => options.look_at = Some(parse_arg(&arg, &value())),
+                "--vfov" => {
+                    let vfov: f64 = parse_positive(&arg, &value());
+                    if vfov >= 180.0 {
+                        eprintln!("--vfov must be below 180 degrees");
+                        std::process::exit(1);
+                    }
+                    options.vfov = Some(vfov);
+                }
+                "--aperture" => {
+                    let aperture: f64 = parse_arg(&arg, &value());
+                    if aperture < 0.0 {
+                        eprintln!("--aperture must not be negative");
+                        std::process::exit(1);
+                    }
+                    options.aperture = Some(aperture);
+                }
+                "--focus" => options.focus = Some(parse_positive(&arg, &value())),
+                "--time" => options.time = parse_arg(&arg, &value()),
+                "--draft" => options.draft = true,
+                "--max-diffuse" => options.max_diffuse = Some(parse_arg(&arg, &value())),
+                "--max-glossy" => options.max_glossy = Some(parse_arg(&arg, &value())),
+                "--max-transmission" => options.max_transmission = Some(parse_arg(&arg, &value())),
+                "--transparent-shadows" => {
+                    options.transparent_shadows = Some(parse_arg(&arg, &value()))
+                }
+                "--progressive" => options.progressive = true,
+                "--progress" => options.progress = true,
+                "--jitter-preview" => options.jitter_preview = true,
+                "--seed" => options.seed = Some(parse_arg(&arg, &value())),
+                "--window" => options.window = true,
+                "--export-gltf" => options.export_gltf = Some(value()),
+                "--focus-overlay" => options.focus_overlay = Some(parse_positive(&arg, &value())),
+                "--preview-file" => options.preview_file = Some(value()),
+                "--checkpoint" => options.checkpoint = Some(value()),
+                "--checkpoint-every" => {
+                    options.checkpoint_every = Some(parse_positive(&arg, &value()))
+                }
+                "--resume" => options.resume = true,
+                "--diagnostics" => options.diagnostics = Some(value()),
+                "--nan-threshold" => options.nan_threshold = Some(parse_arg(&arg, &value())),
+                "--output" | "--out" | "-o" => options.output = Some(value()),
+                "--format" => options.format = Some(parse_arg(&arg, &value())),
+                "--stereo" => options.stereo = Some(parse_arg(&arg, &value())),
+                "--eye-separation" => options.eye_separation = Some(parse_arg(&arg, &value())),
+                "--sky" => options.sky = Some(parse_sky(&arg, &value())),
+                "--units" => options.units = Some(parse_arg(&arg, &value())),
+                "--lidar" => options.lidar = Some(value()),
+                "--camera" => options.cameras.push(parse_arg(&arg, &value())),
+                "--projection" => options.projection = Some(parse_arg(&arg, &value())),
+                "--layer" => options.layers.push(parse_arg(&arg, &value())),
+                "--visibility" => {
+                    let value = value();
+                    match value.rsplit_once(':') {
+                        Some((object, rays)) => options
+                            .visibility
+                            .push((object.to_string(), parse_arg(&arg, rays))),
+                        None => {
+                            eprintln!("--visibility expects object:rays");
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                "--importance" => {
+                    let value = value();
+                    match value.rsplit_once(':') {
+                        Some((object, weight)) => options
+                            .importance
+                            .push((object.to_string(), parse_positive(&arg, weight))),
+                        None => {
+                            eprintln!("--importance expects object:weight");
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                "--scene-file" => options.scene_file = Some(value()),
+                "--asset-path" => options.asset_paths.extend(std::env::split_paths(&value())),
+                "--cameras" => {
+                    options.camera_selection = Some(value().split(',').map(String::from).collect())
+                }
+                "--lidar-pattern" => options.lidar_pattern = Some(parse_arg(&arg, &value())),
+                "--beam-divergence" => options.beam_divergence = Some(parse_arg(&arg, &value())),
+                "--range-noise" => options.range_noise = Some(parse_arg(&arg, &value())),
+                "--aerial-perspective" => {
+                    options.aerial_perspective = Some(parse_arg(&arg, &value()))
+                }
+                "--irradiance-cache" => options.irradiance_cache = Some(parse_arg(&arg, &value())),
+                "--sppm" => options.sppm = Some(parse_positive(&arg, &value())),
+                "--photon-radius" => options.photon_radius = Some(parse_positive(&arg, &value())),
+                "--light" => options.lights.push(parse_arg(&arg, &value())),
+                "--negative-light" => options
+                    .lights
+                    .push(parse_arg::<Light>(&arg, &value()).negated()),
+                "--light-link" => {
+                    let value = value();
+                    match value.split_once(':') {
+                        Some((light, objects)) => options
+                            .light_links
+                            .push((parse_arg(&arg, light), objects.to_string())),
+                        None => {
+                            eprintln!("--light-link expects light:objects");
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                "--medium" => {
+                    let value = value();
+                    let c: Vec<f64> = value.split(',').map(|c| parse_arg(&arg, c)).collect();
+                    options.medium = Some(match c[..] {
+                        [sigma_s] => HomogeneousMedium::new(sigma_s, 0.0),
+                        [sigma_s, sigma_a] => HomogeneousMedium::new(sigma_s, sigma_a),
+                        _ => {
+                            eprintln!("--medium expects sigma_s or sigma_s,sigma_a");
+                            std::process::exit(1);
+                        }
+                    });
+                }
+                _ => {
+                    eprintln!("unknown argument {}", arg);
+                    std::process::exit(1);
+                }
+            }
+        }
+        options
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: &str) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("invalid value {:?} for {}", value, name);
+        std::process::exit(1);
+    })
+}
+
+/// Like `parse_arg`, for skies, which may be environment maps to load.
+fn parse_sky(name: &str, value: &str) -> Sky {
+    match Sky::load(value) {
+        Ok(Some(sky)) => sky,
+        Ok(None) => parse_arg(name, value),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The 64-bit FNV-1a hash of `data`, which unlike the standard library's
+/// hashers stays the same across Rust releases and machines.
+fn stable_hash(data: impl AsRef<[u8]>) -> u64 {
+    data.as_ref()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+}
+
+/// Stable hash of the scene the options describe: the scene file with the
+/// files it includes and references, the bytes of the assets it reads and
+/// the options that change the scene, or the preset. Textures given on the
+/// command line count by their paths.
+fn scene_hash(options: &Options) -> u64 {
+    let mut description = Vec::new();
+    match &options.scene_file {
+        Some(path) => {
+            let value = scene_file::load_scene(std::path::Path::new(path), &options.asset_paths)
+                .unwrap_or_default();
+            description.extend(value.to_string().into_bytes());
+            for file in scene_file::asset_files(&value) {
+                description.extend(embed::read(&file).unwrap_or_default());
+            }
+        }
+        None => {
+            description.extend(format!("{:?}", options.scene).into_bytes());
+            if options.scene == ScenePreset::Random {
+                // the balls are drawn from the seed
+                description.extend(format!(" {:?}", options.seed).into_bytes());
+            }
+        }
+    }
+    let shaping = format!(
+        "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} \
+         {:?} {:?} {:?} {:?} {:?} {:?}",
+        options.lights,
+        options.medium,
+        options.phase,
+        options.sky,
+        options.aerial_perspective,
+        options.ball_material,
+        options.time,
+        options.draft,
+        options.sky_rotation,
+        options.sky_intensity,
+        options.backdrop,
+        options.plate,
+        options.fog,
+        options.fog_color,
+        options.color_space,
+        options.max_diffuse,
+        options.max_glossy,
+        options.max_transmission,
+        options.transparent_shadows,
+        options.units,
+        options.visibility,
+        options.importance,
+        options.light_links,
+    );
+    description.extend(shaping.into_bytes());
+    stable_hash(description)
+}
+
+/// Like `parse_arg`, for values that must be above zero.
+fn parse_positive<T: std::str::FromStr + PartialOrd + Default>(name: &str, value: &str) -> T {
+    let parsed = parse_arg(name, value);
+    if parsed <= T::default() {
+        eprintln!("{} must be positive, got {}", name, value);
+        std::process::exit(1);
+    }
+    parsed
+}
+
+/// `raytracer merge <a.ckpt> <b.ckpt>... -o <combined.ckpt>`: sums the
+/// samples of checkpoints of the same scene and settings rendered with
+/// different seeds, say on several machines, into one checkpoint with
+/// less noise. Resuming from it with `--spp` at its sample count writes
+/// the image.
+fn merge(args: &[String]) {
+    let usage = || {
+        eprintln!("usage: raytracer merge <a.ckpt> <b.ckpt>... -o <combined.ckpt>");
+        std::process::exit(1);
+    };
+    let mut output = None;
+    let mut inputs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" | "--out" => output = Some(args.next().unwrap_or_else(usage)),
+            _ => inputs.push(arg),
+        }
+    }
+    let output = output.unwrap_or_else(usage);
+    if inputs.len() < 2 {
+        usage();
+    }
+    let mut merged: Option<(&String, Checkpoint)> = None;
+    for path in inputs {
+        let checkpoint = read_checkpoint(path).unwrap_or_else(|e| {
+            eprintln!("could not read checkpoint {}: {}", path, e);
+            std::process::exit(1);
+        });
+        if checkpoint.hash.is_none() {
+            eprintln!(
+                "checkpoint {} does not record its scene and settings, render it again to merge it",
+                path
+            );
+            std::process::exit(1);
+        }
+        let (first, sum) = match &mut merged {
+            Some((first, sum)) => (first, sum),
+            None => {
+                merged = Some((path, checkpoint));
+                continue;
+            }
+        };
+        if (checkpoint.width, checkpoint.height, checkpoint.hash)
+            != (sum.width, sum.height, sum.hash)
+        {
+            eprintln!(
+                "checkpoints {} and {} are of different scenes or settings",
+                first, path
+            );
+            std::process::exit(1);
+        }
+        if let Some(seed) = checkpoint.seeds.iter().find(|s| sum.seeds.contains(s)) {
+            eprintln!(
+                "{} repeats the samples of seed {} already merged, render it with another --seed",
+                path, seed
+            );
+            std::process::exit(1);
+        }
+        sum.samples += checkpoint.samples;
+        sum.seeds.extend(checkpoint.seeds);
+        for (a, b) in sum.pixels.iter_mut().zip(checkpoint.pixels) {
+            *a += b;
+        }
+        for (a, b) in sum.coverage.iter_mut().zip(checkpoint.coverage) {
+            *a += b;
+        }
+    }
+    let (_, sum) = merged.unwrap();
+    write_checkpoint(output, &sum).unwrap_or_else(|e| {
+        eprintln!("could not write {}: {}", output, e);
+        std::process::exit(1);
+    });
+    eprintln!("{} holds {} samples per pixel", output, sum.samples);
+}
+
+/// `raytracer stitch <output> <tiles>...`: assembles tiles rendered with
+/// `--tiles`, cross-fading linearly where they overlap.
+fn stitch(args: &[String]) {
+    let (output, tiles) = match args {
+        [output, tiles @ ..] if !tiles.is_empty() => (output, tiles),
+        _ => {
+            eprintln!("usage: raytracer stitch <output.ppm> <tile.ppm>...");
+            std::process::exit(1);
+        }
+    };
+    let mut size = None;
+    let mut sums = Vec::new();
+    for path in tiles {
+        let read = read_tile_placement(path).and_then(|p| read_ppm(path).map(|t| (p, t)));
+        let ((x0, y0, width, height), (w, h, pixels)) = read.unwrap_or_else(|e| {
+            eprintln!("could not read tile {}: {}", path, e);
+            std::process::exit(1);
+        });
+        if *size.get_or_insert((width, height)) != (width, height)
+            || x0 + w > width
+            || y0 + h > height
+        {
+            eprintln!(
+                "tile {} does not belong to a {}x{} image",
+                path, width, height
+            );
+            std::process::exit(1);
+        }
+        if sums.is_empty() {
+            sums = vec![(Vector3(0.0, 0.0, 0.0), 0.0); width * height];
+        }
+        for (i, color) in pixels.iter().enumerate() {
+            let (x, y) = (i % w, i / w);
+            // distance to the nearest tile edge that is not also an image edge
+            let edges = [
+                (x0 > 0, x + 1),
+                (y0 > 0, y + 1),
+                (x0 + w < width, w - x),
+                (y0 + h < height, h - y),
+            ];
+            let distance = edges
+                .iter()
+                .filter(|(inner, _)| *inner)
+                .map(|(_, d)| *d)
+                .fold(width.max(height), usize::min);
+            let (sum, weight) = &mut sums[(y0 + y) * width + x0 + x];
+            *sum += *color * distance as f64;
+            *weight += distance as f64;
+        }
+    }
+    let (width, height) = size.unwrap();
+    if sums.iter().any(|(_, weight)| *weight == 0.0) {
+        eprintln!(
+            "the tiles do not cover the whole {}x{} image",
+            width, height
+        );
+        std::process::exit(1);
+    }
+    let encoded: Vec<Vector3> = sums
+        .iter()
+        .map(|(sum, weight)| {
+            let c = *sum / *weight;
+            let encode = |x: f64| srgb_encode(x.max(0.0));
+            Vector3(encode(c.x()), encode(c.y()), encode(c.z()))
+        })
+        .collect();
+    write_ppm(output, width as u32, height as u32, &encoded).unwrap_or_else(|e| {
+        eprintln!("could not write {}: {}", output, e);
+        std::process::exit(1);
+    });
+}
+
+/// `raytracer batch [--jobs N] <job files>...`: renders every job file in
+/// turn, at most N at a time. A job file holds the command line options for
+/// one render, `#` starts a comment. The image of `name.txt` goes to
+/// `name.ppm` and its log to `name.log`.
+fn batch(args: &[String]) {
+    let mut concurrency = 1;
+    let mut jobs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--jobs" => {
+                let value = args.next().map(String::as_str).unwrap_or("");
+                concurrency = parse_arg::<usize>(arg, value).max(1);
+            }
+            _ => jobs.push(std::path::PathBuf::from(arg)),
+        }
+    }
+    if jobs.is_empty() {
+        eprintln!("usage: raytracer batch [--jobs N] <job file>...");
+        std::process::exit(1);
+    }
+    let exe = std::env::current_exe().unwrap_or_else(|e| {
+        eprintln!("could not locate the raytracer executable: {}", e);
+        std::process::exit(1);
+    });
+    let run = |job: &std::path::Path| -> std::io::Result<(bool, f64)> {
+        let options: Vec<String> = std::fs::read_to_string(job)?
+            .lines()
+            .flat_map(|line| line.split('#').next().unwrap().split_whitespace())
+            .map(String::from)
+            .collect();
+        let start = std::time::Instant::now();
+        let status = std::process::Command::new(&exe)
+            .args(&options)
+            .stdout(std::fs::File::create(job.with_extension("ppm"))?)
+            .stderr(std::fs::File::create(job.with_extension("log"))?)
+            .status()?;
+        Ok((status.success(), start.elapsed().as_secs_f64()))
+    };
+
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let results = std::sync::Mutex::new(vec![None; jobs.len()]);
+    std::thread::scope(|s| {
+        for _ in 0..concurrency.min(jobs.len()) {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                let job = match jobs.get(i) {
+                    Some(job) => job,
+                    None => break,
+                };
+                eprintln!("started {}", job.display());
+                let result = run(job).map_err(|e| e.to_string());
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+
+    let mut failed = 0;
+    for (job, result) in jobs.iter().zip(results.into_inner().unwrap()) {
+        let (status, seconds) = match result.unwrap() {
+            Ok((true, seconds)) => ("ok".to_string(), seconds),
+            Ok((false, seconds)) => ("failed".to_string(), seconds),
+            Err(e) => (e, 0.0),
+        };
+        if status != "ok" {
+            failed += 1;
+        }
+        println!("{:<40} {:>10.1}s  {}", job.display(), seconds, status);
+    }
+    println!("{} of {} jobs succeeded", jobs.len() - failed, jobs.len());
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+/// `raytracer stats [options]`: prints what the scene selected by the options
+/// is made of.
+fn scene_stats(scene: &Scene) {
+    let count = |names: Vec<String>| {
+        let mut counts = std::collections::BTreeMap::new();
+        for name in names {
+            *counts.entry(name).or_insert(0) += 1;
+        }
+        counts
+    };
+    let objects = &scene.world.objects;
+    println!("objects    {}", objects.len());
+    for (kind, n) in count(objects.iter().map(|h| h.kind().to_string()).collect()) {
+        println!("  {:<16} {}", kind, n);
+    }
+    let named = scene.world.names.iter().filter(|n| n.is_some()).count();
+    println!("  {:<16} {}", "(named)", named);
+
+    let materials: Vec<String> = scene
+        .world
+        .materials()
+        .iter()
+        .map(|m| format!("{:?}", m))
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    println!("materials  {} distinct", materials.len());
+    let variant = |m: &String| m.split([' ', '{']).next().unwrap().to_string();
+    for (kind, n) in count(materials.iter().map(variant).collect()) {
+        println!("  {:<16} {}", kind, n);
+    }
+
+    println!("lights     {}", scene.lights.len());
+    let kinds = scene.lights.iter().map(|light| match light {
+        Light::Directional { .. } => "sun".to_string(),
+        Light::Point { .. } => "point".to_string(),
+    });
+    for (kind, n) in count(kinds.collect()) {
+        println!("  {:<16} {}", kind, n);
+    }
+
+    match &scene.world.bvh {
+        Some(bvh) => println!(
+            "bvh        {} nodes, {} of them 4 wide, {} unbounded objects",
+            bvh.nodes.len(),
+            bvh.wide.len(),
+            bvh.unbounded.len()
+        ),
+        None => println!("bvh        none, every ray tests every object"),
+    }
+    let bytes: usize = objects
+        .iter()
+        .map(|h| {
+            std::mem::size_of_val(h.as_ref())
+                + std::mem::size_of::<Box<dyn Hittable>>()
+                + h.heap_bytes()
+        })
+        .sum();
+    println!(
+        "memory     about {:.1} KiB of geometry",
+        bytes as f64 / 1024.0
+    );
+    match scene.world.bounding_box() {
+        Some(b) => println!("bounds     {:?} to {:?}", b.min, b.max),
+        None => println!("bounds     unbounded"),
+    }
+}
+
+/// Prints what the camera ray through the center of pixel (x, y), counted
+/// from the top left, hits.
+fn pick(cam: &Camera, scene: &Scene, settings: &RenderSettings, x: u32, y: u32) {
+    let u = x as f64 / (settings.width - 1) as f64;
+    let v = (settings.height - 1 - y) as f64 / (settings.height - 1) as f64;
+    let r = cam.center_ray(u, v);
+    match scene.intersect(&r) {
+        Some(i) => {
+            println!("object    {}", scene.world.describe(i.object));
+            println!("material  {:?}", i.material);
+            println!("distance  {}", i.t * r.direction.length());
+            println!("point     {:?}", i.point);
+            println!("normal    {:?}", i.normal);
+            println!("uv        ({}, {})", i.u, i.v);
+            println!(
+                "facing    {}",
+                if i.front_facing { "front" } else { "back" }
+            );
+        }
+        None => println!("nothing, the ray escapes to the sky"),
+    }
+}
+
+/// Traces every sample of pixel (x, y) again, one thread, in the passes and
+/// with the seeds of the render, and prints each sample's radiance and the
+/// events along its path.
+#[allow(clippy::too_many_arguments)]
+fn debug_pixel(
+    cam: &Camera,
+    scene: &Scene,
+    settings: &RenderSettings,
+    samples_per_pixel: usize,
+    pass_samples: usize,
+    jitter_preview: bool,
+    cache: Option<&IrradianceCache>,
+    (x, y): (u32, u32),
+) {
+    let mut samples_done = 0;
+    let mut sum = Vector3(0.0, 0.0, 0.0);
+    while samples_done < samples_per_pixel {
+        let samples = pass_samples.min(samples_per_pixel - samples_done);
+        let pass_settings = RenderSettings {
+            crop: Some((x, y, x + 1, y + 1)),
+            jitter: jitter_preview.then(|| r2_jitter(samples_done)),
+            seed: settings
+                .seed
+                .map(|seed| derive_seed(seed, samples_done as u64)),
+            trace: true,
+            ..*settings
+        };
+        let pass = render_pass(cam, scene, &pass_settings, samples, cache, None, None);
+        let index = (y * settings.width + x) as usize;
+        let (color, cost) = &pass[index];
+        sum += *color;
+        for (k, sample) in cost.trace.iter().enumerate() {
+            println!(
+                "sample {:>4}  radiance {:?}  {}",
+                samples_done + k,
+                sample.radiance,
+                Termination::NAMES[sample.termination.index()]
+            );
+            for (bounce, event) in sample.events.iter().enumerate() {
+                match event {
+                    PathEvent::Hit {
+                        object,
+                        point,
+                        material,
+                        front_facing,
+                        throughput,
+                        direct,
+                        attenuation,
+                    } => {
+                        println!(
+                            "  {:>3} hit {} {} at {:?}",
+                            bounce,
+                            scene.world.describe(*object),
+                            if *front_facing { "front" } else { "back" },
+                            point
+                        );
+                        println!("      material    {:?}", material);
+                        println!("      throughput  {:?}", throughput);
+                        println!("      direct      {:?}", direct);
+                        match attenuation {
+                            Some(a) => println!("      scatters    {:?}", a),
+                            None => println!("      absorbed"),
+                        }
+                    }
+                    PathEvent::Escape {
+                        direction,
+                        throughput,
+                        radiance,
+                    } => {
+                        println!("  {:>3} escapes towards {:?}", bounce, direction);
+                        println!("      throughput  {:?}", throughput);
+                        println!("      radiance    {:?}", radiance);
+                    }
+                }
+            }
+        }
+        samples_done += samples;
+    }
+    println!(
+        "mean {:?} over {} samples",
+        sum / samples_per_pixel as f64,
+        samples_per_pixel
+    );
+}
+
+/// `raytracer bench [options]`: times closest-hit queries of random rays
+/// through the scene on one thread, with the wide BVH nodes and with the
+/// scalar traversal of the binary ones, and checks both find the same hits.
+fn bench(scene: &Scene) {
+    const RAYS: usize = 1 << 18;
+    let boxes: Vec<_> = scene
+        .world
+        .objects
+        .iter()
+        .filter_map(|h| h.bounding_box())
+        .collect();
+    if scene.world.bvh.is_none() || boxes.is_empty() {
+        eprintln!("the scene has no BVH over bounded objects to benchmark");
+        std::process::exit(1);
+    }
+    // in any direction from inside the box of an object picked at random, so
+    // small detailed objects get as many rays as a ground sphere
+    reseed(1);
+    let rays: Vec<Ray> = (0..RAYS)
+        .map(|_| {
+            let b = boxes[(random() * boxes.len() as f64) as usize % boxes.len()];
+            let size = b.max - b.min;
+            Ray {
+                origin: b.min
+                    + Vector3(
+                        random() * size.x(),
+                        random() * size.y(),
+                        random() * size.z(),
+                    ),
+                direction: Vector3::random_unit_vector(),
+                time: 0.0,
+            }
+        })
+        .collect();
+    let run = |scalar: bool| {
+        SCALAR_TRAVERSAL.store(scalar, std::sync::atomic::Ordering::Relaxed);
+        let start = std::time::Instant::now();
+        let hits: Vec<_> = rays
+            .iter()
+            .map(|r| {
+                scene
+                    .world
+                    .hit(r, 0.001, f64::INFINITY)
+                    .map(|i| (i.object, i.t))
+            })
+            .collect();
+        (hits, start.elapsed().as_secs_f64())
+    };
+    let (wide, wide_seconds) = run(false);
+    let (scalar, scalar_seconds) = run(true);
+    SCALAR_TRAVERSAL.store(false, std::sync::atomic::Ordering::Relaxed);
+    let rate = |seconds: f64| RAYS as f64 / seconds / 1e6;
+    println!(
+        "rays       {} random, {} hit",
+        RAYS,
+        wide.iter().flatten().count()
+    );
+    println!("wide       {:.2} Mrays/s", rate(wide_seconds));
+    println!(
+        "scalar     {:.2} Mrays/s, wide is {:.2}x as fast",
+        rate(scalar_seconds),
+        scalar_seconds / wide_seconds
+    );
+    let differing = wide.iter().zip(&scalar).filter(|(a, b)| a != b).count();
+    if differing > 0 {
+        eprintln!("{} rays hit differently", differing);
+        std::process::exit(1);
+    }
+}
+
+/// `raytracer query [options]`: answers ray queries against the scene, one
+/// per line of standard input, so other programs can use it for picking and
+/// visibility. `ray ox,oy,oz dx,dy,dz` prints `hit t x,y,z nx,ny,nz id` or
+/// `miss`; `occluded ax,ay,az bx,by,bz` prints `true` or `false`.
+fn query(scene: &Scene) {
+    enum Query {
+        Ray(usize),
+        Occluded(usize),
+        Sky(Vector3),
+    }
+    let mut rays = Vec::new();
+    let mut segments = Vec::new();
+    let mut queries = Vec::new();
+    for (n, line) in std::io::stdin().lines().enumerate() {
+        let line = line.unwrap_or_else(|e| {
+            eprintln!("could not read queries: {}", e);
+            std::process::exit(1);
+        });
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let vectors: Option<Vec<Vector3>> = words.iter().skip(1).map(|w| w.parse().ok()).collect();
+        match (words.first(), vectors.as_deref()) {
+            (None, _) => {}
+            (Some(&"ray"), Some(&[origin, direction])) => {
+                queries.push(Query::Ray(rays.len()));
+                rays.push(Ray {
+                    origin,
+                    direction,
+                    time: 0.0,
+                });
+            }
+            (Some(&"occluded"), Some(&[a, b])) => {
+                queries.push(Query::Occluded(segments.len()));
+                segments.push((a, b));
+            }
+            (Some(&"sky"), Some(&[direction])) => queries.push(Query::Sky(direction)),
+            _ => {
+                eprintln!(
+                    "line {}: expected 'ray' or 'occluded' and two vectors, or 'sky' and a direction",
+                    n + 1
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    let hits = scene.intersect_batch(&rays);
+    let occluded = scene.occluded_batch(&segments);
+    let xyz = |v: Vector3| format!("{},{},{}", v.x(), v.y(), v.z());
+    for q in queries {
+        match q {
+            Query::Ray(i) => match hits[i] {
+                Some(hit) => println!(
+                    "hit {} {} {} {}",
+                    hit.t,
+                    xyz(hit.point),
+                    xyz(hit.normal),
+                    hit.object
+                ),
+                None => println!("miss"),
+            },
+            Query::Occluded(i) => println!("{}", occluded[i]),
+            Query::Sky(direction) => {
+                // what a camera ray escaping in `direction` sees
+                let radiance = scene
+                    .color_space
+                    .to_linear_srgb(scene.background(&direction) + scene.sun_disk(&direction));
+                let luminance =
+                    0.2126 * radiance.x() + 0.7152 * radiance.y() + 0.0722 * radiance.z();
+                println!("{} {}", xyz(radiance), luminance * LUMENS_PER_WATT);
+            }
+        }
+    }
+}
+
+/// Resolution and sampling shared by every view rendered in a run.
+#[derive(Clone, Copy)]
+struct ImageSettings {
+    aspect_ratio: f64,
+    width: u32,
+    height: u32,
+    samples_per_pixel: usize,
+    max_depth: usize,
+    shutter: (f64, f64),
+    shutter_curve: ShutterCurve,
+    rolling_shutter: f64,
+}
+
+/// Renders the material ball once for every value of `--sheet-range`
+/// substituted for `{}` in the material `template`, and writes the renders to
+/// `out` as a grid, in rows.
+fn contact_sheet(
+    options: &Options,
+    view: &CameraView,
+    image: ImageSettings,
+    template: &str,
+    out: impl Write,
+) -> std::io::Result<()> {
+    let (from, to, count) = options.sheet_range.unwrap_or((0.0, 1.0, 5));
+    let columns = (count as f64).sqrt().ceil() as usize;
+    let rows = count.div_ceil(columns);
+    let (w, h) = (image.width as usize, image.height as usize);
+    let mut camera = Camera::new(
+        view.from,
+        view.at,
+        Vector3(0.0, 1.0, 0.0),
+        view.vfov,
+        image.aspect_ratio,
+        view.aperture,
+        view.focus,
+    );
+    camera.shutter = image.shutter;
+    camera.shutter_curve = image.shutter_curve;
+    camera.rolling_shutter = image.rolling_shutter;
+    let mut pixels = vec![Vector3(0.0, 0.0, 0.0); columns * w * rows * h];
+    for n in 0..count {
+        let value = match count {
+            1 => from,
+            _ => from + (to - from) * n as f64 / (count - 1) as f64,
+        };
+        let spec = template.replace("{}", &format!("{:.3}", value));
+        let material: Material = spec.parse().unwrap_or_else(|_| {
+            eprintln!("invalid material {:?} for --contact-sheet", spec);
+            std::process::exit(1);
+        });
+        eprintln!(
+            "row {}, column {}: {}",
+            n / columns + 1,
+            n % columns + 1,
+            spec
+        );
+        let scene = Scene::material_ball(options, material);
+        let settings = Settings {
+            samples_per_pixel: image.samples_per_pixel,
+            max_depth: image.max_depth,
+            ..Settings::new(camera.clone(), image.width, image.height)
+        };
+        let cell = render(&scene, &settings);
+        let (x0, y0) = (n % columns * w, n / columns * h);
+        for (y, row) in cell.pixels.chunks(w).enumerate() {
+            let start = (y0 + y) * columns * w + x0;
+            pixels[start..start + w].copy_from_slice(row);
+        }
+    }
+    let sheet = Image {
+        width: (columns * w) as u32,
+        height: (rows * h) as u32,
+        pixels,
+        gamma: true,
+    };
+    sheet.write(out, options.output_format())
+}
+
+/// Everything needed to reproduce an image rendered from `view`, as
+/// `key: value` lines for its metadata, apart from the wall time.
+fn image_metadata(
+    args: &[String],
+    options: &Options,
+    scene_name: &str,
+    view: &CameraView,
+    samples: usize,
+    max_depth: usize,
+    irradiance_cache: Option<&IrradianceCache>,
+) -> Vec<String> {
+    let integrator = match (options.mode, irradiance_cache) {
+        (RenderMode::Path, Some(cache)) => format!("path, irradiance cache {}", cache.accuracy),
+        (RenderMode::Path, None) if options.sppm.is_some() => format!(
+            "progressive photon mapping, {} photons per iteration",
+            options.sppm.unwrap_or(0)
+        ),
+        (RenderMode::Path, None) => "path".to_string(),
+        (mode, _) => format!("{:?}", mode).to_lowercase(),
+    };
+    [
+        ("command", args[1..].join(" ")),
+        ("version", env!("CARGO_PKG_VERSION").to_string()),
+        ("scene", scene_name.to_string()),
+        (
+            "seed",
+            options
+                .seed
+                .map_or("none".to_string(), |seed| seed.to_string()),
+        ),
+        ("samples", samples.to_string()),
+        (
+            "max depth",
+            match [
+                ("diffuse", options.max_diffuse),
+                ("glossy", options.max_glossy),
+                ("transmission", options.max_transmission),
+                ("roulette after", options.roulette),
+                ("split up to", options.split),
+            ]
+            .iter()
+            .filter_map(|(kind, limit)| limit.map(|limit| format!("{} {}", kind, limit)))
+            .collect::<Vec<_>>()
+            {
+                limits if limits.is_empty() => max_depth.to_string(),
+                limits => format!("{} ({})", max_depth, limits.join(", ")),
+            },
+        ),
+        ("integrator", integrator),
+        (
+            "tone map",
+            format!(
+                "{}, exposure {:+} EV",
+                format!("{:?}", options.tone_map).to_lowercase(),
+                options.exposure
+            ),
+        ),
+        (
+            "physical camera",
+            options.physical_camera().map_or_else(
+                || "off".to_string(),
+                |c| {
+                    format!(
+                        "f/{} {}s ISO {} (EV100 {:.2})",
+                        c.f_stop,
+                        c.shutter,
+                        c.iso,
+                        c.ev100()
+                    )
+                },
+            ),
+        ),
+        (
+            "camera",
+            format!(
+                "from {:?} at {:?} vfov {} aperture {} focus {}",
+                view.from, view.at, view.vfov, view.aperture, view.focus
+            ),
+        ),
+    ]
+    .iter()
+    .map(|(key, value)| format!("{}: {}", key, value))
+    .collect()
+}
+
+/// Renders the scene as seen from `view` and writes it to `out` as PPM.
+fn render_view(
+    args: &[String],
+    options: &Options,
+    scene: &Scene,
+    scene_hash: u64,
+    view: &CameraView,
+    image: ImageSettings,
+    out: impl Write,
+) -> std::io::Result<()> {
+    let ImageSettings {
+        aspect_ratio,
+        width,
+        height,
+        mut samples_per_pixel,
+        max_depth,
+        shutter,
+        shutter_curve,
+        rolling_shutter,
+    } = image;
+    let (lookfrom, lookat, vfov, dist_to_focus) = (view.from, view.at, view.vfov, view.focus);
+    let vup = Vector3(0.0, 1.0, 0.0);
+    let aperture = view.aperture;
+    let mut cam = Camera::new(
+        lookfrom,
+        lookat,
+        vup,
+        vfov,
+        aspect_ratio,
+        aperture,
+        dist_to_focus,
+    );
+    if let Some(path) = &options.aperture_image {
+        let mask = ApertureMask::load(path).unwrap_or_else(|e| {
+            log_eprintln!("could not read aperture image {}: {}", path, e);
+            std::process::exit(1);
+        });
+        cam.aperture_mask = Some(std::sync::Arc::new(mask));
+    }
+    cam.projection = options.projection.unwrap_or_default();
+    cam.shutter = shutter;
+    cam.shutter_curve = shutter_curve;
+    cam.rolling_shutter = rolling_shutter;
+    let lut = options.lut.as_ref().map(|path| {
+        Lut::load(path).unwrap_or_else(|e| {
+            log_eprintln!("could not read LUT {}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+    // with stereo output, the main render is the left eye
+    let eye_separation = options.eye_separation.unwrap_or(0.4);
+    let (cam, right_eye) = match options.stereo {
+        Some(_) => (
+            cam.eye(-eye_separation / 2.0),
+            cam.eye(eye_separation / 2.0),
+        ),
+        None => (cam.clone(), cam),
+    };
+
+    //Render
+    let reference = options.reference.as_ref().map(|path| {
+        let (w, h, pixels) = read_ppm(path).unwrap_or_else(|e| {
+            log_eprintln!("could not read reference image {}: {}", path, e);
+            std::process::exit(1);
+        });
+        if w != width as usize || h != height as usize {
+            log_eprintln!(
+                "reference image is {}x{}, but the render is {}x{}",
+                w,
+                h,
+                width,
+                height
+            );
+            std::process::exit(1);
+        }
+        pixels
+    });
+    let pass_samples = match options.report_every {
+        _ if options.jitter_preview => 1,
+        Some(n) => n,
+        None if reference.is_some()
+            || options.progressive
+            || options.window
+            || options.checkpoint.is_some() =>
+        {
+            4
+        }
+        None => samples_per_pixel,
+    };
+
+    let crop = options.crop.map(|[x0, y0, x1, y1]| {
+        // all coordinates within [0, 1] are fractions of the image size
+        let (sx, sy) = if [x0, y0, x1, y1].iter().all(|c| *c <= 1.0) {
+            (width as f64, height as f64)
+        } else {
+            (1.0, 1.0)
+        };
+        let x = |c: f64| ((c * sx).round().max(0.0) as u32).min(width);
+        let y = |c: f64| ((c * sy).round().max(0.0) as u32).min(height);
+        let crop = (x(x0), y(y0), x(x1), y(y1));
+        if crop.0 >= crop.2 || crop.1 >= crop.3 {
+            log_eprintln!("--crop rectangle is empty");
+            std::process::exit(1);
+        }
+        crop
+    });
+    // a tile is a crop of its grid cell grown by the overlap on every side
+    let tile = options.tiles.map(|(columns, rows)| {
+        if options.tile >= columns * rows {
+            log_eprintln!("--tile must be below {}", columns * rows);
+            std::process::exit(1);
+        }
+        let overlap = options.tile_overlap.unwrap_or(16);
+        let (column, row) = (options.tile % columns, options.tile / columns);
+        (
+            (width * column / columns).saturating_sub(overlap),
+            (height * row / rows).saturating_sub(overlap),
+            (width * (column + 1) / columns + overlap).min(width),
+            (height * (row + 1) / rows + overlap).min(height),
+        )
+    });
+    let crop = tile.or(crop);
+    let settings = RenderSettings {
+        width,
+        height,
+        max_depth,
+        mode: options.mode,
+        crop,
+        jitter: None,
+        adaptive: options.adaptive.map(|threshold| Adaptive {
+            threshold,
+            min_samples: options.min_samples.unwrap_or(16),
+        }),
+        light_groups: options.light_groups.is_some(),
+        shadow_catcher: options.shadow_catcher.then(|| {
+            ShadowCatcher::under(scene).unwrap_or_else(|| {
+                log_eprintln!("--shadow-catcher needs a scene with bounds to stand on");
+                std::process::exit(1);
+            })
+        }),
+        seed: options.seed,
+        buckets: options.median_of_means,
+        trace: false,
+        aovs: !options.aovs.is_empty() || options.denoise,
+        roulette: options.roulette,
+        split: options.split,
+        sampler: options.sampler,
+    };
+    check_view_options(options, pass_samples >= samples_per_pixel);
+    if let Some((x, y)) = options.pick {
+        if x >= width || y >= height {
+            log_eprintln!(
+                "pixel ({}, {}) is outside the {}x{} image",
+                x,
+                y,
+                width,
+                height
+            );
+            std::process::exit(1);
+        }
+        pick(&cam, scene, &settings, x, y);
+        return Ok(());
+    }
+    if let Some(path) = &options.lidar {
+        let pattern = options.lidar_pattern.unwrap_or(LidarPattern::Grid);
+        let divergence = options.beam_divergence.unwrap_or(0.0) * 1e-3;
+        let noise = options.range_noise.unwrap_or(0.0);
+        lidar_scan(path, &cam, scene, &settings, pattern, divergence, noise).unwrap_or_else(|e| {
+            log_eprintln!("could not write point cloud {}: {}", path, e);
+            std::process::exit(1);
+        });
+        return Ok(());
+    }
+    let irradiance_cache = options.irradiance_cache.map(IrradianceCache::new);
+    if let Some((x, y)) = options.debug_pixel {
+        if x >= width || y >= height {
+            log_eprintln!(
+                "pixel ({}, {}) is outside the {}x{} image",
+                x,
+                y,
+                width,
+                height
+            );
+            std::process::exit(1);
+        }
+        if options.mode != RenderMode::Path || settings.shadow_catcher.is_some() {
+            log_eprintln!("--debug-pixel only works when path tracing");
+            std::process::exit(1);
+        }
+        if options.seed.is_none() {
+            log_eprintln!("note: without --seed the samples differ from any render");
+        }
+        debug_pixel(
+            &cam,
+            scene,
+            &settings,
+            samples_per_pixel,
+            pass_samples,
+            options.jitter_preview,
+            irradiance_cache.as_ref(),
+            (x, y),
+        );
+        return Ok(());
+    }
+
+    let start = std::time::Instant::now();
+    let scene_name = match &options.scene_file {
+        Some(path) => format!("{} {:016x}", path, scene_hash),
+        None => format!("{:?} {:016x}", options.scene, scene_hash),
+    };
+    // everything needed to reproduce the image
+    let metadata = |samples: usize| {
+        let mut metadata = image_metadata(
+            args,
+            options,
+            &scene_name,
+            view,
+            samples,
+            max_depth,
+            irradiance_cache.as_ref(),
+        );
+        metadata.push(format!("wall time: {:.2}s", start.elapsed().as_secs_f64()));
+        metadata
+    };
+    if let Some(directory) = &options.diagnostics {
+        diagnostics::enable(directory);
+    }
+    diagnostics::set("command", args[1..].join(" "));
+    diagnostics::set("version", env!("CARGO_PKG_VERSION"));
+    diagnostics::set("scene", &scene_name);
+    diagnostics::set(
+        "seed",
+        options
+            .seed
+            .map_or("none".to_string(), |seed| seed.to_string()),
+    );
+    diagnostics::set("settings", format!("{:?}", settings));
+    if let Some(path) = &options.scene_file {
+        diagnostics::attach(std::path::Path::new(path));
+    }
+    // checkpoints of the same hash hold samples of the same image, whatever
+    // their seeds, and can be resumed or merged; hashed before any pass runs
+    let checkpoint_hash = options.checkpoint.as_ref().map(|_| {
+        let unseeded = RenderSettings {
+            seed: None,
+            ..settings
+        };
+        stable_hash(format!("{:016x} {:?} {:?}", scene_hash, view, unseeded))
+    });
+    let preview_file = options.preview_file.as_deref().unwrap_or("preview.ppm");
+    let preview = |source: &RenderSettings, pixels: &[Vector3], samples: usize| {
+        write_preview(preview_file, source, pixels, samples, width, height).unwrap_or_else(|e| {
+            log_eprintln!("could not write preview {}: {}", preview_file, e);
+        });
+    };
+    if options.progressive {
+        // quick low-resolution previews before the full-resolution passes start
+        for factor in [8, 4, 2] {
+            let coarse = RenderSettings {
+                width: (width / factor).max(2),
+                height: (height / factor).max(2),
+                crop: crop.map(|(x0, y0, x1, y1)| {
+                    (
+                        x0 / factor,
+                        y0 / factor,
+                        x1.div_ceil(factor),
+                        y1.div_ceil(factor),
+                    )
+                }),
+                ..settings
+            };
+            let samples = pass_samples.min(4);
+            let pass = render_pass(
+                &cam,
+                scene,
+                &coarse,
+                samples,
+                irradiance_cache.as_ref(),
+                None,
+                None,
+            );
+            let pixels: Vec<Vector3> = pass.into_iter().map(|(color, _)| color).collect();
+            preview(&coarse, &pixels, samples);
+        }
+    }
+    let mut image = vec![Vector3(0.0, 0.0, 0.0); (width * height) as usize];
+    let mut costs = vec![PixelCost::default(); image.len()];
+    let mut samples_done = 0;
+    let mut seeds: Vec<u64> = options.seed.into_iter().collect();
+    if options.resume {
+        let path = options.checkpoint.as_deref().unwrap_or_else(|| {
+            log_eprintln!("--resume needs the --checkpoint to resume from");
+            std::process::exit(1);
+        });
+        if options.light_groups.is_some()
+            || options.median_of_means.is_some()
+            || !options.aovs.is_empty()
+            || options.denoise
+        {
+            log_eprintln!(
+                "--light-groups, --median-of-means, --aov and --denoise cannot be resumed \
+                 from a checkpoint"
+            );
+            std::process::exit(1);
+        }
+        match read_checkpoint(path) {
+            Ok(checkpoint) => {
+                if checkpoint.hash.is_some() && checkpoint.hash != checkpoint_hash {
+                    log_eprintln!(
+                        "checkpoint {} was rendered from another scene or with other settings",
+                        path
+                    );
+                    std::process::exit(1);
+                }
+                if (checkpoint.width, checkpoint.height) != (width, height)
+                    || checkpoint.samples > samples_per_pixel
+                {
+                    log_eprintln!(
+                        "checkpoint {} holds {} samples per pixel of a {}x{} image, \
+                         but the render takes {} of {}x{}",
+                        path,
+                        checkpoint.samples,
+                        checkpoint.width,
+                        checkpoint.height,
+                        samples_per_pixel,
+                        width,
+                        height
+                    );
+                    std::process::exit(1);
+                }
+                image = checkpoint.pixels;
+                for (cost, coverage) in costs.iter_mut().zip(checkpoint.coverage) {
+                    cost.coverage = coverage;
+                }
+                samples_done = checkpoint.samples;
+                for seed in checkpoint.seeds {
+                    if !seeds.contains(&seed) {
+                        seeds.push(seed);
+                    }
+                }
+                log_eprintln!(
+                    "resuming at {} of {} samples per pixel",
+                    samples_done,
+                    samples_per_pixel
+                );
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                log_eprintln!("no checkpoint {} yet, starting from the beginning", path);
+            }
+            Err(e) => {
+                log_eprintln!("could not read checkpoint {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let resumed_at = samples_done;
+    if options.sppm.is_some() && scene.has_light_modifiers() {
+        log_eprintln!("--sppm cannot render negative lights or gels");
+        std::process::exit(1);
+    }
+    let mut sppm = options
+        .sppm
+        .map(|photons| Sppm::new(width, height, photons, options.photon_radius));
+    let mut window = options.window.then(|| {
+        PreviewWindow::open(width, height).unwrap_or_else(|e| {
+            log_eprintln!("could not open a window: {}", e);
+            std::process::exit(1);
+        })
+    });
+    if let Some(window) = &mut window {
+        let limit = options.focus_overlay.unwrap_or(1.0);
+        window.set_depth_of_field(
+            focus_blur(&cam, scene, &settings),
+            limit,
+            cam.depth_of_field(limit, height),
+            options.focus_overlay.is_some(),
+        );
+    }
+    // percentage done and time left, unless stderr goes to a file such as a
+    // batch log or frames render at the same time
+    let progress = ((options.progress || std::io::stderr().is_terminal())
+        && options.frames.is_none())
+    .then(|| {
+        let (x0, y0, x1, y1) = crop.unwrap_or((0, 0, width, height));
+        let eyes = if options.stereo.is_some() { 2 } else { 1 };
+        let samples = (samples_per_pixel - samples_done) as u64;
+        Progress::new((x1 - x0) as u64 * (y1 - y0) as u64 * samples * eyes)
+    });
+    // extra samples summed per pixel inside the rectangle dragged in the
+    // window, and how many
+    let mut boost = vec![(Vector3(0.0, 0.0, 0.0), 0); if options.window { image.len() } else { 0 }];
+    let mut boosted_passes = 0;
+    // the image as if every pixel had `samples_done` samples
+    let with_boost = |image: &[Vector3], boost: &[(Vector3, usize)], samples_done: usize| {
+        image
+            .iter()
+            .zip(boost)
+            .map(|(c, (sum, n))| match n {
+                0 => *c,
+                _ => (*c + *sum) * samples_done as f64 / (samples_done + n) as f64,
+            })
+            .collect::<Vec<_>>()
+    };
+    let checkpoint_every =
+        std::time::Duration::from_secs_f64(options.checkpoint_every.unwrap_or(60.0));
+    let mut last_checkpoint = std::time::Instant::now();
+    while samples_done < samples_per_pixel {
+        let samples = pass_samples.min(samples_per_pixel - samples_done);
+        // after the first pass, the time spent on each pixel so far predicts the next
+        let estimate = (samples_done > resumed_at).then(|| &costs[..]);
+        // one sample per pass, each frame shifted by the next low-discrepancy offset
+        let pass_settings = RenderSettings {
+            jitter: options.jitter_preview.then(|| r2_jitter(samples_done)),
+            seed: options
+                .seed
+                .map(|seed| derive_seed(seed, samples_done as u64)),
+            ..settings
+        };
+        if let Some(sppm) = &mut sppm {
+            // an iteration is one camera path per pixel and its own photons
+            for k in samples_done..samples_done + samples {
+                let iteration = RenderSettings {
+                    jitter: options.jitter_preview.then(|| r2_jitter(k)),
+                    ..settings
+                };
+                sppm.iterate(&cam, scene, &iteration, progress.as_ref());
+            }
+            let iterations = (samples_done + samples) as f64;
+            for (acc, x) in image.iter_mut().zip(sppm.radiance(scene)) {
+                *acc = x * iterations;
+            }
+        } else {
+            let pass = render_pass(
+                &cam,
+                scene,
+                &pass_settings,
+                samples,
+                irradiance_cache.as_ref(),
+                estimate,
+                progress.as_ref(),
+            );
+            for ((acc, acc_cost), (x, cost)) in image.iter_mut().zip(costs.iter_mut()).zip(pass) {
+                *acc += x;
+                *acc_cost += cost;
+            }
+            // the rectangle dragged in the window gets a pass of its own, with as
+            // many more samples as the image is larger than it, up to 16 times
+            let (cx0, cy0, cx1, cy1) = crop.unwrap_or((0, 0, width, height));
+            let focus = window.as_ref().and_then(PreviewWindow::focus);
+            if let Some((x0, y0, x1, y1)) =
+                focus.map(|(x0, y0, x1, y1)| (x0.max(cx0), y0.max(cy0), x1.min(cx1), y1.min(cy1)))
+            {
+                if x1 > x0 && y1 > y0 {
+                    let area = ((x1 - x0) * (y1 - y0)) as usize;
+                    let factor = (width as usize * height as usize / area).clamp(1, 16);
+                    let focus_settings = RenderSettings {
+                        crop: Some((x0, y0, x1, y1)),
+                        seed: options
+                            .seed
+                            .map(|seed| derive_seed(derive_seed(seed, u64::MAX), boosted_passes)),
+                        ..pass_settings
+                    };
+                    boosted_passes += 1;
+                    let extra = samples * factor;
+                    let pass = render_pass(
+                        &cam,
+                        scene,
+                        &focus_settings,
+                        extra,
+                        irradiance_cache.as_ref(),
+                        None,
+                        None,
+                    );
+                    for (k, ((sum, n), (x, _))) in boost.iter_mut().zip(pass).enumerate() {
+                        let (px, py) = ((k % width as usize) as u32, (k / width as usize) as u32);
+                        if px >= x0 && px < x1 && py >= y0 && py < y1 {
+                            *sum += x;
+                            *n += extra;
+                        }
+                    }
+                }
+            }
+        }
+        samples_done += samples;
+        diagnostics::set("samples done", samples_done);
+        diagnostics::snapshot(&image, width, height, samples_done);
+        if let Some(path) = &options.checkpoint {
+            // the finished render is saved too, for merging with others
+            if last_checkpoint.elapsed() >= checkpoint_every || samples_done >= samples_per_pixel {
+                let checkpoint = Checkpoint {
+                    width,
+                    height,
+                    samples: samples_done,
+                    hash: checkpoint_hash,
+                    seeds: seeds.clone(),
+                    pixels: image.clone(),
+                    coverage: costs.iter().map(|cost| cost.coverage).collect(),
+                };
+                write_checkpoint(path, &checkpoint).unwrap_or_else(|e| {
+                    log_eprintln!("could not write checkpoint {}: {}", path, e)
+                });
+                last_checkpoint = std::time::Instant::now();
+            }
+        }
+        if let Some(window) = &mut window {
+            let shown = with_boost(&image, &boost, samples_done);
+            if !window.show(&shown, samples_done, settings.mode == RenderMode::Path) {
+                // keep what has been rendered so far
+                log_eprintln!("window closed at {} samples per pixel", samples_done);
+                samples_per_pixel = samples_done;
+            }
+        }
+        if options.progressive || options.jitter_preview {
+            preview(&settings, &image, samples_done);
+        }
+        if let Some(reference) = &reference {
+            let (rmse, rel_mse) = error_metrics(&image, samples_done, reference);
+            log_eprintln!(
+                "{:>5} spp  {:>8.2}s  RMSE {:.6}  relMSE {:.6}",
+                samples_done,
+                start.elapsed().as_secs_f64(),
+                rmse,
+                rel_mse
+            );
+        }
+    }
+    if boost.iter().any(|(_, n)| *n > 0) {
+        image = with_boost(&image, &boost, samples_done);
+    }
+
+    // a bundle for the bug report when the image came out broken
+    if options.mode == RenderMode::Path && samples_done > 0 {
+        let finite = |c: &&Vector3| c.x().is_finite() && c.y().is_finite() && c.z().is_finite();
+        let broken = image.len() - image.iter().filter(finite).count();
+        let luminance: f64 = image
+            .iter()
+            .filter(finite)
+            .map(|c| 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z())
+            .sum();
+        diagnostics::set("pixels NaN or infinite", broken);
+        diagnostics::set(
+            "mean luminance",
+            luminance / (image.len() * samples_done) as f64,
+        );
+        diagnostics::set(
+            "wall time",
+            format!("{:.2}s", start.elapsed().as_secs_f64()),
+        );
+        let reason = if broken > options.nan_threshold.unwrap_or(0) {
+            Some(format!("{} pixels are NaN or infinite", broken))
+        } else if image.iter().all(|c| c.length_squared() == 0.0) {
+            Some("the image is black".to_string())
+        } else {
+            None
+        };
+        match reason.as_deref().and_then(diagnostics::write_bundle) {
+            Some(Ok(path)) => log_eprintln!(
+                "{}; diagnostics written to {}",
+                reason.unwrap_or_default(),
+                path.display()
+            ),
+            Some(Err(e)) => log_eprintln!("could not write diagnostics: {}", e),
+            None => {}
+        }
+    }
+
+    if let (Some(_), RenderMode::Path) = (options.median_of_means, options.mode) {
+        // previews showed the plain mean, the image gets the robust one
+        for (color, cost) in image.iter_mut().zip(&costs) {
+            if let Some(median) = cost.median_of_means() {
+                *color = median * samples_per_pixel as f64;
+            }
+        }
+    }
+
+    let mut light_layers = Vec::new();
+    if let Some(path) = &options.light_groups {
+        // one layer per light, then the sky, glowing objects and the rest of
+        // the image, which add up to it before any post-processing
+        let scale = 1.0 / samples_per_pixel as f64;
+        let mut rest: Vec<Vector3> = image.iter().map(|c| *c * scale).collect();
+        let mut layers = Vec::new();
+        for group in 0..scene.lights.len() + 2 {
+            let name = match group {
+                _ if group < scene.lights.len() => format!("light{}", group),
+                _ if group == scene.lights.len() => "sky".to_string(),
+                _ => "emission".to_string(),
+            };
+            let pixels: Vec<Vector3> = costs
+                .iter()
+                .map(|cost| {
+                    cost.lights
+                        .get(group)
+                        .map_or(Vector3(0.0, 0.0, 0.0), |c| *c * scale)
+                })
+                .collect();
+            for (r, c) in rest.iter_mut().zip(&pixels) {
+                *r -= *c;
+            }
+            layers.push((name, RGB, pixels));
+        }
+        layers.push(("rest".to_string(), RGB, rest));
+        write_exr(path, width, height, &layers, &metadata(samples_per_pixel)).unwrap_or_else(|e| {
+            log_eprintln!("could not write light groups {}: {}", path, e);
+        });
+        light_layers = layers;
+    }
+
+    if let Some(region) = options.dump_region {
+        let dumps = dump_paths(&cam, scene, &settings, samples_per_pixel, region);
+        let path = options.dump_file.as_deref().unwrap_or("paths.json");
+        write_path_dump(path, &dumps, dist_to_focus).unwrap_or_else(|e| {
+            log_eprintln!("could not write path dump {}: {}", path, e);
+        });
+    }
+
+    if options.denoise {
+        denoise(&mut image, width as usize, height as usize, &costs);
+    }
+    // linear, before exposure and the effects on top of it
+    let beauty: Option<Vec<Vector3>> = options.passes.as_ref().map(|_| {
+        let scale = 1.0 / samples_per_pixel as f64;
+        image.iter().map(|c| *c * scale).collect()
+    });
+
+    if let (Some(key), RenderMode::Path) = (options.auto_exposure, options.mode) {
+        let clip = options.exposure_clip.unwrap_or((0.05, 0.95));
+        let exposure = auto_exposure(&image, samples_per_pixel, key, clip);
+        log_eprintln!("auto exposure: {:+.2} EV", exposure.log2());
+        for c in image.iter_mut() {
+            *c *= exposure;
+        }
+    }
+
+    if let (Some((threshold, strength)), RenderMode::Path) = (options.bloom, options.mode) {
+        bloom(
+            &mut image,
+            width as usize,
+            height as usize,
+            samples_per_pixel,
+            threshold,
+            strength,
+        );
+    }
+
+    if let (Some(strength), RenderMode::Path) = (options.lens_flare, options.mode) {
+        lens_flare(
+            &mut image,
+            samples_per_pixel,
+            &cam,
+            scene,
+            &settings,
+            strength,
+        );
+    }
+
+    if let Some(overlay) = options.bounds {
+        draw_bounds(
+            &mut image,
+            samples_per_pixel,
+            &cam,
+            scene,
+            &settings,
+            overlay,
+        );
+    }
+
+    if options.mode == RenderMode::Lights {
+        // the legend, with the share of pixels each light dominates
+        let colors: Vec<[u8; 3]> = (0..=scene.lights.len() + 1)
+            .map(|id| id_color(id).to_rgb8(1, false))
+            .collect();
+        for (id, rgb) in colors.iter().enumerate() {
+            let pixels = image
+                .iter()
+                .filter(|c| (**c / samples_per_pixel as f64).to_rgb8(1, false) == *rgb)
+                .count();
+            let light = match scene.lights.get(id) {
+                Some(light) => format!("light {} {:?}", id, light),
+                None if id == scene.lights.len() => "sky".to_string(),
+                None => "glowing objects".to_string(),
+            };
+            log_eprintln!(
+                "#{:02x}{:02x}{:02x} {:>5.1}%  {}",
+                rgb[0],
+                rgb[1],
+                rgb[2],
+                100.0 * pixels as f64 / image.len() as f64,
+                light
+            );
+        }
+    }
+
+    if let Some(metric) = options.heatmap {
+        let (heatmap, top) = cost_heatmap(&costs, metric);
+        let path = options.heatmap_file.as_deref().unwrap_or("heatmap.ppm");
+        write_ppm(path, width, height, &heatmap).unwrap_or_else(|e| {
+            log_eprintln!("could not write heatmap {}: {}", path, e);
+        });
+        match metric {
+            HeatmapMetric::Time => log_eprintln!("heatmap: yellow = {:.3} ms per pixel", top * 1e3),
+            HeatmapMetric::Bounces => log_eprintln!("heatmap: yellow = {} bounces per pixel", top),
+            HeatmapMetric::Length => log_eprintln!("heatmap: yellow = {:.2} bounces per path", top),
+            HeatmapMetric::Throughput => {
+                log_eprintln!("heatmap: yellow = {:.4} final throughput per path", top)
+            }
+            HeatmapMetric::Samples => log_eprintln!("heatmap: yellow = {} samples per pixel", top),
+            HeatmapMetric::Termination => log_eprintln!(
+                "heatmap: red = max depth, blue = absorbed, green = escaped, yellow = cached"
+            ),
+        }
+    }
+    if options.path_stats {
+        print_path_stats(&costs);
+    }
+    if settings.adaptive.is_some() && options.mode == RenderMode::Path {
+        // pixels outside the crop trace no paths
+        let rendered = costs.iter().filter(|cost| cost.paths() > 0);
+        let (pixels, paths) = rendered.fold((0, 0), |(n, sum), cost| (n + 1, sum + cost.paths()));
+        log_eprintln!(
+            "adaptive sampling: {:.1} of {} samples per pixel on average",
+            paths as f64 / pixels.max(1) as f64,
+            samples_per_pixel
+        );
+    }
+
+    let full_size = (width, height);
+    let output_crop = crop.filter(|_| options.crop_only || tile.is_some());
+    let crop_output = |pixels: Vec<Vector3>| match output_crop {
+        Some(crop) => crop_pixels(&pixels, width, crop),
+        None => pixels,
+    };
+    let (width, height) = match output_crop {
+        Some((x0, y0, x1, y1)) => (x1 - x0, y1 - y0),
+        None => (width, height),
+    };
+    let image = crop_output(image);
+    let alpha = settings.shadow_catcher.map(|_| {
+        let coverage = costs.iter().map(|cost| {
+            let a = cost.coverage / samples_per_pixel as f64;
+            Vector3(a, a, a)
+        });
+        crop_output(coverage.collect())
+    });
+    let aov_pixels = |aov: Aov| crop_output(costs.iter().map(|cost| cost.aov(aov)).collect());
+    if let (Some(path), Some(beauty)) = (&options.passes, beauty) {
+        // everything in one file: the image, the AOVs and the light groups
+        let mut layers = vec![(String::new(), RGB, crop_output(beauty))];
+        for &aov in &options.aovs {
+            let (name, channels) = aov.exr_channels();
+            layers.push((name.to_string(), channels, aov_pixels(aov)));
+        }
+        for (name, channels, pixels) in light_layers {
+            layers.push((name, channels, crop_output(pixels)));
+        }
+        write_exr(path, width, height, &layers, &metadata(samples_per_pixel)).unwrap_or_else(|e| {
+            log_eprintln!("could not write passes {}: {}", path, e);
+        });
+    } else {
+        // <aov-file>_depth.exr and so on, cropped like the image
+        let aov_stem = options.aov_file.as_deref().unwrap_or("aov");
+        for &aov in &options.aovs {
+            let path = format!("{}_{}.exr", aov_stem, aov.name());
+            let layers = [(String::new(), RGB, aov_pixels(aov))];
+            write_exr(&path, width, height, &layers, &metadata(samples_per_pixel)).unwrap_or_else(
+                |e| {
+                    log_eprintln!("could not write {} {}: {}", aov.name(), path, e);
+                },
+            );
+        }
+    }
+
+    let (image, width) = match options.stereo {
+        Some(stereo) => {
+            let right: Vec<Vector3> = render_pass(
+                &right_eye,
+                scene,
+                &settings,
+                samples_per_pixel,
+                irradiance_cache.as_ref(),
+                Some(&costs),
+                progress.as_ref(),
+            )
+            .into_iter()
+            .map(|(color, _)| color)
+            .collect();
+            let image = compose_stereo(stereo, &image, &crop_output(right), width);
+            match stereo {
+                Stereo::Anaglyph => (image, width),
+                Stereo::SideBySide => (image, width * 2),
+            }
+        }
+        None => (image, width),
+    };
+
+    let hits = match options.false_color {
+        Some(FalseColor::Hits) => {
+            let hits = costs.iter().map(|cost| {
+                let h = cost.path_length();
+                Vector3(h, h, h)
+            });
+            crop_output(hits.collect()).iter().map(|c| c.x()).collect()
+        }
+        _ => Vec::new(),
+    };
+    let rendered = RenderedView {
+        image,
+        alpha,
+        width,
+        height,
+        samples_per_pixel,
+        hits,
+        placement: tile
+            .map(|(x0, y0, _, _)| format!("tile {} {} {} {}", x0, y0, full_size.0, full_size.1)),
+        metadata: metadata(samples_per_pixel),
+    };
+    write_view(out, options, rendered, lut.as_ref())
+}
+
+/// Exits with an error for options that cannot be combined; `one_pass` is
+/// whether all samples are taken in a single pass.
+fn check_view_options(options: &Options, one_pass: bool) {
+    if options.light_groups.is_some() && options.mode != RenderMode::Path {
+        log_eprintln!("--light-groups only works when path tracing");
+        std::process::exit(1);
+    }
+    if options.shadow_catcher
+        && (options.mode != RenderMode::Path
+            || options.stereo.is_some()
+            || options.output_format() != ImageFormat::Png)
+    {
+        log_eprintln!(
+            "--shadow-catcher only works when path tracing to a PNG image, without stereo"
+        );
+        std::process::exit(1);
+    }
+    if options.sppm.is_some()
+        && (options.mode != RenderMode::Path
+            || options.irradiance_cache.is_some()
+            || options.adaptive.is_some()
+            || options.median_of_means.is_some()
+            || options.light_groups.is_some()
+            || options.shadow_catcher
+            || options.checkpoint.is_some()
+            || options.debug_pixel.is_some()
+            || !options.aovs.is_empty()
+            || options.denoise)
+    {
+        log_eprintln!(
+            "--sppm only works when path tracing, without the irradiance cache, \
+             --adaptive, --median-of-means, --light-groups, --shadow-catcher, \
+             --checkpoint, --debug-pixel, --aov or --denoise"
+        );
+        std::process::exit(1);
+    }
+    if options.focus_overlay.is_some() && !options.window {
+        log_eprintln!("--focus-overlay only works with --window");
+        std::process::exit(1);
+    }
+    if options.f_stop.is_none() && (options.shutter_speed.is_some() || options.iso.is_some()) {
+        log_eprintln!("--shutter-speed and --iso need --f-stop");
+        std::process::exit(1);
+    }
+    if options.f_stop.is_some() && (options.aperture.is_some() || options.auto_exposure.is_some()) {
+        log_eprintln!("--f-stop cannot be combined with --aperture or --auto-exposure");
+        std::process::exit(1);
+    }
+    if options.plate.is_some() && options.backdrop.is_some() {
+        log_eprintln!("--plate cannot be combined with --backdrop");
+        std::process::exit(1);
+    }
+    if options.false_color.is_some()
+        && (options.stereo.is_some() || options.sppm.is_some() || options.shadow_catcher)
+    {
+        log_eprintln!("--false-color cannot be combined with --stereo, --sppm or --shadow-catcher");
+        std::process::exit(1);
+    }
+    if options.sampler.is_some()
+        && (!one_pass || options.adaptive.is_some() || options.sppm.is_some())
+    {
+        log_eprintln!(
+            "--sampler needs all samples in one pass: not with --progressive, --window, \
+             --report-every, --jitter-preview, --checkpoint, --reference, --adaptive \
+             or --sppm"
+        );
+        std::process::exit(1);
+    }
+    if options.denoise && (options.mode != RenderMode::Path || options.stereo.is_some()) {
+        log_eprintln!("--denoise only works when path tracing, without stereo");
+        std::process::exit(1);
+    }
+}
+
+/// A view as rendered, its pixels still the sums of its samples.
+struct RenderedView {
+    image: Vec<Vector3>,
+    /// Coverage for the alpha channel of a shadow catcher.
+    alpha: Option<Vec<Vector3>>,
+    width: u32,
+    height: u32,
+    samples_per_pixel: usize,
+    /// The path length of each pixel, for `--false-color hits`.
+    hits: Vec<f64>,
+    /// Where a tile goes in the full image.
+    placement: Option<String>,
+    metadata: Vec<String>,
+}
+
+/// Maps a rendered view to display values, in false colors if asked, and
+/// writes it to `out` with its metadata in the output format.
+fn write_view(
+    out: impl Write,
+    options: &Options,
+    rendered: RenderedView,
+    lut: Option<&Lut>,
+) -> std::io::Result<()> {
+    let RenderedView {
+        mut image,
+        alpha,
+        width,
+        height,
+        samples_per_pixel,
+        hits,
+        placement,
+        metadata,
+    } = rendered;
+    if options.mode == RenderMode::Depth && options.false_color.is_none() {
+        // map distances to [0, 1], far = white, misses stay infinite and clamp to white
+        let max = image
+            .iter()
+            .map(|c| c.x() / samples_per_pixel as f64)
+            .filter(|d| d.is_finite())
+            .fold(0.0, f64::max);
+        for c in image.iter_mut() {
+            *c /= max;
+        }
+    }
+
+    if options.mode == RenderMode::Motion {
+        // map motion to [0, 1] around mid gray, the longest vector reaching 0 or 1
+        let max = image
+            .iter()
+            .map(|c| f64::max(c.x().abs(), c.y().abs()) / samples_per_pixel as f64)
+            .fold(f64::MIN_POSITIVE, f64::max);
+        let still = Vector3(0.5, 0.5, 0.0) * samples_per_pixel as f64;
+        for c in image.iter_mut() {
+            *c = still + *c / (2.0 * max);
+        }
+        log_eprintln!(
+            "motion: red and green 0 and 1 = -{:.2} and +{:.2} pixels per unit of time",
+            max,
+            max
+        );
+    }
+
+    if options.mode == RenderMode::Path {
+        tone_map(
+            &mut image,
+            samples_per_pixel,
+            options.exposure.exp2() * options.physical_camera().map_or(1.0, |c| c.exposure()),
+            options.tone_map,
+        );
+    }
+
+    if let (Some(lut), RenderMode::Path) = (lut, options.mode) {
+        apply_lut(&mut image, samples_per_pixel, lut);
+    }
+
+    if let (Some(amount), RenderMode::Path) = (options.film_grain, options.mode) {
+        film_grain(&mut image, samples_per_pixel, amount, options.grain_color);
+    }
+
+    // the quantity through the colormap, with a legend below
+    let mut false_color_range = None;
+    let (image, height) = match options.false_color {
+        Some(quantity) => {
+            let n = samples_per_pixel as f64;
+            let values: Vec<f64> = match quantity {
+                FalseColor::Depth => image.iter().map(|c| c.x() / n).collect(),
+                FalseColor::Irradiance => image
+                    .iter()
+                    .map(|c| (0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()) / n)
+                    .collect(),
+                FalseColor::Hits => hits,
+            };
+            // by default from the 1st to the 99th percentile
+            let range = options.false_color_range.unwrap_or_else(|| {
+                let mut sorted: Vec<f64> =
+                    values.iter().copied().filter(|v| v.is_finite()).collect();
+                sorted.sort_by(f64::total_cmp);
+                match sorted.len() {
+                    0 => (0.0, 1.0),
+                    k => (sorted[(k - 1) / 100], sorted[(k - 1) * 99 / 100]),
+                }
+            });
+            log_eprintln!(
+                "false color: {} from {} to {}",
+                format!("{:?}", quantity).to_lowercase(),
+                range.0,
+                range.1
+            );
+            false_color_range = Some((quantity, range));
+            let (colors, height) = false_color(&values, width as usize, range, options.colormap);
+            (colors.into_iter().map(|c| c * n).collect(), height as u32)
+        }
+        None => (image, height),
+    };
+
+    let comments: Vec<String> = placement
+        .into_iter()
+        .chain(false_color_range.map(|(quantity, (low, high))| {
+            format!(
+                "false color: {} from {} to {}, {}",
+                format!("{:?}", quantity).to_lowercase(),
+                low,
+                high,
+                format!("{:?}", options.colormap).to_lowercase()
+            )
+        }))
+        .chain(metadata)
+        .collect();
+    let gamma = options.mode == RenderMode::Path && options.false_color.is_none();
+    let pixels: Vec<u8> = match &alpha {
+        // PNG stores straight alpha, so the premultiplied colors are divided
+        // by it; shadows are black either way
+        Some(alpha) => image
+            .iter()
+            .zip(alpha)
+            .flat_map(|(color, a)| {
+                let straight = if a.x() > 0.0 { *color / a.x() } else { *color };
+                let [r, g, b] = straight.to_rgb8(samples_per_pixel, gamma);
+                [r, g, b, (a.x().clamp(0.0, 1.0) * 255.0).round() as u8]
+            })
+            .collect(),
+        None => image
+            .iter()
+            .flat_map(|color| color.to_rgb8(samples_per_pixel, gamma))
+            .collect(),
+    };
+    write_image(
+        out,
+        options.output_format(),
+        width,
+        height,
+        &pixels,
+        &comments,
+    )
+}
+
+/// Runs the command line given to the process.
+pub fn main() {
+    run(std::env::args().collect())
+}
+
+/// Runs a command line, the program name first, as if the process had been
+/// started with it.
+pub fn run(args: Vec<String>) {
+    diagnostics::write_bundle_on_panic();
+    match args.get(1).map(String::as_str) {
+        Some("stitch") => return stitch(&args[2..]),
+        Some("batch") => return batch(&args[2..]),
+        Some("serve") => return serve(&args[2..]),
+        Some("merge") => return merge(&args[2..]),
+        Some("stats") => {
+            let options = Options::parse(args[2..].iter().cloned());
+            return scene_stats(&load_scene(&options).0);
+        }
+        Some("bench") => {
+            let options = Options::parse(args[2..].iter().cloned());
+            return bench(&load_scene(&options).0);
+        }
+        Some("query") => {
+            let options = Options::parse(args[2..].iter().cloned());
+            return query(&load_scene(&options).0);
+        }
+        _ => {}
+    }
+    let mut options = Options::parse(args[1..].iter().cloned());
+    if let Some(quantity) = options.false_color {
+        if options.mode != RenderMode::Path {
+            eprintln!("--false-color renders its quantity in a mode of its own, without --mode");
+            std::process::exit(1);
+        }
+        options.mode = quantity.mode();
+    }
+    // a single frame of an animation at its time
+    if let (Some(fps), None) = (options.fps, options.frames) {
+        options.time += options.frame / fps;
+    }
+    let (mut scene, scene_settings) = load_scene(&options);
+    let scene_hash = scene_hash(&options);
+    if options.aovs.is_empty() {
+        options.aovs = scene_settings.aovs.clone().unwrap_or_default();
+    }
+    options.roulette = options.roulette.or(scene_settings.roulette);
+
+    // Image
+    let aspect_ratio = 16.0 / 9.0;
+    // the command line overrides the scene file
+    let width = options.width.or(scene_settings.width).unwrap_or(2560);
+    let (height, aspect_ratio) = match options.height {
+        Some(height) => (height, width as f64 / height as f64),
+        None => (((width as f64 / aspect_ratio) as u32).max(1), aspect_ratio),
+    };
+    let samples_per_pixel = options.samples.or(scene_settings.samples).unwrap_or(100);
+    let max_depth = options.max_depth.or(scene_settings.max_depth).unwrap_or(50);
+    let (width, height, samples_per_pixel, max_depth) = if options.draft {
+        (
+            (width / 2).max(1),
+            (height / 2).max(1),
+            samples_per_pixel.min(8),
+            max_depth.min(8),
+        )
+    } else {
+        (width, height, samples_per_pixel, max_depth)
+    };
+    // pixels map to the camera by their distance from the first, over the
+    // distance between the first and the last
+    if width < 2 || height < 2 {
+        eprintln!(
+            "images must be at least 2 pixels wide and high, but this one is {}x{}",
+            width, height
+        );
+        std::process::exit(1);
+    }
+
+    if let Some(name) = &options.bake {
+        let id = scene.world.find(name).unwrap_or_else(|| {
+            eprintln!("there is no object {} to bake", name);
+            std::process::exit(1);
+        });
+        let object = &scene.world.objects[id];
+        if object.surface_at(0.5, 0.5).is_none() {
+            eprintln!(
+                "object {} has no UV layout to bake into",
+                scene.world.describe(id)
+            );
+            std::process::exit(1);
+        }
+        let size = options.bake_size.unwrap_or(512);
+        let map = options.bake_map.unwrap_or_default();
+        let encoded: Vec<Vector3> = match map {
+            BakeMap::Lightmap => {
+                bake_lightmap(&scene, object.as_ref(), size, samples_per_pixel, max_depth)
+                    .iter()
+                    .map(|c| {
+                        let c = *c / samples_per_pixel as f64;
+                        let encode = |x: f64| srgb_encode(x.max(0.0));
+                        Vector3(encode(c.x()), encode(c.y()), encode(c.z()))
+                    })
+                    .collect()
+            }
+            BakeMap::Ao => {
+                let distance = options.ao_distance.unwrap_or(1.0);
+                bake_ao(&scene, object.as_ref(), size, samples_per_pixel, distance)
+            }
+            BakeMap::Curvature => bake_curvature(object.as_ref(), size),
+        };
+        let default_path = match map {
+            BakeMap::Lightmap => "lightmap.ppm",
+            BakeMap::Ao => "ao.ppm",
+            BakeMap::Curvature => "curvature.ppm",
+        };
+        let path = options.bake_file.as_deref().unwrap_or(default_path);
+        write_ppm(path, size, size, &encoded).unwrap_or_else(|e| {
+            eprintln!("could not write baked map {}: {}", path, e);
+            std::process::exit(1);
+        });
+        return;
+    }
+
+    //Camera
+    let units = options.units.unwrap_or(scene_settings.units);
+    let scene_view = scene_settings.camera.unwrap_or(CameraView {
+        name: None,
+        from: Vector3(13.0, 2.0, 3.0),
+        at: Vector3(0.0, 0.0, 0.0),
+        vfov: 20.0,
+        aperture: 0.1,
+        focus: 10.0,
+    });
+    let keys = options.camera_path.as_ref().map(|path| {
+        load_camera_path(path).unwrap_or_else(|e| {
+            eprintln!("could not read camera path {}: {}", path, e);
+            std::process::exit(1);
+        })
+    });
+    // the camera path at `frame`, then the camera options
+    let view_at = |frame: f64| {
+        let mut view = scene_view.clone();
+        if let Some(keys) = &keys {
+            let key = camera_at(keys, frame);
+            view = CameraView {
+                from: key.position,
+                at: key.look_at,
+                vfov: key.vfov,
+                aperture: key.aperture.unwrap_or(view.aperture),
+                focus: (key.look_at - key.position).length(),
+                ..view
+            };
+        }
+        if options.look_from.is_some() || options.look_at.is_some() {
+            view.from = options.look_from.unwrap_or(view.from);
+            view.at = options.look_at.unwrap_or(view.at);
+            view.focus = (view.at - view.from).length();
+        }
+        view.vfov = options.vfov.unwrap_or(view.vfov);
+        view.aperture = options.aperture.unwrap_or(view.aperture);
+        if let Some(camera) = options.physical_camera() {
+            view.aperture = camera.aperture(view.vfov, units.meters());
+        }
+        view.focus = options.focus.unwrap_or(view.focus);
+        view
+    };
+    let view = view_at(options.frame);
+    // an animation keeps the tessellation for its first frame
+    let first_frame = options
+        .frames
+        .map_or(options.frame, |(first, _)| first as f64);
+    scene.world.tessellate(&view_at(first_frame), height);
+    if let Some(path) = &options.export_gltf {
+        match export_gltf(path, &scene, &view, aspect_ratio) {
+            Ok(left_out) => {
+                for what in left_out {
+                    eprintln!("left out of {}: {}", path, what);
+                }
+            }
+            Err(e) => {
+                eprintln!("could not write {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let image = ImageSettings {
+        aspect_ratio,
+        width,
+        height,
+        samples_per_pixel,
+        max_depth,
+        shutter: match options.shutter.or(scene_settings.shutter) {
+            // without motion blur, everything stops halfway through the exposure
+            Some((open, close))
+                if !options
+                    .motion_blur
+                    .or(scene_settings.motion_blur)
+                    .unwrap_or(true) =>
+            {
+                let middle = (open + close) / 2.0;
+                (middle, middle)
+            }
+            shutter => shutter.unwrap_or((0.0, 0.0)),
+        },
+        shutter_curve: options
+            .shutter_curve
+            .or(scene_settings.shutter_curve)
+            .unwrap_or_default(),
+        rolling_shutter: options
+            .rolling_shutter
+            .or(scene_settings.rolling_shutter)
+            .unwrap_or(0.0),
+    };
+    if let Some(template) = &options.contact_sheet {
+        let written = match &options.output {
+            Some(path) => std::fs::File::create(path).and_then(|f| {
+                contact_sheet(&options, &view, image, template, std::io::BufWriter::new(f))
+            }),
+            None => contact_sheet(&options, &view, image, template, std::io::stdout().lock()),
+        };
+        if let Err(e) = written {
+            eprintln!("could not write contact sheet: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some((first, last)) = options.frames {
+        if keys.is_none() && options.fps.is_none() {
+            eprintln!("--frames needs a --camera-path or --fps to animate");
+            std::process::exit(1);
+        }
+        if options.checkpoint.is_some()
+            || options.window
+            || options.light_groups.is_some()
+            || !options.aovs.is_empty()
+        {
+            eprintln!(
+                "--frames cannot be combined with --checkpoint, --window, --light-groups or --aov"
+            );
+            std::process::exit(1);
+        }
+        // <output>_0001.png and so on, or frame_0001.ppm without --output
+        let output = std::path::Path::new(options.output.as_deref().unwrap_or("frame"));
+        let stem = output.with_extension("");
+        let extension = output
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or(options.output_format().extension());
+        let render_frame = |frame: u32, options: &Options, scene: &Scene, scene_hash: u64| {
+            let path = format!("{}_{:04}.{}", stem.display(), frame, extension);
+            let written = std::fs::File::create(&path).and_then(|f| {
+                render_view(
+                    &args,
+                    options,
+                    scene,
+                    scene_hash,
+                    &view_at(frame as f64),
+                    image,
+                    std::io::BufWriter::new(f),
+                )
+            });
+            match written {
+                Ok(()) => eprintln!("wrote {}", path),
+                Err(e) => {
+                    eprintln!("could not write {}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+        };
+        match options.fps {
+            // every frame builds the scene at its own time, one after another
+            Some(fps) => {
+                for frame in first..=last {
+                    let mut options = options.clone();
+                    options.time += frame as f64 / fps;
+                    let (mut scene, _) = load_scene(&options);
+                    scene.world.tessellate(&view_at(frame as f64), height);
+                    render_frame(frame, &options, &scene, self::scene_hash(&options));
+                }
+            }
+            // the frames share the scene and its BVH and render side by side,
+            // each also spreading its pixels over the threads
+            None => (first..=last)
+                .into_par_iter()
+                .for_each(|frame| render_frame(frame, &options, &scene, scene_hash)),
+        }
+        return;
+    }
+    if options.cameras.is_empty() && options.layers.is_empty() {
+        match &options.output {
+            Some(path) => {
+                let written = std::fs::File::create(path).and_then(|f| {
+                    render_view(
+                        &args,
+                        &options,
+                        &scene,
+                        scene_hash,
+                        &view,
+                        image,
+                        std::io::BufWriter::new(f),
+                    )
+                });
+                if let Err(e) = written {
+                    eprintln!("could not write {}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+            None => {
+                let stdout = std::io::stdout();
+                render_view(
+                    &args,
+                    &options,
+                    &scene,
+                    scene_hash,
+                    &view,
+                    image,
+                    stdout.lock(),
+                )
+                .expect("failed to write image");
+            }
+        }
+        return;
+    }
+    // several cameras or layers: every selected camera is rendered for every
+    // layer into <camera>_<layer>.ppm (or the extension of --format)
+    for name in options.camera_selection.iter().flatten() {
+        if !options
+            .cameras
+            .iter()
+            .any(|c| c.name.as_ref() == Some(name))
+        {
+            eprintln!("there is no camera {}", name);
+            std::process::exit(1);
+        }
+    }
+    let views: Vec<&CameraView> = if options.cameras.is_empty() {
+        vec![&view]
+    } else {
+        options
+            .cameras
+            .iter()
+            .filter(|view| match &options.camera_selection {
+                Some(selected) => selected.iter().any(|s| Some(s) == view.name.as_ref()),
+                None => true,
+            })
+            .collect()
+    };
+    let layers: Vec<Option<&RenderLayer>> = if options.layers.is_empty() {
+        vec![None]
+    } else {
+        options.layers.iter().map(Some).collect()
+    };
+    for layer in layers {
+        if let Some(layer) = layer {
+            scene.apply_layer(layer).unwrap_or_else(|e| {
+                eprintln!("layer {}: {}", layer.name, e);
+                std::process::exit(1);
+            });
+        }
+        let scene_hash = match layer {
+            Some(layer) => stable_hash(format!("{:016x} {:?}", scene_hash, layer)),
+            None => scene_hash,
+        };
+        for view in &views {
+            let name = [view.name.as_deref(), layer.map(|l| l.name.as_str())]
+                .iter()
+                .flatten()
+                .copied()
+                .collect::<Vec<_>>()
+                .join("_");
+            let path = format!("{}.{}", name, options.output_format().extension());
+            let written = std::fs::File::create(&path).and_then(|f| {
+                render_view(
+                    &args,
+                    &options,
+                    &scene,
+                    scene_hash,
+                    view,
+                    image,
+                    std::io::BufWriter::new(f),
+                )
+            });
+            match written {
+                Ok(()) => eprintln!("{}: wrote {}", name, path),
+                Err(e) => {
+                    eprintln!("could not write {}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Color spaces, blackbody colors and false colors.
+
+use crate::material::Material;
+use crate::texture::Texture;
+use crate::vec3::Vector3;
+
+/// The RGB space light is transported in. Scene colors are authored in linear
+/// sRGB and converted into it; rendered radiance is converted back for output.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorSpace {
+    #[default]
+    LinearSrgb,
+    /// The AP1 primaries of ACES, wide enough for saturated light sources.
+    AcesCg,
+}
+
+impl ColorSpace {
+    pub fn from_linear_srgb(&self, c: Vector3) -> Vector3 {
+        match self {
+            ColorSpace::LinearSrgb => c,
+            ColorSpace::AcesCg => Vector3(
+                0.613097 * c.x() + 0.339523 * c.y() + 0.047379 * c.z(),
+                0.070194 * c.x() + 0.916354 * c.y() + 0.013452 * c.z(),
+                0.020616 * c.x() + 0.109570 * c.y() + 0.869815 * c.z(),
+            ),
+        }
+    }
+
+    pub fn to_linear_srgb(&self, c: Vector3) -> Vector3 {
+        match self {
+            ColorSpace::LinearSrgb => c,
+            ColorSpace::AcesCg => Vector3(
+                1.704859 * c.x() - 0.621715 * c.y() - 0.083299 * c.z(),
+                -0.130078 * c.x() + 1.140734 * c.y() - 0.010560 * c.z(),
+                -0.023964 * c.x() - 0.128975 * c.y() + 1.153013 * c.z(),
+            ),
+        }
+    }
+
+    /// `texture` with its colors converted from linear sRGB.
+    pub fn texture(&self, texture: Texture) -> Texture {
+        match texture {
+            Texture::Solid(color) => Texture::Solid(self.from_linear_srgb(color)),
+            Texture::Checker { even, odd, scale } => Texture::Checker {
+                even: self.from_linear_srgb(even),
+                odd: self.from_linear_srgb(odd),
+                scale,
+            },
+            // gray stays gray, and grids hold data such as densities
+            unchanged @ (Texture::Noise { .. }
+            | Texture::Turbulence { .. }
+            | Texture::Grid(_)
+            | Texture::Node(_)) => unchanged,
+            Texture::Ramp {
+                start,
+                end,
+                from,
+                to,
+            } => Texture::Ramp {
+                start,
+                end,
+                from: self.from_linear_srgb(from),
+                to: self.from_linear_srgb(to),
+            },
+            Texture::Image { image, .. } => Texture::Image {
+                image,
+                space: *self,
+            },
+            Texture::VertexColor { .. } => Texture::VertexColor { space: *self },
+        }
+    }
+
+    /// `material` with its colors converted from linear sRGB.
+    pub fn material(&self, material: Material) -> Material {
+        match material {
+            Material::Lambertian { albedo } => Material::Lambertian {
+                albedo: self.texture(albedo),
+            },
+            Material::Metal { albedo, fuzz } => Material::Metal {
+                albedo: self.texture(albedo),
+                fuzz,
+            },
+            // mapped and mixed materials give way to their bases before
+            // shading, see `Intersection::shaded_with`
+            unchanged @ (Material::Dielectric { .. }
+            | Material::Portal { .. }
+            | Material::Mapped { .. }
+            | Material::Mix { .. }
+            | Material::Pbr(_)) => unchanged,
+            Material::DiffuseLight { emit, gel } => Material::DiffuseLight {
+                emit: self.from_linear_srgb(emit),
+                gel: gel.map(|gel| self.texture(gel)),
+            },
+            Material::Isotropic { albedo, emit } => Material::Isotropic {
+                albedo: self.texture(albedo),
+                emit: self.from_linear_srgb(emit),
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for ColorSpace {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" | "linear-srgb" => Ok(ColorSpace::LinearSrgb),
+            "acescg" => Ok(ColorSpace::AcesCg),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The sRGB transfer function, from linear light to display values.
+pub(crate) fn srgb_encode(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Inverse of `srgb_encode`.
+pub(crate) fn srgb_decode(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// A saturated color for an object ID; hues of consecutive IDs are spread
+/// with the golden ratio so neighbours are easy to tell apart.
+pub(crate) fn id_color(id: usize) -> Vector3 {
+    let hue = (id as f64 * 0.618_033_988_75).fract() * 6.0;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    Vector3(0.2 + 0.7 * r, 0.2 + 0.7 * g, 0.2 + 0.7 * b)
+}
+
+/// Perceptually uniform colormaps for scalar images.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Colormap {
+    /// Dark blue through green to yellow.
+    #[default]
+    Viridis,
+    /// Black through purple and orange to pale yellow.
+    Magma,
+}
+
+impl Colormap {
+    /// The color for t in [0, 1], in display (not linear) values.
+    pub(crate) fn at(self, t: f64) -> Vector3 {
+        match self {
+            Colormap::Viridis => viridis(t),
+            Colormap::Magma => magma(t),
+        }
+    }
+}
+
+impl std::str::FromStr for Colormap {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viridis" => Ok(Colormap::Viridis),
+            "magma" => Ok(Colormap::Magma),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Approximation of matplotlib's viridis colormap for t in [0, 1], in display
+/// (not linear) values.
+pub(crate) fn viridis(t: f64) -> Vector3 {
+    let c = [
+        Vector3(0.2777273272234177, 0.005407344544966578, 0.3340998053353061),
+        Vector3(0.1050930431085774, 1.404613529898575, 1.384590162594685),
+        Vector3(-0.3308618287255563, 0.214847559468213, 0.09509516302823659),
+        Vector3(-4.634230498983486, -5.799100973351585, -19.33244095627987),
+        Vector3(6.228269936347081, 14.17993336680509, 56.69055260068105),
+        Vector3(4.776384997670288, -13.74514537774601, -65.35303263337234),
+        Vector3(-5.435455855934631, 4.645852612178535, 26.3124352495832),
+    ];
+    let t = t.clamp(0.0, 1.0);
+    c.iter()
+        .rev()
+        .fold(Vector3(0.0, 0.0, 0.0), |acc, &c| acc * t + c)
+}
+
+/// Approximation of matplotlib's magma colormap, like `viridis`.
+fn magma(t: f64) -> Vector3 {
+    let c = [
+        Vector3(
+            -0.002136485053939582,
+            -0.000749655052795221,
+            -0.005386127855323933,
+        ),
+        Vector3(0.2516605407371642, 0.6775232436837668, 2.494026599312351),
+        Vector3(8.353717279216625, -3.577719514958484, 0.3144679030132573),
+        Vector3(-27.66873308576866, 14.26473078096533, -13.64921318813922),
+        Vector3(52.17613981234068, -27.94360607168351, 12.94416944238394),
+        Vector3(-50.76852536473588, 29.04658282127291, 4.23415299384598),
+        Vector3(18.65570506591883, -11.48977351997711, -5.601961508734096),
+    ];
+    let t = t.clamp(0.0, 1.0);
+    c.iter()
+        .rev()
+        .fold(Vector3(0.0, 0.0, 0.0), |acc, &c| acc * t + c)
+}
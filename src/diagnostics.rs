@@ -0,0 +1,170 @@
+//! What a render was doing, kept as it goes, so that when it panics or its
+//! image comes out broken a bundle of everything needed to reproduce it can
+//! be written for the bug report.
+
+use crate::image::{write_image, ImageFormat};
+use crate::vec3::Vector3;
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// `eprintln!`, keeping the line for the bundle too.
+macro_rules! log_eprintln {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        eprintln!("{}", line);
+        $crate::diagnostics::log(line);
+    }};
+}
+pub(crate) use log_eprintln;
+
+/// How many of the last lines written to stderr a bundle holds.
+const LOG_LINES: usize = 50;
+
+/// Widest snapshot of the image kept.
+const SNAPSHOT_WIDTH: u32 = 160;
+
+struct Context {
+    /// Where bundles go; none are written without.
+    directory: Option<PathBuf>,
+    /// Settings and statistics by name, in the order first set.
+    entries: Vec<(String, String)>,
+    /// Files copied into the bundle, such as the scene file.
+    files: Vec<PathBuf>,
+    log: VecDeque<String>,
+    /// The image so far, scaled down: width, height and 8-bit sRGB pixels.
+    snapshot: Option<(u32, u32, Vec<u8>)>,
+}
+
+static CONTEXT: Mutex<Context> = Mutex::new(Context {
+    directory: None,
+    entries: Vec::new(),
+    files: Vec::new(),
+    log: VecDeque::new(),
+    snapshot: None,
+});
+
+fn context() -> std::sync::MutexGuard<'static, Context> {
+    // a panic while it was held leaves nothing half written worth losing
+    CONTEXT.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Keeps `line` among the last ones written to stderr.
+pub(crate) fn log(line: String) {
+    let mut context = context();
+    if context.log.len() == LOG_LINES {
+        context.log.pop_front();
+    }
+    context.log.push_back(line);
+}
+
+/// Writes bundles to `directory` from now on.
+pub(crate) fn enable(directory: &str) {
+    context().directory = Some(PathBuf::from(directory));
+}
+
+/// Records `key` for any bundle written later, replacing an earlier value.
+pub(crate) fn set(key: &str, value: impl Display) {
+    let mut context = context();
+    let value = value.to_string();
+    match context.entries.iter_mut().find(|(k, _)| k == key) {
+        Some(entry) => entry.1 = value,
+        None => context.entries.push((key.to_string(), value)),
+    }
+}
+
+/// Copies `path` into any bundle written later.
+pub(crate) fn attach(path: &Path) {
+    let mut context = context();
+    if !context.files.iter().any(|f| f == path) {
+        context.files.push(path.to_path_buf());
+    }
+}
+
+/// Keeps a scaled down copy of `pixels`, summed over `samples` per pixel.
+/// Pixels that are not finite show magenta.
+pub(crate) fn snapshot(pixels: &[Vector3], width: u32, height: u32, samples: usize) {
+    let factor = width.div_ceil(SNAPSHOT_WIDTH).max(1);
+    let (w, h) = (width.div_ceil(factor), height.div_ceil(factor));
+    let mut data = Vec::with_capacity((w * h * 3) as usize);
+    for y in 0..h {
+        for x in 0..w {
+            let mut sum = Vector3(0.0, 0.0, 0.0);
+            let mut n = 0;
+            let mut broken = false;
+            for sy in y * factor..((y + 1) * factor).min(height) {
+                for sx in x * factor..((x + 1) * factor).min(width) {
+                    let c = pixels[(sy * width + sx) as usize];
+                    broken |= !(c.x().is_finite() && c.y().is_finite() && c.z().is_finite());
+                    sum += c;
+                    n += 1;
+                }
+            }
+            let rgb = if broken {
+                [255, 0, 255]
+            } else {
+                sum.to_rgb8(samples.max(1) * n, true)
+            };
+            data.extend_from_slice(&rgb);
+        }
+    }
+    context().snapshot = Some((w, h, data));
+}
+
+/// Writes a bundle for `reason` to a new directory in the one given to
+/// `enable`: report.txt with the settings, statistics and last lines of
+/// the log, snapshot.png and the attached files. Returns its directory, or
+/// `None` if bundles are not enabled.
+pub(crate) fn write_bundle(reason: &str) -> Option<std::io::Result<PathBuf>> {
+    let context = context();
+    let directory = context.directory.as_ref()?;
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let bundle = directory.join(format!("render-{}-{}", seconds, std::process::id()));
+    let write = || -> std::io::Result<()> {
+        std::fs::create_dir_all(&bundle)?;
+        let mut report = format!("reason: {}\n", reason);
+        for (key, value) in &context.entries {
+            report += &format!("{}: {}\n", key, value);
+        }
+        report += "\nlast lines of the log:\n";
+        for line in &context.log {
+            report += line;
+            report += "\n";
+        }
+        std::fs::write(bundle.join("report.txt"), report)?;
+        if let Some((width, height, pixels)) = &context.snapshot {
+            let file = std::fs::File::create(bundle.join("snapshot.png"))?;
+            let out = std::io::BufWriter::new(file);
+            write_image(out, ImageFormat::Png, *width, *height, pixels, &[])?;
+        }
+        for file in &context.files {
+            if let Some(name) = file.file_name() {
+                std::fs::write(bundle.join(name), crate::embed::read(file)?)?;
+            }
+        }
+        Ok(())
+    };
+    Some(write().map(|_| bundle.clone()))
+}
+
+/// Writes a bundle when the render panics, after the usual message.
+pub(crate) fn write_bundle_on_panic() {
+    static WRITTEN: AtomicBool = AtomicBool::new(false);
+    let report = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        report(info);
+        // the threads of a render can fail together
+        if WRITTEN.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        match write_bundle(&format!("panicked {}", info)) {
+            Some(Ok(path)) => eprintln!("diagnostics written to {}", path.display()),
+            Some(Err(e)) => eprintln!("could not write diagnostics: {}", e),
+            None => {}
+        }
+    }));
+}
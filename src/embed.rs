@@ -0,0 +1,94 @@
+//! Scene files and assets built into the binary, so demos and benchmarks
+//! render without any files next to them.
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+static FILES: Mutex<Vec<(PathBuf, &'static [u8])>> = Mutex::new(Vec::new());
+
+// `__scene_files!`, the files of each scene of the crate
+include!(concat!(env!("OUT_DIR"), "/scene_files.rs"));
+
+/// Builds a scene file and the files it reads, with paths relative to the
+/// crate root, into the binary and registers them, evaluating to the path
+/// of the scene to give to `--scene-file`. The scenes of this crate are
+/// read by its build script, which finds their files and fails the build
+/// when one is missing or malformed; other crates list the files a scene
+/// reads after it, and only missing ones fail the build.
+///
+/// ```ignore
+/// let scene = raytracer::embed_scene!("scenes/mesh.toml");
+/// let own = raytracer::embed_scene!("scenes/car.toml", "scenes/models/car.obj");
+/// ```
+#[macro_export]
+macro_rules! embed_scene {
+    ($scene:tt $(,)?) => {{
+        $crate::embed::register($crate::__scene_files!($scene));
+        $scene
+    }};
+    ($scene:literal $(, $file:literal)+ $(,)?) => {{
+        $crate::embed::register(&[
+            ($scene, include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $scene)) as &[u8]),
+            $(($file, include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/", $file)) as &[u8]),)*
+        ]);
+        $scene
+    }};
+}
+
+/// Makes each file, a path and its contents, readable as if it were on
+/// disk at that path, ahead of any file that is.
+pub fn register(files: &[(&str, &'static [u8])]) {
+    let mut registered = FILES.lock().unwrap();
+    registered.extend(
+        files
+            .iter()
+            .map(|(path, data)| (normalize(Path::new(path)), *data)),
+    );
+}
+
+/// The contents of the file registered at `path`.
+pub(crate) fn find(path: &Path) -> Option<&'static [u8]> {
+    let path = normalize(path);
+    let registered = FILES.lock().unwrap();
+    registered
+        .iter()
+        .rev()
+        .find(|(p, _)| *p == path)
+        .map(|(_, data)| *data)
+}
+
+/// Whether `path` is registered or on disk.
+pub(crate) fn exists(path: &Path) -> bool {
+    find(path).is_some() || path.exists()
+}
+
+/// Reads a registered file, else the one on disk.
+pub(crate) fn read(path: impl AsRef<Path>) -> std::io::Result<Vec<u8>> {
+    match find(path.as_ref()) {
+        Some(data) => Ok(data.to_vec()),
+        None => std::fs::read(path),
+    }
+}
+
+pub(crate) fn read_to_string(path: impl AsRef<Path>) -> std::io::Result<String> {
+    String::from_utf8(read(path)?)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+/// `path` with `.` and `dir/..` left out, without looking at the disk, as
+/// scenes name files next to each other.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(normal.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                normal.pop();
+            }
+            c => normal.push(c),
+        }
+    }
+    normal
+}
@@ -0,0 +1,336 @@
+//! Export of a scene to glTF 2.0, for editing it further in Blender and
+//! other tools.
+
+use crate::camera::CameraView;
+use crate::hittable::Facet;
+use crate::light::{Light, LUMENS_PER_WATT};
+use crate::material::Material;
+use crate::scene::Scene;
+use crate::vec3::Vector3;
+use serde_json::{json, Value};
+
+/// Writes the shown objects of `scene` as meshes, its lights and the camera
+/// of `view` to `path`, a .gltf file with the geometry in a .bin file next
+/// to it. Surfaces are exported as triangles, see `Hittable::triangles`,
+/// and materials approximated by metallic-roughness ones with the color of
+/// their textures at the middle of the UV square. Returns what could not be
+/// exported.
+pub(crate) fn export_gltf(
+    path: &str,
+    scene: &Scene,
+    view: &CameraView,
+    aspect_ratio: f64,
+) -> std::io::Result<Vec<String>> {
+    let mut left_out = Vec::new();
+    let mut buffer: Vec<u8> = Vec::new();
+    let (mut accessors, mut views, mut meshes, mut nodes) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let mut materials: Vec<(String, Value)> = Vec::new();
+    // appends the values and describes them as an accessor, returning its index
+    let mut add_vectors = |values: &[Vector3], bounds: bool| -> usize {
+        let offset = buffer.len();
+        for v in values {
+            for c in [v.x(), v.y(), v.z()] {
+                buffer.extend_from_slice(&(c as f32).to_le_bytes());
+            }
+        }
+        views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": buffer.len() - offset,
+            "target": 34962,
+        }));
+        let mut accessor = json!({
+            "bufferView": views.len() - 1,
+            "componentType": 5126,
+            "count": values.len(),
+            "type": "VEC3",
+        });
+        if bounds {
+            // of the values as stored, which readers check them against
+            let fold = |f: fn(f32, f32) -> f32, start: f32| {
+                values.iter().fold([start; 3], |acc, v| {
+                    [
+                        f(acc[0], v.x() as f32),
+                        f(acc[1], v.y() as f32),
+                        f(acc[2], v.z() as f32),
+                    ]
+                })
+            };
+            accessor["min"] = json!(fold(f32::min, f32::INFINITY));
+            accessor["max"] = json!(fold(f32::max, f32::NEG_INFINITY));
+        }
+        accessors.push(accessor);
+        accessors.len() - 1
+    };
+
+    for (id, object) in scene.world.objects.iter().enumerate() {
+        if scene.world.hidden[id] {
+            continue;
+        }
+        let name = scene.world.names[id]
+            .clone()
+            .unwrap_or_else(|| format!("{} {}", object.kind(), id));
+        let facets = object.triangles();
+        if facets.is_empty() {
+            left_out.push(format!("{}, without a surface", name));
+            continue;
+        }
+        // one primitive per material
+        let mut groups: Vec<(usize, Vec<Facet>)> = Vec::new();
+        for facet in facets {
+            let key = format!("{:?}", facet.material);
+            let index = match materials.iter().position(|(k, _)| *k == key) {
+                Some(index) => index,
+                None => {
+                    materials.push((key, pbr(&facet.material, materials.len())));
+                    materials.len() - 1
+                }
+            };
+            match groups.iter_mut().find(|(m, _)| *m == index) {
+                Some((_, group)) => group.push(facet),
+                None => groups.push((index, vec![facet])),
+            }
+        }
+        let primitives: Vec<Value> = groups
+            .iter()
+            .map(|(material, facets)| {
+                let positions: Vec<Vector3> = facets.iter().flat_map(|f| f.corners).collect();
+                let normals: Vec<Vector3> = facets.iter().flat_map(|f| f.normals).collect();
+                json!({
+                    "attributes": {
+                        "POSITION": add_vectors(&positions, true),
+                        "NORMAL": add_vectors(&normals, false),
+                    },
+                    "material": material,
+                })
+            })
+            .collect();
+        meshes.push(json!({ "name": name, "primitives": primitives }));
+        nodes.push(json!({ "name": name, "mesh": meshes.len() - 1 }));
+    }
+
+    // glTF cameras look down their -z axis with y up
+    let forward = (view.at - view.from).normalize();
+    nodes.push(json!({
+        "name": view.name.clone().unwrap_or_else(|| "camera".to_string()),
+        "camera": 0,
+        "translation": [view.from.x(), view.from.y(), view.from.z()],
+        "rotation": facing(forward * -1.0),
+    }));
+    let camera = json!({
+        "type": "perspective",
+        "perspective": {
+            "yfov": view.vfov.to_radians(),
+            "aspectRatio": aspect_ratio,
+            "znear": 0.01,
+        },
+        "extras": { "aperture": view.aperture, "focus_distance": view.focus },
+    });
+
+    // render units are W/sr and W/m2, glTF lights candela and lux
+    let mut lights = Vec::new();
+    for (id, light) in scene.lights.iter().enumerate() {
+        let color = match light {
+            Light::Directional { color, .. } | Light::Point { color, .. } => {
+                scene.color_space.to_linear_srgb(*color)
+            }
+        };
+        let strength = color.x().max(color.y()).max(color.z());
+        if strength <= 0.0 || color.x().min(color.y()).min(color.z()) < 0.0 {
+            left_out.push(format!("light {}, which is not a real light", id));
+            continue;
+        }
+        let color = color / strength;
+        let (light, node) = match light {
+            Light::Directional { direction, .. } => (
+                json!({ "type": "directional" }),
+                // the light shines down the node's -z axis, away from `direction`
+                json!({ "rotation": facing(direction.normalize()) }),
+            ),
+            Light::Point {
+                position, falloff, ..
+            } => {
+                if *falloff != 2.0 {
+                    left_out.push(format!("the falloff of light {}, exported as 2", id));
+                }
+                (
+                    json!({ "type": "point" }),
+                    json!({ "translation": [position.x(), position.y(), position.z()] }),
+                )
+            }
+        };
+        let mut light = light;
+        light["name"] = json!(format!("light{}", id));
+        light["color"] = json!([color.x(), color.y(), color.z()]);
+        light["intensity"] = json!(strength * LUMENS_PER_WATT);
+        lights.push(light);
+        let mut node = node;
+        node["name"] = json!(format!("light{}", id));
+        node["extensions"] = json!({ "KHR_lights_punctual": { "light": lights.len() - 1 } });
+        nodes.push(node);
+    }
+
+    let bin = std::path::Path::new(path).with_extension("bin");
+    let bin_name = bin
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("scene.bin");
+    let gltf = json!({
+        "asset": { "version": "2.0", "generator": "raytracer" },
+        "extensionsUsed": [
+            "KHR_lights_punctual",
+            "KHR_materials_emissive_strength",
+            "KHR_materials_ior",
+            "KHR_materials_transmission",
+        ],
+        "extensions": { "KHR_lights_punctual": { "lights": lights } },
+        "scene": 0,
+        "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "cameras": [camera],
+        "materials": materials.into_iter().map(|(_, m)| m).collect::<Vec<_>>(),
+        "accessors": accessors,
+        "bufferViews": views,
+        "buffers": [{ "uri": bin_name, "byteLength": buffer.len() }],
+    });
+    std::fs::write(&bin, &buffer)?;
+    std::fs::write(path, serde_json::to_string_pretty(&gltf)?)?;
+    Ok(left_out)
+}
+
+/// The nearest metallic-roughness material, named by its `index`.
+fn pbr(material: &Material, index: usize) -> Value {
+    let color = |c: Vector3| [c.x(), c.y(), c.z(), 1.0];
+    let middle = |t: &crate::texture::Texture| t.value(0.5, 0.5, &Vector3(0.0, 0.0, 0.0));
+    let mut m = json!({ "name": format!("material{}", index) });
+    match material {
+        Material::Lambertian { albedo } | Material::Isotropic { albedo, .. } => {
+            m["pbrMetallicRoughness"] = json!({
+                "baseColorFactor": color(middle(albedo)),
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            });
+        }
+        Material::Metal { albedo, fuzz } => {
+            m["pbrMetallicRoughness"] = json!({
+                "baseColorFactor": color(middle(albedo)),
+                "metallicFactor": 1.0,
+                "roughnessFactor": fuzz.clamp(0.0, 1.0),
+            });
+        }
+        Material::Dielectric { ir, roughness, .. } => {
+            m["pbrMetallicRoughness"] = json!({
+                "metallicFactor": 0.0,
+                "roughnessFactor": roughness.clamp(0.0, 1.0),
+            });
+            m["extensions"] = json!({
+                "KHR_materials_transmission": { "transmissionFactor": 1.0 },
+                "KHR_materials_ior": { "ior": ir },
+            });
+        }
+        Material::DiffuseLight { .. } => {
+            m["pbrMetallicRoughness"] = json!({ "baseColorFactor": [0.0, 0.0, 0.0, 1.0] });
+            emissive(
+                &mut m,
+                material.emitted_at(0.5, 0.5, &Vector3(0.0, 0.0, 0.0)),
+            );
+        }
+        Material::Pbr(p) => {
+            m["pbrMetallicRoughness"] = json!({
+                "baseColorFactor": color(middle(&p.albedo)),
+                "metallicFactor": p.metallic,
+                "roughnessFactor": p.roughness,
+            });
+            if !p.emission.near_zero() {
+                emissive(
+                    &mut m,
+                    material.emitted_at(0.5, 0.5, &Vector3(0.0, 0.0, 0.0)),
+                );
+            }
+        }
+        // a window on another place has nothing to look like
+        Material::Portal { .. } => {
+            m["pbrMetallicRoughness"] = json!({ "baseColorFactor": [0.0, 0.0, 0.0, 1.0] });
+        }
+        Material::Mapped { base, .. } => return pbr(base, index),
+        Material::Mix { factor, a, b } => {
+            let f = middle(factor);
+            let luminance = 0.2126 * f.x() + 0.7152 * f.y() + 0.0722 * f.z();
+            return pbr(if luminance < 0.5 { a } else { b }, index);
+        }
+    }
+    m
+}
+
+/// Sets the emission of the glTF material `m` to `emit`, as a color and a
+/// strength.
+fn emissive(m: &mut Value, emit: Vector3) {
+    let strength = emit.x().max(emit.y()).max(emit.z()).max(0.0);
+    let factor = if strength > 0.0 {
+        emit / strength
+    } else {
+        Vector3(0.0, 0.0, 0.0)
+    };
+    m["emissiveFactor"] = json!([
+        factor.x().max(0.0),
+        factor.y().max(0.0),
+        factor.z().max(0.0)
+    ]);
+    m["extensions"] = json!({
+        "KHR_materials_emissive_strength": { "emissiveStrength": strength },
+    });
+}
+
+/// The rotation, as a quaternion x, y, z, w, turning the +z axis to unit
+/// `z` while keeping the y axis as upright as it can.
+fn facing(z: Vector3) -> [f64; 4] {
+    let up = if z.y().abs() > 0.999 {
+        Vector3(0.0, 0.0, 1.0)
+    } else {
+        Vector3(0.0, 1.0, 0.0)
+    };
+    let x = up.cross(&z).normalize();
+    let y = z.cross(&x);
+    // the matrix with columns x, y, z, after Shoemake
+    let m = [
+        [x.x(), y.x(), z.x()],
+        [x.y(), y.y(), z.y()],
+        [x.z(), y.z(), z.z()],
+    ];
+    let trace = m[0][0] + m[1][1] + m[2][2];
+    if trace > 0.0 {
+        let s = 0.5 / (trace + 1.0).sqrt();
+        [
+            (m[2][1] - m[1][2]) * s,
+            (m[0][2] - m[2][0]) * s,
+            (m[1][0] - m[0][1]) * s,
+            0.25 / s,
+        ]
+    } else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+        let s = 2.0 * (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt();
+        [
+            0.25 * s,
+            (m[0][1] + m[1][0]) / s,
+            (m[0][2] + m[2][0]) / s,
+            (m[2][1] - m[1][2]) / s,
+        ]
+    } else if m[1][1] > m[2][2] {
+        let s = 2.0 * (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt();
+        [
+            (m[0][1] + m[1][0]) / s,
+            0.25 * s,
+            (m[1][2] + m[2][1]) / s,
+            (m[0][2] - m[2][0]) / s,
+        ]
+    } else {
+        let s = 2.0 * (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt();
+        [
+            (m[0][2] + m[2][0]) / s,
+            (m[1][2] + m[2][1]) / s,
+            0.25 * s,
+            (m[1][0] - m[0][1]) / s,
+        ]
+    }
+}
@@ -0,0 +1,928 @@
+//! Geometry rays can hit, and the object store and BVH over it.
+
+use crate::camera::CameraView;
+use crate::hittable::bvh::Bvh;
+use crate::light::blackbody;
+use crate::material::Material;
+use crate::pdf::basis;
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::vec3::{random, Vector3};
+
+pub mod bvh;
+pub mod displacement;
+pub mod lod;
+pub mod mesh;
+pub mod portal;
+pub mod quad;
+pub mod scatter;
+pub mod terrain;
+pub mod transform;
+pub mod water;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Intersection {
+    pub(crate) p: Vector3,
+    pub(crate) normal: Vector3,
+    pub(crate) material: Material,
+    pub(crate) t: f64,
+    pub(crate) u: f64,
+    pub(crate) v: f64,
+    pub(crate) front_facing: bool,
+    /// ID of the hit object, its index in the `HittableStore`.
+    pub(crate) object: usize,
+    /// Direction along the surface in which `u` grows, for normal and bump
+    /// maps; zero where the surface has no such direction.
+    pub(crate) tangent: Vector3,
+    /// Color painted on the surface at the hit, for vertex color textures;
+    /// white where nothing is.
+    pub(crate) color: Vector3,
+}
+
+impl Intersection {
+    /// The intersection as shaded with `material` for a ray along
+    /// `direction`: a `Material::Mapped` tilts the normal by its map and
+    /// leaves its base material in its place, a `Material::Mix` one of its
+    /// two picked at random, a `Material::Pbr` the material it acts as there,
+    /// and node textures settle on their color as seen from the ray.
+    pub(crate) fn shaded_with(self, material: Material, direction: &Vector3) -> Self {
+        let cos = direction.normalize().dot(&self.normal).abs();
+        match material {
+            Material::Mapped { base, map } => Self {
+                normal: map.normal(&self),
+                ..self
+            }
+            .shaded_with(*base, direction),
+            Material::Mix { factor, a, b } => {
+                let f = luminance(factor.value_facing(self.u, self.v, &self.p, cos, self.color));
+                self.shaded_with(if random() < f { *b } else { *a }, direction)
+            }
+            Material::Pbr(pbr) => {
+                let settled = pbr.settle(&self, cos);
+                self.shaded_with(settled, direction)
+            }
+            _ => Self {
+                material: material.facing(self.u, self.v, &self.p, cos, self.color),
+                ..self
+            },
+        }
+    }
+
+    fn set_face_normal(&mut self, r: &Ray, outward_normal: &Vector3) {
+        self.front_facing = r.direction.dot(outward_normal) < 0.0;
+        self.normal = if self.front_facing {
+            *outward_normal
+        } else {
+            *outward_normal * -1.0
+        }
+    }
+}
+
+pub trait Hittable: Send + Sync + std::fmt::Debug {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection>;
+
+    /// The surface point and outward normal at texture coordinates (u, v), used
+    /// for baking. Objects without a UV layout return `None`.
+    fn surface_at(&self, _u: f64, _v: f64) -> Option<(Vector3, Vector3)> {
+        None
+    }
+
+    /// Axis-aligned box enclosing the object, `None` if it is unbounded.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Primitive type, for scene statistics.
+    fn kind(&self) -> &'static str;
+
+    /// Every material the object may report on a hit.
+    fn materials(&self) -> Vec<Material>;
+
+    /// Memory held outside the object itself, for scene statistics.
+    fn heap_bytes(&self) -> usize {
+        0
+    }
+
+    /// Adapts the geometry to the view about to be rendered, for objects
+    /// tessellated by screen size.
+    fn tessellate(&mut self, _view: &CameraView, _image_height: u32) {}
+
+    /// How fast the object moves at `time`, per unit of time.
+    fn velocity(&self, _time: f64) -> Vector3 {
+        Vector3(0.0, 0.0, 0.0)
+    }
+
+    /// Density, per steradian, with which `random_direction` from the origin
+    /// of `r` picks its direction.
+    fn pdf_value(&self, _r: &Ray) -> f64 {
+        0.0
+    }
+
+    /// A random direction from `origin` towards the object, for sampling
+    /// lights; `None` for objects that cannot be sampled.
+    fn random_direction(&self, _origin: &Vector3) -> Option<Vector3> {
+        None
+    }
+
+    /// A random point spread evenly over the surface, the outward normal
+    /// there and the area of the surface, for emitting photons; `None` for
+    /// objects that cannot be sampled.
+    fn random_point(&self) -> Option<(Vector3, Vector3, f64)> {
+        None
+    }
+
+    /// The surface as triangles, for exporting. By default a grid over the
+    /// UV layout; empty for objects without a surface, like media.
+    fn triangles(&self) -> Vec<Facet> {
+        match self.materials().first() {
+            Some(&material) => grid_facets(48, 24, |u, v| self.surface_at(u, v), |_| material),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// A triangle of a surface with the normals at its corners, see
+/// `Hittable::triangles`.
+#[derive(Debug, Copy, Clone)]
+pub struct Facet {
+    pub corners: [Vector3; 3],
+    pub normals: [Vector3; 3],
+    pub material: Material,
+}
+
+impl Facet {
+    /// A flat triangle, facing the side its corners turn counterclockwise on.
+    pub(crate) fn flat(corners: [Vector3; 3], material: Material) -> Self {
+        let normal = (corners[1] - corners[0])
+            .cross(&(corners[2] - corners[0]))
+            .normalize();
+        Self {
+            corners,
+            normals: [normal; 3],
+            material,
+        }
+    }
+}
+
+/// Triangles over a `columns` by `rows` grid of the unit square mapped onto
+/// a surface by `at`, which gives points and normals; empty if `at` does not.
+/// Triangles collapsed to a line, as at the poles of a sphere, are left out.
+pub(crate) fn grid_facets(
+    columns: usize,
+    rows: usize,
+    at: impl Fn(f64, f64) -> Option<(Vector3, Vector3)>,
+    material: impl Fn(&Vector3) -> Material,
+) -> Vec<Facet> {
+    let mut points = Vec::with_capacity((columns + 1) * (rows + 1));
+    for j in 0..=rows {
+        for i in 0..=columns {
+            match at(i as f64 / columns as f64, j as f64 / rows as f64) {
+                Some(p) => points.push(p),
+                None => return Vec::new(),
+            }
+        }
+    }
+    let corner = |i: usize, j: usize| points[j * (columns + 1) + i];
+    let mut facets = Vec::with_capacity(2 * columns * rows);
+    for j in 0..rows {
+        for i in 0..columns {
+            let quad = [
+                corner(i, j),
+                corner(i + 1, j),
+                corner(i + 1, j + 1),
+                corner(i, j + 1),
+            ];
+            for [a, b, c] in [[quad[0], quad[1], quad[2]], [quad[0], quad[2], quad[3]]] {
+                if (b.0 - a.0).cross(&(c.0 - a.0)).length_squared() < 1e-24 {
+                    continue;
+                }
+                // keep the winding facing the way the normals do
+                let (b, c) = if (b.0 - a.0).cross(&(c.0 - a.0)).dot(&(a.1 + b.1 + c.1)) < 0.0 {
+                    (c, b)
+                } else {
+                    (b, c)
+                };
+                facets.push(Facet {
+                    corners: [a.0, b.0, c.0],
+                    normals: [a.1, b.1, c.1],
+                    material: material(&((a.0 + b.0 + c.0) / 3.0)),
+                });
+            }
+        }
+    }
+    facets
+}
+
+/// Stretches the far end of slab tests by the most that rounding in their
+/// three operations can cut off, after Ize, so that rays grazing a box or
+/// running along a flat one are not lost.
+pub(crate) const SLAB_ROUNDING: f64 = 1.0 + 2.0 * (3.0 * f64::EPSILON / 2.0);
+
+#[derive(Debug, Copy, Clone)]
+pub struct Aabb {
+    pub(crate) min: Vector3,
+    pub(crate) max: Vector3,
+}
+
+impl Aabb {
+    /// Clips the ray interval [t_min, t_max] to the box.
+    pub(crate) fn clip(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let (mut t0, mut t1) = (t_min, t_max);
+        for (o, d, lo, hi) in [
+            (r.origin.x(), r.direction.x(), self.min.x(), self.max.x()),
+            (r.origin.y(), r.direction.y(), self.min.y(), self.max.y()),
+            (r.origin.z(), r.direction.z(), self.min.z(), self.max.z()),
+        ] {
+            if d.abs() < 1e-12 {
+                if o < lo || o > hi {
+                    return None;
+                }
+                continue;
+            }
+            let (ta, tb) = ((lo - o) / d, (hi - o) / d);
+            t0 = t0.max(ta.min(tb));
+            t1 = t1.min(ta.max(tb) * SLAB_ROUNDING);
+        }
+        (t0 <= t1).then_some((t0, t1))
+    }
+
+    pub(crate) fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vector3(
+                self.min.x().min(other.min.x()),
+                self.min.y().min(other.min.y()),
+                self.min.z().min(other.min.z()),
+            ),
+            max: Vector3(
+                self.max.x().max(other.max.x()),
+                self.max.y().max(other.max.y()),
+                self.max.z().max(other.max.z()),
+            ),
+        }
+    }
+
+    /// Whether `p` on the surface of the box lies within `width` of one of its
+    /// edges, that is close to the bounds in at least two axes.
+    pub(crate) fn near_edge(&self, p: &Vector3, width: f64) -> bool {
+        [
+            (p.x(), self.min.x(), self.max.x()),
+            (p.y(), self.min.y(), self.max.y()),
+            (p.z(), self.min.z(), self.max.z()),
+        ]
+        .iter()
+        .filter(|(c, lo, hi)| (c - lo).abs() < width || (c - hi).abs() < width)
+        .count()
+            >= 2
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Sphere {
+    pub center: Vector3,
+    pub radius: f64,
+    pub material: Material,
+}
+
+impl Hittable for Sphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let oc = r.origin - self.center;
+        let a = r.direction.length_squared();
+        let hb = oc.dot(&r.direction);
+        let c = oc.length_squared() - self.radius * self.radius;
+
+        let discriminant = hb * hb - a * c;
+        if discriminant < 0.0 {
+            None
+        } else {
+            let sd = discriminant.sqrt();
+            let mut root = (-hb - sd) / a;
+            if root < t_min || t_max < root {
+                root = (-hb + sd) / a;
+                if root < t_min || t_max < root {
+                    return None;
+                }
+            }
+            let normal = (r.at(root) - self.center) / self.radius;
+            let (u, v) = sphere_uv(&normal);
+            let mut i = Intersection {
+                p: r.at(root),
+                normal,
+                material: self.material,
+                t: root,
+                u,
+                v,
+                front_facing: false,
+                object: 0,
+                // around the vertical, against the turn of `sphere_uv`'s angle
+                tangent: Vector3(normal.z(), 0.0, -normal.x()),
+                color: Vector3(1.0, 1.0, 1.0),
+            };
+            let outward_normal = i.normal;
+            i.set_face_normal(r, &outward_normal);
+            Some(i)
+        }
+    }
+
+    fn surface_at(&self, u: f64, v: f64) -> Option<(Vector3, Vector3)> {
+        // inverse of sphere_uv
+        let theta = v * std::f64::consts::PI;
+        let phi = u * 2.0 * std::f64::consts::PI - std::f64::consts::PI;
+        let normal = Vector3(
+            theta.sin() * phi.cos(),
+            -theta.cos(),
+            -theta.sin() * phi.sin(),
+        );
+        Some((self.center + normal * self.radius, normal))
+    }
+
+    /// Uniform over the cone of directions the sphere covers, or over all
+    /// directions from inside it.
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        if self.hit(r, 0.001, f64::INFINITY).is_none() {
+            return 0.0;
+        }
+        let distance_squared = (self.center - r.origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return 1.0 / (4.0 * std::f64::consts::PI);
+        }
+        let cos_max = (1.0 - radius_squared / distance_squared).sqrt();
+        1.0 / (2.0 * std::f64::consts::PI * (1.0 - cos_max))
+    }
+
+    fn random_direction(&self, origin: &Vector3) -> Option<Vector3> {
+        let to_center = self.center - *origin;
+        let distance_squared = to_center.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return Some(Vector3::random_unit_vector());
+        }
+        let cos_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let z = 1.0 + random() * (cos_max - 1.0);
+        let phi = 2.0 * std::f64::consts::PI * random();
+        let sin = (1.0 - z * z).sqrt();
+        let w = to_center.normalize();
+        let (u, v) = basis(&w);
+        Some(u * (phi.cos() * sin) + v * (phi.sin() * sin) + w * z)
+    }
+
+    fn random_point(&self) -> Option<(Vector3, Vector3, f64)> {
+        let normal = Vector3::random_unit_vector();
+        let area = 4.0 * std::f64::consts::PI * self.radius * self.radius;
+        Some((self.center + normal * self.radius, normal, area))
+    }
+
+    fn kind(&self) -> &'static str {
+        "sphere"
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![self.material]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = self.radius.abs();
+        Some(Aabb {
+            min: self.center - Vector3(r, r, r),
+            max: self.center + Vector3(r, r, r),
+        })
+    }
+}
+
+/// A sphere moving in a straight line from `center0` at `time0` to `center1`
+/// at `time1`, blurred by the camera's shutter.
+#[derive(Debug, Copy, Clone)]
+pub struct MovingSphere {
+    pub center0: Vector3,
+    pub center1: Vector3,
+    pub time0: f64,
+    pub time1: f64,
+    pub radius: f64,
+    pub material: Material,
+}
+
+impl MovingSphere {
+    /// The sphere as it is at `time`, resting at either end of its path
+    /// outside `time0..time1`.
+    fn at(&self, time: f64) -> Sphere {
+        let t = if self.time1 > self.time0 {
+            ((time - self.time0) / (self.time1 - self.time0)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        Sphere {
+            center: self.center0 + (self.center1 - self.center0) * t,
+            radius: self.radius,
+            material: self.material,
+        }
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.at(r.time).hit(r, t_min, t_max)
+    }
+
+    fn surface_at(&self, u: f64, v: f64) -> Option<(Vector3, Vector3)> {
+        self.at(self.time0).surface_at(u, v)
+    }
+
+    fn kind(&self) -> &'static str {
+        "moving sphere"
+    }
+
+    fn velocity(&self, time: f64) -> Vector3 {
+        if self.time0 < time && time < self.time1 {
+            (self.center1 - self.center0) / (self.time1 - self.time0)
+        } else {
+            Vector3(0.0, 0.0, 0.0)
+        }
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![self.material]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let start = self.at(self.time0).bounding_box()?;
+        Some(start.union(&self.at(self.time1).bounding_box()?))
+    }
+}
+
+/// Smoke or fog of constant `density` filling the closed, convex `boundary`.
+/// Rays scatter inside after an exponentially distributed distance, in a
+/// random direction and tinted by `albedo`; those that make it through see
+/// nothing.
+#[derive(Debug)]
+pub struct ConstantMedium {
+    pub boundary: Box<dyn Hittable>,
+    pub density: f64,
+    pub albedo: Texture,
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        // where the ray's line enters and leaves the boundary, possibly behind
+        // the origin for rays starting inside
+        let entry = self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY)?;
+        let exit = self.boundary.hit(r, entry.t + 0.0001, f64::INFINITY)?;
+        let (t0, t1) = (entry.t.max(t_min).max(0.0), exit.t.min(t_max));
+        if t0 >= t1 {
+            return None;
+        }
+        let length = r.direction.length();
+        let distance = -random().ln() / self.density;
+        if distance > (t1 - t0) * length {
+            return None;
+        }
+        let t = t0 + distance / length;
+        Some(Intersection {
+            p: r.at(t),
+            // facing the ray, for the debug views
+            normal: r.direction / -length,
+            material: Material::Isotropic {
+                albedo: self.albedo,
+                emit: Vector3(0.0, 0.0, 0.0),
+            },
+            t,
+            u: 0.0,
+            v: 0.0,
+            front_facing: true,
+            object: 0,
+            tangent: Vector3(0.0, 0.0, 0.0),
+            color: Vector3(1.0, 1.0, 1.0),
+        })
+    }
+
+    fn kind(&self) -> &'static str {
+        "constant medium"
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![Material::Isotropic {
+            albedo: self.albedo,
+            emit: Vector3(0.0, 0.0, 0.0),
+        }]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+
+    fn velocity(&self, time: f64) -> Vector3 {
+        self.boundary.velocity(time)
+    }
+}
+
+/// Smoke, fire or clouds whose density varies: `density` times the
+/// luminance of `density_map`, clamped to [0, 1], filling the closed, convex
+/// `boundary`. Rays are tracked through it by delta tracking, taking steps
+/// as if it were `density` everywhere and scattering at each step with a
+/// chance of the density there over `density`. Where the medium absorbs
+/// rather than scatters, the share 1 - `albedo`, it glows with `emission`.
+#[derive(Debug)]
+pub struct HeterogeneousMedium {
+    pub boundary: Box<dyn Hittable>,
+    pub density: f64,
+    pub density_map: Texture,
+    pub albedo: Texture,
+    pub emission: Option<VolumeEmission>,
+}
+
+/// Light given off by a `HeterogeneousMedium`, per unit of absorption.
+#[derive(Debug, Clone, Copy)]
+pub enum VolumeEmission {
+    /// A color or a texture of colors.
+    Color(Texture),
+    /// A black body between `min` kelvin, where the luminance of
+    /// `temperature` is 0, and `max` kelvin, where it is 1, as bright as
+    /// `intensity` at `max` and dimming with the fourth power of the
+    /// temperature. Where `temperature` is 0 or below the medium is cold
+    /// and does not glow.
+    Blackbody {
+        temperature: Texture,
+        min: f64,
+        max: f64,
+        intensity: f64,
+    },
+}
+
+impl VolumeEmission {
+    /// Emitted radiance at `p`, in linear sRGB.
+    pub fn at(&self, p: &Vector3) -> Vector3 {
+        match self {
+            VolumeEmission::Color(color) => color.value(0.0, 0.0, p),
+            VolumeEmission::Blackbody {
+                temperature,
+                min,
+                max,
+                intensity,
+            } => {
+                let t = luminance(temperature.value(0.0, 0.0, p)).min(1.0);
+                if t <= 0.0 {
+                    return Vector3(0.0, 0.0, 0.0);
+                }
+                let kelvin = min + (max - min) * t;
+                blackbody(kelvin) * (intensity * (kelvin / max).powi(4))
+            }
+        }
+    }
+}
+
+fn luminance(c: Vector3) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let entry = self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY)?;
+        let exit = self.boundary.hit(r, entry.t + 0.0001, f64::INFINITY)?;
+        let (t0, t1) = (entry.t.max(t_min).max(0.0), exit.t.min(t_max));
+        if t0 >= t1 || self.density <= 0.0 {
+            return None;
+        }
+        let length = r.direction.length();
+        let mut t = t0;
+        loop {
+            t += -random().ln() / (self.density * length);
+            if t >= t1 {
+                return None;
+            }
+            let p = r.at(t);
+            let density = luminance(self.density_map.value(0.0, 0.0, &p)).clamp(0.0, 1.0);
+            if random() < density {
+                let albedo = self.albedo.value(0.0, 0.0, &p);
+                let emit = match &self.emission {
+                    Some(emission) => (Vector3(1.0, 1.0, 1.0) - albedo) * emission.at(&p),
+                    None => Vector3(0.0, 0.0, 0.0),
+                };
+                return Some(Intersection {
+                    p,
+                    normal: r.direction / -length,
+                    material: Material::Isotropic {
+                        albedo: albedo.into(),
+                        emit,
+                    },
+                    t,
+                    u: 0.0,
+                    v: 0.0,
+                    front_facing: true,
+                    object: 0,
+                    tangent: Vector3(0.0, 0.0, 0.0),
+                    color: Vector3(1.0, 1.0, 1.0),
+                });
+            }
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "heterogeneous medium"
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![Material::Isotropic {
+            albedo: self.albedo,
+            emit: Vector3(0.0, 0.0, 0.0),
+        }]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+
+    fn velocity(&self, time: f64) -> Vector3 {
+        self.boundary.velocity(time)
+    }
+}
+
+/// Maps a point on the unit sphere to (u, v) in [0, 1], with v = 0 at the
+/// bottom pole and u running around the y axis starting from -x.
+fn sphere_uv(p: &Vector3) -> (f64, f64) {
+    let theta = (-p.y()).acos();
+    let phi = (-p.z()).atan2(p.x()) + std::f64::consts::PI;
+    (
+        phi / (2.0 * std::f64::consts::PI),
+        theta / std::f64::consts::PI,
+    )
+}
+
+/// The kinds of rays that see an object; the others pass through it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Visibility {
+    pub camera: bool,
+    /// Rays towards lights, so the object casts shadows.
+    pub shadow: bool,
+    /// Rays after the first bounce, so the object shows up in reflections and
+    /// refractions and lights its surroundings.
+    pub indirect: bool,
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Self {
+            camera: true,
+            shadow: true,
+            indirect: true,
+        }
+    }
+}
+
+impl std::str::FromStr for Visibility {
+    type Err = ();
+    /// A comma-separated list of `camera`, `shadow` and `indirect`, or `none`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut visibility = Self {
+            camera: false,
+            shadow: false,
+            indirect: false,
+        };
+        if s == "none" {
+            return Ok(visibility);
+        }
+        for kind in s.split(',') {
+            match kind {
+                "camera" => visibility.camera = true,
+                "shadow" => visibility.shadow = true,
+                "indirect" => visibility.indirect = true,
+                _ => return Err(()),
+            }
+        }
+        Ok(visibility)
+    }
+}
+
+/// What a ray traced through the store is for, see `Visibility`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RayKind {
+    Camera,
+    Shadow,
+    Indirect,
+}
+
+impl Visibility {
+    fn sees(self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Indirect => self.indirect,
+        }
+    }
+}
+
+/// Where an object stops being worth tracing, for scenes of so many
+/// instances that the distant ones hardly show. Measured from the camera to
+/// the nearest point of the object's bounding box, for the view rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Cull {
+    /// Farthest the object is shown at.
+    pub(crate) distance: Option<f64>,
+    /// How far before `distance` the object starts to fade out, dissolving
+    /// at random for more and more rays, rather than vanish at once.
+    pub(crate) fade: f64,
+    /// Fewest pixels the diagonal of its bounding box may cover on screen.
+    pub(crate) pixels: Option<f64>,
+}
+
+impl Cull {
+    /// The chance of a ray skipping an object in `bounds` seen from `eye`
+    /// with `pixels_per_unit` at distance 1.
+    fn chance(&self, bounds: &Aabb, eye: Vector3, pixels_per_unit: f64) -> f64 {
+        let (lo, hi) = (bounds.min, bounds.max);
+        let nearest = Vector3(
+            eye.x().clamp(lo.x(), hi.x()),
+            eye.y().clamp(lo.y(), hi.y()),
+            eye.z().clamp(lo.z(), hi.z()),
+        );
+        let distance = (nearest - eye).length();
+        if let Some(pixels) = self.pixels {
+            if (hi - lo).length() * pixels_per_unit < pixels * distance {
+                return 1.0;
+            }
+        }
+        match self.distance {
+            Some(d) if self.fade > 0.0 => ((distance - d + self.fade) / self.fade).clamp(0.0, 1.0),
+            Some(d) if distance > d => 1.0,
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct HittableStore {
+    pub(crate) objects: Vec<Box<dyn Hittable>>,
+    pub(crate) names: Vec<Option<String>>,
+    /// Acceleration structure over `objects`; rays test every object while
+    /// this is `None`.
+    pub(crate) bvh: Option<Bvh>,
+    /// Objects rays pass through, per object ID.
+    pub(crate) hidden: Vec<bool>,
+    /// Which rays see each object, per object ID.
+    pub(crate) visibility: Vec<Visibility>,
+    /// Nesting priority per object ID. Where dielectric volumes overlap, the
+    /// one with the highest priority fills the overlap and the surfaces of the
+    /// others inside it are ignored; among equals the one entered last wins.
+    pub(crate) priorities: Vec<u32>,
+    /// How much likelier each object is picked when sampling directions
+    /// towards the glowing objects, per object ID; 1 by default.
+    pub(crate) importance: Vec<f64>,
+    /// Cutout masks per object ID, `None` for solid objects: rays stop at
+    /// the surface with a chance given by the luminance of the texture and
+    /// pass on through it otherwise, for leaves and fences on a few polygons.
+    pub(crate) opacity: Vec<Option<Texture>>,
+    /// Culling per object ID, `None` for objects shown at any distance.
+    pub(crate) cull: Vec<Option<Cull>>,
+    /// The chance of a ray skipping each object by its `cull`, per object
+    /// ID, worked out for the view in `Hittable::tessellate`.
+    pub(crate) culled: Vec<f64>,
+}
+
+impl HittableStore {
+    pub fn add(&mut self, object: impl Hittable + 'static) {
+        self.add_boxed(Box::new(object));
+    }
+
+    pub fn add_boxed(&mut self, object: Box<dyn Hittable>) {
+        self.objects.push(object);
+        self.names.push(None);
+        self.hidden.push(false);
+        self.visibility.push(Visibility::default());
+        self.priorities.push(0);
+        self.importance.push(1.0);
+        self.opacity.push(None);
+        self.cull.push(None);
+        self.culled.push(0.0);
+        self.bvh = None;
+    }
+
+    /// Builds the BVH over the current objects; call again after adding more.
+    pub fn build_bvh(&mut self) {
+        self.bvh = Some(Bvh::new(self.objects.iter().map(|o| o.bounding_box())));
+    }
+
+    pub fn add_named(&mut self, name: &str, object: impl Hittable + 'static) {
+        self.add(object);
+        *self.names.last_mut().unwrap() = Some(name.to_string());
+    }
+
+    /// Looks an object up by name or by ID.
+    pub fn find(&self, name_or_id: &str) -> Option<usize> {
+        self.names
+            .iter()
+            .position(|name| name.as_deref() == Some(name_or_id))
+            .or_else(|| name_or_id.parse().ok())
+            .filter(|id| *id < self.objects.len())
+    }
+
+    /// The first hit of `r` on an object visible to rays of `kind`.
+    pub(crate) fn hit_as(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        kind: RayKind,
+    ) -> Option<Intersection> {
+        self.hit_where(r, t_min, t_max, |id| self.visibility[id].sees(kind))
+    }
+
+    /// The first hit of `r` on the shown objects for which `visible` holds,
+    /// not counting the cutouts it is let through.
+    fn hit_where(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        visible: impl Fn(usize) -> bool,
+    ) -> Option<Intersection> {
+        if let Some(bvh) = &self.bvh {
+            return bvh.hit(r, t_min, t_max, |id, closest| {
+                if self.hidden[id] || !visible(id) || self.skips(id) {
+                    return None;
+                }
+                self.hit_object(id, r, t_min, closest)
+            });
+        }
+        (0..self.objects.len())
+            .filter(|id| !self.hidden[*id] && visible(*id) && !self.skips(*id))
+            .fold((None, t_max), |(i, closest), id| {
+                if let Some(ni) = self.hit_object(id, r, t_min, closest) {
+                    (Some(Intersection { object: id, ..ni }), ni.t)
+                } else {
+                    (i, closest)
+                }
+            })
+            .0
+    }
+
+    /// Whether a ray leaves culled object `id` out.
+    fn skips(&self, id: usize) -> bool {
+        self.culled[id] > 0.0 && random() < self.culled[id]
+    }
+
+    /// The first hit of `r` on object `id` that its opacity stops. Each
+    /// cutout surface is decided on once, at random, so stacks of them cost
+    /// a lookup per surface crossed rather than a bounce each.
+    fn hit_object(&self, id: usize, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let mut t_min = t_min;
+        loop {
+            let i = self.objects[id].hit(r, t_min, t_max)?;
+            match &self.opacity[id] {
+                Some(opacity) if random() >= luminance(opacity.value(i.u, i.v, &i.p)) => {
+                    t_min = i.t + 0.001;
+                }
+                _ => return Some(Intersection { object: id, ..i }),
+            }
+        }
+    }
+
+    /// `'name' (#id)`, or just `#id` for unnamed objects, for messages.
+    pub fn describe(&self, id: usize) -> String {
+        match self.names.get(id).and_then(Option::as_ref) {
+            Some(name) => format!("'{}' (#{})", name, id),
+            None => format!("#{}", id),
+        }
+    }
+}
+
+impl Hittable for HittableStore {
+    /// The first hit on any shown object, whatever its visibility.
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.hit_where(r, t_min, t_max, |_| true)
+    }
+
+    fn kind(&self) -> &'static str {
+        "group"
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        self.objects.iter().flat_map(|h| h.materials()).collect()
+    }
+
+    /// The triangles of every shown object.
+    fn triangles(&self) -> Vec<Facet> {
+        self.objects
+            .iter()
+            .zip(&self.hidden)
+            .filter(|(_, hidden)| !**hidden)
+            .flat_map(|(h, _)| h.triangles())
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut boxes = self.objects.iter().map(|h| h.bounding_box());
+        let first = boxes.next()??;
+        boxes.try_fold(first, |acc, b| Some(acc.union(&b?)))
+    }
+
+    /// Adapts every object to the view about to be rendered and culls
+    /// them for it.
+    fn tessellate(&mut self, view: &CameraView, image_height: u32) {
+        for object in self.objects.iter_mut() {
+            object.tessellate(view, image_height);
+        }
+        let pixels_per_unit = image_height as f64 / (2.0 * (view.vfov.to_radians() / 2.0).tan());
+        for (id, object) in self.objects.iter().enumerate() {
+            self.culled[id] = match (self.cull[id], object.bounding_box()) {
+                (Some(cull), Some(bounds)) => cull.chance(&bounds, view.from, pixels_per_unit),
+                _ => 0.0,
+            };
+        }
+    }
+}
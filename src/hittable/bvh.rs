@@ -0,0 +1,367 @@
+//! Bounding volume hierarchy over anything with bounding boxes.
+
+use crate::hittable::{Aabb, Intersection, SLAB_ROUNDING};
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Makes every `Bvh` traverse its binary nodes one box at a time instead of
+/// the wide nodes, for `raytracer bench` to compare against.
+pub(crate) static SCALAR_TRAVERSAL: AtomicBool = AtomicBool::new(false);
+
+/// Bounding volume hierarchy over the objects of a `HittableStore` or the
+/// triangles of a `Mesh`, so rays only test the items whose boxes they pass
+/// through. Nodes are stored flat with the root first.
+#[derive(Debug)]
+pub(crate) struct Bvh {
+    pub(crate) nodes: Vec<BvhNode>,
+    /// The same hierarchy with up to four children per node, which rays
+    /// traverse; root first.
+    pub(crate) wide: Vec<WideNode>,
+    /// Item indices ordered so that every leaf covers a contiguous range.
+    pub(crate) order: Vec<usize>,
+    /// Items without a bounding box, which every ray tests.
+    pub(crate) unbounded: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub(crate) enum BvhNode {
+    Leaf {
+        bounds: Aabb,
+        start: usize,
+        end: usize,
+    },
+    Inner {
+        bounds: Aabb,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl BvhNode {
+    fn bounds(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf { bounds, .. } | BvhNode::Inner { bounds, .. } => bounds,
+        }
+    }
+}
+
+/// A node of the wide hierarchy. The boxes of its children are stored side
+/// by side, one array per axis, so one ray is tested against all four in
+/// loops the compiler turns into SIMD instructions.
+#[derive(Debug)]
+pub(crate) struct WideNode {
+    min: [[f64; 4]; 3],
+    max: [[f64; 4]; 3],
+    children: [WideChild; 4],
+}
+
+#[derive(Debug, Clone, Copy)]
+enum WideChild {
+    /// Pads nodes with fewer than four children.
+    Empty,
+    Node(usize),
+    /// Items `order[start..end]`.
+    Leaf {
+        start: usize,
+        end: usize,
+    },
+}
+
+impl WideNode {
+    /// Distance along the ray to where it enters each child's box, infinite
+    /// for children it misses within `t_min..t_max`.
+    fn entries(&self, origin: [f64; 3], inverse: [f64; 3], t_min: f64, t_max: f64) -> [f64; 4] {
+        let (mut t0, mut t1) = ([t_min; 4], [t_max; 4]);
+        for axis in 0..3 {
+            for k in 0..4 {
+                let ta = (self.min[axis][k] - origin[axis]) * inverse[axis];
+                let tb = (self.max[axis][k] - origin[axis]) * inverse[axis];
+                t0[k] = t0[k].max(ta.min(tb));
+                t1[k] = t1[k].min(ta.max(tb) * SLAB_ROUNDING);
+            }
+        }
+        let mut entries = [f64::INFINITY; 4];
+        for k in 0..4 {
+            if t0[k] <= t1[k] && !matches!(self.children[k], WideChild::Empty) {
+                entries[k] = t0[k];
+            }
+        }
+        entries
+    }
+}
+
+impl Bvh {
+    const LEAF_SIZE: usize = 2;
+
+    /// Builds the hierarchy over items with the given bounding boxes.
+    pub(crate) fn new(boxes: impl Iterator<Item = Option<Aabb>>) -> Self {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for (id, bounds) in boxes.enumerate() {
+            match bounds {
+                Some(b) => bounded.push((id, b)),
+                None => unbounded.push(id),
+            }
+        }
+        let mut bvh = Bvh {
+            nodes: Vec::new(),
+            wide: Vec::new(),
+            order: Vec::new(),
+            unbounded,
+        };
+        if !bounded.is_empty() {
+            bvh.build(&mut bounded);
+            bvh.widen(0);
+        }
+        bvh
+    }
+
+    /// Adds the wide node over binary node `node` and what is below it, and
+    /// returns its index. Its children are found by opening the inner node
+    /// with the largest surface among them until there are four.
+    fn widen(&mut self, node: usize) -> usize {
+        let area = |b: &Aabb| {
+            let d = b.max - b.min;
+            d.x() * d.y() + d.y() * d.z() + d.z() * d.x()
+        };
+        let mut picked = vec![node];
+        while picked.len() < 4 {
+            let largest = picked
+                .iter()
+                .enumerate()
+                .filter(|(_, &n)| matches!(self.nodes[n], BvhNode::Inner { .. }))
+                .max_by(|(_, &a), (_, &b)| {
+                    area(self.nodes[a].bounds()).total_cmp(&area(self.nodes[b].bounds()))
+                })
+                .map(|(k, _)| k);
+            match largest.map(|k| (k, &self.nodes[picked[k]])) {
+                Some((k, &BvhNode::Inner { left, right, .. })) => {
+                    picked[k] = left;
+                    picked.push(right);
+                }
+                _ => break,
+            }
+        }
+        let index = self.wide.len();
+        self.wide.push(WideNode {
+            min: [[f64::INFINITY; 4]; 3],
+            max: [[f64::NEG_INFINITY; 4]; 3],
+            children: [WideChild::Empty; 4],
+        });
+        for (k, &n) in picked.iter().enumerate() {
+            let b = *self.nodes[n].bounds();
+            let child = match self.nodes[n] {
+                BvhNode::Leaf { start, end, .. } => WideChild::Leaf { start, end },
+                BvhNode::Inner { .. } => WideChild::Node(self.widen(n)),
+            };
+            let node = &mut self.wide[index];
+            for (axis, &(lo, hi)) in [
+                (b.min.x(), b.max.x()),
+                (b.min.y(), b.max.y()),
+                (b.min.z(), b.max.z()),
+            ]
+            .iter()
+            .enumerate()
+            {
+                node.min[axis][k] = lo;
+                node.max[axis][k] = hi;
+            }
+            node.children[k] = child;
+        }
+        index
+    }
+
+    /// Adds the subtree over `items` and returns the index of its root.
+    fn build(&mut self, items: &mut [(usize, Aabb)]) -> usize {
+        let bounds = items[1..]
+            .iter()
+            .fold(items[0].1, |acc, (_, b)| acc.union(b));
+        let index = self.nodes.len();
+        if items.len() <= Self::LEAF_SIZE {
+            let start = self.order.len();
+            self.order.extend(items.iter().map(|(id, _)| *id));
+            self.nodes.push(BvhNode::Leaf {
+                bounds,
+                start,
+                end: self.order.len(),
+            });
+            return index;
+        }
+        // split at the median along the axis the box centers spread most on
+        let center = |b: &Aabb| (b.min + b.max) * 0.5;
+        let (lo, hi) = items[1..].iter().fold(
+            (center(&items[0].1), center(&items[0].1)),
+            |(lo, hi), (_, b)| {
+                let c = center(b);
+                (
+                    Vector3(lo.x().min(c.x()), lo.y().min(c.y()), lo.z().min(c.z())),
+                    Vector3(hi.x().max(c.x()), hi.y().max(c.y()), hi.z().max(c.z())),
+                )
+            },
+        );
+        let spread = hi - lo;
+        let axis = |v: Vector3| {
+            if spread.x() >= spread.y() && spread.x() >= spread.z() {
+                v.x()
+            } else if spread.y() >= spread.z() {
+                v.y()
+            } else {
+                v.z()
+            }
+        };
+        items.sort_by(|(_, a), (_, b)| axis(center(a)).total_cmp(&axis(center(b))));
+        // reserve the slot, the children follow it
+        self.nodes.push(BvhNode::Leaf {
+            bounds,
+            start: 0,
+            end: 0,
+        });
+        let (first, second) = items.split_at_mut(items.len() / 2);
+        let left = self.build(first);
+        let right = self.build(second);
+        self.nodes[index] = BvhNode::Inner {
+            bounds,
+            left,
+            right,
+        };
+        index
+    }
+
+    /// Box around everything bounded.
+    pub(crate) fn bounds(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| *node.bounds())
+    }
+
+    /// The boxes of the wide nodes down to `max_depth` levels below the root,
+    /// each with its depth; the root's box is at depth 0.
+    pub(crate) fn node_boxes(&self, max_depth: usize) -> Vec<(Aabb, usize)> {
+        let mut boxes: Vec<_> = self.bounds().map(|b| (b, 0)).into_iter().collect();
+        let mut stack = if self.wide.is_empty() {
+            Vec::new()
+        } else {
+            vec![(0, 1)]
+        };
+        while let Some((index, depth)) = stack.pop() {
+            if depth > max_depth {
+                continue;
+            }
+            let node = &self.wide[index];
+            for (k, child) in node.children.iter().enumerate() {
+                if matches!(child, WideChild::Empty) {
+                    continue;
+                }
+                let corner = |side: &[[f64; 4]; 3]| Vector3(side[0][k], side[1][k], side[2][k]);
+                let b = Aabb {
+                    min: corner(&node.min),
+                    max: corner(&node.max),
+                };
+                boxes.push((b, depth));
+                if let WideChild::Node(child) = child {
+                    stack.push((*child, depth + 1));
+                }
+            }
+        }
+        boxes
+    }
+
+    /// The closest hit along `r`, where `hit_item(index, t_max)` intersects a
+    /// single item.
+    pub(crate) fn hit(
+        &self,
+        r: &Ray,
+        t_min: f64,
+        t_max: f64,
+        mut hit_item: impl FnMut(usize, f64) -> Option<Intersection>,
+    ) -> Option<Intersection> {
+        let mut found = None;
+        let mut closest = t_max;
+        let mut test = |id: usize, closest: &mut f64| {
+            if let Some(i) = hit_item(id, *closest) {
+                *closest = i.t;
+                found = Some(i);
+            }
+        };
+        for &id in &self.unbounded {
+            test(id, &mut closest);
+        }
+        if self.nodes.is_empty() {
+            return found;
+        }
+        if !SCALAR_TRAVERSAL.load(Ordering::Relaxed) {
+            let origin = [r.origin.x(), r.origin.y(), r.origin.z()];
+            let inverse = [
+                1.0 / r.direction.x(),
+                1.0 / r.direction.y(),
+                1.0 / r.direction.z(),
+            ];
+            // children waiting to be visited, with where the ray enters them
+            let mut stack = [(WideChild::Node(0), t_min); 64];
+            let mut len = 1;
+            while len > 0 {
+                len -= 1;
+                let (child, entry) = stack[len];
+                if entry > closest {
+                    continue;
+                }
+                match child {
+                    WideChild::Empty => {}
+                    WideChild::Leaf { start, end } => {
+                        for &id in &self.order[start..end] {
+                            test(id, &mut closest);
+                        }
+                    }
+                    WideChild::Node(n) => {
+                        let node = &self.wide[n];
+                        let entries = node.entries(origin, inverse, t_min, closest);
+                        // pushed farthest first, so the nearest is visited
+                        // first and can cut the others short
+                        let mut order = [0, 1, 2, 3];
+                        order.sort_unstable_by(|&a, &b| entries[b].total_cmp(&entries[a]));
+                        for k in order {
+                            if entries[k].is_finite() && entries[k] <= closest {
+                                stack[len] = (node.children[k], entries[k]);
+                                len += 1;
+                            }
+                        }
+                    }
+                }
+            }
+            return found;
+        }
+        let mut stack = [0; 64];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let node = &self.nodes[stack[len]];
+            if node.bounds().clip(r, t_min, closest).is_none() {
+                continue;
+            }
+            match node {
+                BvhNode::Leaf { start, end, .. } => {
+                    for &id in &self.order[*start..*end] {
+                        test(id, &mut closest);
+                    }
+                }
+                BvhNode::Inner { left, right, .. } => {
+                    // visit the nearer child first so it can cut the farther one short
+                    let entry = |n: usize| {
+                        self.nodes[n]
+                            .bounds()
+                            .clip(r, t_min, closest)
+                            .map_or(f64::INFINITY, |(t0, _)| t0)
+                    };
+                    let (near, far) = if entry(*left) <= entry(*right) {
+                        (*left, *right)
+                    } else {
+                        (*right, *left)
+                    };
+                    stack[len] = far;
+                    stack[len + 1] = near;
+                    len += 2;
+                }
+            }
+        }
+        found
+    }
+}
@@ -0,0 +1,321 @@
+//! Displacement-mapped meshes diced on demand.
+
+use crate::camera::CameraView;
+use crate::color::srgb_encode;
+use crate::hittable::bvh::{Bvh, BvhNode, WideNode};
+use crate::hittable::mesh::{smooth_normals, triangle_bounds, Mesh};
+use crate::hittable::terrain::fbm;
+use crate::hittable::{Aabb, Facet, Hittable, Intersection};
+use crate::image::read_ppm;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+
+/// Scalar displacement of a surface along its normal, in [-1, 1].
+#[derive(Debug)]
+pub enum Displacement {
+    /// A grayscale PPM image over the texture coordinates, repeating, black
+    /// at 0 and white at 1.
+    Map {
+        width: usize,
+        height: usize,
+        values: Vec<f64>,
+    },
+    /// fBm noise over the undisplaced position, `frequency` features per
+    /// scene unit.
+    Noise { frequency: f64, seed: u32 },
+}
+
+impl Displacement {
+    pub fn load_map(path: &str) -> std::io::Result<Self> {
+        let (width, height, pixels) = read_ppm(path)?;
+        Ok(Displacement::Map {
+            width,
+            height,
+            // heights are stored as they are, not sRGB encoded
+            values: pixels.iter().map(|c| srgb_encode(c.x())).collect(),
+        })
+    }
+
+    fn at(&self, p: Vector3, (u, v): (f64, f64)) -> f64 {
+        match self {
+            Displacement::Map {
+                width,
+                height,
+                values,
+            } => {
+                // bilinear, with v = 0 at the bottom of the image
+                let x = u.rem_euclid(1.0) * *width as f64 - 0.5;
+                let y = (1.0 - v.rem_euclid(1.0)) * *height as f64 - 0.5;
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let texel = |x: f64, y: f64| {
+                    let x = (x as i64).rem_euclid(*width as i64) as usize;
+                    let y = (y as i64).rem_euclid(*height as i64) as usize;
+                    values[y * width + x]
+                };
+                let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1.0, y0) * fx;
+                let bottom = texel(x0, y0 + 1.0) * (1.0 - fx) + texel(x0 + 1.0, y0 + 1.0) * fx;
+                top * (1.0 - fy) + bottom * fy
+            }
+            Displacement::Noise { frequency, seed } => {
+                // three planar projections, so no direction is stretched
+                let q = p * *frequency;
+                (fbm(q.x(), q.y(), 4, *seed)
+                    + fbm(q.y(), q.z(), 4, seed.wrapping_add(10))
+                    + fbm(q.z(), q.x(), 4, seed.wrapping_add(20)))
+                    / 3.0
+            }
+        }
+    }
+}
+
+/// Displaced patches built so far, keyed by base face, with the clock tick of
+/// their last use.
+#[derive(Default)]
+struct PatchTable {
+    patches: std::collections::HashMap<usize, (std::sync::Arc<Mesh>, std::sync::atomic::AtomicU64)>,
+    bytes: usize,
+}
+
+/// Lazily built geometry under a memory budget: once the patches take more
+/// than `budget` bytes, the least recently used are dropped until they fit
+/// into three quarters of it. Rays still holding a dropped patch keep it alive
+/// until they are done.
+struct PatchCache {
+    budget: usize,
+    clock: std::sync::atomic::AtomicU64,
+    table: std::sync::RwLock<PatchTable>,
+}
+
+impl std::fmt::Debug for PatchCache {
+    // what is cached depends on the rays traced so far, not on the scene
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PatchCache")
+            .field("budget", &self.budget)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PatchCache {
+    fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            clock: Default::default(),
+            table: Default::default(),
+        }
+    }
+
+    /// The patch of `face`, built by `build` if it is not cached.
+    fn get(&self, face: usize, build: impl FnOnce() -> Mesh) -> std::sync::Arc<Mesh> {
+        use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+        let now = self.clock.fetch_add(1, Relaxed);
+        if let Some((patch, used)) = self.table.read().unwrap().patches.get(&face) {
+            used.store(now, Relaxed);
+            return patch.clone();
+        }
+        let patch = std::sync::Arc::new(build());
+        let mut table = self.table.write().unwrap();
+        // another thread may have built it in the meantime
+        if let Some((existing, _)) = table.patches.get(&face) {
+            return existing.clone();
+        }
+        table.bytes += std::mem::size_of::<Mesh>() + patch.heap_bytes();
+        table
+            .patches
+            .insert(face, (patch.clone(), AtomicU64::new(now)));
+        if table.bytes > self.budget {
+            let mut by_age: Vec<(u64, usize)> = table
+                .patches
+                .iter()
+                .filter(|(&f, _)| f != face)
+                .map(|(&f, (_, used))| (used.load(Relaxed), f))
+                .collect();
+            by_age.sort_unstable();
+            for (_, f) in by_age {
+                if table.bytes <= self.budget / 4 * 3 {
+                    break;
+                }
+                let (old, _) = table.patches.remove(&f).unwrap();
+                table.bytes -= std::mem::size_of::<Mesh>() + old.heap_bytes();
+            }
+        }
+        patch
+    }
+
+    fn clear(&mut self) {
+        *self.table.get_mut().unwrap() = PatchTable::default();
+    }
+
+    fn bytes(&self) -> usize {
+        self.table.read().unwrap().bytes
+    }
+}
+
+/// A smooth mesh displaced along its normals by `height` times a
+/// displacement. Each base triangle is diced into a grid of micro triangles
+/// only when a ray first reaches its bounds, and the diced patches live in a
+/// cache with a memory budget, so fine displacement never needs the whole
+/// micro mesh at once. Triangles are diced `subdivisions` times along each
+/// edge or, with `edge_pixels`, until their edges span at most that many
+/// pixels in the view being rendered.
+#[derive(Debug)]
+pub struct DisplacedMesh {
+    base: Mesh,
+    displacement: Displacement,
+    height: f64,
+    subdivisions: u32,
+    edge_pixels: Option<f64>,
+    /// Camera position and pixels per scene unit at distance 1, once known.
+    view: Option<(Vector3, f64)>,
+    /// Over the base faces, grown by the largest displacement.
+    bvh: Bvh,
+    cache: PatchCache,
+}
+
+impl DisplacedMesh {
+    /// Finest dicing, 4096 micro triangles per base triangle.
+    pub(crate) const MAX_SUBDIVISIONS: u32 = 64;
+
+    pub fn new(
+        base: Mesh,
+        displacement: Displacement,
+        height: f64,
+        subdivisions: u32,
+        edge_pixels: Option<f64>,
+        budget: usize,
+    ) -> Self {
+        let reach = height.abs();
+        let bvh = Bvh::new(base.faces.iter().map(|f| {
+            let bounds = triangle_bounds(f.map(|k| base.positions[k]));
+            Some(Aabb {
+                min: bounds.min - Vector3(reach, reach, reach),
+                max: bounds.max + Vector3(reach, reach, reach),
+            })
+        }));
+        Self {
+            base,
+            displacement,
+            height,
+            subdivisions,
+            edge_pixels,
+            view: None,
+            bvh,
+            cache: PatchCache::new(budget),
+        }
+    }
+
+    /// How often `face` is split along each edge.
+    fn rate(&self, face: usize) -> u32 {
+        let (edge_pixels, (eye, scale)) = match (self.edge_pixels, self.view) {
+            (Some(e), Some(view)) => (e, view),
+            _ => return self.subdivisions,
+        };
+        let [a, b, c] = self.base.faces[face].map(|k| self.base.positions[k]);
+        let distance = ((a + b + c) / 3.0 - eye).length().max(1e-6);
+        let longest = (b - a).length().max((c - b).length()).max((a - c).length());
+        (longest * scale / distance / edge_pixels)
+            .ceil()
+            .clamp(1.0, Self::MAX_SUBDIVISIONS as f64) as u32
+    }
+
+    /// Dices and displaces one base triangle.
+    fn patch(&self, face: usize) -> Mesh {
+        let f = self.base.faces[face];
+        let n = self.rate(face) as usize;
+        let mut positions = Vec::with_capacity((n + 1) * (n + 2) / 2);
+        let mut uvs = Vec::new();
+        for i in 0..=n {
+            for j in 0..=n - i {
+                let (beta, gamma) = (i as f64 / n as f64, j as f64 / n as f64);
+                let weights = [1.0 - beta - gamma, beta, gamma];
+                let blend = |values: &[Vector3]| {
+                    (0..3).fold(Vector3(0.0, 0.0, 0.0), |acc, k| {
+                        acc + values[f[k]] * weights[k]
+                    })
+                };
+                let p = blend(&self.base.positions);
+                let normal = blend(&self.base.normals).normalize();
+                let uv = if self.base.uvs.is_empty() {
+                    (beta, gamma)
+                } else {
+                    (0..3).fold((0.0, 0.0), |(u, v), k| {
+                        let (uk, vk) = self.base.uvs[f[k]];
+                        (u + uk * weights[k], v + vk * weights[k])
+                    })
+                };
+                positions.push(p + normal * (self.height * self.displacement.at(p, uv)));
+                uvs.push(uv);
+            }
+        }
+        // vertex (i, j) follows the shorter rows of all i' < i
+        let index = |i: usize, j: usize| i * (2 * n + 3 - i) / 2 + j;
+        let mut faces = Vec::with_capacity(n * n);
+        for i in 0..n {
+            for j in 0..n - i {
+                faces.push([index(i, j), index(i + 1, j), index(i, j + 1)]);
+                if i + j + 1 < n {
+                    faces.push([index(i + 1, j), index(i + 1, j + 1), index(i, j + 1)]);
+                }
+            }
+        }
+        let normals = smooth_normals(&positions, faces.iter().copied());
+        if self.base.uvs.is_empty() {
+            uvs.clear();
+        }
+        Mesh::new(positions, normals, uvs, faces, self.base.material)
+    }
+}
+
+impl Hittable for DisplacedMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.bvh.hit(r, t_min, t_max, |face, closest| {
+            self.cache
+                .get(face, || self.patch(face))
+                .hit(r, t_min, closest)
+        })
+    }
+
+    fn tessellate(&mut self, view: &CameraView, image_height: u32) {
+        if self.edge_pixels.is_none() {
+            return;
+        }
+        let scale = image_height as f64 / (2.0 * (view.vfov.to_radians() / 2.0).tan());
+        self.view = Some((view.from, scale));
+        // patches diced for another view are too fine or too coarse
+        self.cache.clear();
+    }
+
+    fn kind(&self) -> &'static str {
+        "displaced mesh"
+    }
+
+    /// Every base triangle diced and displaced, bypassing the cache.
+    fn triangles(&self) -> Vec<Facet> {
+        (0..self.base.faces.len())
+            .flat_map(|face| self.patch(face).triangles())
+            .collect()
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![self.base.material]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+
+    fn heap_bytes(&self) -> usize {
+        use std::mem::size_of;
+        let displacement = match &self.displacement {
+            Displacement::Map { values, .. } => values.len() * size_of::<f64>(),
+            Displacement::Noise { .. } => 0,
+        };
+        self.base.heap_bytes()
+            + displacement
+            + self.bvh.nodes.len() * size_of::<BvhNode>()
+            + self.bvh.wide.len() * size_of::<WideNode>()
+            + self.bvh.order.len() * size_of::<usize>()
+            + self.cache.bytes()
+    }
+}
@@ -0,0 +1,415 @@
+//! Meshes simplified at import, and levels of detail picked by screen size.
+
+use crate::camera::CameraView;
+use crate::hittable::mesh::{smooth_normals, Mesh};
+use crate::hittable::transform::{Matrix4, Transform};
+use crate::hittable::{Aabb, Facet, Hittable, Intersection};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+use std::sync::Arc;
+
+/// The squared distance of points from a set of planes, summed, as the
+/// symmetric matrix of Garland and Heckbert's quadric error metric:
+/// a², ab, ac, ad, b², bc, bd, c², cd and d² of the planes ax + by + cz + d.
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// The plane through `p` with unit normal `n`, weighted by `weight`.
+    fn plane(n: Vector3, p: Vector3, weight: f64) -> Self {
+        let (a, b, c) = (n.x(), n.y(), n.z());
+        let d = -n.dot(&p);
+        Quadric(
+            [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ]
+            .map(|q| q * weight),
+        )
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        let mut sum = *self;
+        sum.0.iter_mut().zip(other.0).for_each(|(s, o)| *s += o);
+        sum
+    }
+
+    fn error(&self, v: Vector3) -> f64 {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.0;
+        let (x, y, z) = (v.x(), v.y(), v.z());
+        aa * x * x
+            + 2.0 * ab * x * y
+            + 2.0 * ac * x * z
+            + 2.0 * ad * x
+            + bb * y * y
+            + 2.0 * bc * y * z
+            + 2.0 * bd * y
+            + cc * z * z
+            + 2.0 * cd * z
+            + dd
+    }
+
+    /// The point of least error, if the planes pin one down.
+    fn minimum(&self) -> Option<Vector3> {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, _] = self.0;
+        let det = aa * (bb * cc - bc * bc) - ab * (ab * cc - bc * ac) + ac * (ab * bc - bb * ac);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        // Cramer's rule on the gradient set to zero
+        let (r0, r1, r2) = (-ad, -bd, -cd);
+        let x =
+            (r0 * (bb * cc - bc * bc) - ab * (r1 * cc - bc * r2) + ac * (r1 * bc - bb * r2)) / det;
+        let y =
+            (aa * (r1 * cc - bc * r2) - r0 * (ab * cc - bc * ac) + ac * (ab * r2 - r1 * ac)) / det;
+        let z =
+            (aa * (bb * r2 - r1 * bc) - ab * (ab * r2 - r1 * ac) + r0 * (ab * bc - bb * ac)) / det;
+        Some(Vector3(x, y, z))
+    }
+}
+
+/// `mesh` with edges collapsed until at most `target` triangles are left,
+/// each time the edge whose collapse moves the surface least by the
+/// quadric error metric of Garland and Heckbert. Corners at the same
+/// position are welded first; the result is smooth if `mesh` was, keeps its
+/// vertex colors and drops its texture coordinates.
+pub fn decimate(mesh: &Mesh, target: usize) -> Mesh {
+    // welded vertices, one per position
+    let mut weld = std::collections::HashMap::new();
+    let mut positions = Vec::new();
+    let mut colors = Vec::new();
+    let index: Vec<usize> = (0..mesh.positions.len())
+        .map(|k| {
+            let p = mesh.positions[k];
+            *weld
+                .entry([p.x(), p.y(), p.z()].map(f64::to_bits))
+                .or_insert_with(|| {
+                    positions.push(p);
+                    colors.push(mesh.colors.get(k).copied());
+                    positions.len() - 1
+                })
+        })
+        .collect();
+    let mut faces: Vec<[usize; 3]> = mesh.faces.iter().map(|f| f.map(|k| index[k])).collect();
+
+    let normal = |positions: &[Vector3], f: [usize; 3]| {
+        let [a, b, c] = f.map(|k| positions[k]);
+        (b - a).cross(&(c - a))
+    };
+    let mut quadrics = vec![Quadric::default(); positions.len()];
+    let mut around = vec![Vec::new(); positions.len()];
+    for (k, &f) in faces.iter().enumerate() {
+        let n = normal(&positions, f);
+        let area = n.length();
+        if area > 0.0 {
+            let plane = Quadric::plane(n / area, positions[f[0]], area);
+            f.iter()
+                .for_each(|&v| quadrics[v] = quadrics[v].add(&plane));
+        }
+        f.iter().for_each(|&v| around[v].push(k));
+    }
+
+    // the best place for the vertex both ends collapse into, and its error
+    let collapse = |quadrics: &[Quadric], positions: &[Vector3], a: usize, b: usize| {
+        let q = quadrics[a].add(&quadrics[b]);
+        let (pa, pb) = (positions[a], positions[b]);
+        let candidates = [q.minimum(), Some(pa), Some(pb), Some((pa + pb) * 0.5)];
+        candidates
+            .iter()
+            .flatten()
+            .map(|&p| (q.error(p).max(0.0), p))
+            .min_by(|x, y| x.0.total_cmp(&y.0))
+            .unwrap()
+    };
+    // collapses by error, dropped once either end has changed since
+    let mut changes = vec![0u32; positions.len()];
+    let mut heap = std::collections::BinaryHeap::new();
+    let push = |heap: &mut std::collections::BinaryHeap<_>,
+                quadrics: &[Quadric],
+                positions: &[Vector3],
+                changes: &[u32],
+                (a, b): (usize, usize)| {
+        let (error, _) = collapse(quadrics, positions, a, b);
+        // errors are not negative, so their bits sort like them
+        heap.push(std::cmp::Reverse((
+            error.to_bits(),
+            a,
+            b,
+            changes[a],
+            changes[b],
+        )));
+    };
+    let mut edges = std::collections::HashSet::new();
+    for f in &faces {
+        for k in 0..3 {
+            let (a, b) = (f[k], f[(k + 1) % 3]);
+            if edges.insert((a.min(b), a.max(b))) {
+                push(
+                    &mut heap,
+                    &quadrics,
+                    &positions,
+                    &changes,
+                    (a.min(b), a.max(b)),
+                );
+            }
+        }
+    }
+
+    let mut alive = vec![true; faces.len()];
+    let mut left = faces.len();
+    while left > target {
+        let (a, b) = match heap.pop() {
+            Some(std::cmp::Reverse((_, a, b, ca, cb))) if changes[a] == ca && changes[b] == cb => {
+                (a, b)
+            }
+            Some(_) => continue,
+            None => break,
+        };
+        let (_, p) = collapse(&quadrics, &positions, a, b);
+        // faces kept must not turn over
+        let kept: Vec<usize> = around[a]
+            .iter()
+            .chain(&around[b])
+            .copied()
+            .filter(|&f| alive[f] && !(faces[f].contains(&a) && faces[f].contains(&b)))
+            .collect();
+        let flips = kept.iter().any(|&f| {
+            let [x, y, z] = faces[f].map(|v| if v == a || v == b { p } else { positions[v] });
+            normal(&positions, faces[f]).dot(&(y - x).cross(&(z - x))) <= 0.0
+        });
+        // nor may the last face go
+        let removed = around[a]
+            .iter()
+            .filter(|&&f| alive[f] && faces[f].contains(&b))
+            .count();
+        if flips || removed >= left {
+            continue;
+        }
+        positions[a] = p;
+        quadrics[a] = quadrics[a].add(&quadrics[b]);
+        colors[a] = match (colors[a], colors[b]) {
+            (Some(x), Some(y)) => Some((x + y) * 0.5),
+            (x, y) => x.or(y),
+        };
+        for f in std::mem::take(&mut around[b]) {
+            if !alive[f] {
+                continue;
+            }
+            if faces[f].contains(&a) {
+                alive[f] = false;
+                left -= 1;
+            } else {
+                faces[f] = faces[f].map(|v| if v == b { a } else { v });
+                around[a].push(f);
+            }
+        }
+        around[a].retain(|&f| alive[f]);
+        changes[a] += 1;
+        changes[b] += 1;
+        let neighbours: std::collections::BTreeSet<usize> = around[a]
+            .iter()
+            .flat_map(|&f| faces[f])
+            .filter(|&v| v != a)
+            .collect();
+        for n in neighbours {
+            push(
+                &mut heap,
+                &quadrics,
+                &positions,
+                &changes,
+                (a.min(n), a.max(n)),
+            );
+        }
+    }
+
+    // the vertices still in use, renumbered
+    let mut renumber = vec![usize::MAX; positions.len()];
+    let (mut kept_positions, mut kept_colors) = (Vec::new(), Vec::new());
+    let faces: Vec<[usize; 3]> = faces
+        .iter()
+        .zip(&alive)
+        .filter(|(_, &alive)| alive)
+        .map(|(f, _)| {
+            f.map(|v| {
+                if renumber[v] == usize::MAX {
+                    renumber[v] = kept_positions.len();
+                    kept_positions.push(positions[v]);
+                    kept_colors.push(colors[v].unwrap_or(Vector3(1.0, 1.0, 1.0)));
+                }
+                renumber[v]
+            })
+        })
+        .collect();
+    let normals = if mesh.normals.is_empty() {
+        Vec::new()
+    } else {
+        smooth_normals(&kept_positions, faces.iter().copied())
+    };
+    let simplified = Mesh::new(kept_positions, normals, Vec::new(), faces, mesh.material);
+    if mesh.colors.is_empty() {
+        simplified
+    } else {
+        simplified.with_colors(kept_colors)
+    }
+}
+
+/// Versions of a mesh at ever less detail, each with half the triangles of
+/// the one before, shared by its instances. Each view renders the coarsest
+/// whose edges span at most `edge_pixels` pixels where the instance stands,
+/// so far copies of a massive scan cost little.
+#[derive(Debug, Clone)]
+pub struct LodMesh {
+    /// The levels, finest first, each with the mean length of its edges.
+    levels: Arc<Vec<(Arc<Mesh>, f64)>>,
+    edge_pixels: f64,
+    matrix: Matrix4,
+    /// How much longer edges are after the placement.
+    stretch: f64,
+    bounds: Aabb,
+    /// The level picked for the view, placed.
+    placed: Transform,
+    /// Whether the levels are counted with another instance.
+    shared: bool,
+}
+
+impl LodMesh {
+    /// `mesh` and up to `count - 1` simplifications of it, stopping at a
+    /// handful of triangles.
+    pub fn new(mesh: Mesh, count: usize, edge_pixels: f64) -> Self {
+        let mean_edge = |mesh: &Mesh| {
+            let sum: f64 = mesh
+                .faces
+                .iter()
+                .map(|f| {
+                    let [a, b, c] = f.map(|k| mesh.positions[k]);
+                    (b - a).length() + (c - b).length() + (a - c).length()
+                })
+                .sum();
+            sum / (3 * mesh.faces.len()).max(1) as f64
+        };
+        let edge = mean_edge(&mesh);
+        let mut levels = vec![(Arc::new(mesh), edge)];
+        for _ in 1..count {
+            let finer = &levels[levels.len() - 1].0;
+            if finer.faces.len() < 8 {
+                break;
+            }
+            let coarser = decimate(finer, finer.faces.len() / 2);
+            let edge = mean_edge(&coarser);
+            levels.push((Arc::new(coarser), edge));
+        }
+        Self::placed(Arc::new(levels), edge_pixels, Matrix4::IDENTITY, false)
+    }
+
+    fn placed(
+        levels: Arc<Vec<(Arc<Mesh>, f64)>>,
+        edge_pixels: f64,
+        matrix: Matrix4,
+        shared: bool,
+    ) -> Self {
+        let transform = |level: usize| {
+            let mesh: Arc<dyn Hittable> = levels[level].0.clone();
+            Transform::new(mesh, matrix).unwrap()
+        };
+        let diagonal = |bounds: Option<Aabb>| bounds.map_or(0.0, |b| (b.max - b.min).length());
+        let placed = transform(0);
+        let local = diagonal(levels[0].0.bounding_box());
+        let stretch = if local > 0.0 {
+            diagonal(placed.bounding_box()) / local
+        } else {
+            1.0
+        };
+        let bounds = (0..levels.len())
+            .filter_map(|level| transform(level).bounding_box())
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        Self {
+            levels,
+            edge_pixels,
+            matrix,
+            stretch,
+            bounds,
+            placed,
+            shared,
+        }
+    }
+
+    /// Another instance of the mesh, placed by `matrix` after this one's
+    /// placement and picking its own level; `None` if `matrix` cannot be
+    /// inverted.
+    pub fn instance(&self, matrix: Matrix4) -> Option<Self> {
+        matrix.inverse()?;
+        Some(Self::placed(
+            self.levels.clone(),
+            self.edge_pixels,
+            matrix.then_after(&self.matrix),
+            true,
+        ))
+    }
+}
+
+impl Hittable for LodMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.placed.hit(r, t_min, t_max)
+    }
+
+    fn tessellate(&mut self, view: &CameraView, image_height: u32) {
+        // pixels per scene unit at distance 1
+        let scale = image_height as f64 / (2.0 * (view.vfov.to_radians() / 2.0).tan());
+        let nearest = Vector3(
+            view.from
+                .x()
+                .clamp(self.bounds.min.x(), self.bounds.max.x()),
+            view.from
+                .y()
+                .clamp(self.bounds.min.y(), self.bounds.max.y()),
+            view.from
+                .z()
+                .clamp(self.bounds.min.z(), self.bounds.max.z()),
+        );
+        let distance = (nearest - view.from).length().max(1e-6);
+        let level = (0..self.levels.len())
+            .rev()
+            .find(|&k| self.levels[k].1 * self.stretch * scale / distance <= self.edge_pixels)
+            .unwrap_or(0);
+        let mesh: Arc<dyn Hittable> = self.levels[level].0.clone();
+        self.placed = Transform::new(mesh, self.matrix).unwrap();
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+
+    fn kind(&self) -> &'static str {
+        "lod mesh"
+    }
+
+    /// The triangles of the current level.
+    fn triangles(&self) -> Vec<Facet> {
+        self.placed.triangles()
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![self.levels[0].0.material]
+    }
+
+    fn heap_bytes(&self) -> usize {
+        match self.shared {
+            true => 0,
+            false => self
+                .levels
+                .iter()
+                .map(|(mesh, _)| std::mem::size_of::<Mesh>() + mesh.heap_bytes())
+                .sum(),
+        }
+    }
+}
@@ -0,0 +1,732 @@
+//! Triangles, triangle meshes, OBJ files and subdivision surfaces.
+
+use crate::camera::CameraView;
+use crate::color::srgb_decode;
+use crate::hittable::bvh::{Bvh, BvhNode, WideNode};
+use crate::hittable::{Aabb, Facet, Hittable, Intersection};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+
+/// Ray parameter and barycentric coordinates of the second and third vertex
+/// where `r` crosses the triangle. Watertight, after Woop, Benthin and Wald:
+/// the vertices are sheared so the ray runs along z from the origin and the
+/// edges are tested in 2D there, each shared edge giving both triangles the
+/// same value with opposite signs, so no ray slips between them.
+fn intersect_triangle(
+    r: &Ray,
+    [a, b, c]: [Vector3; 3],
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    let d = [r.direction.x(), r.direction.y(), r.direction.z()];
+    // z is the largest direction component, x and y keep the winding
+    let kz = if d[0].abs() > d[1].abs() {
+        if d[0].abs() > d[2].abs() {
+            0
+        } else {
+            2
+        }
+    } else if d[1].abs() > d[2].abs() {
+        1
+    } else {
+        2
+    };
+    let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+    if d[kz] < 0.0 {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+    let (sx, sy, sz) = (d[kx] / d[kz], d[ky] / d[kz], 1.0 / d[kz]);
+    let shear = |p: Vector3| {
+        let p = p - r.origin;
+        let p = [p.x(), p.y(), p.z()];
+        (p[kx] - sx * p[kz], p[ky] - sy * p[kz], p[kz])
+    };
+    let ((ax, ay, az), (bx, by, bz), (cx, cy, cz)) = (shear(a), shear(b), shear(c));
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+    // inside when all three agree, edges included
+    if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+        return None;
+    }
+    let det = u + v + w;
+    if det == 0.0 {
+        return None;
+    }
+    let t = (u * az + v * bz + w * cz) * sz / det;
+    (t_min..=t_max)
+        .contains(&t)
+        .then_some((t, v / det, w / det))
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Triangle {
+    pub vertices: [Vector3; 3],
+    pub material: Material,
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let (t, u, v) = intersect_triangle(r, self.vertices, t_min, t_max)?;
+        let [a, b, c] = self.vertices;
+        let mut i = Intersection {
+            p: r.at(t),
+            normal: Vector3(0.0, 0.0, 0.0),
+            material: self.material,
+            t,
+            u,
+            v,
+            front_facing: false,
+            object: 0,
+            tangent: b - a,
+            color: Vector3(1.0, 1.0, 1.0),
+        };
+        i.set_face_normal(r, &(b - a).cross(&(c - a)).normalize());
+        Some(i)
+    }
+
+    fn kind(&self) -> &'static str {
+        "triangle"
+    }
+
+    fn triangles(&self) -> Vec<Facet> {
+        vec![Facet::flat(self.vertices, self.material)]
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![self.material]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(triangle_bounds(self.vertices))
+    }
+}
+
+pub(crate) fn triangle_bounds([a, b, c]: [Vector3; 3]) -> Aabb {
+    let corner = Aabb { min: a, max: a };
+    corner
+        .union(&Aabb { min: b, max: b })
+        .union(&Aabb { min: c, max: c })
+}
+
+/// Indices of the position, texture coordinates and normal at a face corner.
+type ObjCorner = (usize, Option<usize>, Option<usize>);
+
+/// The geometry of a Wavefront OBJ file, with the vertex colors some
+/// exporters write after the position. Groups, objects and material
+/// libraries are ignored.
+#[derive(Debug, Default)]
+pub struct ObjFile {
+    pub(crate) positions: Vec<Vector3>,
+    /// The sRGB color of each position, if it has one.
+    colors: Vec<Option<Vector3>>,
+    uvs: Vec<(f64, f64)>,
+    normals: Vec<Vector3>,
+    pub(crate) polygons: Vec<Vec<ObjCorner>>,
+}
+
+impl ObjFile {
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text = crate::embed::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut obj = Self::default();
+        for (n, line) in text.lines().enumerate() {
+            let at = |what: &str| format!("{}:{}: {}", path, n + 1, what);
+            let mut words = line.split_whitespace();
+            let numbers = |words: std::str::SplitWhitespace| -> Result<Vec<f64>, String> {
+                words
+                    .map(|w| w.parse().map_err(|_| at("expected numbers")))
+                    .collect()
+            };
+            match words.next() {
+                Some("v") => match numbers(words)?[..] {
+                    [x, y, z, r, g, b] => {
+                        obj.positions.push(Vector3(x, y, z));
+                        obj.colors.push(Some(Vector3(r, g, b)));
+                    }
+                    [x, y, z, ..] => {
+                        obj.positions.push(Vector3(x, y, z));
+                        obj.colors.push(None);
+                    }
+                    _ => return Err(at("a vertex needs x, y and z")),
+                },
+                Some("vt") => match numbers(words)?[..] {
+                    [u] => obj.uvs.push((u, 0.0)),
+                    [u, v, ..] => obj.uvs.push((u, v)),
+                    _ => return Err(at("a texture coordinate needs u")),
+                },
+                Some("vn") => match numbers(words)?[..] {
+                    [x, y, z] => obj.normals.push(Vector3(x, y, z).normalize()),
+                    _ => return Err(at("a normal needs x, y and z")),
+                },
+                Some("f") => {
+                    // 1-based, or negative counting back from the last one
+                    let index = |s: Option<&str>, len: usize| -> Result<Option<usize>, String> {
+                        match s.filter(|s| !s.is_empty()) {
+                            None => Ok(None),
+                            Some(s) => match s.parse::<i64>() {
+                                Ok(i) if i > 0 && i as usize <= len => Ok(Some(i as usize - 1)),
+                                Ok(i) if i < 0 && (-i) as usize <= len => {
+                                    Ok(Some(len - (-i) as usize))
+                                }
+                                _ => Err(at("face index out of range")),
+                            },
+                        }
+                    };
+                    let mut polygon = Vec::new();
+                    for corner in words {
+                        let mut parts = corner.split('/');
+                        polygon.push((
+                            index(parts.next(), obj.positions.len())?
+                                .ok_or_else(|| at("face without vertex"))?,
+                            index(parts.next(), obj.uvs.len())?,
+                            index(parts.next(), obj.normals.len())?,
+                        ));
+                    }
+                    if polygon.len() < 3 {
+                        return Err(at("a face needs at least three vertices"));
+                    }
+                    obj.polygons.push(polygon);
+                }
+                _ => {}
+            }
+        }
+        if obj.polygons.is_empty() {
+            return Err(format!("{}: no faces", path));
+        }
+        Ok(obj)
+    }
+}
+
+/// Area-weighted vertex normals of the triangles `faces`, indexing `positions`.
+pub(crate) fn smooth_normals(
+    positions: &[Vector3],
+    faces: impl Iterator<Item = [usize; 3]>,
+) -> Vec<Vector3> {
+    // the cross products weight the face normals by area
+    let mut sums = vec![Vector3(0.0, 0.0, 0.0); positions.len()];
+    for f in faces {
+        let [a, b, c] = [positions[f[0]], positions[f[1]], positions[f[2]]];
+        let normal = (b - a).cross(&(c - a));
+        for k in f {
+            sums[k] += normal;
+        }
+    }
+    sums.into_iter().map(Vector3::normalize).collect()
+}
+
+/// The normal at each corner of the triangles `faces`, indexing
+/// `positions`: the area-weighted normal of the faces sharing the corner's
+/// position that meet its face at less than `angle` degrees.
+fn crease_normals(positions: &[Vector3], faces: &[[usize; 3]], angle: f64) -> Vec<[Vector3; 3]> {
+    let weighted: Vec<Vector3> = faces
+        .iter()
+        .map(|f| {
+            let [a, b, c] = [positions[f[0]], positions[f[1]], positions[f[2]]];
+            (b - a).cross(&(c - a))
+        })
+        .collect();
+    let mut around = vec![Vec::new(); positions.len()];
+    for (k, f) in faces.iter().enumerate() {
+        for &p in f {
+            around[p].push(k);
+        }
+    }
+    let cos_angle = angle.to_radians().cos();
+    faces
+        .iter()
+        .enumerate()
+        .map(|(k, f)| {
+            let own = weighted[k].normalize();
+            // a face without area takes any normal around it
+            let any = angle >= 180.0 || weighted[k].length_squared() == 0.0;
+            f.map(|p| {
+                let sum = around[p]
+                    .iter()
+                    .filter(|&&g| any || g == k || weighted[g].normalize().dot(&own) >= cos_angle)
+                    .fold(Vector3(0.0, 0.0, 0.0), |sum, &g| sum + weighted[g]);
+                sum.normalize()
+            })
+        })
+        .collect()
+}
+
+/// Triangles sharing vertex buffers, with a BVH of their own so that meshes
+/// of any size count as a single object in the scene.
+#[derive(Debug)]
+pub struct Mesh {
+    pub(crate) positions: Vec<Vector3>,
+    /// Per vertex normals for smooth shading; empty for flat faces.
+    pub(crate) normals: Vec<Vector3>,
+    /// Per vertex texture coordinates; empty to use barycentric coordinates.
+    pub(crate) uvs: Vec<(f64, f64)>,
+    /// Per vertex colors in linear sRGB, for vertex color textures; empty
+    /// for white.
+    pub(crate) colors: Vec<Vector3>,
+    /// Vertex indices of each triangle, counterclockwise seen from outside.
+    pub(crate) faces: Vec<[usize; 3]>,
+    pub(crate) material: Material,
+    bvh: Bvh,
+}
+
+impl Mesh {
+    pub fn new(
+        positions: Vec<Vector3>,
+        normals: Vec<Vector3>,
+        uvs: Vec<(f64, f64)>,
+        faces: Vec<[usize; 3]>,
+        material: Material,
+    ) -> Self {
+        let bvh = Bvh::new(faces.iter().map(|f| {
+            Some(triangle_bounds([
+                positions[f[0]],
+                positions[f[1]],
+                positions[f[2]],
+            ]))
+        }));
+        Self {
+            positions,
+            normals,
+            uvs,
+            colors: Vec::new(),
+            faces,
+            material,
+            bvh,
+        }
+    }
+
+    /// The mesh with the vertex colors `colors`, one per position.
+    pub fn with_colors(self, colors: Vec<Vector3>) -> Self {
+        assert_eq!(colors.len(), self.positions.len());
+        Self { colors, ..self }
+    }
+
+    /// Builds a mesh from an OBJ file. Polygons are split into fans of
+    /// triangles. Vertex normals are used if every face has them, otherwise
+    /// faces are flat unless `smooth` gives a smoothing angle in degrees:
+    /// each corner then gets the area-weighted normal of the faces around it
+    /// that meet its face at less than that angle, so edges sharper than it
+    /// stay creased. Vertex colors are kept if any vertex has one, white for
+    /// those without.
+    pub fn from_obj(obj: &ObjFile, material: Material, smooth: Option<f64>) -> Self {
+        // OBJ indexes positions, texture coordinates and normals separately;
+        // every distinct combination becomes one vertex
+        let mut corners: Vec<ObjCorner> = Vec::new();
+        let mut vertex_of = std::collections::HashMap::new();
+        let mut faces = Vec::new();
+        for polygon in &obj.polygons {
+            let polygon: Vec<usize> = polygon
+                .iter()
+                .map(|&key| {
+                    *vertex_of.entry(key).or_insert_with(|| {
+                        corners.push(key);
+                        corners.len() - 1
+                    })
+                })
+                .collect();
+            for k in 1..polygon.len() - 1 {
+                faces.push([polygon[0], polygon[k], polygon[k + 1]]);
+            }
+        }
+        let mut normals = Vec::new();
+        if corners.iter().all(|c| c.2.is_some()) {
+            normals = corners.iter().map(|c| obj.normals[c.2.unwrap()]).collect();
+        } else if let Some(angle) = smooth {
+            let corner_normals = crease_normals(
+                &obj.positions,
+                &faces
+                    .iter()
+                    .map(|f| f.map(|k| corners[k].0))
+                    .collect::<Vec<_>>(),
+                angle,
+            );
+            // corners of one vertex with different normals become vertices
+            // of their own
+            let mut split = std::collections::HashMap::new();
+            let mut split_corners = Vec::new();
+            for (f, face) in faces.iter_mut().enumerate() {
+                for (k, vertex) in face.iter_mut().enumerate() {
+                    let n = corner_normals[f][k];
+                    let key = (*vertex, [n.x(), n.y(), n.z()].map(f64::to_bits));
+                    *vertex = *split.entry(key).or_insert_with(|| {
+                        split_corners.push(corners[*vertex]);
+                        normals.push(n);
+                        split_corners.len() - 1
+                    });
+                }
+            }
+            corners = split_corners;
+        }
+        let positions: Vec<Vector3> = corners.iter().map(|c| obj.positions[c.0]).collect();
+        let uvs = if corners.iter().all(|c| c.1.is_some()) {
+            corners.iter().map(|c| obj.uvs[c.1.unwrap()]).collect()
+        } else {
+            Vec::new()
+        };
+        let mesh = Self::new(positions, normals, uvs, faces, material);
+        if obj.colors.iter().all(Option::is_none) {
+            return mesh;
+        }
+        let colors = corners
+            .iter()
+            .map(|c| {
+                obj.colors[c.0].map_or(Vector3(1.0, 1.0, 1.0), |c| {
+                    Vector3(srgb_decode(c.x()), srgb_decode(c.y()), srgb_decode(c.z()))
+                })
+            })
+            .collect();
+        mesh.with_colors(colors)
+    }
+
+    /// Scales the mesh by `scale` and then moves it by `offset`.
+    pub fn place(mut self, scale: f64, offset: Vector3) -> Self {
+        for p in self.positions.iter_mut() {
+            *p = *p * scale + offset;
+        }
+        let mesh = Self::new(
+            self.positions,
+            self.normals,
+            self.uvs,
+            self.faces,
+            self.material,
+        );
+        Self {
+            colors: self.colors,
+            ..mesh
+        }
+    }
+
+    fn hit_face(&self, face: usize, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let f = self.faces[face];
+        let vertices = [
+            self.positions[f[0]],
+            self.positions[f[1]],
+            self.positions[f[2]],
+        ];
+        let (t, beta, gamma) = intersect_triangle(r, vertices, t_min, t_max)?;
+        let alpha = 1.0 - beta - gamma;
+        let [a, b, c] = vertices;
+        let geometric = (b - a).cross(&(c - a)).normalize();
+        let (u, v, tangent) = if self.uvs.is_empty() {
+            (beta, gamma, b - a)
+        } else {
+            let [ua, ub, uc] = [self.uvs[f[0]], self.uvs[f[1]], self.uvs[f[2]]];
+            // solve the edges for the direction of growing u
+            let (du1, dv1, du2, dv2) = (ub.0 - ua.0, ub.1 - ua.1, uc.0 - ua.0, uc.1 - ua.1);
+            let determinant = du1 * dv2 - du2 * dv1;
+            let tangent = if determinant.abs() < 1e-12 {
+                b - a
+            } else {
+                ((b - a) * dv2 - (c - a) * dv1) / determinant
+            };
+            (
+                alpha * ua.0 + beta * ub.0 + gamma * uc.0,
+                alpha * ua.1 + beta * ub.1 + gamma * uc.1,
+                tangent,
+            )
+        };
+        let mut i = Intersection {
+            p: r.at(t),
+            normal: Vector3(0.0, 0.0, 0.0),
+            material: self.material,
+            t,
+            u,
+            v,
+            front_facing: false,
+            object: 0,
+            tangent,
+            color: if self.colors.is_empty() {
+                Vector3(1.0, 1.0, 1.0)
+            } else {
+                self.colors[f[0]] * alpha + self.colors[f[1]] * beta + self.colors[f[2]] * gamma
+            },
+        };
+        i.set_face_normal(r, &geometric);
+        if !self.normals.is_empty() {
+            let shading = (self.normals[f[0]] * alpha
+                + self.normals[f[1]] * beta
+                + self.normals[f[2]] * gamma)
+                .normalize();
+            // keep the side decided by the geometric normal
+            i.normal = if i.front_facing {
+                shading
+            } else {
+                shading * -1.0
+            };
+        }
+        Some(i)
+    }
+}
+
+/// One step of Catmull-Clark subdivision of the polygons `polygons` over
+/// `positions`: every n-gon becomes n quads, open borders are kept as creases.
+fn catmull_clark(
+    positions: &[Vector3],
+    polygons: &[Vec<usize>],
+) -> (Vec<Vector3>, Vec<Vec<usize>>) {
+    let zero = Vector3(0.0, 0.0, 0.0);
+    let face_points: Vec<Vector3> = polygons
+        .iter()
+        .map(|p| p.iter().fold(zero, |acc, &v| acc + positions[v]) / p.len() as f64)
+        .collect();
+    // faces around each edge, keyed by its vertices in ascending order, and
+    // the edges in order of appearance
+    let key = |a: usize, b: usize| (a.min(b), a.max(b));
+    let mut edge_faces = std::collections::HashMap::new();
+    let mut edges = Vec::new();
+    for (f, p) in polygons.iter().enumerate() {
+        for k in 0..p.len() {
+            let edge = key(p[k], p[(k + 1) % p.len()]);
+            edge_faces
+                .entry(edge)
+                .or_insert_with(|| {
+                    edges.push(edge);
+                    Vec::new()
+                })
+                .push(f);
+        }
+    }
+
+    let n = positions.len();
+    let (mut face_sum, mut face_count) = (vec![zero; n], vec![0usize; n]);
+    for (f, p) in polygons.iter().enumerate() {
+        for &v in p {
+            face_sum[v] += face_points[f];
+            face_count[v] += 1;
+        }
+    }
+    let (mut edge_sum, mut edge_count) = (vec![zero; n], vec![0; n]);
+    let (mut border_sum, mut border_count) = (vec![zero; n], vec![0usize; n]);
+    for &(a, b) in &edges {
+        let middle = (positions[a] + positions[b]) * 0.5;
+        for v in [a, b].iter().copied() {
+            edge_sum[v] += middle;
+            edge_count[v] += 1;
+        }
+        if edge_faces[&(a, b)].len() == 1 {
+            border_sum[a] += positions[b];
+            border_sum[b] += positions[a];
+            border_count[a] += 1;
+            border_count[b] += 1;
+        }
+    }
+
+    // original points moved, then face points, then edge points
+    let mut new_positions: Vec<Vector3> = (0..n)
+        .map(|v| match (border_count[v], face_count[v]) {
+            (2, _) => (positions[v] * 6.0 + border_sum[v]) / 8.0,
+            // corners, unused and non-manifold vertices stay
+            (1, _) | (3.., _) | (0, 0) => positions[v],
+            (0, k) => {
+                let k = k as f64;
+                let faces = face_sum[v] / k;
+                let edges = edge_sum[v] / edge_count[v] as f64;
+                (faces + edges * 2.0 + positions[v] * (k - 3.0)) / k
+            }
+        })
+        .collect();
+    new_positions.extend(face_points.iter().copied());
+    let mut edge_point = std::collections::HashMap::new();
+    for &(a, b) in &edges {
+        let faces = &edge_faces[&(a, b)];
+        let point = if faces.len() == 2 {
+            (positions[a] + positions[b] + face_points[faces[0]] + face_points[faces[1]]) / 4.0
+        } else {
+            (positions[a] + positions[b]) * 0.5
+        };
+        edge_point.insert((a, b), new_positions.len());
+        new_positions.push(point);
+    }
+
+    let mut quads = Vec::new();
+    for (f, p) in polygons.iter().enumerate() {
+        let len = p.len();
+        for k in 0..len {
+            let (prev, v, next) = (p[(k + len - 1) % len], p[k], p[(k + 1) % len]);
+            quads.push(vec![
+                v,
+                edge_point[&key(v, next)],
+                n + f,
+                edge_point[&key(prev, v)],
+            ]);
+        }
+    }
+    (new_positions, quads)
+}
+
+/// A Catmull-Clark subdivision surface over a polygonal control cage,
+/// tessellated into a smooth mesh either a fixed number of `levels` deep or,
+/// with `edge_pixels`, until the cage's edges would span at most that many
+/// pixels in the view being rendered.
+#[derive(Debug)]
+pub struct SubdivisionSurface {
+    cage: Vec<Vector3>,
+    polygons: Vec<Vec<usize>>,
+    levels: u32,
+    edge_pixels: Option<f64>,
+    material: Material,
+    mesh: Mesh,
+}
+
+impl SubdivisionSurface {
+    /// Deepest subdivision, which splits every quad into 4^8 triangle pairs.
+    pub(crate) const MAX_LEVELS: u32 = 8;
+
+    pub fn new(
+        cage: Vec<Vector3>,
+        polygons: Vec<Vec<usize>>,
+        levels: u32,
+        edge_pixels: Option<f64>,
+        material: Material,
+    ) -> Self {
+        let mesh = Self::tessellation(&cage, &polygons, levels, material);
+        Self {
+            cage,
+            polygons,
+            levels,
+            edge_pixels,
+            material,
+            mesh,
+        }
+    }
+
+    fn tessellation(
+        cage: &[Vector3],
+        polygons: &[Vec<usize>],
+        levels: u32,
+        material: Material,
+    ) -> Mesh {
+        let (mut positions, mut polygons) = (cage.to_vec(), polygons.to_vec());
+        for _ in 0..levels {
+            let (p, q) = catmull_clark(&positions, &polygons);
+            positions = p;
+            polygons = q;
+        }
+        let faces: Vec<[usize; 3]> = polygons
+            .iter()
+            .flat_map(|p| (1..p.len() - 1).map(move |k| [p[0], p[k], p[k + 1]]))
+            .collect();
+        let normals = smooth_normals(&positions, faces.iter().copied());
+        Mesh::new(positions, normals, Vec::new(), faces, material)
+    }
+}
+
+impl Hittable for SubdivisionSurface {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.mesh.hit(r, t_min, t_max)
+    }
+
+    fn tessellate(&mut self, view: &CameraView, image_height: u32) {
+        let edge_pixels = match self.edge_pixels {
+            Some(e) => e,
+            None => return,
+        };
+        // pixels per scene unit at distance 1
+        let scale = image_height as f64 / (2.0 * (view.vfov.to_radians() / 2.0).tan());
+        let longest = self
+            .polygons
+            .iter()
+            .flat_map(|p| (0..p.len()).map(move |k| (p[k], p[(k + 1) % p.len()])))
+            .map(|(a, b)| {
+                let (a, b) = (self.cage[a], self.cage[b]);
+                let distance = ((a + b) * 0.5 - view.from).length().max(1e-6);
+                (b - a).length() * scale / distance
+            })
+            .fold(0.0, f64::max);
+        // every level halves the edges
+        let levels = (longest / edge_pixels)
+            .log2()
+            .ceil()
+            .clamp(0.0, Self::MAX_LEVELS as f64) as u32;
+        if levels != self.levels {
+            self.levels = levels;
+            self.mesh = Self::tessellation(&self.cage, &self.polygons, levels, self.material);
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        "subdivision"
+    }
+
+    /// The triangles of the current level.
+    fn triangles(&self) -> Vec<Facet> {
+        self.mesh.triangles()
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![self.material]
+    }
+
+    /// The surface stays within the convex hull of the cage, so the cage's box
+    /// holds at every level.
+    fn bounding_box(&self) -> Option<Aabb> {
+        let first = Aabb {
+            min: self.cage[0],
+            max: self.cage[0],
+        };
+        Some(
+            self.cage
+                .iter()
+                .fold(first, |acc, &p| acc.union(&Aabb { min: p, max: p })),
+        )
+    }
+
+    fn heap_bytes(&self) -> usize {
+        self.cage.len() * std::mem::size_of::<Vector3>()
+            + self
+                .polygons
+                .iter()
+                .map(|p| p.len() * std::mem::size_of::<usize>())
+                .sum::<usize>()
+            + self.mesh.heap_bytes()
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.bvh.hit(r, t_min, t_max, |face, closest| {
+            self.hit_face(face, r, t_min, closest)
+        })
+    }
+
+    fn kind(&self) -> &'static str {
+        "mesh"
+    }
+
+    fn triangles(&self) -> Vec<Facet> {
+        self.faces
+            .iter()
+            .map(|face| {
+                let flat = Facet::flat(face.map(|k| self.positions[k]), self.material);
+                if self.normals.is_empty() {
+                    flat
+                } else {
+                    Facet {
+                        normals: face.map(|k| self.normals[k]),
+                        ..flat
+                    }
+                }
+            })
+            .collect()
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![self.material]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounds()
+    }
+
+    fn heap_bytes(&self) -> usize {
+        use std::mem::size_of;
+        self.positions.len() * size_of::<Vector3>()
+            + self.normals.len() * size_of::<Vector3>()
+            + self.uvs.len() * size_of::<(f64, f64)>()
+            + self.colors.len() * size_of::<Vector3>()
+            + self.faces.len() * size_of::<[usize; 3]>()
+            + self.bvh.nodes.len() * size_of::<BvhNode>()
+            + self.bvh.wide.len() * size_of::<WideNode>()
+            + self.bvh.order.len() * size_of::<usize>()
+    }
+}
@@ -0,0 +1,105 @@
+//! Portals: pairs of windows joined so that rays entering one come out of the
+//! other, for spaces that could not exist.
+
+use crate::hittable::quad::{intersect_parallelogram, parallelogram_bounds};
+use crate::hittable::{Aabb, Hittable, Intersection};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+
+/// A parallelogram spanned by `u` and `v` from `corner`, facing along `u × v`.
+#[derive(Debug, Copy, Clone)]
+pub struct Window {
+    pub corner: Vector3,
+    pub u: Vector3,
+    pub v: Vector3,
+}
+
+impl Window {
+    fn intersect(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        intersect_parallelogram(r, self.corner, self.u, self.v, t_min, t_max)
+    }
+
+    /// Unit vectors along `u`, across it in the plane and along the normal.
+    fn frame(&self) -> [Vector3; 3] {
+        let normal = self.u.cross(&self.v).normalize();
+        let along = self.u.normalize();
+        [along, normal.cross(&along), normal]
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        parallelogram_bounds(self.corner, self.u, self.v)
+    }
+}
+
+/// Carries a ray that reaches window `from` at coordinates `(a, b)` through to
+/// `to`, returning its new origin and direction. The windows are glued back to
+/// back, turned half around their `v` axis: rays entering the front of one
+/// leave the front of the other, with `u` running the other way.
+pub(crate) fn pass_through(
+    from: &Window,
+    to: &Window,
+    a: f64,
+    b: f64,
+    direction: Vector3,
+) -> (Vector3, Vector3) {
+    let origin = to.corner + to.u * (1.0 - a) + to.v * b;
+    let [e0, e1, e2] = from.frame();
+    let [f0, f1, f2] = to.frame();
+    let direction = f0 * -direction.dot(&e0) + f1 * direction.dot(&e1) + f2 * -direction.dot(&e2);
+    (origin, direction)
+}
+
+/// Two windows leading into each other, like a doorway between distant
+/// places. Both sides of each window are open.
+#[derive(Debug, Copy, Clone)]
+pub struct Portal {
+    pub a: Window,
+    pub b: Window,
+}
+
+impl Hittable for Portal {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let hit_a = self.a.intersect(r, t_min, t_max);
+        let closest = hit_a.map_or(t_max, |(t, _, _)| t);
+        let (from, to, (t, u, v)) = match self.b.intersect(r, t_min, closest) {
+            Some(hit) => (self.b, self.a, hit),
+            None => (self.a, self.b, hit_a?),
+        };
+        let mut i = Intersection {
+            p: r.at(t),
+            normal: Vector3(0.0, 0.0, 0.0),
+            material: Material::Portal { from, to },
+            t,
+            u,
+            v,
+            front_facing: false,
+            object: 0,
+            tangent: Vector3(0.0, 0.0, 0.0),
+            color: Vector3(1.0, 1.0, 1.0),
+        };
+        i.set_face_normal(r, &from.frame()[2]);
+        Some(i)
+    }
+
+    fn kind(&self) -> &'static str {
+        "portal"
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![
+            Material::Portal {
+                from: self.a,
+                to: self.b,
+            },
+            Material::Portal {
+                from: self.b,
+                to: self.a,
+            },
+        ]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.a.bounding_box().union(&self.b.bounding_box()))
+    }
+}
@@ -0,0 +1,235 @@
+//! Flat parallelograms and the axis-aligned boxes built from them.
+
+use crate::hittable::{Aabb, Facet, Hittable, Intersection};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{random, Vector3};
+
+/// Ray parameter and coordinates along `u` and `v`, in [0, 1], where `r`
+/// crosses the parallelogram spanned by `u` and `v` from `corner`.
+pub(crate) fn intersect_parallelogram(
+    r: &Ray,
+    corner: Vector3,
+    u: Vector3,
+    v: Vector3,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    let n = u.cross(&v);
+    let denominator = n.dot(&r.direction);
+    if denominator.abs() < 1e-12 {
+        return None;
+    }
+    let t = n.dot(&(corner - r.origin)) / denominator;
+    if !(t_min..=t_max).contains(&t) {
+        return None;
+    }
+    let q = r.at(t) - corner;
+    let w = n / n.dot(&n);
+    let (a, b) = (w.dot(&q.cross(&v)), w.dot(&u.cross(&q)));
+    ((0.0..=1.0).contains(&a) && (0.0..=1.0).contains(&b)).then_some((t, a, b))
+}
+
+/// Bounds of the parallelogram spanned by `u` and `v` from `corner`.
+pub(crate) fn parallelogram_bounds(corner: Vector3, u: Vector3, v: Vector3) -> Aabb {
+    [corner + u, corner + v, corner + u + v].iter().fold(
+        Aabb {
+            min: corner,
+            max: corner,
+        },
+        |acc, p| acc.union(&Aabb { min: *p, max: *p }),
+    )
+}
+
+/// A parallelogram spanned by `u` and `v` from `corner`, its front facing
+/// along `u × v`. Texture coordinates run along `u` and `v`.
+#[derive(Debug, Copy, Clone)]
+pub struct Quad {
+    pub corner: Vector3,
+    pub u: Vector3,
+    pub v: Vector3,
+    pub material: Material,
+}
+
+impl Hittable for Quad {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let (t, u, v) = intersect_parallelogram(r, self.corner, self.u, self.v, t_min, t_max)?;
+        let mut i = Intersection {
+            p: r.at(t),
+            normal: Vector3(0.0, 0.0, 0.0),
+            material: self.material,
+            t,
+            u,
+            v,
+            front_facing: false,
+            object: 0,
+            tangent: self.u,
+            color: Vector3(1.0, 1.0, 1.0),
+        };
+        i.set_face_normal(r, &self.u.cross(&self.v).normalize());
+        Some(i)
+    }
+
+    fn surface_at(&self, u: f64, v: f64) -> Option<(Vector3, Vector3)> {
+        Some((
+            self.corner + self.u * u + self.v * v,
+            self.u.cross(&self.v).normalize(),
+        ))
+    }
+
+    /// Uniform over the area of the quad, unless it glows through an image:
+    /// then by the brightness of its texels.
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        let (t, a, b) =
+            match intersect_parallelogram(r, self.corner, self.u, self.v, 0.001, f64::INFINITY) {
+                Some(hit) => hit,
+                None => return 0.0,
+            };
+        let normal = self.u.cross(&self.v);
+        let length = r.direction.length();
+        let distance_squared = t * t * r.direction.length_squared();
+        let cos = (r.direction.dot(&normal) / (length * normal.length())).abs();
+        let density = match self.material.emission_image() {
+            Some(image) => image.texels().pdf(a, b),
+            None => 1.0,
+        };
+        density * distance_squared / (cos * normal.length())
+    }
+
+    fn random_direction(&self, origin: &Vector3) -> Option<Vector3> {
+        let (a, b) = match self.material.emission_image() {
+            Some(image) => image.texels().sample(),
+            None => (random(), random()),
+        };
+        Some(self.corner + self.u * a + self.v * b - *origin)
+    }
+
+    fn random_point(&self) -> Option<(Vector3, Vector3, f64)> {
+        let normal = self.u.cross(&self.v);
+        let p = self.corner + self.u * random() + self.v * random();
+        Some((p, normal.normalize(), normal.length()))
+    }
+
+    fn kind(&self) -> &'static str {
+        "quad"
+    }
+
+    fn triangles(&self) -> Vec<Facet> {
+        let (a, b, c, d) = (
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.u + self.v,
+            self.corner + self.v,
+        );
+        vec![
+            Facet::flat([a, b, c], self.material),
+            Facet::flat([a, c, d], self.material),
+        ]
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![self.material]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(parallelogram_bounds(self.corner, self.u, self.v))
+    }
+}
+
+/// An axis-aligned box from `min` to `max`, six quads facing outwards.
+#[derive(Debug, Copy, Clone)]
+pub struct Cuboid {
+    sides: [Quad; 6],
+    min: Vector3,
+    max: Vector3,
+}
+
+impl Cuboid {
+    pub fn new(min: Vector3, max: Vector3, material: Material) -> Self {
+        let d = max - min;
+        let (dx, dy, dz) = (
+            Vector3(d.x(), 0.0, 0.0),
+            Vector3(0.0, d.y(), 0.0),
+            Vector3(0.0, 0.0, d.z()),
+        );
+        let quad = |corner: Vector3, u: Vector3, v: Vector3| Quad {
+            corner,
+            u,
+            v,
+            material,
+        };
+        Self {
+            sides: [
+                quad(min + dz, dx, dy),
+                quad(min + dx + dz, dz * -1.0, dy),
+                quad(max - dy - dz, dx * -1.0, dy),
+                quad(min, dz, dy),
+                quad(min + dy + dz, dx, dz * -1.0),
+                quad(min, dx, dz),
+            ],
+            min,
+            max,
+        }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        self.sides
+            .iter()
+            .fold((None, t_max), |(closest, t_max), side| {
+                match side.hit(r, t_min, t_max) {
+                    Some(i) => (Some(i), i.t),
+                    None => (closest, t_max),
+                }
+            })
+            .0
+    }
+
+    /// Each side picked with equal chance.
+    fn pdf_value(&self, r: &Ray) -> f64 {
+        self.sides.iter().map(|side| side.pdf_value(r)).sum::<f64>() / 6.0
+    }
+
+    fn random_direction(&self, origin: &Vector3) -> Option<Vector3> {
+        self.sides[(random() * 6.0) as usize % 6].random_direction(origin)
+    }
+
+    /// Sides picked by their area.
+    fn random_point(&self) -> Option<(Vector3, Vector3, f64)> {
+        let areas = self.sides.map(|side| side.u.cross(&side.v).length());
+        let total: f64 = areas.iter().sum();
+        let mut pick = random() * total;
+        for (side, area) in self.sides.iter().zip(areas) {
+            if pick < area {
+                let (p, normal, _) = side.random_point()?;
+                return Some((p, normal, total));
+            }
+            pick -= area;
+        }
+        let (p, normal, _) = self.sides[5].random_point()?;
+        Some((p, normal, total))
+    }
+
+    fn kind(&self) -> &'static str {
+        "box"
+    }
+
+    fn triangles(&self) -> Vec<Facet> {
+        self.sides
+            .iter()
+            .flat_map(|side| side.triangles())
+            .collect()
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![self.sides[0].material]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb {
+            min: self.min,
+            max: self.max,
+        })
+    }
+}
@@ -0,0 +1,81 @@
+//! Scattering copies of an object over a surface, like pebbles on the ground
+//! or grass on a terrain.
+
+use crate::hittable::Hittable;
+use crate::ray::Ray;
+use crate::texture::Texture;
+use crate::vec3::Vector3;
+use rand::{Rng, SeedableRng};
+
+/// Where and how large the instances of a scatter are. Candidate spots are
+/// drawn uniformly over `region` (x0, z0, x1, z1) and dropped straight down
+/// onto the target surface; the luminance of `density` at each hit is the
+/// chance of keeping it.
+#[derive(Clone, Copy, Debug)]
+pub struct Scatter {
+    pub region: (f64, f64, f64, f64),
+    pub count: usize,
+    pub density: Texture,
+    /// Range of the random scale of each instance.
+    pub scale: (f64, f64),
+    /// Multiplies the scale by its luminance, if given.
+    pub scale_map: Option<Texture>,
+    /// Largest random turn of an instance around the vertical, in degrees.
+    pub rotation: f64,
+    pub seed: u64,
+}
+
+/// An instance placed by a `Scatter`.
+#[derive(Clone, Copy, Debug)]
+pub struct Placement {
+    /// Point on the target surface the instance stands on.
+    pub position: Vector3,
+    pub normal: Vector3,
+    pub scale: f64,
+    /// Turn around the vertical, in degrees.
+    pub rotation: f64,
+}
+
+fn luminance(c: Vector3) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+impl Scatter {
+    /// Places the instances on `target`, the same ones for the same seed.
+    pub fn place(&self, target: &dyn Hittable) -> Vec<Placement> {
+        let (x0, z0, x1, z1) = self.region;
+        let top = target.bounding_box().map_or(1e4, |b| b.max.y().min(1e4)) + 1.0;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(self.seed);
+        let mut placements = Vec::new();
+        for _ in 0..self.count {
+            let (x, z) = (rng.gen_range(x0..=x1), rng.gen_range(z0..=z1));
+            // drawn before the checks below so that every candidate uses the
+            // same number of random values
+            let (keep, scale, rotation): (f64, f64, f64) = (rng.gen(), rng.gen(), rng.gen());
+            let down = Ray {
+                origin: Vector3(x, top, z),
+                direction: Vector3(0.0, -1.0, 0.0),
+                time: 0.0,
+            };
+            let hit = match target.hit(&down, 0.0, f64::INFINITY) {
+                Some(hit) => hit,
+                None => continue,
+            };
+            if keep >= luminance(self.density.value(hit.u, hit.v, &hit.p)) {
+                continue;
+            }
+            let (low, high) = self.scale;
+            let factor = self
+                .scale_map
+                .map_or(1.0, |map| luminance(map.value(hit.u, hit.v, &hit.p)));
+            placements.push(Placement {
+                position: hit.p,
+                // facing the ray, so up
+                normal: hit.normal,
+                scale: (low + (high - low) * scale) * factor,
+                rotation: rotation * self.rotation,
+            });
+        }
+        placements
+    }
+}
@@ -0,0 +1,235 @@
+//! Procedural terrain and the noise it is made of.
+
+use crate::hittable::{grid_facets, Aabb, Facet, Hittable, Intersection};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+
+/// Finds the first crossing of a height field along the ray within [t0, t1].
+/// `above` is the signed height of a point over the surface and `max_slope`
+/// bounds the surface gradient, which limits how fast `above` can change and
+/// so how far the march may safely step.
+pub(crate) fn march_height_field(
+    r: &Ray,
+    t0: f64,
+    t1: f64,
+    max_slope: f64,
+    above: impl Fn(Vector3) -> f64,
+) -> Option<f64> {
+    let horizontal = (r.direction.x().powi(2) + r.direction.z().powi(2)).sqrt();
+    let rate = r.direction.y().abs() + max_slope * horizontal;
+    let min_step = (t1 - t0) / 4096.0;
+    let f = |t: f64| above(r.at(t));
+    let (mut a_t, mut a_f) = (t0, f(t0));
+    while a_t < t1 {
+        let b_t = (a_t + (a_f.abs() / rate).max(min_step)).min(t1);
+        let b_f = f(b_t);
+        if a_f.signum() != b_f.signum() {
+            let (mut lo, mut hi) = (a_t, b_t);
+            for _ in 0..24 {
+                let mid = 0.5 * (lo + hi);
+                if f(mid).signum() == a_f.signum() {
+                    lo = mid;
+                } else {
+                    hi = mid;
+                }
+            }
+            return Some(0.5 * (lo + hi));
+        }
+        a_t = b_t;
+        a_f = b_f;
+    }
+    None
+}
+
+/// Deterministic pseudo-random value in [-1, 1] for a lattice point.
+pub(crate) fn lattice_hash(x: i64, z: i64, seed: u32) -> f64 {
+    let mut h = (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (z as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (seed as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    h ^= h >> 33;
+    (h >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+}
+
+/// Smoothly interpolated 2D value noise in [-1, 1].
+fn value_noise(x: f64, z: f64, seed: u32) -> f64 {
+    let (x0, z0) = (x.floor(), z.floor());
+    let (fx, fz) = (x - x0, z - z0);
+    let (sx, sz) = (fx * fx * (3.0 - 2.0 * fx), fz * fz * (3.0 - 2.0 * fz));
+    let (ix, iz) = (x0 as i64, z0 as i64);
+    let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+    lerp(
+        lerp(
+            lattice_hash(ix, iz, seed),
+            lattice_hash(ix + 1, iz, seed),
+            sx,
+        ),
+        lerp(
+            lattice_hash(ix, iz + 1, seed),
+            lattice_hash(ix + 1, iz + 1, seed),
+            sx,
+        ),
+        sz,
+    )
+}
+
+/// Fractal Brownian motion: `octaves` layers of value noise, each at twice the
+/// frequency and half the amplitude of the previous one.
+pub(crate) fn fbm(x: f64, z: f64, octaves: u32, seed: u32) -> f64 {
+    (0..octaves)
+        .fold((0.0, 1.0, 0.5), |(sum, freq, amp), octave| {
+            (
+                sum + amp * value_noise(x * freq, z * freq, seed.wrapping_add(octave)),
+                freq * 2.0,
+                amp * 0.5,
+            )
+        })
+        .0
+}
+
+/// A square patch of fBm terrain: a gentle valley around the origin rising
+/// into mountains towards the edges. Steep slopes are rock, high flat areas
+/// snow and everything else grass.
+#[derive(Debug)]
+pub struct Terrain {
+    half_extent: f64,
+    seed: u32,
+    min_height: f64,
+    max_height: f64,
+    max_slope: f64,
+    grass: Material,
+    rock: Material,
+    snow: Material,
+}
+
+impl Terrain {
+    pub fn new(half_extent: f64, seed: u32) -> Self {
+        let mut terrain = Self {
+            half_extent,
+            seed,
+            min_height: 0.0,
+            max_height: 0.0,
+            max_slope: 0.0,
+            grass: Material::Lambertian {
+                albedo: Vector3(0.25, 0.4, 0.12).into(),
+            },
+            rock: Material::Lambertian {
+                albedo: Vector3(0.35, 0.32, 0.3).into(),
+            },
+            snow: Material::Lambertian {
+                albedo: Vector3(0.9, 0.9, 0.92).into(),
+            },
+        };
+        // sample the bounds and the steepest slope the march has to respect
+        let n = 256;
+        let cell = 2.0 * half_extent / n as f64;
+        let (mut lo, mut hi, mut slope) = (f64::INFINITY, f64::NEG_INFINITY, 0.0f64);
+        for i in 0..=n {
+            for k in 0..=n {
+                let x = -half_extent + i as f64 * cell;
+                let z = -half_extent + k as f64 * cell;
+                let h = terrain.height(x, z);
+                lo = lo.min(h);
+                hi = hi.max(h);
+                let (dx, dz) = terrain.gradient(x, z);
+                slope = slope.max((dx * dx + dz * dz).sqrt());
+            }
+        }
+        terrain.min_height = lo - 0.5;
+        terrain.max_height = hi + 0.5;
+        terrain.max_slope = slope * 1.5;
+        terrain
+    }
+
+    pub(crate) fn height(&self, x: f64, z: f64) -> f64 {
+        let r = (x * x + z * z).sqrt();
+        let t = ((r - 16.0) / 30.0).clamp(0.0, 1.0);
+        let mountains = t
+            * t
+            * (3.0 - 2.0 * t)
+            * 9.0
+            * (0.6 + 0.4 * fbm(x / 20.0, z / 20.0, 3, self.seed + 100));
+        0.6 * fbm(x / 6.0, z / 6.0, 6, self.seed) + mountains
+    }
+
+    pub(crate) fn gradient(&self, x: f64, z: f64) -> (f64, f64) {
+        let e = 0.01;
+        (
+            (self.height(x + e, z) - self.height(x - e, z)) / (2.0 * e),
+            (self.height(x, z + e) - self.height(x, z - e)) / (2.0 * e),
+        )
+    }
+
+    fn material_at(&self, p: &Vector3, normal: &Vector3) -> Material {
+        let snow_line = 5.0 + 1.5 * value_noise(p.x() / 4.0, p.z() / 4.0, self.seed + 200);
+        if normal.y() < 0.75 {
+            self.rock
+        } else if p.y() > snow_line {
+            self.snow
+        } else {
+            self.grass
+        }
+    }
+}
+
+impl Hittable for Terrain {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let (t0, t1) = self.bounding_box()?.clip(r, t_min, t_max)?;
+        let t = march_height_field(r, t0, t1, self.max_slope, |p| {
+            p.y() - self.height(p.x(), p.z())
+        })?;
+        let p = r.at(t);
+        let (dx, dz) = self.gradient(p.x(), p.z());
+        let normal = Vector3(-dx, 1.0, -dz).normalize();
+        let mut i = Intersection {
+            p,
+            normal,
+            material: self.material_at(&p, &normal),
+            t,
+            u: (p.x() / self.half_extent + 1.0) * 0.5,
+            v: (p.z() / self.half_extent + 1.0) * 0.5,
+            front_facing: false,
+            object: 0,
+            tangent: Vector3(1.0, 0.0, 0.0),
+            color: Vector3(1.0, 1.0, 1.0),
+        };
+        i.set_face_normal(r, &normal);
+        Some(i)
+    }
+
+    fn kind(&self) -> &'static str {
+        "terrain"
+    }
+
+    /// A 256 by 256 grid, each triangle of the material at its center.
+    fn triangles(&self) -> Vec<Facet> {
+        let at = |u: f64, v: f64| {
+            let (x, z) = (
+                (2.0 * u - 1.0) * self.half_extent,
+                (2.0 * v - 1.0) * self.half_extent,
+            );
+            let (dx, dz) = self.gradient(x, z);
+            Some((
+                Vector3(x, self.height(x, z), z),
+                Vector3(-dx, 1.0, -dz).normalize(),
+            ))
+        };
+        grid_facets(256, 256, at, |p| {
+            let (dx, dz) = self.gradient(p.x(), p.z());
+            self.material_at(p, &Vector3(-dx, 1.0, -dz).normalize())
+        })
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![self.grass, self.rock, self.snow]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb {
+            min: Vector3(-self.half_extent, self.min_height, -self.half_extent),
+            max: Vector3(self.half_extent, self.max_height, self.half_extent),
+        })
+    }
+}
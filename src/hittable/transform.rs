@@ -0,0 +1,226 @@
+//! Instances: one object shown again elsewhere, moved, turned or resized,
+//! without copying its geometry.
+
+use crate::hittable::{Aabb, Facet, Hittable, Intersection};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+use std::sync::Arc;
+
+/// An affine transform as a 4x4 matrix acting on column vectors; the last
+/// row is always 0, 0, 0, 1.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Matrix4(pub [[f64; 4]; 4]);
+
+impl Matrix4 {
+    pub const IDENTITY: Self = Self([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+
+    pub fn translation(offset: Vector3) -> Self {
+        let mut m = Self::IDENTITY;
+        m.0[0][3] = offset.x();
+        m.0[1][3] = offset.y();
+        m.0[2][3] = offset.z();
+        m
+    }
+
+    pub fn scaling(factors: Vector3) -> Self {
+        let mut m = Self::IDENTITY;
+        m.0[0][0] = factors.x();
+        m.0[1][1] = factors.y();
+        m.0[2][2] = factors.z();
+        m
+    }
+
+    /// Turns by `degrees` counterclockwise around `axis`, seen from its tip.
+    pub fn rotation(axis: Vector3, degrees: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let a = axis.normalize();
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        let c = 1.0 - cos;
+        Self([
+            [
+                cos + x * x * c,
+                x * y * c - z * sin,
+                x * z * c + y * sin,
+                0.0,
+            ],
+            [
+                y * x * c + z * sin,
+                cos + y * y * c,
+                y * z * c - x * sin,
+                0.0,
+            ],
+            [
+                z * x * c - y * sin,
+                z * y * c + x * sin,
+                cos + z * z * c,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotation_y(degrees: f64) -> Self {
+        Self::rotation(Vector3(0.0, 1.0, 0.0), degrees)
+    }
+
+    /// This transform applied after `first`.
+    pub fn then_after(&self, first: &Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.0[i][k] * first.0[k][j]).sum();
+            }
+        }
+        Self(m)
+    }
+
+    /// The inverse transform, `None` if the matrix flattens space.
+    pub fn inverse(&self) -> Option<Self> {
+        let m = &self.0;
+        let cofactor = |i: usize, j: usize| {
+            let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+            let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let determinant: f64 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let mut inverse = Self::IDENTITY;
+        for i in 0..3 {
+            for j in 0..3 {
+                inverse.0[i][j] = cofactor(j, i) / determinant;
+            }
+        }
+        let offset = inverse.vector(Vector3(m[0][3], m[1][3], m[2][3]));
+        for (i, o) in [offset.x(), offset.y(), offset.z()].iter().enumerate() {
+            inverse.0[i][3] = -o;
+        }
+        Some(inverse)
+    }
+
+    pub fn point(&self, p: Vector3) -> Vector3 {
+        self.vector(p) + Vector3(self.0[0][3], self.0[1][3], self.0[2][3])
+    }
+
+    /// Applies the transform to a direction, ignoring the translation.
+    pub fn vector(&self, v: Vector3) -> Vector3 {
+        let m = &self.0;
+        Vector3(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+
+    /// Applies the inverse transpose, which keeps normals perpendicular to
+    /// transformed surfaces; call it on the inverse.
+    fn normal(&self, n: Vector3) -> Vector3 {
+        let m = &self.0;
+        Vector3(
+            m[0][0] * n.x() + m[1][0] * n.y() + m[2][0] * n.z(),
+            m[0][1] * n.x() + m[1][1] * n.y() + m[2][1] * n.z(),
+            m[0][2] * n.x() + m[1][2] * n.y() + m[2][2] * n.z(),
+        )
+    }
+}
+
+/// A shared object placed by a transform. Rays are taken into the object's
+/// space and hits brought back, so any number of instances can show one
+/// mesh while its triangles and BVH are kept once. Objects tessellated by
+/// screen size keep the tessellation they have when instanced.
+#[derive(Debug, Clone)]
+pub struct Transform {
+    object: Arc<dyn Hittable>,
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Transform {
+    /// `None` if `matrix` cannot be inverted.
+    pub fn new(object: Arc<dyn Hittable>, matrix: Matrix4) -> Option<Self> {
+        Some(Self {
+            object,
+            inverse: matrix.inverse()?,
+            matrix,
+        })
+    }
+
+    pub fn translate(object: Arc<dyn Hittable>, offset: Vector3) -> Self {
+        Self::new(object, Matrix4::translation(offset)).unwrap()
+    }
+
+    /// Turns the object by `degrees` around the vertical through the origin.
+    pub fn rotate_y(object: Arc<dyn Hittable>, degrees: f64) -> Self {
+        Self::new(object, Matrix4::rotation_y(degrees)).unwrap()
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        // the direction is not normalized again, so the ray parameter is the
+        // same in both spaces
+        let local = Ray {
+            origin: self.inverse.point(r.origin),
+            direction: self.inverse.vector(r.direction),
+            time: r.time,
+        };
+        let i = self.object.hit(&local, t_min, t_max)?;
+        Some(Intersection {
+            p: self.matrix.point(i.p),
+            normal: self.inverse.normal(i.normal).normalize(),
+            tangent: self.matrix.vector(i.tangent),
+            ..i
+        })
+    }
+
+    fn surface_at(&self, u: f64, v: f64) -> Option<(Vector3, Vector3)> {
+        let (p, n) = self.object.surface_at(u, v)?;
+        Some((self.matrix.point(p), self.inverse.normal(n).normalize()))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let b = self.object.bounding_box()?;
+        let corners = (0..8).map(|k| {
+            let pick = |bit: usize, lo: f64, hi: f64| if k & bit == 0 { lo } else { hi };
+            self.matrix.point(Vector3(
+                pick(1, b.min.x(), b.max.x()),
+                pick(2, b.min.y(), b.max.y()),
+                pick(4, b.min.z(), b.max.z()),
+            ))
+        });
+        corners
+            .map(|p| Aabb { min: p, max: p })
+            .reduce(|acc, b| acc.union(&b))
+    }
+
+    fn kind(&self) -> &'static str {
+        "instance"
+    }
+
+    fn triangles(&self) -> Vec<Facet> {
+        self.object
+            .triangles()
+            .into_iter()
+            .map(|f| Facet {
+                corners: f.corners.map(|p| self.matrix.point(p)),
+                normals: f.normals.map(|n| self.inverse.normal(n).normalize()),
+                material: f.material,
+            })
+            .collect()
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        self.object.materials()
+    }
+
+    fn velocity(&self, time: f64) -> Vector3 {
+        self.matrix.vector(self.object.velocity(time))
+    }
+}
@@ -0,0 +1,150 @@
+//! An animated ocean surface.
+
+use crate::hittable::terrain::march_height_field;
+use crate::hittable::{grid_facets, Aabb, Facet, Hittable, Intersection};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vector3;
+
+#[derive(Debug, Copy, Clone)]
+struct Wave {
+    /// Unit direction of travel in the xz plane.
+    direction: (f64, f64),
+    wavenumber: f64,
+    amplitude: f64,
+    phase: f64,
+}
+
+/// A horizontal water surface at height `level`, displaced by a sum of
+/// travelling sine waves with deep-water dispersion. Intersections are found by
+/// ray marching the height field, so it works from above and below.
+#[derive(Debug)]
+pub struct WaterSurface {
+    level: f64,
+    /// Half the side length of the square patch of water around the origin.
+    half_extent: f64,
+    waves: Vec<Wave>,
+    /// Seconds into the animation.
+    time: f64,
+    material: Material,
+}
+
+impl WaterSurface {
+    /// Eight waves between 8 and 0.5 units long travelling roughly along
+    /// `wind`, scaled so their amplitudes add up to `amplitude`.
+    pub fn new(
+        level: f64,
+        half_extent: f64,
+        wind: (f64, f64),
+        amplitude: f64,
+        time: f64,
+        material: Material,
+    ) -> Self {
+        let count = 8;
+        let wind_angle = wind.1.atan2(wind.0);
+        let mut waves: Vec<Wave> = (0..count)
+            .map(|i| {
+                let wavelength = 8.0 * (0.5f64 / 8.0).powf(i as f64 / (count - 1) as f64);
+                // spread the directions deterministically so frames line up
+                let spread = ((i as f64 * 0.618_034).fract() - 0.5) * 1.4;
+                let angle = wind_angle + spread;
+                Wave {
+                    direction: (angle.cos(), angle.sin()),
+                    wavenumber: 2.0 * std::f64::consts::PI / wavelength,
+                    amplitude: wavelength,
+                    phase: i as f64 * 2.4,
+                }
+            })
+            .collect();
+        let total: f64 = waves.iter().map(|w| w.amplitude).sum();
+        for w in waves.iter_mut() {
+            w.amplitude *= amplitude / total;
+        }
+        Self {
+            level,
+            half_extent,
+            waves,
+            time,
+            material,
+        }
+    }
+
+    fn max_amplitude(&self) -> f64 {
+        self.waves.iter().map(|w| w.amplitude.abs()).sum()
+    }
+
+    /// Height of the surface and its derivatives along x and z.
+    fn height(&self, x: f64, z: f64) -> (f64, f64, f64) {
+        self.waves
+            .iter()
+            .fold((self.level, 0.0, 0.0), |(h, dx, dz), w| {
+                let omega = (9.81 * w.wavenumber).sqrt();
+                let arg = w.wavenumber * (w.direction.0 * x + w.direction.1 * z)
+                    - omega * self.time
+                    + w.phase;
+                let slope = w.amplitude * w.wavenumber * arg.cos();
+                (
+                    h + w.amplitude * arg.sin(),
+                    dx + slope * w.direction.0,
+                    dz + slope * w.direction.1,
+                )
+            })
+    }
+}
+
+impl Hittable for WaterSurface {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<Intersection> {
+        let (t0, t1) = self.bounding_box()?.clip(r, t_min, t_max)?;
+        let max_slope: f64 = self.waves.iter().map(|w| w.amplitude * w.wavenumber).sum();
+        let t = march_height_field(r, t0, t1, max_slope, |p| {
+            p.y() - self.height(p.x(), p.z()).0
+        })?;
+        let p = r.at(t);
+        let (_, dx, dz) = self.height(p.x(), p.z());
+        let mut i = Intersection {
+            p,
+            normal: Vector3(-dx, 1.0, -dz).normalize(),
+            material: self.material,
+            t,
+            u: p.x().rem_euclid(1.0),
+            v: p.z().rem_euclid(1.0),
+            front_facing: false,
+            object: 0,
+            tangent: Vector3(1.0, 0.0, 0.0),
+            color: Vector3(1.0, 1.0, 1.0),
+        };
+        let outward_normal = i.normal;
+        i.set_face_normal(r, &outward_normal);
+        Some(i)
+    }
+
+    fn kind(&self) -> &'static str {
+        "water surface"
+    }
+
+    /// A grid with cells of a tenth of the shortest wave, at most 512 by 512.
+    fn triangles(&self) -> Vec<Facet> {
+        let cells = (2.0 * self.half_extent / 0.05).clamp(1.0, 512.0) as usize;
+        let at = |u: f64, v: f64| {
+            let (x, z) = (
+                (2.0 * u - 1.0) * self.half_extent,
+                (2.0 * v - 1.0) * self.half_extent,
+            );
+            let (h, dx, dz) = self.height(x, z);
+            Some((Vector3(x, h, z), Vector3(-dx, 1.0, -dz).normalize()))
+        };
+        grid_facets(cells, cells, at, |_| self.material)
+    }
+
+    fn materials(&self) -> Vec<Material> {
+        vec![self.material]
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let a = self.max_amplitude();
+        Some(Aabb {
+            min: Vector3(-self.half_extent, self.level - a, -self.half_extent),
+            max: Vector3(self.half_extent, self.level + a, self.half_extent),
+        })
+    }
+}
@@ -0,0 +1,714 @@
+//! Reading and writing images.
+
+use crate::color::srgb_decode;
+use crate::render::{RenderMode, RenderSettings};
+use crate::vec3::Vector3;
+use std::io::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Stereo {
+    Anaglyph,
+    SideBySide,
+}
+
+impl std::str::FromStr for Stereo {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "anaglyph" => Ok(Stereo::Anaglyph),
+            "side-by-side" => Ok(Stereo::SideBySide),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Cuts the rectangle `x0..x1`, `y0..y1` out of an image `width` pixels wide.
+pub(crate) fn crop_pixels(
+    pixels: &[Vector3],
+    width: u32,
+    crop: (u32, u32, u32, u32),
+) -> Vec<Vector3> {
+    let (x0, y0, x1, y1) = crop;
+    pixels
+        .chunks(width as usize)
+        .skip(y0 as usize)
+        .take((y1 - y0) as usize)
+        .flat_map(|row| row[x0 as usize..x1 as usize].iter().copied())
+        .collect()
+}
+
+/// Combines left and right eye images into a red/cyan anaglyph of the same
+/// size, or places them next to each other in an image twice as wide.
+pub(crate) fn compose_stereo(
+    stereo: Stereo,
+    left: &[Vector3],
+    right: &[Vector3],
+    width: u32,
+) -> Vec<Vector3> {
+    match stereo {
+        Stereo::Anaglyph => left
+            .iter()
+            .zip(right)
+            .map(|(l, r)| Vector3(l.x(), r.y(), r.z()))
+            .collect(),
+        Stereo::SideBySide => left
+            .chunks(width as usize)
+            .zip(right.chunks(width as usize))
+            .flat_map(|(l, r)| l.iter().chain(r).copied())
+            .collect(),
+    }
+}
+
+/// Writes a P3 PPM of display values in [0, 1].
+pub(crate) fn write_ppm(
+    path: &str,
+    width: u32,
+    height: u32,
+    pixels: &[Vector3],
+) -> std::io::Result<()> {
+    let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(f, "P3\n{} {}\n255", width, height)?;
+    for color in pixels {
+        color.write_color(&mut f, 1, false)?;
+    }
+    f.flush()
+}
+
+/// Channel names of a color layer for `write_exr`.
+pub(crate) const RGB: &[&str] = &["R", "G", "B"];
+
+/// Writes named layers of linear values, top row first, as an uncompressed
+/// OpenEXR image with a 32-bit float channel `<name>.<component>` per listed
+/// component, or just `<component>` for a layer without a name. The
+/// components take the x, y and z of the pixels in turn, so a layer of one
+/// component like depth's `Z` keeps only x. Each `key: value` comment
+/// becomes a string attribute.
+pub(crate) fn write_exr(
+    path: &str,
+    width: u32,
+    height: u32,
+    layers: &[(String, &[&str], Vec<Vector3>)],
+    comments: &[String],
+) -> std::io::Result<()> {
+    // channels are stored in alphabetical order, each a layer and a component
+    let mut channels: Vec<(String, usize, usize)> = layers
+        .iter()
+        .enumerate()
+        .flat_map(|(layer, (name, components, _))| {
+            components
+                .iter()
+                .enumerate()
+                .map(move |(c, component)| match name.as_str() {
+                    "" => (component.to_string(), layer, c),
+                    _ => (format!("{}.{}", name, component), layer, c),
+                })
+        })
+        .collect();
+    channels.sort();
+
+    let mut header = Vec::new();
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        for s in [name, kind] {
+            header.extend_from_slice(s.as_bytes());
+            header.push(0);
+        }
+        header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+        header.extend_from_slice(value);
+    };
+    let mut list = Vec::new();
+    for (name, _, _) in &channels {
+        list.extend_from_slice(name.as_bytes());
+        // FLOAT pixels, not perceptually linear, sampled at every pixel
+        list.extend_from_slice(&[0, 2, 0, 0, 0, 0, 0, 0, 0]);
+        list.extend_from_slice(&[1, 0, 0, 0, 1, 0, 0, 0]);
+    }
+    list.push(0);
+    attribute("channels", "chlist", &list);
+    attribute("compression", "compression", &[0]);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    attribute("dataWindow", "box2i", &window);
+    attribute("displayWindow", "box2i", &window);
+    attribute("lineOrder", "lineOrder", &[0]);
+    attribute("pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+    attribute("screenWindowCenter", "v2f", &[0; 8]);
+    attribute("screenWindowWidth", "float", &1.0f32.to_le_bytes());
+    for comment in comments {
+        let (name, text) = comment
+            .split_once(": ")
+            .or_else(|| comment.split_once(' '))
+            .unwrap_or((comment, ""));
+        attribute(name, "string", text.as_bytes());
+    }
+    header.push(0);
+
+    let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+    f.write_all(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0])?;
+    f.write_all(&header)?;
+    // one scanline per block, each its row number, size and channels
+    let row_bytes = 4 * width as u64 * channels.len() as u64;
+    let first = 8 + header.len() as u64 + 8 * height as u64;
+    for y in 0..height as u64 {
+        f.write_all(&(first + y * (8 + row_bytes)).to_le_bytes())?;
+    }
+    for y in 0..height as usize {
+        f.write_all(&(y as i32).to_le_bytes())?;
+        f.write_all(&(row_bytes as i32).to_le_bytes())?;
+        for (_, layer, c) in &channels {
+            let row = &layers[*layer].2[y * width as usize..(y + 1) * width as usize];
+            for pixel in row {
+                let value = [pixel.x(), pixel.y(), pixel.z()][*c] as f32;
+                f.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+    f.flush()
+}
+
+/// The state of an unfinished render, see `write_checkpoint`.
+pub(crate) struct Checkpoint {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) samples: usize,
+    /// Hash of the scene and render settings, apart from the seed and the
+    /// sample count; `None` in checkpoints from before it was recorded.
+    pub(crate) hash: Option<u64>,
+    /// Seeds of the renders whose samples were summed, unseeded ones left
+    /// out.
+    pub(crate) seeds: Vec<u64>,
+    /// Summed color per pixel, top row first.
+    pub(crate) pixels: Vec<Vector3>,
+    /// Summed shadow catcher alpha per pixel.
+    pub(crate) coverage: Vec<f64>,
+}
+
+const CHECKPOINT_MAGIC: &str = "raytracer checkpoint";
+
+/// Saves the sums of every pixel after `samples` samples per pixel: a text
+/// header of the size, sample count, hash and seeds, then color and coverage
+/// as little-endian 64-bit floats. `path` is only replaced once the new file
+/// is complete, so a crash while writing keeps the previous checkpoint.
+pub(crate) fn write_checkpoint(path: &str, checkpoint: &Checkpoint) -> std::io::Result<()> {
+    let partial = format!("{}.partial", path);
+    let mut f = std::io::BufWriter::new(std::fs::File::create(&partial)?);
+    let c = checkpoint;
+    write!(
+        f,
+        "{}\n{} {} {}",
+        CHECKPOINT_MAGIC, c.width, c.height, c.samples
+    )?;
+    if let Some(hash) = c.hash {
+        let seeds: Vec<String> = c.seeds.iter().map(u64::to_string).collect();
+        if seeds.is_empty() {
+            write!(f, " {:016x} -", hash)?;
+        } else {
+            write!(f, " {:016x} {}", hash, seeds.join(","))?;
+        }
+    }
+    writeln!(f)?;
+    for (color, alpha) in c.pixels.iter().zip(&c.coverage) {
+        for value in [color.x(), color.y(), color.z(), *alpha] {
+            f.write_all(&value.to_le_bytes())?;
+        }
+    }
+    f.into_inner()?.sync_all()?;
+    std::fs::rename(partial, path)
+}
+
+pub(crate) fn read_checkpoint(path: &str) -> std::io::Result<Checkpoint> {
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+    let data = std::fs::read(path)?;
+    let mut lines = data.splitn(3, |b| *b == b'\n');
+    if lines.next() != Some(CHECKPOINT_MAGIC.as_bytes()) {
+        return Err(invalid("not a checkpoint"));
+    }
+    let header = lines
+        .next()
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .ok_or_else(|| invalid("truncated header"))?;
+    let fields: Vec<&str> = header.split(' ').collect();
+    let number = |s: &str| s.parse::<usize>().map_err(|_| invalid("bad header"));
+    let (width, height, samples) = match fields[..] {
+        [width, height, samples, ..] => (number(width)?, number(height)?, number(samples)?),
+        _ => return Err(invalid("bad header")),
+    };
+    let (hash, seeds) = match fields[3..] {
+        [] => (None, Vec::new()),
+        [hash, seeds] => (
+            Some(u64::from_str_radix(hash, 16).map_err(|_| invalid("bad hash"))?),
+            match seeds {
+                "-" => Vec::new(),
+                _ => seeds
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid("bad seeds"))?,
+            },
+        ),
+        _ => return Err(invalid("bad header")),
+    };
+    let body = lines.next().unwrap_or_default();
+    if body.len() != width * height * 32 {
+        return Err(invalid("truncated pixels"));
+    }
+    let values: Vec<f64> = body
+        .chunks(8)
+        .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+        .collect();
+    Ok(Checkpoint {
+        width: width as u32,
+        height: height as u32,
+        samples,
+        hash,
+        seeds,
+        pixels: values
+            .chunks(4)
+            .map(|v| Vector3(v[0], v[1], v[2]))
+            .collect(),
+        coverage: values.chunks(4).map(|v| v[3]).collect(),
+    })
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ImageFormat {
+    #[default]
+    Ppm,
+    Png,
+    Jpeg,
+}
+
+impl std::str::FromStr for ImageFormat {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ppm" => Ok(ImageFormat::Ppm),
+            "png" => Ok(ImageFormat::Png),
+            "jpeg" | "jpg" => Ok(ImageFormat::Jpeg),
+            _ => Err(()),
+        }
+    }
+}
+
+impl ImageFormat {
+    /// The format matching the extension of `path`, if it has a known one.
+    pub(crate) fn from_path(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        extension.to_ascii_lowercase().parse().ok()
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+        }
+    }
+}
+
+/// Encodes 8-bit RGB `pixels`, or RGBA for PNG, as `format`. The `comments` go into the PPM
+/// header, PNG text chunks (split into keyword and text at the first `:` or
+/// space) or a JPEG comment segment.
+pub(crate) fn write_image(
+    mut out: impl Write,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+    comments: &[String],
+) -> std::io::Result<()> {
+    match format {
+        ImageFormat::Ppm => {
+            writeln!(out, "P3")?;
+            for comment in comments {
+                writeln!(out, "# {}", comment)?;
+            }
+            writeln!(out, "{} {}\n255", width, height)?;
+            for rgb in pixels.chunks(3) {
+                writeln!(out, "{} {} {}", rgb[0], rgb[1], rgb[2])?;
+            }
+        }
+        ImageFormat::Png => {
+            let mut encoder = png::Encoder::new(&mut out, width, height);
+            encoder.set_color(if pixels.len() == 4 * (width * height) as usize {
+                png::ColorType::Rgba
+            } else {
+                png::ColorType::Rgb
+            });
+            encoder.set_depth(png::BitDepth::Eight);
+            for comment in comments {
+                let (keyword, text) = comment
+                    .split_once(": ")
+                    .or_else(|| comment.split_once(' '))
+                    .unwrap_or((comment, ""));
+                encoder.add_text_chunk(keyword.to_string(), text.to_string())?;
+            }
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(pixels)?;
+            writer.finish()?;
+        }
+        ImageFormat::Jpeg => {
+            if width > u16::MAX as u32 || height > u16::MAX as u32 {
+                return Err(std::io::Error::other(
+                    "JPEG images are at most 65535 pixels wide and high",
+                ));
+            }
+            let mut data = Vec::new();
+            jpeg_encoder::Encoder::new(&mut data, 90)
+                .encode(
+                    pixels,
+                    width as u16,
+                    height as u16,
+                    jpeg_encoder::ColorType::Rgb,
+                )
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            // a COM segment right after SOI and the JFIF APP0 segment
+            let mut text = comments.join("\n").into_bytes();
+            text.truncate(u16::MAX as usize - 2);
+            let at = match data[2..4] {
+                [0xff, 0xe0] => 4 + u16::from_be_bytes([data[4], data[5]]) as usize,
+                _ => 2,
+            };
+            let mut segment = vec![0xff, 0xfe];
+            segment.extend_from_slice(&(text.len() as u16 + 2).to_be_bytes());
+            segment.extend(text);
+            data.splice(at..at, segment);
+            out.write_all(&data)?;
+        }
+    }
+    out.flush()
+}
+
+/// Writes an in-progress render, upscaled with nearest-neighbour filtering to
+/// `width` x `height` if it was rendered at a lower resolution.
+pub(crate) fn write_preview(
+    path: &str,
+    source: &RenderSettings,
+    pixels: &[Vector3],
+    samples: usize,
+    width: u32,
+    height: u32,
+) -> std::io::Result<()> {
+    let gamma = source.mode == RenderMode::Path;
+    let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(f, "P3\n{} {}\n255", width, height)?;
+    for y in 0..height {
+        let sy = (y * source.height / height).min(source.height - 1);
+        for x in 0..width {
+            let sx = (x * source.width / width).min(source.width - 1);
+            pixels[(sy * source.width + sx) as usize].write_color(&mut f, samples, gamma)?;
+        }
+    }
+    f.flush()
+}
+
+/// Reads a P3 or P6 PPM file and returns its size and linear pixel values,
+/// undoing the sRGB encoding of `write_color`.
+pub(crate) fn read_ppm(path: &str) -> std::io::Result<(usize, usize, Vec<Vector3>)> {
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+    let data = crate::embed::read(path)?;
+    let mut pos = 0;
+    let mut token = || -> Option<String> {
+        loop {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        (start < pos).then(|| String::from_utf8_lossy(&data[start..pos]).into_owned())
+    };
+    let magic = token().ok_or_else(|| invalid("empty file"))?;
+    let mut number = || -> std::io::Result<usize> {
+        token()
+            .and_then(|t| t.parse().ok())
+            .ok_or_else(|| invalid("malformed header or pixel data"))
+    };
+    let width = number()?;
+    let height = number()?;
+    let max = number()? as f64;
+    let samples = match magic.as_str() {
+        "P3" => (0..width * height * 3)
+            .map(|_| number())
+            .collect::<std::io::Result<Vec<_>>>()?,
+        "P6" if max < 256.0 => {
+            let start = pos + 1;
+            data.get(start..start + width * height * 3)
+                .ok_or_else(|| invalid("truncated pixel data"))?
+                .iter()
+                .map(|&b| b as usize)
+                .collect()
+        }
+        _ => return Err(invalid("unsupported PPM format")),
+    };
+    let decode = |c: usize| srgb_decode((c as f64 + 0.5) / (max + 1.0));
+    let pixels = samples
+        .chunks(3)
+        .map(|c| Vector3(decode(c[0]), decode(c[1]), decode(c[2])))
+        .collect();
+    Ok((width, height, pixels))
+}
+
+/// Reads a Radiance RGBE (.hdr) file and returns its size and linear pixel
+/// values, top row first. Only the usual `-Y height +X width` orientation is
+/// supported.
+pub(crate) fn read_hdr(path: &str) -> std::io::Result<(usize, usize, Vec<Vector3>)> {
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+    let data = crate::embed::read(path)?;
+    let mut pos = 0;
+    let mut line = || -> std::io::Result<String> {
+        let end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| invalid("truncated header"))?;
+        let text = String::from_utf8_lossy(&data[pos..pos + end]).into_owned();
+        pos += end + 1;
+        Ok(text)
+    };
+    if !line()?.starts_with("#?") {
+        return Err(invalid("not a Radiance HDR file"));
+    }
+    // header lines up to an empty one, then the resolution
+    while !line()?.is_empty() {}
+    let size: Vec<String> = line()?.split_whitespace().map(String::from).collect();
+    let (width, height): (usize, usize) = match &size[..] {
+        [y, height, x, width] if y == "-Y" && x == "+X" => (
+            width.parse().map_err(|_| invalid("malformed resolution"))?,
+            height
+                .parse()
+                .map_err(|_| invalid("malformed resolution"))?,
+        ),
+        _ => return Err(invalid("unsupported orientation")),
+    };
+    // a run of up to 127 pixels takes at least 2 bytes in each component
+    match width.checked_mul(height) {
+        Some(0) => return Err(invalid("empty image")),
+        Some(pixels) if pixels / 127 <= data.len() => {}
+        _ => return Err(invalid("resolution larger than the pixel data")),
+    }
+    let mut bytes = data[pos..].iter().copied();
+    let mut next = || bytes.next().ok_or_else(|| invalid("truncated pixel data"));
+    let mut rgbe = vec![[0u8; 4]; width * height];
+    for row in rgbe.chunks_mut(width) {
+        let start = [next()?, next()?, next()?, next()?];
+        let run_length = (8..32768).contains(&width) && start[..2] == [2, 2] && start[2] < 128;
+        if !run_length {
+            // flat pixels
+            row[0] = start;
+            for pixel in row[1..].iter_mut() {
+                *pixel = [next()?, next()?, next()?, next()?];
+            }
+            continue;
+        }
+        if ((start[2] as usize) << 8 | start[3] as usize) != width {
+            return Err(invalid("scanline width mismatch"));
+        }
+        // each component separately, in runs and literal spans
+        for c in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = next()? as usize;
+                let (count, run) = if count > 128 {
+                    (count - 128, Some(next()?))
+                } else {
+                    (count, None)
+                };
+                if count == 0 || x + count > width {
+                    return Err(invalid("bad scanline data"));
+                }
+                for pixel in row[x..x + count].iter_mut() {
+                    pixel[c] = match run {
+                        Some(value) => value,
+                        None => next()?,
+                    };
+                }
+                x += count;
+            }
+        }
+    }
+    let pixels = rgbe
+        .iter()
+        .map(|&[r, g, b, e]| {
+            if e == 0 {
+                return Vector3(0.0, 0.0, 0.0);
+            }
+            let scale = 2f64.powi(e as i32 - 136);
+            Vector3(r as f64 + 0.5, g as f64 + 0.5, b as f64 + 0.5) * scale
+        })
+        .collect();
+    Ok((width, height, pixels))
+}
+
+/// Reads the R, G and B channels, or those of the first layer if there are
+/// only layers, of an uncompressed scanline OpenEXR file like those of
+/// `write_exr`, and returns its size and linear pixel values, top row first.
+pub(crate) fn read_exr(path: &str) -> std::io::Result<(usize, usize, Vec<Vector3>)> {
+    let invalid = |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+    let data = crate::embed::read(path)?;
+    let truncated = || invalid("truncated file");
+    let int = |at: usize| -> std::io::Result<i32> {
+        let b = at.checked_add(4).and_then(|end| data.get(at..end));
+        let b = b.ok_or_else(truncated)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    if data.get(..4) != Some(&[0x76, 0x2f, 0x31, 0x01]) {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    if int(4)? & 0x1e00 != 0 {
+        return Err(invalid("only single-part scanline images are supported"));
+    }
+    let string = |at: usize| -> std::io::Result<(String, usize)> {
+        let end = data
+            .get(at..)
+            .ok_or_else(truncated)?
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(truncated)?;
+        Ok((
+            String::from_utf8_lossy(&data[at..at + end]).into_owned(),
+            at + end + 1,
+        ))
+    };
+    // (name, bytes per value, half) of every channel, in file order
+    let mut channels = Vec::new();
+    let mut window = None;
+    let mut pos = 8;
+    while *data.get(pos).ok_or_else(truncated)? != 0 {
+        let (name, at) = string(pos)?;
+        let (_, at) = string(at)?;
+        let size = match int(at)? {
+            size if size < 0 => return Err(invalid("negative attribute size")),
+            size => size as usize,
+        };
+        let value = data.get(at + 4..).and_then(|rest| rest.get(..size));
+        let value = value.ok_or_else(truncated)?;
+        match name.as_str() {
+            "channels" => {
+                let mut at = at + 4;
+                while *data.get(at).ok_or_else(truncated)? != 0 {
+                    let (channel, next) = string(at)?;
+                    let (bytes, half) = match int(next)? {
+                        1 => (2, true),
+                        2 => (4, false),
+                        _ => return Err(invalid("only half and float channels are supported")),
+                    };
+                    channels.push((channel, bytes, half));
+                    at = next + 16;
+                }
+            }
+            "compression" if value != [0] => {
+                return Err(invalid("only uncompressed images are supported"))
+            }
+            "dataWindow" => {
+                window = Some((int(at + 4)?, int(at + 8)?, int(at + 12)?, int(at + 16)?))
+            }
+            _ => {}
+        }
+        pos = at + 4 + size;
+    }
+    let (x0, y0, x1, y1) = window.ok_or_else(|| invalid("missing data window"))?;
+    if x1 < x0 || y1 < y0 {
+        return Err(invalid("empty data window"));
+    }
+    let (width, height) = (
+        (x1 as i64 - x0 as i64 + 1) as usize,
+        (y1 as i64 - y0 as i64 + 1) as usize,
+    );
+    // every pixel takes at least 2 bytes in each channel
+    if width.saturating_mul(height) > data.len() {
+        return Err(invalid("data window larger than the file"));
+    }
+    let layer = match channels.iter().any(|(c, _, _)| c == "R") {
+        true => String::new(),
+        false => channels
+            .iter()
+            .find_map(|(c, _, _)| c.strip_suffix('R').filter(|l| l.ends_with('.')))
+            .unwrap_or_default()
+            .to_string(),
+    };
+    let find = |name: &str| {
+        channels
+            .iter()
+            .position(|(c, _, _)| *c == format!("{}{}", layer, name))
+            .ok_or_else(|| invalid("missing R, G or B channel"))
+    };
+    let rgb = [find("R")?, find("G")?, find("B")?];
+    let offsets = pos + 1;
+    let mut pixels = vec![Vector3(0.0, 0.0, 0.0); width * height];
+    for row in 0..height {
+        let b = data
+            .get(offsets + 8 * row..offsets + 8 * row + 8)
+            .ok_or_else(truncated)?;
+        let chunk = u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as usize;
+        // rows above the window wrap around to below it
+        let y = (int(chunk)? as i64 - y0 as i64) as usize;
+        if y >= height {
+            return Err(invalid("scanline outside the data window"));
+        }
+        let mut at = chunk + 8;
+        let mut values = vec![[0.0; 3]; width];
+        for (channel, (_, bytes, half)) in channels.iter().enumerate() {
+            let span = data.get(at..at + bytes * width).ok_or_else(truncated)?;
+            if let Some(c) = rgb.iter().position(|&i| i == channel) {
+                for (value, b) in values.iter_mut().zip(span.chunks(*bytes)) {
+                    value[c] = if *half {
+                        half_to_f64(u16::from_le_bytes([b[0], b[1]]))
+                    } else {
+                        f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64
+                    };
+                }
+            }
+            at += bytes * width;
+        }
+        for (pixel, [r, g, b]) in pixels[y * width..(y + 1) * width].iter_mut().zip(values) {
+            *pixel = Vector3(r, g, b);
+        }
+    }
+    Ok((width, height, pixels))
+}
+
+/// The value of an IEEE 754 half-precision float.
+fn half_to_f64(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10 & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f64;
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+/// Reads the `# tile x0 y0 width height` comment that tile renders carry
+/// after the magic number: the tile's position and the full image size.
+pub(crate) fn read_tile_placement(path: &str) -> std::io::Result<(usize, usize, usize, usize)> {
+    let data = std::fs::read(path)?;
+    let header = String::from_utf8_lossy(&data[..data.len().min(4096)]).into_owned();
+    header
+        .lines()
+        .skip(1)
+        .take_while(|line| line.starts_with('#'))
+        .find_map(|line| {
+            let c: Vec<usize> = line
+                .strip_prefix("# tile ")?
+                .split_whitespace()
+                .map(|c| c.parse().ok())
+                .collect::<Option<_>>()?;
+            match c[..] {
+                [x0, y0, width, height] => Some((x0, y0, width, height)),
+                _ => None,
+            }
+        })
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "not a tile render"))
+}
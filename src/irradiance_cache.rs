@@ -0,0 +1,223 @@
+//! Ward-style irradiance caching for diffuse interreflection.
+
+use crate::hittable::{Intersection, RayKind};
+use crate::ray::{PathInfo, Ray};
+use crate::scene::Scene;
+use crate::vec3::{random, Vector3};
+
+/// Per-channel gradient of a color, one gradient vector per channel.
+type ColorGradient = [Vector3; 3];
+
+fn apply_gradient(g: &ColorGradient, d: &Vector3) -> Vector3 {
+    Vector3(g[0].dot(d), g[1].dot(d), g[2].dot(d))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IrradianceRecord {
+    p: Vector3,
+    normal: Vector3,
+    /// Irradiance / pi, i.e. the mean cosine-weighted incoming radiance.
+    irradiance: Vector3,
+    /// Harmonic mean distance to the surfaces seen from `p`.
+    radius: f64,
+    rotational: ColorGradient,
+    translational: ColorGradient,
+}
+
+#[derive(Default)]
+struct IrradianceCacheData {
+    records: Vec<IrradianceRecord>,
+    cells: std::collections::HashMap<(i64, i64, i64), Vec<usize>>,
+}
+
+/// Ward-style irradiance cache with rotational and translational gradients.
+/// Only the first diffuse bounce of a path uses it; the hemisphere samples of
+/// a new record are path traced without the cache.
+pub struct IrradianceCache {
+    /// Maximum allowed error `a`; records are used where their weight exceeds 1/a.
+    pub(crate) accuracy: f64,
+    theta_divisions: usize,
+    phi_divisions: usize,
+    min_radius: f64,
+    max_radius: f64,
+    cell_size: f64,
+    data: std::sync::RwLock<IrradianceCacheData>,
+}
+
+impl IrradianceCache {
+    pub fn new(accuracy: f64) -> Self {
+        Self {
+            accuracy,
+            theta_divisions: 10,
+            phi_divisions: 30,
+            min_radius: 0.05,
+            max_radius: 5.0,
+            cell_size: 1.0,
+            data: Default::default(),
+        }
+    }
+
+    fn cell(&self, p: &Vector3) -> (i64, i64, i64) {
+        (
+            (p.x() / self.cell_size).floor() as i64,
+            (p.y() / self.cell_size).floor() as i64,
+            (p.z() / self.cell_size).floor() as i64,
+        )
+    }
+
+    /// Irradiance / pi at an intersection, interpolated from cached records or
+    /// computed and cached if no record is close enough.
+    pub fn irradiance(&self, scene: &Scene, i: &Intersection, depth: usize) -> Vector3 {
+        if let Some(e) = self.interpolate(&i.p, &i.normal) {
+            return e;
+        }
+        let record = self.compute(scene, &i.p, &i.normal, depth);
+        self.insert(record);
+        record.irradiance
+    }
+
+    fn interpolate(&self, p: &Vector3, n: &Vector3) -> Option<Vector3> {
+        let data = self.data.read().unwrap();
+        let candidates = data.cells.get(&self.cell(p))?;
+        let mut sum = Vector3(0.0, 0.0, 0.0);
+        let mut total_weight = 0.0;
+        for r in candidates.iter().map(|&idx| &data.records[idx]) {
+            let d = *p - r.p;
+            // skip records in front of p
+            if d.dot(&((*n + r.normal) * 0.5)) < -0.05 {
+                continue;
+            }
+            let error = d.length() / r.radius + (1.0 - n.dot(&r.normal)).max(0.0).sqrt();
+            if error >= self.accuracy {
+                continue;
+            }
+            let w = 1.0 / error.max(1e-6);
+            let e = r.irradiance
+                + apply_gradient(&r.rotational, &r.normal.cross(n))
+                + apply_gradient(&r.translational, &d);
+            sum += e * w;
+            total_weight += w;
+        }
+        (total_weight > 0.0).then(|| {
+            let e = sum / total_weight;
+            Vector3(e.x().max(0.0), e.y().max(0.0), e.z().max(0.0))
+        })
+    }
+
+    fn insert(&self, record: IrradianceRecord) {
+        let reach = record.radius * self.accuracy;
+        let lo = self.cell(&(record.p - Vector3(reach, reach, reach)));
+        let hi = self.cell(&(record.p + Vector3(reach, reach, reach)));
+        let mut data = self.data.write().unwrap();
+        let idx = data.records.len();
+        data.records.push(record);
+        for x in lo.0..=hi.0 {
+            for y in lo.1..=hi.1 {
+                for z in lo.2..=hi.2 {
+                    data.cells.entry((x, y, z)).or_default().push(idx);
+                }
+            }
+        }
+    }
+
+    /// Samples the hemisphere over `n` with stratified cosine-weighted rays and
+    /// estimates the gradients following Ward and Heckbert, "Irradiance
+    /// Gradients" (1992).
+    fn compute(&self, scene: &Scene, p: &Vector3, n: &Vector3, depth: usize) -> IrradianceRecord {
+        let (m, k_count) = (self.theta_divisions, self.phi_divisions);
+        let helper = if n.x().abs() > 0.9 {
+            Vector3(0.0, 1.0, 0.0)
+        } else {
+            Vector3(1.0, 0.0, 0.0)
+        };
+        let u_axis = n.cross(&helper).normalize();
+        let v_axis = n.cross(&u_axis);
+        let in_plane = |phi: f64| u_axis * phi.cos() + v_axis * phi.sin();
+
+        let mut radiance = vec![Vector3(0.0, 0.0, 0.0); m * k_count];
+        let mut distance = vec![f64::INFINITY; m * k_count];
+        for j in 0..m {
+            for k in 0..k_count {
+                let theta = ((j as f64 + random()) / m as f64).sqrt().asin();
+                let phi = 2.0 * std::f64::consts::PI * (k as f64 + random()) / k_count as f64;
+                let r = Ray {
+                    origin: *p,
+                    direction: in_plane(phi) * theta.sin() + *n * theta.cos(),
+                    time: 0.0,
+                };
+                if let Some(hit) = scene
+                    .world
+                    .hit_as(&r, 0.001, f64::INFINITY, RayKind::Indirect)
+                {
+                    distance[j * k_count + k] = hit.t;
+                }
+                radiance[j * k_count + k] =
+                    r.ray_color(scene, depth, None, &mut PathInfo::default());
+            }
+        }
+        let l = |j: usize, k: usize| radiance[j * k_count + k];
+        let dist = |j: usize, k: usize| distance[j * k_count + k];
+        let samples = (m * k_count) as f64;
+
+        let irradiance = radiance.iter().fold(Vector3(0.0, 0.0, 0.0), |a, &b| a + b) / samples;
+        let inv_sum: f64 = distance.iter().map(|d| 1.0 / d).sum();
+        let radius = (samples / inv_sum).clamp(self.min_radius, self.max_radius);
+
+        let theta_at = |f: f64| (f / m as f64).sqrt().asin();
+        let mut rotational = [Vector3(0.0, 0.0, 0.0); 3];
+        let mut translational = [Vector3(0.0, 0.0, 0.0); 3];
+        let add = |g: &mut ColorGradient, dir: Vector3, c: Vector3| {
+            g[0] += dir * c.x();
+            g[1] += dir * c.y();
+            g[2] += dir * c.z();
+        };
+        for k in 0..k_count {
+            let phi = 2.0 * std::f64::consts::PI * (k as f64 + 0.5) / k_count as f64;
+            let phi_minus = 2.0 * std::f64::consts::PI * k as f64 / k_count as f64;
+            let u_k = in_plane(phi);
+            let v_k = in_plane(phi + std::f64::consts::FRAC_PI_2);
+            let v_k_minus = in_plane(phi_minus + std::f64::consts::FRAC_PI_2);
+            let k_prev = (k + k_count - 1) % k_count;
+
+            let mut rot = Vector3(0.0, 0.0, 0.0);
+            let mut along_theta = Vector3(0.0, 0.0, 0.0);
+            let mut along_phi = Vector3(0.0, 0.0, 0.0);
+            for j in 0..m {
+                let theta = theta_at(j as f64 + 0.5);
+                let theta_minus = theta_at(j as f64);
+                let theta_plus = theta_at(j as f64 + 1.0);
+                rot += l(j, k) * -theta.tan();
+                if j > 0 {
+                    let w = theta_minus.sin() * theta_minus.cos().powi(2)
+                        / dist(j, k).min(dist(j - 1, k));
+                    along_theta += (l(j, k) - l(j - 1, k)) * w;
+                }
+                let w = theta.cos() * (theta_minus.cos() - theta_plus.cos())
+                    / (theta.sin() * dist(j, k).min(dist(j, k_prev)));
+                along_phi += (l(j, k) - l(j, k_prev)) * w;
+            }
+            add(&mut rotational, v_k, rot / samples);
+            // Ward and Heckbert's gradient is for E; the record stores E / pi
+            let two_pi_over_n = 2.0 * std::f64::consts::PI / k_count as f64;
+            add(
+                &mut translational,
+                u_k,
+                along_theta * two_pi_over_n / std::f64::consts::PI,
+            );
+            add(
+                &mut translational,
+                v_k_minus,
+                along_phi / std::f64::consts::PI,
+            );
+        }
+
+        IrradianceRecord {
+            p: *p,
+            normal: *n,
+            irradiance,
+            radius,
+            rotational,
+            translational,
+        }
+    }
+}
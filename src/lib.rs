@@ -0,0 +1,34 @@
+//! A path tracer that started out as *Ray Tracing in One Weekend*.
+//!
+//! Build a [`Scene`](scene::Scene) from a [`HittableStore`](hittable::HittableStore)
+//! of objects and render it with [`render`](render::render); the `raytracer`
+//! binary wraps everything in a command line interface.
+
+pub mod bake;
+pub mod camera;
+pub mod cli;
+pub mod color;
+pub mod diagnostics;
+pub mod embed;
+pub mod gltf;
+pub mod hittable;
+pub mod image;
+pub mod irradiance_cache;
+pub mod lidar;
+pub mod light;
+pub mod material;
+pub mod medium;
+pub mod pdf;
+pub mod photon;
+pub mod post;
+pub mod ray;
+pub mod render;
+pub mod scene;
+pub mod scene_file;
+pub mod server;
+pub mod sky;
+pub mod texture;
+pub mod vec3;
+pub mod window;
+
+pub use render::{render, Image};
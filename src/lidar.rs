@@ -0,0 +1,150 @@
+//! Simulated LiDAR scans.
+
+use crate::camera::Camera;
+use crate::ray::Ray;
+use crate::render::RenderSettings;
+use crate::scene::Scene;
+use crate::vec3::{random, Vector3};
+use rayon::prelude::*;
+use std::io::Write;
+
+/// Beam layout of a simulated LiDAR sensor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum LidarPattern {
+    /// One beam through the center of every pixel of the camera.
+    Grid,
+    /// A spinning sensor at the camera position: `channels` beams spread over
+    /// ±15° elevation, each fired at `steps` azimuths around the vertical.
+    Spin { channels: u32, steps: u32 },
+}
+
+impl std::str::FromStr for LidarPattern {
+    type Err = ();
+    /// Parses `grid` or `spin:<channels>x<steps>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "grid" => Ok(LidarPattern::Grid),
+            Some(("spin", size)) => {
+                let (channels, steps) = size.split_once('x').ok_or(())?;
+                Ok(LidarPattern::Spin {
+                    channels: channels.parse().map_err(|_| ())?,
+                    steps: steps.parse().map_err(|_| ())?,
+                })
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+/// Simulates a LiDAR scan and writes the returns as a PLY point cloud with
+/// range, intensity, normal and the beam's row and column. Each beam is a
+/// cone of full angle `divergence` (radians) sampled by several rays; the
+/// return averages the ones that hit, and Gaussian noise of standard
+/// deviation `noise` is added to the range. Intensity is the surface albedo's
+/// luminance times the cosine of incidence.
+pub(crate) fn lidar_scan(
+    path: &str,
+    cam: &Camera,
+    scene: &Scene,
+    settings: &RenderSettings,
+    pattern: LidarPattern,
+    divergence: f64,
+    noise: f64,
+) -> std::io::Result<()> {
+    let (rows, columns) = match pattern {
+        LidarPattern::Grid => (settings.height, settings.width),
+        LidarPattern::Spin { channels, steps } => (channels, steps),
+    };
+    let beam = |row: u32, column: u32| match pattern {
+        LidarPattern::Grid => {
+            let u = column as f64 / (columns - 1) as f64;
+            let v = (rows - 1 - row) as f64 / (rows - 1) as f64;
+            cam.center_ray(u, v).direction.normalize()
+        }
+        LidarPattern::Spin { .. } => {
+            let elevation = (15.0 - 30.0 * row as f64 / (rows - 1).max(1) as f64).to_radians();
+            let azimuth = 2.0 * std::f64::consts::PI * column as f64 / columns as f64;
+            Vector3(
+                elevation.cos() * azimuth.sin(),
+                elevation.sin(),
+                elevation.cos() * azimuth.cos(),
+            )
+        }
+    };
+    let rays_per_beam = if divergence > 0.0 { 16 } else { 1 };
+    let returns: Vec<_> = (0..rows * columns)
+        .into_par_iter()
+        .filter_map(|i| {
+            let (row, column) = (i / columns, i % columns);
+            let direction = beam(row, column);
+            let helper = if direction.x().abs() > 0.9 {
+                Vector3(0.0, 1.0, 0.0)
+            } else {
+                Vector3(1.0, 0.0, 0.0)
+            };
+            let u_axis = direction.cross(&helper).normalize();
+            let v_axis = direction.cross(&u_axis);
+            let spread = (divergence / 2.0).tan();
+            let mut hits = 0;
+            let (mut range, mut intensity, mut normal) = (0.0, 0.0, Vector3(0.0, 0.0, 0.0));
+            for _ in 0..rays_per_beam {
+                let d = Vector3::random_in_unit_disk() * spread;
+                let r = Ray {
+                    origin: cam.origin,
+                    direction: (direction + u_axis * d.x() + v_axis * d.y()).normalize(),
+                    time: cam.shutter.0,
+                };
+                if let Some(hit) = scene.intersect(&r) {
+                    let albedo = hit.material.albedo(hit.u, hit.v, &hit.point);
+                    let reflectivity =
+                        0.2126 * albedo.x() + 0.7152 * albedo.y() + 0.0722 * albedo.z();
+                    hits += 1;
+                    range += hit.t;
+                    intensity += reflectivity * hit.normal.dot(&r.direction).abs();
+                    normal += hit.normal;
+                }
+            }
+            if hits == 0 {
+                return None;
+            }
+            let gaussian = {
+                let (u1, u2): (f64, f64) = (random(), random());
+                (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+            };
+            let range = range / hits as f64 + noise * gaussian;
+            Some((
+                row,
+                column,
+                cam.origin + direction * range,
+                range,
+                intensity / rays_per_beam as f64,
+                normal.normalize(),
+            ))
+        })
+        .collect();
+
+    let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(f, "ply\nformat ascii 1.0")?;
+    writeln!(f, "element vertex {}", returns.len())?;
+    writeln!(f, "property float x\nproperty float y\nproperty float z")?;
+    writeln!(f, "property float range\nproperty float intensity")?;
+    writeln!(f, "property float nx\nproperty float ny\nproperty float nz")?;
+    writeln!(f, "property int row\nproperty int column\nend_header")?;
+    for (row, column, p, range, intensity, n) in &returns {
+        writeln!(
+            f,
+            "{} {} {} {} {} {} {} {} {} {}",
+            p.x(),
+            p.y(),
+            p.z(),
+            range,
+            intensity,
+            n.x(),
+            n.y(),
+            n.z(),
+            row,
+            column
+        )?;
+    }
+    f.flush()
+}
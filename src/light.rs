@@ -0,0 +1,192 @@
+//! Explicit lights and their physical units.
+
+use crate::vec3::Vector3;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Light {
+    /// Parallel light arriving from `direction`, like the sun. `color` is the
+    /// irradiance it delivers to a surface facing it.
+    Directional { direction: Vector3, color: Vector3 },
+    /// Light emitted equally in all directions from `position`. `color` is the
+    /// radiant intensity, which falls off with distance to the power
+    /// `falloff`: 2 is physically correct, lower values are for art direction.
+    Point {
+        position: Vector3,
+        color: Vector3,
+        falloff: f64,
+    },
+}
+
+impl Light {
+    /// The same light taking its color away instead of adding it. Not
+    /// physical, for darkening corners by hand.
+    pub fn negated(self) -> Self {
+        match self {
+            Light::Directional { direction, color } => Light::Directional {
+                direction,
+                color: color * -1.0,
+            },
+            Light::Point {
+                position,
+                color,
+                falloff,
+            } => Light::Point {
+                position,
+                color: color * -1.0,
+                falloff,
+            },
+        }
+    }
+
+    /// The direction towards the light as seen from `p`, the distance to it and
+    /// the irradiance it delivers at `p` perpendicular to that direction.
+    pub(crate) fn illuminate(&self, p: &Vector3) -> (Vector3, f64, Vector3) {
+        match self {
+            Light::Directional { direction, color } => {
+                (direction.normalize(), f64::INFINITY, *color)
+            }
+            Light::Point {
+                position,
+                color,
+                falloff,
+            } => {
+                let to_light = *position - *p;
+                let distance = to_light.length();
+                (
+                    to_light / distance,
+                    distance,
+                    *color / distance.powf(*falloff),
+                )
+            }
+        }
+    }
+}
+
+/// Luminous efficacy of 555 nm light in lm/W, for converting photometric units.
+pub(crate) const LUMENS_PER_WATT: f64 = 683.0;
+
+/// Linear sRGB color of unit luminance of a black body at `kelvin`, from
+/// Planck's law integrated against a fit of the CIE 1931 color matching
+/// functions (Wyman et al. 2013).
+pub(crate) fn blackbody(kelvin: f64) -> Vector3 {
+    let g = |x: f64, mu: f64, sigma_low: f64, sigma_high: f64| {
+        let sigma = if x < mu { sigma_low } else { sigma_high };
+        (-0.5 * ((x - mu) / sigma).powi(2)).exp()
+    };
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for nm in (380..=780).step_by(5) {
+        let l = nm as f64;
+        let meters = l * 1e-9;
+        let radiance = 1.0 / (meters.powi(5) * ((1.4388e-2 / (meters * kelvin)).exp() - 1.0));
+        x += radiance
+            * (1.056 * g(l, 599.8, 37.9, 31.0) + 0.362 * g(l, 442.0, 16.0, 26.7)
+                - 0.065 * g(l, 501.1, 20.4, 26.2));
+        y += radiance * (0.821 * g(l, 568.8, 46.9, 40.5) + 0.286 * g(l, 530.9, 16.3, 31.1));
+        z += radiance * (1.217 * g(l, 437.0, 11.8, 36.0) + 0.681 * g(l, 459.0, 26.0, 13.8));
+    }
+    let rgb = Vector3(
+        (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    );
+    rgb / (0.2126 * rgb.x() + 0.7152 * rgb.y() + 0.0722 * rgb.z())
+}
+
+/// Parses a light color: either plain `r,g,b` in render units, or a tint
+/// `r,g,b@<amount><unit>` scaled to the given amount. The tint can also be a
+/// color temperature like `3200K`, alone giving unit luminance. Render units are W/sr
+/// for point lights and W/m² for the sun, with distances in meters; see
+/// `SceneUnit` for scenes modeled in other units.
+fn parse_light_color(s: &str, point: bool) -> Result<Vector3, ()> {
+    let parse_tint = |tint: &str| match tint.strip_suffix('K') {
+        Some(kelvin) => match kelvin.parse::<f64>() {
+            Ok(kelvin) if kelvin > 0.0 => Ok(blackbody(kelvin)),
+            _ => Err(()),
+        },
+        None => tint.parse::<Vector3>(),
+    };
+    let (tint, amount) = match s.split_once('@') {
+        Some((tint, amount)) => (parse_tint(tint)?, amount),
+        None => return parse_tint(s),
+    };
+    let split = amount.find(|c: char| c.is_ascii_alphabetic()).ok_or(())?;
+    let value: f64 = amount[..split].parse().map_err(|_| ())?;
+    let value = match (point, &amount[split..]) {
+        // radiant and luminous flux spread over the whole sphere
+        (true, "W") => value / (4.0 * std::f64::consts::PI),
+        (true, "lm") => value / LUMENS_PER_WATT / (4.0 * std::f64::consts::PI),
+        (true, "cd") => value / LUMENS_PER_WATT,
+        (false, "W/m2") => value,
+        (false, "lx") => value / LUMENS_PER_WATT,
+        _ => return Err(()),
+    };
+    let luminance = 0.2126 * tint.x() + 0.7152 * tint.y() + 0.0722 * tint.z();
+    if luminance <= 0.0 {
+        return Err(());
+    }
+    Ok(tint * (value / luminance))
+}
+
+/// The length of one scene unit. Point light intensities are rescaled so that
+/// their falloff is computed over meters whatever the scene was modeled in.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SceneUnit {
+    #[default]
+    Meter,
+    Centimeter,
+    Millimeter,
+    Inch,
+    Foot,
+}
+
+impl SceneUnit {
+    pub fn meters(&self) -> f64 {
+        match self {
+            SceneUnit::Meter => 1.0,
+            SceneUnit::Centimeter => 0.01,
+            SceneUnit::Millimeter => 0.001,
+            SceneUnit::Inch => 0.0254,
+            SceneUnit::Foot => 0.3048,
+        }
+    }
+}
+
+impl std::str::FromStr for SceneUnit {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "m" => Ok(SceneUnit::Meter),
+            "cm" => Ok(SceneUnit::Centimeter),
+            "mm" => Ok(SceneUnit::Millimeter),
+            "in" => Ok(SceneUnit::Inch),
+            "ft" => Ok(SceneUnit::Foot),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::str::FromStr for Light {
+    type Err = ();
+    /// Parses `sun:dx,dy,dz:color` or `point:x,y,z:color[:falloff]`, see
+    /// `parse_light_color` for the color.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        match parts[..] {
+            ["sun", direction, color] => Ok(Light::Directional {
+                direction: direction.parse()?,
+                color: parse_light_color(color, false)?,
+            }),
+            ["point", position, color] => Ok(Light::Point {
+                position: position.parse()?,
+                color: parse_light_color(color, true)?,
+                falloff: 2.0,
+            }),
+            ["point", position, color, falloff] => Ok(Light::Point {
+                position: position.parse()?,
+                color: parse_light_color(color, true)?,
+                falloff: falloff.parse().map_err(|_| ())?,
+            }),
+            _ => Err(()),
+        }
+    }
+}
@@ -0,0 +1,489 @@
+//! Surface materials and how they scatter light.
+
+use crate::hittable::portal::Window;
+use crate::hittable::Intersection;
+use crate::pdf::basis;
+use crate::ray::Ray;
+use crate::texture::{ImageTexture, Texture};
+use crate::vec3::{random, Vector3};
+
+#[derive(Copy, Clone, Debug)]
+pub enum Material {
+    Lambertian {
+        albedo: Texture,
+    },
+    Metal {
+        albedo: Texture,
+        fuzz: f64,
+    },
+    /// Glass-like refraction; `roughness` perturbs the surface normal for
+    /// frosted glass and choppy water. Light inside fades by `absorption`
+    /// per unit of distance and color channel, after Beer and Lambert.
+    Dielectric {
+        ir: f64,
+        roughness: f64,
+        absorption: Vector3,
+    },
+    /// Emits `emit` from both sides and scatters nothing, for area lights.
+    /// A `gel` multiplies the emission over the surface, for gobos and
+    /// patterned softboxes. Negative emission darkens what it reaches, which
+    /// no real light does, for art direction.
+    DiffuseLight {
+        emit: Vector3,
+        gel: Option<Texture>,
+    },
+    /// Scatters equally in all directions, for points inside a
+    /// `ConstantMedium` or `HeterogeneousMedium`, and glows with `emit`.
+    Isotropic {
+        albedo: Texture,
+        emit: Vector3,
+    },
+    /// A window of a `Portal`: rays go on from the matching spot of `to`.
+    Portal {
+        from: Window,
+        to: Window,
+    },
+    /// `base` with its shading normal tilted by `map`, for surface detail
+    /// without extra geometry; see `Intersection::shaded_with`.
+    Mapped {
+        base: &'static Material,
+        map: SurfaceMap,
+    },
+    /// `a` where the luminance of `factor` is 0 and `b` where it is 1, picked
+    /// at random in between; see `Intersection::shaded_with`.
+    Mix {
+        factor: Texture,
+        a: &'static Material,
+        b: &'static Material,
+    },
+    /// A glTF metallic-roughness material, which gives way to a diffuse,
+    /// metal or light material at each hit; see `Intersection::shaded_with`.
+    Pbr(&'static PbrMaterial),
+}
+
+/// The textures and factors of a glTF metallic-roughness material.
+#[derive(Copy, Clone, Debug)]
+pub struct PbrMaterial {
+    pub albedo: Texture,
+    pub metallic: f64,
+    pub roughness: f64,
+    /// Roughness in green and metalness in blue, scaling `roughness` and
+    /// `metallic`.
+    pub metallic_roughness_map: Option<&'static ImageTexture>,
+    /// Ambient occlusion in red, darkening the albedo by
+    /// `occlusion_strength` of it.
+    pub occlusion_map: Option<&'static ImageTexture>,
+    pub occlusion_strength: f64,
+    /// Radiance emitted, times `emission_map` where given.
+    pub emission: Vector3,
+    pub emission_map: Option<Texture>,
+}
+
+impl PbrMaterial {
+    /// The material the surface acts as at `i`, seen at an angle with cosine
+    /// `cos` to its normal: metal as often as it is metallic, else a mirror
+    /// as often as a dielectric of index 1.5 reflects there and diffuse
+    /// otherwise, all as rough as it is. Surfaces either glow or scatter, so
+    /// glowing ones do each half of the time, twice as strongly.
+    pub(crate) fn settle(&self, i: &Intersection, cos: f64) -> Material {
+        let (u, v, p) = (i.u, i.v, &i.p);
+        let mut albedo = self.albedo.value_facing(u, v, p, cos, i.color);
+        let (mut metallic, mut roughness) = (self.metallic, self.roughness);
+        if let Some(map) = self.metallic_roughness_map {
+            let c = map.at(u, v);
+            metallic *= c.z();
+            roughness *= c.y();
+        }
+        if let Some(map) = self.occlusion_map {
+            albedo *= 1.0 + self.occlusion_strength * (map.at(u, v).x() - 1.0);
+        }
+        let emission = match self.emission_map {
+            Some(map) => self.emission * map.value_facing(u, v, p, cos, i.color),
+            None => self.emission,
+        };
+        let mut scale = 1.0;
+        if !emission.near_zero() {
+            if random() < 0.5 {
+                return Material::DiffuseLight {
+                    emit: emission * 2.0,
+                    gel: None,
+                };
+            }
+            scale = 2.0;
+        }
+        if random() < metallic {
+            Material::Metal {
+                albedo: Texture::Solid(albedo * scale),
+                fuzz: roughness,
+            }
+        } else if random() < reflectance(cos, 1.5) {
+            Material::Metal {
+                albedo: Texture::Solid(Vector3(scale, scale, scale)),
+                fuzz: roughness,
+            }
+        } else {
+            Material::Lambertian {
+                albedo: Texture::Solid(albedo * scale),
+            }
+        }
+    }
+}
+
+/// Surface detail faked by tilting the shading normal.
+#[derive(Copy, Clone, Debug)]
+pub enum SurfaceMap {
+    /// A tangent-space normal map over the texture coordinates: red, green
+    /// and blue hold the normal along growing u, along growing v and out of
+    /// the surface, from -1 at zero to 1 at full. `strength` scales the tilt.
+    Normal {
+        image: &'static ImageTexture,
+        strength: f64,
+    },
+    /// Bumps as high as the luminance of `height` times `strength`, tilting
+    /// the normal down their slopes.
+    Bump { height: Texture, strength: f64 },
+}
+
+impl SurfaceMap {
+    /// The shading normal at `i`, on the same side of the surface as
+    /// `i.normal`.
+    pub(crate) fn normal(&self, i: &Intersection) -> Vector3 {
+        let n = i.normal;
+        let tangent = i.tangent - n * n.dot(&i.tangent);
+        let (tangent, bitangent) = if tangent.near_zero() {
+            basis(&n)
+        } else {
+            let tangent = tangent.normalize();
+            (tangent, n.cross(&tangent))
+        };
+        let tilted = match self {
+            SurfaceMap::Normal { image, strength } => {
+                let c = image.at(i.u, i.v) * 2.0 - Vector3(1.0, 1.0, 1.0);
+                tangent * (c.x() * strength) + bitangent * (c.y() * strength) + n * c.z()
+            }
+            SurfaceMap::Bump { height, strength } => {
+                // slopes by forward differences, stepping the texture
+                // coordinates and the point alike
+                const STEP: f64 = 1e-3;
+                let h = |u: f64, v: f64, p: Vector3| {
+                    let c = height.value(u, v, &p);
+                    (0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()) * strength
+                };
+                let h0 = h(i.u, i.v, i.p);
+                let du = (h(i.u + STEP, i.v, i.p + tangent * STEP) - h0) / STEP;
+                let dv = (h(i.u, i.v + STEP, i.p + bitangent * STEP) - h0) / STEP;
+                n - tangent * du - bitangent * dv
+            }
+        };
+        // a tilt past the horizon would shade the surface from behind
+        if tilted.dot(&n) > 1e-3 {
+            tilted.normalize()
+        } else {
+            n
+        }
+    }
+}
+
+impl std::str::FromStr for Material {
+    type Err = ();
+    /// Parses `clay` (plain gray), `diffuse:r,g,b`, `metal:r,g,b[:fuzz]`,
+    /// `glass[:ir]` or `light:r,g,b`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let number = |s: &str| s.parse::<f64>().map_err(|_| ());
+        match parts[..] {
+            ["clay"] => Ok(Material::Lambertian {
+                albedo: Vector3(0.5, 0.5, 0.5).into(),
+            }),
+            ["diffuse", albedo] => Ok(Material::Lambertian {
+                albedo: Texture::Solid(albedo.parse()?),
+            }),
+            ["metal", albedo] => Ok(Material::Metal {
+                albedo: Texture::Solid(albedo.parse()?),
+                fuzz: 0.0,
+            }),
+            ["metal", albedo, fuzz] => Ok(Material::Metal {
+                albedo: Texture::Solid(albedo.parse()?),
+                fuzz: number(fuzz)?,
+            }),
+            ["glass"] => Ok(Material::Dielectric {
+                ir: 1.5,
+                roughness: 0.0,
+                absorption: Vector3(0.0, 0.0, 0.0),
+            }),
+            ["glass", ir] => Ok(Material::Dielectric {
+                ir: number(ir)?,
+                roughness: 0.0,
+                absorption: Vector3(0.0, 0.0, 0.0),
+            }),
+            ["light", emit] => Ok(Material::DiffuseLight {
+                emit: emit.parse()?,
+                gel: None,
+            }),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Material {
+    /// The material with its node and vertex color textures replaced by
+    /// their colors at `u`, `v` and `p` as seen at an angle with cosine `cos`
+    /// to the normal, where the surface is painted `color`.
+    pub(crate) fn facing(self, u: f64, v: f64, p: &Vector3, cos: f64, color: Vector3) -> Self {
+        let settle = |t: Texture| match t {
+            Texture::Node(_) | Texture::VertexColor { .. } => {
+                Texture::Solid(t.value_facing(u, v, p, cos, color))
+            }
+            _ => t,
+        };
+        match self {
+            Material::Lambertian { albedo } => Material::Lambertian {
+                albedo: settle(albedo),
+            },
+            Material::Metal { albedo, fuzz } => Material::Metal {
+                albedo: settle(albedo),
+                fuzz,
+            },
+            Material::Isotropic { albedo, emit } => Material::Isotropic {
+                albedo: settle(albedo),
+                emit,
+            },
+            Material::DiffuseLight { emit, gel } => Material::DiffuseLight {
+                emit,
+                gel: gel.map(settle),
+            },
+            other => other,
+        }
+    }
+
+    /// The surface color at texture coordinates `u`, `v` and point `p`,
+    /// ignoring lighting; dielectrics and portals count as white and lights as
+    /// their emission.
+    pub fn albedo(&self, u: f64, v: f64, p: &Vector3) -> Vector3 {
+        match self {
+            Material::Lambertian { albedo }
+            | Material::Metal { albedo, .. }
+            | Material::Isotropic { albedo, .. } => albedo.value(u, v, p),
+            Material::Dielectric { .. } | Material::Portal { .. } => Vector3(1.0, 1.0, 1.0),
+            Material::DiffuseLight { .. } => self.emitted_at(u, v, p),
+            Material::Mapped { base, .. } => base.albedo(u, v, p),
+            Material::Mix { factor, a, b } => {
+                let f = luminance(factor.value(u, v, p));
+                a.albedo(u, v, p) * (1.0 - f) + b.albedo(u, v, p) * f
+            }
+            Material::Pbr(pbr) => pbr.albedo.value(u, v, p),
+        }
+    }
+
+    /// Radiance the surface emits by itself, before any gel.
+    pub fn emitted(&self) -> Vector3 {
+        match self {
+            Material::DiffuseLight { emit, .. } | Material::Isotropic { emit, .. } => *emit,
+            Material::Pbr(pbr) => pbr.emission,
+            Material::Mapped { base, .. } => base.emitted(),
+            // without a place on the surface, half of each
+            Material::Mix { a, b, .. } => (a.emitted() + b.emitted()) * 0.5,
+            _ => Vector3(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Radiance the surface emits at texture coordinates `u`, `v` and point
+    /// `p`, through its gel.
+    pub fn emitted_at(&self, u: f64, v: f64, p: &Vector3) -> Vector3 {
+        match self {
+            Material::DiffuseLight {
+                emit,
+                gel: Some(gel),
+            } => *emit * gel.value(u, v, p),
+            Material::Pbr(PbrMaterial {
+                emission,
+                emission_map: Some(map),
+                ..
+            }) => *emission * map.value(u, v, p),
+            Material::Mapped { base, .. } => base.emitted_at(u, v, p),
+            Material::Mix { factor, a, b } => {
+                let f = luminance(factor.value(u, v, p));
+                a.emitted_at(u, v, p) * (1.0 - f) + b.emitted_at(u, v, p) * f
+            }
+            _ => self.emitted(),
+        }
+    }
+
+    /// The image the surface glows through, as a light's gel or a
+    /// metallic-roughness material's emission map, for sampling its bright
+    /// texels.
+    pub(crate) fn emission_image(&self) -> Option<&'static ImageTexture> {
+        match self {
+            Material::DiffuseLight {
+                gel: Some(Texture::Image { image, .. }),
+                ..
+            }
+            | Material::Pbr(PbrMaterial {
+                emission_map: Some(Texture::Image { image, .. }),
+                ..
+            }) => Some(image),
+            _ => None,
+        }
+    }
+
+    /// Whether the surface's emission passes through a gel.
+    pub(crate) fn gelled(&self) -> bool {
+        match self {
+            Material::DiffuseLight { gel, .. } => gel.is_some(),
+            Material::Pbr(pbr) => pbr.emission_map.is_some(),
+            Material::Mapped { base, .. } => base.gelled(),
+            Material::Mix { a, b, .. } => a.gelled() || b.gelled(),
+            _ => false,
+        }
+    }
+
+    /// Density, per steradian, with which the surface scatters light into the
+    /// direction of `scattered`; zero for materials that scatter into single
+    /// directions or not at all.
+    pub fn scattering_pdf(&self, intersection: &Intersection, scattered: &Ray) -> f64 {
+        match self {
+            Material::Lambertian { .. } => {
+                let cos = intersection.normal.dot(&scattered.direction.normalize());
+                (cos / std::f64::consts::PI).max(0.0)
+            }
+            Material::Isotropic { .. } => 1.0 / (4.0 * std::f64::consts::PI),
+            Material::Mapped { base, .. } => base.scattering_pdf(intersection, scattered),
+            Material::Mix { factor, a, b } => {
+                let i = intersection;
+                let f = luminance(factor.value(i.u, i.v, &i.p));
+                a.scattering_pdf(i, scattered) * (1.0 - f) + b.scattering_pdf(i, scattered) * f
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Scatters `r_in` at `intersection`. `surrounding` is the index of
+    /// refraction outside a dielectric, 1 for air.
+    pub fn scatter(
+        &self,
+        r_in: &Ray,
+        intersection: Intersection,
+        surrounding: f64,
+    ) -> Option<(Vector3, Ray)> {
+        match self {
+            Material::Lambertian { albedo } => {
+                let mut scatter_direction = intersection.normal + Vector3::random_unit_vector();
+                if scatter_direction.near_zero() {
+                    scatter_direction = intersection.normal;
+                }
+                let scattered = Ray {
+                    direction: scatter_direction,
+                    origin: intersection.p,
+                    time: r_in.time,
+                };
+                Some((
+                    albedo.value(intersection.u, intersection.v, &intersection.p),
+                    scattered,
+                ))
+            }
+            Material::Metal { albedo, fuzz } => {
+                let reflected = r_in.direction.normalize().reflect(&intersection.normal);
+                // directions the fuzz sends below the surface are drawn again
+                // rather than absorbed, which would darken rough metals the
+                // rougher they are: the lobe is cut off at the surface and
+                // scaled up to reflect all the light it receives
+                let direction = (0..FUZZ_TRIES)
+                    .map(|_| reflected + Vector3::random_in_unit_sphere() * *fuzz)
+                    .find(|d| d.dot(&intersection.normal) > 0.0)
+                    .unwrap_or(reflected);
+                let scattered = Ray {
+                    direction,
+                    origin: intersection.p,
+                    time: r_in.time,
+                };
+                let attenuation = albedo.value(intersection.u, intersection.v, &intersection.p);
+                Some((attenuation, scattered))
+            }
+            Material::Dielectric { ir, roughness, .. } => {
+                let attenuation = Vector3(1.0, 1.0, 1.0);
+                let refraction_ratio = if intersection.front_facing {
+                    surrounding / ir
+                } else {
+                    ir / surrounding
+                };
+                let unit_direction = r_in.direction.normalize();
+                let mut normal = intersection.normal;
+                if *roughness > 0.0 {
+                    let perturbed =
+                        (normal + Vector3::random_in_unit_sphere() * *roughness).normalize();
+                    if perturbed.dot(&unit_direction) < 0.0 {
+                        normal = perturbed;
+                    }
+                }
+
+                let cos_theta = (unit_direction * -1.0).dot(&normal).min(1.0);
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+                let cannot_refract = refraction_ratio * sin_theta > 1.0;
+                let direction =
+                    if cannot_refract || reflectance(cos_theta, refraction_ratio) > random() {
+                        unit_direction.reflect(&normal)
+                    } else {
+                        unit_direction.refract(&normal, refraction_ratio)
+                    };
+                let scattered = Ray {
+                    direction,
+                    origin: intersection.p,
+                    time: r_in.time,
+                };
+                Some((attenuation, scattered))
+            }
+            // portals are passed through by the integrator, see `Ray::shade`
+            Material::DiffuseLight { .. } | Material::Portal { .. } => None,
+            Material::Mix { factor, a, b } => {
+                let i = &intersection;
+                let f = luminance(factor.value(i.u, i.v, &i.p));
+                let picked = if random() < f { b } else { a };
+                picked.scatter(r_in, intersection, surrounding)
+            }
+            Material::Pbr(pbr) => {
+                let cos = r_in.direction.normalize().dot(&intersection.normal).abs();
+                pbr.settle(&intersection, cos)
+                    .scatter(r_in, intersection, surrounding)
+            }
+            Material::Mapped { base, map } => {
+                let normal = map.normal(&intersection);
+                base.scatter(
+                    r_in,
+                    Intersection {
+                        normal,
+                        ..intersection
+                    },
+                    surrounding,
+                )
+            }
+            Material::Isotropic { albedo, .. } => {
+                let scattered = Ray {
+                    direction: Vector3::random_unit_vector(),
+                    origin: intersection.p,
+                    time: r_in.time,
+                };
+                Some((
+                    albedo.value(intersection.u, intersection.v, &intersection.p),
+                    scattered,
+                ))
+            }
+        }
+    }
+}
+
+/// Draws of a fuzzy reflection before settling for the mirror direction;
+/// even at grazing angles on the roughest metals all of them land below the
+/// surface less than once in 10^4.
+const FUZZ_TRIES: usize = 16;
+
+fn luminance(c: Vector3) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+/// Schlick's approximation of the share of light a dielectric reflects.
+pub(crate) fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
+    let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
+    r0 = r0 * r0;
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+}
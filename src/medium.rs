@@ -0,0 +1,143 @@
+//! Participating media filling the space between objects.
+
+use crate::hittable::RayKind;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::vec3::{random, Vector3};
+
+/// Angular distribution of light scattered inside a medium.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum PhaseFunction {
+    #[default]
+    Isotropic,
+    /// Henyey-Greenstein with anisotropy `g` in (-1, 1); positive values
+    /// scatter forward.
+    HenyeyGreenstein { g: f64 },
+    /// Mix of a forward and a backward Henyey-Greenstein lobe, `weight` being
+    /// the share of the forward lobe. Gives clouds their silver lining while
+    /// keeping some back scattering.
+    TwoLobe {
+        g_forward: f64,
+        g_backward: f64,
+        weight: f64,
+    },
+}
+
+impl PhaseFunction {
+    /// Density per steradian of scattering by an angle with cosine `cos_theta`
+    /// between the incoming and the outgoing propagation direction.
+    pub fn eval(&self, cos_theta: f64) -> f64 {
+        let hg = |g: f64| {
+            let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+            (1.0 - g * g) / (4.0 * std::f64::consts::PI * denom * denom.sqrt())
+        };
+        match *self {
+            PhaseFunction::Isotropic => 1.0 / (4.0 * std::f64::consts::PI),
+            PhaseFunction::HenyeyGreenstein { g } => hg(g),
+            PhaseFunction::TwoLobe {
+                g_forward,
+                g_backward,
+                weight,
+            } => weight * hg(g_forward) + (1.0 - weight) * hg(g_backward),
+        }
+    }
+}
+
+impl std::str::FromStr for PhaseFunction {
+    type Err = ();
+    /// Parses `isotropic`, `hg:g` or `two-lobe:g_forward,g_backward,weight`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, params) = s.split_once(':').unwrap_or((s, ""));
+        let params: Vec<f64> = params
+            .split(',')
+            .filter(|p| !p.is_empty())
+            .map(|p| p.trim().parse().map_err(|_| ()))
+            .collect::<Result<_, _>>()?;
+        let valid_g = |g: f64| g.abs() < 1.0;
+        match (name, &params[..]) {
+            ("isotropic", []) => Ok(PhaseFunction::Isotropic),
+            ("hg", &[g]) if valid_g(g) => Ok(PhaseFunction::HenyeyGreenstein { g }),
+            ("two-lobe", &[g_forward, g_backward, weight])
+                if valid_g(g_forward) && valid_g(g_backward) && (0.0..=1.0).contains(&weight) =>
+            {
+                Ok(PhaseFunction::TwoLobe {
+                    g_forward,
+                    g_backward,
+                    weight,
+                })
+            }
+            _ => Err(()),
+        }
+    }
+}
+
+/// A homogeneous participating medium filling the whole scene. It is only
+/// applied to camera rays, with single scattering of the lights and the
+/// background computed by ray marching.
+#[derive(Clone, Copy, Debug)]
+pub struct HomogeneousMedium {
+    pub(crate) sigma_s: f64,
+    pub(crate) sigma_a: f64,
+    pub(crate) phase: PhaseFunction,
+    pub(crate) steps: usize,
+    /// How far camera rays that hit nothing travel through the medium.
+    pub(crate) max_distance: f64,
+}
+
+impl HomogeneousMedium {
+    pub fn new(sigma_s: f64, sigma_a: f64) -> Self {
+        Self {
+            sigma_s,
+            sigma_a,
+            phase: PhaseFunction::Isotropic,
+            steps: 16,
+            max_distance: 100.0,
+        }
+    }
+
+    /// Attenuates the `radiance` arriving along the camera ray `r` and adds the
+    /// light scattered towards the camera along the way.
+    pub(crate) fn apply(&self, scene: &Scene, r: &Ray, radiance: Vector3) -> Vector3 {
+        let direction = r.direction.normalize();
+        let r = Ray {
+            origin: r.origin,
+            direction,
+            time: r.time,
+        };
+        let end = scene
+            .world
+            .hit_as(&r, 0.001, f64::INFINITY, RayKind::Camera)
+            .map_or(self.max_distance, |i| i.t);
+        let sigma_t = self.sigma_s + self.sigma_a;
+        let step = end / self.steps as f64;
+        let offset = random();
+        let mut inscattered = Vector3(0.0, 0.0, 0.0);
+        for k in 0..self.steps {
+            let s = (k as f64 + offset) * step;
+            let x = r.at(s);
+            // one background sample for the light scattered from the sky
+            let sky_direction = Vector3::random_unit_vector();
+            let mut incoming = if scene.visible(&x, &sky_direction, f64::INFINITY) {
+                let phase = self.phase.eval(sky_direction.dot(&direction));
+                scene.background(&sky_direction) * (4.0 * std::f64::consts::PI) * phase
+            } else {
+                Vector3(0.0, 0.0, 0.0)
+            };
+            for light in &scene.lights {
+                let (to_light, distance, irradiance) = light.illuminate(&x);
+                let shadow = scene.transmittance_at(&x, &to_light, distance, r.time);
+                if shadow.length_squared() > 0.0 {
+                    let attenuation = if distance.is_finite() {
+                        (-sigma_t * distance).exp()
+                    } else {
+                        1.0
+                    };
+                    let phase = self.phase.eval(to_light.dot(&direction));
+                    incoming += irradiance * shadow * phase * attenuation;
+                }
+            }
+            inscattered += incoming * (self.sigma_s * (-sigma_t * s).exp() * step);
+        }
+        radiance * (-sigma_t * end).exp() + inscattered
+    }
+}
@@ -0,0 +1,111 @@
+//! Probability densities over directions, for sending scattered rays where
+//! light is likely to come from, after *Ray Tracing: The Rest of Your Life*.
+
+use crate::hittable::HittableStore;
+use crate::ray::Ray;
+use crate::vec3::{random, Vector3};
+
+/// A distribution of directions that can be sampled and evaluated.
+pub trait Pdf {
+    /// Density of `direction`, per steradian.
+    fn value(&self, direction: &Vector3) -> f64;
+
+    /// A random direction drawn from the distribution.
+    fn generate(&self) -> Vector3;
+}
+
+/// Two unit vectors that make a right-handed orthonormal basis with unit `w`.
+pub(crate) fn basis(w: &Vector3) -> (Vector3, Vector3) {
+    let a = if w.x().abs() > 0.9 {
+        Vector3(0.0, 1.0, 0.0)
+    } else {
+        Vector3(1.0, 0.0, 0.0)
+    };
+    let v = w.cross(&a).normalize();
+    (w.cross(&v), v)
+}
+
+/// Directions around `normal` with density proportional to their cosine, how
+/// a diffuse surface scatters.
+pub struct CosinePdf {
+    pub normal: Vector3,
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: &Vector3) -> f64 {
+        (direction.normalize().dot(&self.normal) / std::f64::consts::PI).max(0.0)
+    }
+
+    fn generate(&self) -> Vector3 {
+        let direction = self.normal + Vector3::random_unit_vector();
+        if direction.near_zero() {
+            self.normal
+        } else {
+            direction
+        }
+    }
+}
+
+/// Directions from `origin` towards the objects `ids` of `world`, picked in
+/// proportion to their `HittableStore::importance`; see
+/// `Hittable::random_direction`.
+pub struct HittablePdf<'a> {
+    pub world: &'a HittableStore,
+    pub ids: &'a [usize],
+    pub origin: Vector3,
+    pub time: f64,
+}
+
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: &Vector3) -> f64 {
+        let r = Ray {
+            origin: self.origin,
+            direction: *direction,
+            time: self.time,
+        };
+        let (sum, total) = self.ids.iter().fold((0.0, 0.0), |(sum, total), &id| {
+            let weight = self.world.importance[id];
+            (
+                sum + weight * self.world.objects[id].pdf_value(&r),
+                total + weight,
+            )
+        });
+        sum / total
+    }
+
+    fn generate(&self) -> Vector3 {
+        let total: f64 = self.ids.iter().map(|&id| self.world.importance[id]).sum();
+        let mut pick = random() * total;
+        let id = *self
+            .ids
+            .iter()
+            .find(|&&id| {
+                pick -= self.world.importance[id];
+                pick < 0.0
+            })
+            .unwrap_or(&self.ids[self.ids.len() - 1]);
+        self.world.objects[id]
+            .random_direction(&self.origin)
+            .unwrap_or_else(Vector3::random_unit_vector)
+    }
+}
+
+/// An even mix of two distributions.
+pub struct MixturePdf<A, B> {
+    pub a: A,
+    pub b: B,
+}
+
+impl<A: Pdf, B: Pdf> Pdf for MixturePdf<A, B> {
+    fn value(&self, direction: &Vector3) -> f64 {
+        0.5 * self.a.value(direction) + 0.5 * self.b.value(direction)
+    }
+
+    fn generate(&self) -> Vector3 {
+        if random() < 0.5 {
+            self.a.generate()
+        } else {
+            self.b.generate()
+        }
+    }
+}
@@ -0,0 +1,604 @@
+//! Stochastic progressive photon mapping (Hachisuka and Jensen 2009). Every
+//! iteration follows one camera path per pixel to the first diffuse surface
+//! it meets, then shoots photons from the lights; photons landing within a
+//! pixel's radius of that point add to its flux, and the radius shrinks as
+//! they arrive. Caustics converge this way with a photon map no larger than
+//! one iteration's photons.
+//!
+//! Fog, participating media, volumes, light linking and dielectric
+//! priorities are left out; every dielectric borders air.
+
+use crate::camera::Camera;
+use crate::hittable::portal::pass_through;
+use crate::hittable::{Aabb, Hittable, Intersection, RayKind};
+use crate::light::Light;
+use crate::material::Material;
+use crate::pdf::{basis, CosinePdf, HittablePdf, MixturePdf, Pdf};
+use crate::ray::Ray;
+use crate::render::{film_position, Progress, RenderSettings};
+use crate::scene::Scene;
+use crate::vec3::{derive_seed, random, reseed, Vector3};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+/// Share of the photons found within a pixel's radius that are kept when it
+/// shrinks, alpha in the paper.
+const ALPHA: f64 = 2.0 / 3.0;
+
+/// Photons traced with the random numbers of one seed.
+const PHOTON_CHUNK: usize = 4096;
+
+/// Directions the sky is looked at in to weigh it against the lights.
+const SKY_DIRECTIONS: usize = 256;
+
+/// The starting radius without `--photon-radius`, as a fraction of the
+/// diagonal of the box around the first iteration's visible points.
+const RADIUS_FRACTION: f64 = 0.02;
+
+fn luminance(c: &Vector3) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+/// What a pixel has gathered over all iterations.
+#[derive(Clone, Copy, Debug, Default)]
+struct PixelState {
+    radius: f64,
+    /// Photons counted so far, N in the paper.
+    photons: f64,
+    /// Flux of those photons times the camera path's throughput, tau.
+    flux: Vector3,
+    /// Radiance summed over iterations from the lights and glowing objects
+    /// seen directly or after specular bounces.
+    direct: Vector3,
+}
+
+/// Where a pixel's camera path met a diffuse surface in this iteration.
+#[derive(Clone, Copy, Debug)]
+struct VisiblePoint {
+    p: Vector3,
+    /// Facing the camera path.
+    normal: Vector3,
+    /// Throughput of the camera path times the diffuse BRDF.
+    weight: Vector3,
+}
+
+/// A photon that arrived at a diffuse surface after at least one bounce.
+#[derive(Clone, Copy, Debug)]
+struct PhotonHit {
+    p: Vector3,
+    direction: Vector3,
+    power: Vector3,
+}
+
+/// Where photons come from, with what share of them.
+#[derive(Clone, Copy, Debug)]
+enum Source {
+    Light(usize),
+    /// A glowing object and its emission.
+    Emitter(usize, Vector3),
+    Sky,
+}
+
+pub(crate) struct Sppm {
+    /// Photons shot per iteration.
+    photons: usize,
+    /// Starting radius, picked in the first iteration if `None`.
+    radius: Option<f64>,
+    pixels: Vec<PixelState>,
+    iterations: usize,
+}
+
+impl Sppm {
+    pub(crate) fn new(width: u32, height: u32, photons: usize, radius: Option<f64>) -> Self {
+        Self {
+            photons,
+            radius,
+            pixels: vec![PixelState::default(); (width * height) as usize],
+            iterations: 0,
+        }
+    }
+
+    /// Runs one iteration: a camera path per pixel within the crop, then the
+    /// photons. Its random numbers follow from the seed of `settings`.
+    pub(crate) fn iterate(
+        &mut self,
+        cam: &Camera,
+        scene: &Scene,
+        settings: &RenderSettings,
+        progress: Option<&Progress>,
+    ) {
+        let RenderSettings {
+            width,
+            height,
+            max_depth,
+            crop,
+            jitter,
+            ..
+        } = *settings;
+        let seed = settings
+            .seed
+            .map(|seed| derive_seed(seed, self.iterations as u64));
+        let (jitter_x, jitter_y) = jitter.unwrap_or((0.0, 0.0));
+        let (x0, y0, x1, y1) = crop.unwrap_or((0, 0, width, height));
+        let points: Vec<Option<VisiblePoint>> = self
+            .pixels
+            .par_iter_mut()
+            .enumerate()
+            .map(|(index, pixel)| {
+                let (i, y) = (index as u32 % width, index as u32 / width);
+                if i < x0 || i >= x1 || y < y0 || y >= y1 {
+                    return None;
+                }
+                if let Some(seed) = seed {
+                    reseed(derive_seed(seed, index as u64));
+                }
+                let u = (i as f64 + jitter_x) / (width - 1) as f64;
+                let v = ((height - 1 - y) as f64 - jitter_y) / (height - 1) as f64;
+                let film = film_position(u, v, width, height);
+                let (direct, point) = camera_path(&cam.get_ray(u, v), film, scene, max_depth);
+                pixel.direct += direct;
+                point
+            })
+            .collect();
+        self.iterations += 1;
+
+        let bounds = points
+            .iter()
+            .flatten()
+            .map(|point| Aabb {
+                min: point.p,
+                max: point.p,
+            })
+            .reduce(|a, b| a.union(&b));
+        if let Some(bounds) = bounds {
+            let diagonal = (bounds.max - bounds.min).length().max(1e-3);
+            let start = *self.radius.get_or_insert(diagonal * RADIUS_FRACTION);
+            for pixel in &mut self.pixels {
+                if pixel.radius == 0.0 {
+                    pixel.radius = start;
+                }
+            }
+            let hits = self.shoot(cam, scene, max_depth, &bounds, seed);
+            self.gather(&points, &hits);
+        }
+        if let Some(progress) = progress {
+            progress.add((x1 - x0) as u64 * (y1 - y0) as u64);
+        }
+    }
+
+    /// Traces this iteration's photons and returns those that land within
+    /// reach of `bounds`, the box around the visible points.
+    fn shoot(
+        &self,
+        cam: &Camera,
+        scene: &Scene,
+        max_depth: usize,
+        bounds: &Aabb,
+        seed: Option<u64>,
+    ) -> Vec<PhotonHit> {
+        let reach = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.radius)
+            .fold(0.0, f64::max);
+        let center = (bounds.min + bounds.max) * 0.5;
+        // photons from far away light the sphere around the visible points
+        // through a disk as large, starting outside everything in the scene
+        let radius = (bounds.max - center).length() + reach;
+        let distance = scene.world.bounding_box().map_or(100.0 * radius, |world| {
+            (world.max - world.min).length() + (world.min - center).length()
+        }) + radius;
+        let disk = radius * radius * PI;
+
+        let mut sources: Vec<(Source, f64)> = Vec::new();
+        for (id, light) in scene.lights.iter().enumerate() {
+            let power = match light {
+                Light::Point { color, .. } => *color * (4.0 * PI),
+                Light::Directional { color, .. } => *color * disk,
+            };
+            sources.push((Source::Light(id), luminance(&power)));
+        }
+        for &id in &scene.emitters {
+            let object = &scene.world.objects[id];
+            let emit = object
+                .materials()
+                .iter()
+                .map(|m| scene.color_space.material(*m).emitted())
+                .find(|e| luminance(e) > 0.0);
+            if let (Some(emit), Some((_, _, area))) = (emit, object.random_point()) {
+                let power = emit * (area * 2.0 * PI);
+                sources.push((Source::Emitter(id, emit), luminance(&power)));
+            }
+        }
+        // mean sky luminance over directions of a Fibonacci spiral, only to
+        // share out the photons, so the same in every iteration
+        let sky: f64 = (0..SKY_DIRECTIONS)
+            .map(|k| {
+                let y = 1.0 - (2 * k + 1) as f64 / SKY_DIRECTIONS as f64;
+                let r = (1.0 - y * y).sqrt();
+                let phi = k as f64 * PI * (3.0 - 5f64.sqrt());
+                luminance(&scene.background(&Vector3(r * phi.cos(), y, r * phi.sin())))
+            })
+            .sum::<f64>()
+            / SKY_DIRECTIONS as f64;
+        sources.push((Source::Sky, sky * 4.0 * PI * disk));
+        let total: f64 = sources.iter().map(|(_, power)| power).sum();
+        if total <= 0.0 {
+            return Vec::new();
+        }
+
+        let emit = |source: Source| -> (Ray, Vector3) {
+            let time = cam.time();
+            let from_far = |toward: Vector3| {
+                let (u, v) = basis(&toward);
+                let offset = Vector3::random_in_unit_disk() * radius;
+                Ray {
+                    origin: center + toward * distance + u * offset.x() + v * offset.y(),
+                    direction: toward * -1.0,
+                    time,
+                }
+            };
+            match source {
+                Source::Light(id) => match scene.lights[id] {
+                    Light::Point {
+                        position, color, ..
+                    } => {
+                        let r = Ray {
+                            origin: position,
+                            direction: Vector3::random_unit_vector(),
+                            time,
+                        };
+                        (r, color * (4.0 * PI))
+                    }
+                    Light::Directional { direction, color } => {
+                        (from_far(direction.normalize()), color * disk)
+                    }
+                },
+                Source::Emitter(id, emit) => {
+                    let (p, normal, area) = scene.world.objects[id].random_point().unwrap();
+                    // both sides glow
+                    let normal = if random() < 0.5 {
+                        normal
+                    } else {
+                        normal * -1.0
+                    };
+                    let r = Ray {
+                        origin: p,
+                        direction: CosinePdf { normal }.generate(),
+                        time,
+                    };
+                    (r, emit * (area * 2.0 * PI))
+                }
+                Source::Sky => {
+                    let toward = Vector3::random_unit_vector();
+                    (
+                        from_far(toward),
+                        scene.background(&toward) * (4.0 * PI * disk),
+                    )
+                }
+            }
+        };
+        let (lo, hi) = (
+            bounds.min - Vector3(reach, reach, reach),
+            bounds.max + Vector3(reach, reach, reach),
+        );
+        let within = |p: &Vector3| {
+            (lo.x()..=hi.x()).contains(&p.x())
+                && (lo.y()..=hi.y()).contains(&p.y())
+                && (lo.z()..=hi.z()).contains(&p.z())
+        };
+        let chunks = self.photons.div_ceil(PHOTON_CHUNK);
+        let pixels = self.pixels.len() as u64;
+        (0..chunks)
+            .into_par_iter()
+            .flat_map_iter(|chunk| {
+                if let Some(seed) = seed {
+                    reseed(derive_seed(seed, pixels + chunk as u64));
+                }
+                let count = PHOTON_CHUNK.min(self.photons - chunk * PHOTON_CHUNK);
+                let mut hits = Vec::new();
+                for _ in 0..count {
+                    let mut pick = random() * total;
+                    let (source, power) = *sources
+                        .iter()
+                        .find(|(_, power)| {
+                            pick -= *power;
+                            pick < 0.0
+                        })
+                        .unwrap_or(&sources[sources.len() - 1]);
+                    if power <= 0.0 {
+                        continue;
+                    }
+                    let (r, flux) = emit(source);
+                    trace_photon(r, flux * (total / power), scene, max_depth, &mut |hit| {
+                        if within(&hit.p) {
+                            hits.push(hit);
+                        }
+                    });
+                }
+                hits
+            })
+            .collect()
+    }
+
+    /// Adds the photons that landed near each visible point to its pixel and
+    /// shrinks the pixel's radius.
+    fn gather(&mut self, points: &[Option<VisiblePoint>], hits: &[PhotonHit]) {
+        let cell = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.radius)
+            .fold(0.0, f64::max);
+        let key = |p: Vector3| {
+            (
+                (p.x() / cell).floor() as i64,
+                (p.y() / cell).floor() as i64,
+                (p.z() / cell).floor() as i64,
+            )
+        };
+        let mut grid: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+        for (index, hit) in hits.iter().enumerate() {
+            grid.entry(key(hit.p)).or_default().push(index);
+        }
+        self.pixels
+            .par_iter_mut()
+            .zip(points)
+            .for_each(|(pixel, point)| {
+                let point = match point {
+                    Some(point) => point,
+                    None => return,
+                };
+                let r = pixel.radius;
+                let (lo, hi) = (
+                    key(point.p - Vector3(r, r, r)),
+                    key(point.p + Vector3(r, r, r)),
+                );
+                let mut found = 0.0;
+                let mut flux = Vector3(0.0, 0.0, 0.0);
+                for x in lo.0..=hi.0 {
+                    for y in lo.1..=hi.1 {
+                        for z in lo.2..=hi.2 {
+                            for &index in grid.get(&(x, y, z)).into_iter().flatten() {
+                                let hit = &hits[index];
+                                // only photons arriving on the side the camera sees
+                                if (hit.p - point.p).length_squared() <= r * r
+                                    && hit.direction.dot(&point.normal) < 0.0
+                                {
+                                    found += 1.0;
+                                    flux += hit.power * point.weight;
+                                }
+                            }
+                        }
+                    }
+                }
+                if found > 0.0 {
+                    let photons = pixel.photons + ALPHA * found;
+                    let radius = r * (photons / (pixel.photons + found)).sqrt();
+                    pixel.flux = (pixel.flux + flux) * ((radius * radius) / (r * r));
+                    pixel.photons = photons;
+                    pixel.radius = radius;
+                }
+            });
+    }
+
+    /// The estimate per pixel so far, in linear sRGB.
+    pub(crate) fn radiance(&self, scene: &Scene) -> Vec<Vector3> {
+        let n = self.iterations.max(1) as f64;
+        let emitted = n * self.photons as f64;
+        self.pixels
+            .iter()
+            .map(|pixel| {
+                let area = PI * pixel.radius * pixel.radius;
+                let gathered = if area > 0.0 {
+                    pixel.flux / (emitted * area)
+                } else {
+                    Vector3(0.0, 0.0, 0.0)
+                };
+                scene
+                    .color_space
+                    .to_linear_srgb(pixel.direct / n + gathered)
+            })
+            .collect()
+    }
+}
+
+/// `i` with the material it is shaded with, hit by a ray along `direction`.
+fn shaded(scene: &Scene, i: Intersection, direction: &Vector3) -> Intersection {
+    let i = i.shaded_with(scene.material_override.unwrap_or(i.material), direction);
+    Intersection {
+        material: scene.color_space.material(i.material),
+        ..i
+    }
+}
+
+/// Light fading inside a dielectric over the segment of `r` to `i`, which
+/// leaves it if `i` is hit from the back.
+fn transmittance(r: &Ray, i: &Intersection) -> Vector3 {
+    match i.material {
+        Material::Dielectric { absorption, .. } if !i.front_facing => {
+            let d = i.t * r.direction.length();
+            Vector3(
+                (-absorption.x() * d).exp(),
+                (-absorption.y() * d).exp(),
+                (-absorption.z() * d).exp(),
+            )
+        }
+        _ => Vector3(1.0, 1.0, 1.0),
+    }
+}
+
+/// Follows a camera ray through specular bounces to its first diffuse
+/// surface. Returns the radiance reaching the camera directly from lights,
+/// glowing objects and the sky, including what they shine onto that surface,
+/// and the surface point to gather photons at. `film` is where the ray
+/// leaves the film, see `film_position`.
+fn camera_path(
+    r: &Ray,
+    film: (f64, f64),
+    scene: &Scene,
+    max_depth: usize,
+) -> (Vector3, Option<VisiblePoint>) {
+    let mut ray = *r;
+    let mut throughput = Vector3(1.0, 1.0, 1.0);
+    for depth in 0..max_depth {
+        let kind = if depth == 0 {
+            RayKind::Camera
+        } else {
+            RayKind::Indirect
+        };
+        let i = match scene.world.hit_as(&ray, 0.001, f64::INFINITY, kind) {
+            Some(i) => shaded(scene, i, &ray.direction),
+            None => {
+                let radiance = match (&scene.backdrop, scene.plate) {
+                    (_, Some(plate)) if depth == 0 => {
+                        scene.color_space.from_linear_srgb(plate.at(film.0, film.1))
+                    }
+                    (Some(backdrop), _) if depth == 0 => scene
+                        .color_space
+                        .from_linear_srgb(backdrop.radiance(&ray.direction)),
+                    _ => scene.background(&ray.direction) + scene.sun_disk(&ray.direction),
+                };
+                return (throughput * radiance, None);
+            }
+        };
+        throughput = throughput * transmittance(&ray, &i);
+        match i.material {
+            Material::Lambertian { .. } => {
+                let albedo = i.material.albedo(i.u, i.v, &i.p);
+                let lights = scene
+                    .light_irradiance(&i.p, Some(&i.normal), Some(i.object), ray.time)
+                    .fold(Vector3(0.0, 0.0, 0.0), |acc, (_, irradiance)| {
+                        acc + irradiance / PI
+                    });
+                let direct = albedo * (lights + gather_emission(&ray, &i, scene));
+                let point = VisiblePoint {
+                    p: i.p,
+                    normal: i.normal,
+                    weight: throughput * albedo / PI,
+                };
+                return (throughput * direct, Some(point));
+            }
+            Material::Portal { from, to } => {
+                let (origin, direction) = pass_through(&from, &to, i.u, i.v, ray.direction);
+                ray = Ray {
+                    origin,
+                    direction,
+                    time: ray.time,
+                };
+            }
+            Material::Isotropic { .. } => {
+                ray.origin = i.p;
+            }
+            _ => match i.material.scatter(&ray, i, 1.0) {
+                Some((attenuation, scattered)) => {
+                    throughput = throughput * attenuation;
+                    ray = scattered;
+                }
+                None => return (throughput * i.material.emitted_at(i.u, i.v, &i.p), None),
+            },
+        }
+    }
+    (Vector3(0.0, 0.0, 0.0), None)
+}
+
+/// Radiance from the sky and glowing objects arriving at diffuse `i`, over
+/// pi, from one direction sampled towards the glowing objects and by cosine.
+fn gather_emission(r: &Ray, i: &Intersection, scene: &Scene) -> Vector3 {
+    let cosine = CosinePdf { normal: i.normal };
+    let (direction, weight) = if scene.emitters.is_empty() {
+        (cosine.generate(), 1.0)
+    } else {
+        let pdf = MixturePdf {
+            a: CosinePdf { normal: i.normal },
+            b: HittablePdf {
+                world: &scene.world,
+                ids: &scene.emitters,
+                origin: i.p,
+                time: r.time,
+            },
+        };
+        let direction = pdf.generate();
+        let density = pdf.value(&direction);
+        if density <= 0.0 {
+            return Vector3(0.0, 0.0, 0.0);
+        }
+        (direction, cosine.value(&direction) / density)
+    };
+    let gather = Ray {
+        origin: i.p,
+        direction,
+        time: r.time,
+    };
+    let radiance = match scene
+        .world
+        .hit_as(&gather, 0.001, f64::INFINITY, RayKind::Indirect)
+    {
+        Some(hit) => {
+            let i = shaded(scene, hit, &direction);
+            i.material.emitted_at(i.u, i.v, &i.p)
+        }
+        None => scene.background(&direction),
+    };
+    radiance * weight
+}
+
+/// Follows a photon of `power` along `r`, reporting where it lands on diffuse
+/// surfaces after the first bounce; light arriving without one is what
+/// `camera_path` adds directly.
+fn trace_photon(
+    r: Ray,
+    power: Vector3,
+    scene: &Scene,
+    max_depth: usize,
+    land: &mut dyn FnMut(PhotonHit),
+) {
+    let mut ray = r;
+    let mut power = power;
+    for depth in 0..max_depth {
+        let i = match scene
+            .world
+            .hit_as(&ray, 0.001, f64::INFINITY, RayKind::Indirect)
+        {
+            Some(i) => shaded(scene, i, &ray.direction),
+            None => return,
+        };
+        power = power * transmittance(&ray, &i);
+        match i.material {
+            Material::Portal { from, to } => {
+                let (origin, direction) = pass_through(&from, &to, i.u, i.v, ray.direction);
+                ray = Ray {
+                    origin,
+                    direction,
+                    time: ray.time,
+                };
+                continue;
+            }
+            Material::Isotropic { .. } => {
+                ray.origin = i.p;
+                continue;
+            }
+            Material::Lambertian { .. } if depth > 0 => land(PhotonHit {
+                p: i.p,
+                direction: ray.direction,
+                power,
+            }),
+            _ => {}
+        }
+        let (attenuation, scattered) = match i.material.scatter(&ray, i, 1.0) {
+            Some(s) => s,
+            None => return,
+        };
+        // Russian roulette keeps bright photons going
+        let survive = attenuation
+            .x()
+            .max(attenuation.y())
+            .max(attenuation.z())
+            .min(1.0);
+        if survive <= 0.0 || random() >= survive {
+            return;
+        }
+        power = power * attenuation / survive;
+        ray = scattered;
+    }
+}
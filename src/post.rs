@@ -0,0 +1,650 @@
+//! Post-processing of rendered images.
+
+use crate::camera::Camera;
+use crate::color::{srgb_decode, srgb_encode, Colormap};
+use crate::hittable::bvh::Bvh;
+use crate::hittable::{Aabb, RayKind};
+use crate::render::{Aov, PixelCost, RenderSettings};
+use crate::scene::Scene;
+use crate::vec3::{random, Vector3};
+use rayon::prelude::*;
+
+/// Curves compressing the unbounded radiance of a render into the range a
+/// display shows.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ToneMap {
+    /// Clips everything brighter than white.
+    #[default]
+    Linear,
+    /// `x / (1 + x)` per channel, which approaches white without reaching it.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve: a slight toe in the shadows
+    /// and a soft shoulder that desaturates highlights towards white.
+    Aces,
+}
+
+impl std::str::FromStr for ToneMap {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(ToneMap::Linear),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Which boxes `draw_bounds` outlines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BoundsOverlay {
+    /// The bounding box of every object.
+    Objects,
+    /// The nodes of the scene's BVH down to the given depth below the root.
+    Bvh(usize),
+}
+
+impl std::str::FromStr for BoundsOverlay {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "objects" => Ok(BoundsOverlay::Objects),
+            None if s == "bvh" => Ok(BoundsOverlay::Bvh(usize::MAX)),
+            Some(("bvh", depth)) => depth.parse().map(BoundsOverlay::Bvh).map_err(|_| ()),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Scales `image` by `exposure` and maps it through `curve`, in linear light
+/// just before the sRGB encoding.
+pub(crate) fn tone_map(image: &mut [Vector3], samples: usize, exposure: f64, curve: ToneMap) {
+    let n = samples as f64;
+    let map = |x: f64| {
+        let x = (x / n * exposure).max(0.0);
+        let y = match curve {
+            ToneMap::Linear => x,
+            ToneMap::Reinhard => x / (1.0 + x),
+            ToneMap::Aces => {
+                (x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0.0, 1.0)
+            }
+        };
+        y * n
+    };
+    image.par_iter_mut().for_each(|c| {
+        *c = Vector3(map(c.x()), map(c.y()), map(c.z()));
+    });
+}
+
+/// Picks an exposure multiplier mapping the average scene luminance to `key`.
+/// The average is the geometric mean over a histogram of log luminance that
+/// ignores the darkest and brightest pixels, given as fractions in `clip`.
+pub(crate) fn auto_exposure(image: &[Vector3], samples: usize, key: f64, clip: (f64, f64)) -> f64 {
+    const BINS: usize = 256;
+    const MIN_EV: f64 = -20.0;
+    const MAX_EV: f64 = 20.0;
+    let bin_width = (MAX_EV - MIN_EV) / BINS as f64;
+    let mut histogram = [0usize; BINS];
+    for c in image {
+        let luminance = (0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()) / samples as f64;
+        // pure black carries no information about the exposure
+        if luminance > 0.0 && luminance.is_finite() {
+            let ev = luminance.log2().clamp(MIN_EV, MAX_EV - 1e-9);
+            histogram[((ev - MIN_EV) / bin_width) as usize] += 1;
+        }
+    }
+    let total: usize = histogram.iter().sum();
+    let (low, high) = (clip.0 * total as f64, clip.1 * total as f64);
+    let (mut seen, mut sum, mut count) = (0.0, 0.0, 0.0);
+    for (bin, n) in histogram.iter().enumerate() {
+        // the part of this bin between the clip percentiles
+        let inside = (seen + *n as f64).min(high) - seen.max(low);
+        if inside > 0.0 {
+            sum += inside * (MIN_EV + (bin as f64 + 0.5) * bin_width);
+            count += inside;
+        }
+        seen += *n as f64;
+    }
+    if count == 0.0 {
+        return 1.0;
+    }
+    key / (sum / count).exp2()
+}
+
+/// Separable Gaussian blur with standard deviation `sigma` in pixels, clamping
+/// at the image borders.
+fn gaussian_blur(pixels: &[Vector3], width: usize, height: usize, sigma: f64) -> Vec<Vector3> {
+    let radius = (3.0 * sigma).ceil() as isize;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let norm: f64 = kernel.iter().sum();
+    let pass = |src: &[Vector3], step: (isize, isize)| -> Vec<Vector3> {
+        (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = ((i % width) as isize, (i / width) as isize);
+                let mut sum = Vector3(0.0, 0.0, 0.0);
+                for (k, weight) in (-radius..=radius).zip(&kernel) {
+                    let sx = (x + k * step.0).clamp(0, width as isize - 1) as usize;
+                    let sy = (y + k * step.1).clamp(0, height as isize - 1) as usize;
+                    sum += src[sy * width + sx] * *weight;
+                }
+                sum / norm
+            })
+            .collect()
+    };
+    pass(&pass(pixels, (1, 0)), (0, 1))
+}
+
+/// Adds a glow around everything brighter than `threshold`: the excess is
+/// blurred at five scales, each twice as wide as the previous, and added back
+/// scaled by `strength`.
+pub(crate) fn bloom(
+    image: &mut [Vector3],
+    width: usize,
+    height: usize,
+    samples: usize,
+    threshold: f64,
+    strength: f64,
+) {
+    let mut level: Vec<Vector3> = image
+        .iter()
+        .map(|c| {
+            let c = *c / samples as f64;
+            let luminance = 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+            if luminance > threshold {
+                c * ((luminance - threshold) / luminance)
+            } else {
+                Vector3(0.0, 0.0, 0.0)
+            }
+        })
+        .collect();
+    let (mut w, mut h) = (width, height);
+    let mut glow = vec![Vector3(0.0, 0.0, 0.0); width * height];
+    const LEVELS: usize = 5;
+    for _ in 0..LEVELS {
+        let blurred = gaussian_blur(&level, w, h, 2.0);
+        // upsample bilinearly to full resolution
+        for (i, g) in glow.iter_mut().enumerate() {
+            let x = ((i % width) as f64 + 0.5) * w as f64 / width as f64 - 0.5;
+            let y = ((i / width) as f64 + 0.5) * h as f64 / height as f64 - 0.5;
+            let (x0, y0) = (x.floor().max(0.0) as usize, y.floor().max(0.0) as usize);
+            let (x1, y1) = ((x0 + 1).min(w - 1), (y0 + 1).min(h - 1));
+            let (fx, fy) = (
+                (x - x0 as f64).clamp(0.0, 1.0),
+                (y - y0 as f64).clamp(0.0, 1.0),
+            );
+            let top = blurred[y0 * w + x0] * (1.0 - fx) + blurred[y0 * w + x1] * fx;
+            let bottom = blurred[y1 * w + x0] * (1.0 - fx) + blurred[y1 * w + x1] * fx;
+            *g += (top * (1.0 - fy) + bottom * fy) / LEVELS as f64;
+        }
+        if w < 4 || h < 4 {
+            break;
+        }
+        // halve the resolution for the next, twice as wide, level
+        let (nw, nh) = (w / 2, h / 2);
+        level = (0..nw * nh)
+            .map(|i| {
+                let (x, y) = (2 * (i % nw), 2 * (i / nw));
+                (blurred[y * w + x]
+                    + blurred[y * w + x + 1]
+                    + blurred[(y + 1) * w + x]
+                    + blurred[(y + 1) * w + x + 1])
+                    * 0.25
+            })
+            .collect();
+        w = nw;
+        h = nh;
+    }
+    for (c, g) in image.iter_mut().zip(glow) {
+        *c += g * (strength * samples as f64);
+    }
+}
+
+/// Adds a six-bladed starburst around every light the camera sees directly and
+/// a chain of tinted ghosts mirrored through the image center, all scaled by
+/// the light's brightness and `strength`.
+pub(crate) fn lens_flare(
+    image: &mut [Vector3],
+    samples: usize,
+    cam: &Camera,
+    scene: &Scene,
+    settings: &RenderSettings,
+    strength: f64,
+) {
+    let (width, height) = (settings.width as f64, settings.height as f64);
+    let diagonal = width.hypot(height);
+    let center = (width / 2.0, height / 2.0);
+    // (position along the axis through the center, radius, tint)
+    const GHOSTS: [(f64, f64, Vector3); 5] = [
+        (0.5, 0.02, Vector3(0.3, 0.5, 1.0)),
+        (-0.3, 0.04, Vector3(1.0, 0.6, 0.2)),
+        (-0.6, 0.015, Vector3(0.4, 1.0, 0.5)),
+        (-1.0, 0.07, Vector3(0.6, 0.4, 1.0)),
+        (-1.4, 0.03, Vector3(1.0, 0.3, 0.4)),
+    ];
+    let mut flares = Vec::new();
+    for light in &scene.lights {
+        let (direction, distance, irradiance) = light.illuminate(&cam.origin);
+        let (s, t) = match cam.project(&direction) {
+            Some(st) if (0.0..=1.0).contains(&st.0) && (0.0..=1.0).contains(&st.1) => st,
+            _ => continue,
+        };
+        if !scene.visible(&cam.origin, &direction, distance) {
+            continue;
+        }
+        let x = s * (width - 1.0);
+        let y = (height - 1.0) - t * (height - 1.0);
+        flares.push((x, y, irradiance * strength));
+    }
+    if flares.is_empty() {
+        return;
+    }
+    image.par_iter_mut().enumerate().for_each(|(i, c)| {
+        let (px, py) = (
+            (i % settings.width as usize) as f64,
+            (i / settings.width as usize) as f64,
+        );
+        for &(x, y, color) in &flares {
+            let (dx, dy) = (px - x, py - y);
+            let r = dx.hypot(dy) / diagonal;
+            let blades = (3.0 * dy.atan2(dx)).cos().abs().powi(64);
+            let mut flare = color * (blades * (-r / 0.1).exp() + (-r / 0.02).exp());
+            for (k, radius, tint) in GHOSTS {
+                let gx = center.0 + (x - center.0) * k;
+                let gy = center.1 + (y - center.1) * k;
+                let d = (px - gx).hypot(py - gy) / diagonal / radius;
+                // a disk with a soft rim
+                let disk = (1.0 - d).clamp(0.0, 1.0).min(0.2) * 5.0 * (1.0 - 0.5 * d.min(1.0));
+                flare += color * tint * (0.1 * disk);
+            }
+            *c += flare * samples as f64;
+        }
+    });
+}
+
+/// A 3D color lookup table from a `.cube` file, applied to display-referred
+/// colors.
+pub(crate) struct Lut {
+    size: usize,
+    domain_min: Vector3,
+    domain_max: Vector3,
+    /// `size³` output colors, red varying fastest.
+    table: Vec<Vector3>,
+}
+
+impl Lut {
+    pub(crate) fn load(path: &str) -> std::io::Result<Self> {
+        let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        let mut size = 0;
+        let mut domain_min = Vector3(0.0, 0.0, 0.0);
+        let mut domain_max = Vector3(1.0, 1.0, 1.0);
+        let mut table = Vec::new();
+        for (n, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            let mut words = line.split_whitespace();
+            let parse_vector = |words: std::str::SplitWhitespace| {
+                let c: Vec<f64> = words.filter_map(|w| w.parse().ok()).collect();
+                match c[..] {
+                    [r, g, b] => Ok(Vector3(r, g, b)),
+                    _ => Err(invalid(format!("line {}: expected three numbers", n + 1))),
+                }
+            };
+            match words.next() {
+                None => {}
+                Some(w) if w.starts_with('#') => {}
+                Some("LUT_3D_SIZE") => {
+                    size = words
+                        .next()
+                        .and_then(|w| w.parse().ok())
+                        .filter(|&s| s >= 2)
+                        .ok_or_else(|| invalid(format!("line {}: bad LUT_3D_SIZE", n + 1)))?;
+                }
+                Some("LUT_1D_SIZE") => return Err(invalid("1D LUTs are not supported".into())),
+                Some("DOMAIN_MIN") => domain_min = parse_vector(words)?,
+                Some("DOMAIN_MAX") => domain_max = parse_vector(words)?,
+                // TITLE and other keywords this reader does not need
+                Some(w) if w.parse::<f64>().is_err() => {}
+                Some(_) => table.push(parse_vector(line.split_whitespace())?),
+            }
+        }
+        if size == 0 || table.len() != size * size * size {
+            return Err(invalid(format!(
+                "expected LUT_3D_SIZE and its cube of entries, found {} entries",
+                table.len()
+            )));
+        }
+        Ok(Self {
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    /// Looks up `c` with trilinear interpolation.
+    fn apply(&self, c: Vector3) -> Vector3 {
+        let n = self.size - 1;
+        let coordinate = |x: f64, min: f64, max: f64| {
+            let x = ((x - min) / (max - min)).clamp(0.0, 1.0) * n as f64;
+            let i = (x.floor() as usize).min(n - 1);
+            (i, x - i as f64)
+        };
+        let (r, fr) = coordinate(c.x(), self.domain_min.x(), self.domain_max.x());
+        let (g, fg) = coordinate(c.y(), self.domain_min.y(), self.domain_max.y());
+        let (b, fb) = coordinate(c.z(), self.domain_min.z(), self.domain_max.z());
+        let at = |r, g, b| self.table[(b * self.size + g) * self.size + r];
+        let lerp = |a: Vector3, b: Vector3, t: f64| a * (1.0 - t) + b * t;
+        let along_r = |g, b| lerp(at(r, g, b), at(r + 1, g, b), fr);
+        let along_g = |b| lerp(along_r(g, b), along_r(g + 1, b), fg);
+        lerp(along_g(b), along_g(b + 1), fb)
+    }
+}
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) smoothing the
+/// noise of a path traced `image` guided by the first hits in `costs`. The
+/// lighting is divided by the albedo first, so textures stay sharp, then
+/// blurred over five levels of a 5x5 B-spline kernel spread twice as wide
+/// each time; neighbors count less the more their normal, depth or lighting
+/// differs.
+pub(crate) fn denoise(image: &mut [Vector3], width: usize, height: usize, costs: &[PixelCost]) {
+    const LEVELS: usize = 5;
+    const KERNEL: [f64; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+    /// How far normals may differ, as the length of their difference.
+    const SIGMA_NORMAL: f64 = 0.3;
+    /// How far depths may differ, relative to the depth per pixel of offset.
+    const SIGMA_DEPTH: f64 = 0.02;
+    /// How far lighting may differ relative to its brightness at the first
+    /// level, halving with each further level as the noise goes down.
+    const SIGMA_COLOR: f64 = 2.0;
+    let normal: Vec<Vector3> = costs.iter().map(|c| c.aov(Aov::Normal)).collect();
+    let depth: Vec<f64> = costs.iter().map(|c| c.aov(Aov::Depth).x()).collect();
+    // black and missing albedos leave the color as it is
+    let albedo: Vec<Vector3> = costs
+        .iter()
+        .map(|c| {
+            let a = c.aov(Aov::Albedo);
+            let keep = |x: f64| if x > 1e-3 { x } else { 1.0 };
+            Vector3(keep(a.x()), keep(a.y()), keep(a.z()))
+        })
+        .collect();
+    let luminance = |c: &Vector3| 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+    let mut lighting: Vec<Vector3> = image
+        .iter()
+        .zip(&albedo)
+        .map(|(c, a)| Vector3(c.x() / a.x(), c.y() / a.y(), c.z() / a.z()))
+        .collect();
+    for level in 0..LEVELS {
+        let step = 1isize << level;
+        let sigma_color = SIGMA_COLOR / (1 << level) as f64;
+        lighting = (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = ((i % width) as isize, (i / width) as isize);
+                let (c, n, z) = (lighting[i], normal[i], depth[i]);
+                let (mut sum, mut total) = (Vector3(0.0, 0.0, 0.0), 0.0);
+                for dy in -2..=2isize {
+                    for dx in -2..=2isize {
+                        let (sx, sy) = (x + dx * step, y + dy * step);
+                        if sx < 0 || sy < 0 || sx >= width as isize || sy >= height as isize {
+                            continue;
+                        }
+                        let j = sy as usize * width + sx as usize;
+                        let kernel = KERNEL[dx.unsigned_abs()] * KERNEL[dy.unsigned_abs()];
+                        let w_normal = (-(normal[j] - n).length_squared()
+                            / (SIGMA_NORMAL * SIGMA_NORMAL))
+                            .exp();
+                        // the sky is infinitely far: it only mixes with itself
+                        let w_depth = match (z.is_finite(), depth[j].is_finite()) {
+                            (true, true) => {
+                                let offset = ((dx * dx + dy * dy) as f64).sqrt() * step as f64;
+                                let scale = SIGMA_DEPTH * z.max(depth[j]) * offset;
+                                (-(depth[j] - z).abs() / scale.max(1e-9)).exp()
+                            }
+                            (false, false) => 1.0,
+                            _ => 0.0,
+                        };
+                        let brightness = luminance(&c) + luminance(&lighting[j]) + 1e-6;
+                        let w_color = (-(lighting[j] - c).length_squared()
+                            / (sigma_color * sigma_color * brightness * brightness))
+                            .exp();
+                        let w = kernel * w_normal * w_depth * w_color;
+                        sum += lighting[j] * w;
+                        total += w;
+                    }
+                }
+                sum / total
+            })
+            .collect();
+    }
+    for ((c, l), a) in image.iter_mut().zip(&lighting).zip(&albedo) {
+        *c = *l * *a;
+    }
+}
+
+/// Maps `values`, rows of `width`, through `colormap` from `range.0` to
+/// `range.1`, black where a value is not finite, and adds a legend strip
+/// below: the colormap as a bar labeled with the values at its ends and
+/// middle. Returns display values and the height with the legend.
+pub(crate) fn false_color(
+    values: &[f64],
+    width: usize,
+    range: (f64, f64),
+    colormap: Colormap,
+) -> (Vec<Vector3>, usize) {
+    let (low, high) = range;
+    let span = (high - low).max(f64::MIN_POSITIVE);
+    let mut image: Vec<Vector3> = values
+        .iter()
+        .map(|v| match v.is_finite() {
+            true => colormap.at((v - low) / span),
+            false => Vector3(0.0, 0.0, 0.0),
+        })
+        .collect();
+    // the legend in units of `scale` pixels: a margin, the bar 4 high, a
+    // gap, the labels 5 high and a margin
+    let scale = (width / 320).max(1);
+    let (margin, rows) = (2 * scale, 14 * scale);
+    let mut legend = vec![Vector3(0.1, 0.1, 0.1); width * rows];
+    let bar = margin..width.saturating_sub(margin).max(margin + 1);
+    for y in 2 * scale..6 * scale {
+        for x in bar.clone() {
+            let t = (x - bar.start) as f64 / (bar.len() - 1).max(1) as f64;
+            legend[y * width + x] = colormap.at(t);
+        }
+    }
+    let labels = [low, (low + high) / 2.0, high].map(legend_label);
+    let text_width = |text: &str| (text.len() * 4 - 1) * scale;
+    let starts = [
+        bar.start,
+        (width / 2).saturating_sub(text_width(&labels[1]) / 2),
+        bar.end.saturating_sub(text_width(&labels[2])),
+    ];
+    for (label, x) in labels.iter().zip(starts) {
+        draw_text(&mut legend, width, (x, 7 * scale), label, scale);
+    }
+    image.extend(legend);
+    let height = image.len() / width;
+    (image, height)
+}
+
+/// A legend value in at most a handful of characters: three significant
+/// digits, in scientific notation when very large or small.
+fn legend_label(v: f64) -> String {
+    if v == 0.0 || !v.is_finite() {
+        return "0".to_string();
+    }
+    let magnitude = v.abs().log10().floor();
+    if (-2.0..4.0).contains(&magnitude) {
+        format!("{:.*}", (2.0 - magnitude).max(0.0) as usize, v)
+    } else {
+        format!("{:.2e}", v)
+    }
+}
+
+/// Draws `text` in white with a 3x5 pixel font, each font pixel `scale`
+/// pixels wide, its top left corner at `at`; characters other than digits,
+/// `.`, `-`, `+` and `e` are left blank.
+fn draw_text(image: &mut [Vector3], width: usize, at: (usize, usize), text: &str, scale: usize) {
+    // rows top first, three bits each, the leftmost column highest
+    let glyph = |c: char| -> [u8; 5] {
+        match c {
+            '0' => [7, 5, 5, 5, 7],
+            '1' => [2, 6, 2, 2, 7],
+            '2' => [7, 1, 7, 4, 7],
+            '3' => [7, 1, 7, 1, 7],
+            '4' => [5, 5, 7, 1, 1],
+            '5' => [7, 4, 7, 1, 7],
+            '6' => [7, 4, 7, 5, 7],
+            '7' => [7, 1, 1, 1, 1],
+            '8' => [7, 5, 7, 5, 7],
+            '9' => [7, 5, 7, 1, 7],
+            '.' => [0, 0, 0, 0, 2],
+            '-' => [0, 0, 7, 0, 0],
+            '+' => [0, 2, 7, 2, 0],
+            'e' => [2, 5, 7, 4, 3],
+            _ => [0; 5],
+        }
+    };
+    let height = image.len() / width;
+    for (k, c) in text.chars().enumerate() {
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..3 {
+                if bits >> (2 - column) & 1 == 0 {
+                    continue;
+                }
+                let x0 = at.0 + (4 * k + column) * scale;
+                let y0 = at.1 + row * scale;
+                for y in y0..(y0 + scale).min(height) {
+                    for x in x0..(x0 + scale).min(width) {
+                        image[y * width + x] = Vector3(1.0, 1.0, 1.0);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Grades `image` with `lut` in display space.
+pub(crate) fn apply_lut(image: &mut [Vector3], samples: usize, lut: &Lut) {
+    image.par_iter_mut().for_each(|c| {
+        // same encoding as write_color
+        let display = |x: f64| srgb_encode((x / samples as f64).max(0.0)).min(1.0);
+        let graded = lut.apply(Vector3(display(c.x()), display(c.y()), display(c.z())));
+        let linear = |x: f64| srgb_decode(x.clamp(0.0, 1.0)) * samples as f64;
+        *c = Vector3(linear(graded.x()), linear(graded.y()), linear(graded.z()));
+    });
+}
+
+/// Adds Gaussian grain of standard deviation `amount` in display space, where
+/// it is strongest in the midtones and fades out towards black and white. The
+/// grain is shared by all channels unless `per_channel` is set.
+pub(crate) fn film_grain(image: &mut [Vector3], samples: usize, amount: f64, per_channel: bool) {
+    let gaussian = || {
+        let (u1, u2): (f64, f64) = (random(), random());
+        (-2.0 * (1.0 - u1).ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    };
+    for c in image.iter_mut() {
+        // same encoding as write_color
+        let display = |x: f64| srgb_encode((x / samples as f64).max(0.0)).min(1.0);
+        let d = Vector3(display(c.x()), display(c.y()), display(c.z()));
+        let luminance = 0.2126 * d.x() + 0.7152 * d.y() + 0.0722 * d.z();
+        let strength = amount * 2.0 * (luminance * (1.0 - luminance)).sqrt();
+        let noise = if per_channel {
+            Vector3(gaussian(), gaussian(), gaussian())
+        } else {
+            let n = gaussian();
+            Vector3(n, n, n)
+        };
+        let grainy = d + noise * strength;
+        let linear = |x: f64| srgb_decode(x.clamp(0.0, 1.0)) * samples as f64;
+        *c = Vector3(linear(grainy.x()), linear(grainy.y()), linear(grainy.z()));
+    }
+}
+
+/// For each pixel, top row first, the diameter in pixels of the blur of the
+/// surface it first sees, negative in front of the focal plane; see
+/// `Camera::blur`.
+pub(crate) fn focus_blur(cam: &Camera, scene: &Scene, settings: &RenderSettings) -> Vec<f64> {
+    let (width, height) = (settings.width, settings.height);
+    let forward = (cam.center_ray(0.5, 0.5).direction).normalize();
+    (0..width * height)
+        .into_par_iter()
+        .map(|k| {
+            let (x, y) = (k % width, k / width);
+            let r = cam.center_ray(
+                x as f64 / (width - 1) as f64,
+                (height - 1 - y) as f64 / (height - 1) as f64,
+            );
+            let distance = scene
+                .world
+                .hit_as(&r, 0.001, f64::INFINITY, RayKind::Camera)
+                .map_or(f64::INFINITY, |i| (i.p - r.origin).dot(&forward));
+            cam.blur(distance, height)
+        })
+        .collect()
+}
+
+/// Draws the edges of the boxes `overlay` picks over an accumulated image:
+/// opaque where they are in front of the scene, translucent behind it. BVH
+/// nodes are colored by their depth.
+pub(crate) fn draw_bounds(
+    image: &mut [Vector3],
+    samples: usize,
+    cam: &Camera,
+    scene: &Scene,
+    settings: &RenderSettings,
+    overlay: BoundsOverlay,
+) {
+    let boxes: Vec<(Aabb, usize)> = match overlay {
+        BoundsOverlay::Objects => scene
+            .world
+            .objects
+            .iter()
+            .filter_map(|h| h.bounding_box())
+            .map(|b| (b, 0))
+            .collect(),
+        BoundsOverlay::Bvh(depth) => match &scene.world.bvh {
+            Some(bvh) => bvh.node_boxes(depth),
+            None => {
+                Bvh::new(scene.world.objects.iter().map(|o| o.bounding_box())).node_boxes(depth)
+            }
+        },
+    };
+    let (width, height) = (settings.width, settings.height);
+    // edges are about a pixel wide at any distance
+    let pixel = cam.horizontal.length() / width as f64;
+    let palette = [
+        Vector3(1.0, 0.85, 0.1),
+        Vector3(0.1, 0.8, 1.0),
+        Vector3(1.0, 0.2, 0.6),
+        Vector3(0.3, 1.0, 0.3),
+    ];
+    image
+        .par_chunks_mut(width as usize)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let v = (height - 1 - y as u32) as f64 / (height - 1) as f64;
+            for (x, color) in row.iter_mut().enumerate() {
+                let r = cam.center_ray(x as f64 / (width - 1) as f64, v);
+                let visible_until = scene
+                    .world
+                    .hit_as(&r, 0.001, f64::INFINITY, RayKind::Camera)
+                    .map_or(f64::INFINITY, |i| i.t);
+                let (mut alpha, mut line): (f64, Vector3) = (0.0, palette[0]);
+                for (b, depth) in &boxes {
+                    if let Some((t0, t1)) = b.clip(&r, 0.001, f64::INFINITY) {
+                        for t in [t0, t1] {
+                            let a = if t < visible_until { 1.0 } else { 0.35 };
+                            if t > 0.001 && a > alpha && b.near_edge(&r.at(t), t * pixel) {
+                                alpha = a;
+                                line = palette[depth % palette.len()];
+                            }
+                        }
+                    }
+                }
+                *color = *color * (1.0 - alpha) + line * samples as f64 * alpha;
+            }
+        });
+}
@@ -0,0 +1,643 @@
+//! Rays and the path tracing integrator that follows them through a scene.
+
+use crate::camera::Camera;
+use crate::color::id_color;
+use crate::hittable::portal::pass_through;
+use crate::hittable::{Intersection, RayKind};
+use crate::irradiance_cache::IrradianceCache;
+use crate::material::Material;
+use crate::pdf::{CosinePdf, HittablePdf, MixturePdf, Pdf};
+use crate::render::RenderMode;
+use crate::scene::Scene;
+use crate::sky::Sky;
+use crate::vec3::{random, Vector3};
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin: Vector3,
+    pub direction: Vector3,
+    /// The moment the ray samples, for moving objects.
+    pub time: f64,
+}
+
+impl Ray {
+    pub fn at(&self, t: f64) -> Vector3 {
+        self.origin + self.direction * t
+    }
+    pub fn ray_color(
+        &self,
+        scene: &Scene,
+        depth: usize,
+        cache: Option<&IrradianceCache>,
+        path: &mut PathInfo,
+    ) -> Vector3 {
+        if depth == 0 {
+            path.terminate(Termination::MaxDepth);
+            return Vector3(0.0, 0.0, 0.0);
+        }
+        let kind = match path.camera_hits {
+            Some(0) => RayKind::Camera,
+            _ => RayKind::Indirect,
+        };
+        let hit = scene.world.hit_as(self, 0.001, f64::INFINITY, kind);
+        // light fades on its way through the absorbing dielectric the ray
+        // is in, up to the next surface
+        let transmittance = match (hit, path.inside()) {
+            (Some(i), Some(medium)) => {
+                let d = i.t * self.direction.length();
+                let a = medium.absorption;
+                Vector3((-a.x() * d).exp(), (-a.y() * d).exp(), (-a.z() * d).exp())
+            }
+            _ => Vector3(1.0, 1.0, 1.0),
+        };
+        let throughput = path.throughput;
+        path.throughput = throughput * transmittance;
+        let radiance = self.shade(scene, hit, depth, cache, path) * transmittance;
+        path.throughput = throughput;
+        match &scene.fog {
+            Some(fog) => fog.apply(self, hit.map_or(f64::INFINITY, |i| i.t), radiance),
+            None => radiance,
+        }
+    }
+
+    /// Radiance arriving along the ray from its first hit `hit`, or from the
+    /// sky if there is none.
+    fn shade(
+        &self,
+        scene: &Scene,
+        hit: Option<Intersection>,
+        depth: usize,
+        cache: Option<&IrradianceCache>,
+        path: &mut PathInfo,
+    ) -> Vector3 {
+        if let Some(i) = hit {
+            if let Material::Portal { from, to } = i.material {
+                // on through the other window, counting as a bounce so that
+                // paths between facing portals end
+                let (origin, direction) = pass_through(&from, &to, i.u, i.v, self.direction);
+                let r = Ray {
+                    origin,
+                    direction,
+                    time: self.time,
+                };
+                return r.ray_color(scene, depth - 1, cache, path);
+            }
+            let i = i.shaded_with(
+                scene.material_override.unwrap_or(i.material),
+                &self.direction,
+            );
+            let i = Intersection {
+                material: scene.color_space.material(i.material),
+                ..i
+            };
+            // index of refraction on the other side of a dielectric surface
+            let mut surrounding = 1.0;
+            if let Material::Dielectric { ir, absorption, .. } = i.material {
+                let priority = scene.world.priorities[i.object];
+                let outside = path
+                    .media
+                    .iter()
+                    .filter(|m| m.object != i.object)
+                    .max_by_key(|m| m.priority)
+                    .copied();
+                if outside.is_some_and(|m| m.priority > priority) {
+                    // the surface lies inside a volume of higher priority, so
+                    // the ray only enters or leaves this object's volume
+                    if i.front_facing {
+                        path.media.push(Medium {
+                            object: i.object,
+                            ir,
+                            absorption,
+                            priority,
+                        });
+                    } else {
+                        path.media.retain(|m| m.object != i.object);
+                    }
+                    let r = Ray {
+                        origin: i.p,
+                        direction: self.direction,
+                        time: self.time,
+                    };
+                    return r.ray_color(scene, depth, cache, path);
+                }
+                surrounding = outside.map_or(1.0, |m| m.ir);
+            }
+            path.bounces += 1;
+            path.last_object = Some(i.object);
+            if let Some(hits) = &mut path.camera_hits {
+                *hits += 1;
+            }
+            if let Some(vertices) = &mut path.vertices {
+                vertices.push(i.p);
+            }
+            if scene.draft && path.camera_hits > Some(1) {
+                if let Material::Dielectric { .. } = i.material {
+                    path.terminate(Termination::Escaped(self.direction.normalize()));
+                    return scene.background(&self.direction);
+                }
+            }
+            let glow = i.material.emitted_at(i.u, i.v, &i.p);
+            let direct = match i.material {
+                Material::Lambertian { .. } | Material::Isotropic { .. } => {
+                    // diffuse surfaces reflect irradiance over the hemisphere,
+                    // volumes scatter it over the whole sphere
+                    let (normal, spread) = match i.material {
+                        Material::Isotropic { .. } => (None, 4.0 * std::f64::consts::PI),
+                        _ => (Some(&i.normal), std::f64::consts::PI),
+                    };
+                    scene
+                        .light_irradiance(&i.p, normal, Some(i.object), self.time)
+                        .fold(glow, |acc, (light, irradiance)| {
+                            let albedo = i.material.albedo(i.u, i.v, &i.p);
+                            let radiance = albedo * irradiance / spread;
+                            if let Some(tally) = &mut path.light_tally {
+                                tally.add(scene, light, path.throughput * radiance);
+                            }
+                            acc + radiance
+                        })
+                }
+                _ => glow,
+            };
+            if let Some(tally) = &mut path.light_tally {
+                let glowing = scene.lights.len() + 1;
+                tally.add(scene, glowing, path.throughput * glow);
+            }
+            if let Some(events) = &mut path.events {
+                events.push(PathEvent::Hit {
+                    object: i.object,
+                    point: i.p,
+                    material: i.material,
+                    front_facing: i.front_facing,
+                    throughput: path.throughput,
+                    direct,
+                    attenuation: None,
+                });
+            }
+            if let (Some(cache), Material::Lambertian { .. }) = (cache, i.material) {
+                path.terminate(Termination::Cached);
+                return direct
+                    + i.material.albedo(i.u, i.v, &i.p) * cache.irradiance(scene, &i, depth - 1);
+            }
+            let sample = || match i.material {
+                Material::Lambertian { .. } if !scene.emitters.is_empty() => {
+                    // half of the bounces go towards the glowing objects,
+                    // weighted by how much likelier that made their direction
+                    let pdf = MixturePdf {
+                        a: CosinePdf { normal: i.normal },
+                        b: HittablePdf {
+                            world: &scene.world,
+                            ids: &scene.emitters,
+                            origin: i.p,
+                            time: self.time,
+                        },
+                    };
+                    let scattered = Ray {
+                        origin: i.p,
+                        direction: pdf.generate(),
+                        time: self.time,
+                    };
+                    let weight =
+                        i.material.scattering_pdf(&i, &scattered) / pdf.value(&scattered.direction);
+                    (weight > 0.0).then(|| (i.material.albedo(i.u, i.v, &i.p) * weight, scattered))
+                }
+                _ => i.material.scatter(self, i, surrounding),
+            };
+            let scattered = sample();
+            if let (Some(events), Some((a, _))) = (&mut path.events, &scattered) {
+                if let Some(PathEvent::Hit { attenuation, .. }) = events.last_mut() {
+                    *attenuation = Some(*a);
+                }
+            }
+            if let Some((attenuation, scattered)) = scattered {
+                let (count, limit) = match i.material {
+                    Material::Lambertian { .. }
+                    | Material::DiffuseLight { .. }
+                    | Material::Isotropic { .. } => {
+                        (&mut path.kind_bounces.diffuse, scene.max_bounces.diffuse)
+                    }
+                    Material::Metal { .. } => {
+                        (&mut path.kind_bounces.glossy, scene.max_bounces.glossy)
+                    }
+                    Material::Dielectric { .. } | Material::Portal { .. } => (
+                        &mut path.kind_bounces.transmission,
+                        scene.max_bounces.transmission,
+                    ),
+                    Material::Mapped { .. } | Material::Mix { .. } | Material::Pbr(_) => {
+                        unreachable!("shaded with the base material")
+                    }
+                };
+                if *count >= limit {
+                    path.terminate(Termination::MaxDepth);
+                    return direct;
+                }
+                *count += 1;
+                // Russian roulette: past the guaranteed bounces the path goes
+                // on with a chance given by its throughput, and what it
+                // brings back counts as much more as it was likely to end
+                let mut weight = 1.0;
+                let b = &path.kind_bounces;
+                if path
+                    .roulette
+                    .is_some_and(|r| b.diffuse + b.glossy + b.transmission > r)
+                {
+                    let t = path.throughput * attenuation;
+                    let survival = t.x().max(t.y()).max(t.z()).min(1.0);
+                    if random() >= survival {
+                        path.terminate(Termination::Absorbed);
+                        return direct;
+                    }
+                    weight /= survival;
+                }
+                // splitting, the other way round: an indirect path a probe
+                // ray finds bringing back much light goes on several times,
+                // each continuation counting for its share
+                let splits = match path.split {
+                    Some(most)
+                        if path.bounces > 1 && path.events.is_none() && path.vertices.is_none() =>
+                    {
+                        let probe = sample().map_or(Vector3(0.0, 0.0, 0.0), |(a, r)| {
+                            let kind = RayKind::Indirect;
+                            a * match scene.world.hit_as(&r, 0.001, f64::INFINITY, kind) {
+                                Some(h) => scene
+                                    .color_space
+                                    .material(scene.material_override.unwrap_or(h.material))
+                                    .emitted_at(h.u, h.v, &h.p),
+                                None => scene.background(&r.direction),
+                            }
+                        });
+                        let t = path.throughput * probe;
+                        // negative lights matter as much as they darken
+                        let estimate = (0.2126 * t.x() + 0.7152 * t.y() + 0.0722 * t.z()).abs();
+                        (estimate.ceil() as usize).clamp(1, most)
+                    }
+                    _ => 1,
+                };
+                weight /= splits as f64;
+                let saved =
+                    (splits > 1).then(|| (path.kind_bounces, path.media.clone(), path.camera_hits));
+                let mut radiance = direct;
+                let mut next = Some((attenuation, scattered));
+                for _ in 0..splits {
+                    let (attenuation, scattered) = match next.take().or_else(sample) {
+                        Some((a, scattered)) => (a * weight, scattered),
+                        None => continue,
+                    };
+                    if let Some((bounces, media, hits)) = &saved {
+                        path.kind_bounces = *bounces;
+                        path.media.clone_from(media);
+                        path.camera_hits = *hits;
+                    }
+                    if let Material::Dielectric { ir, absorption, .. } = i.material {
+                        // refracted through the surface
+                        if scattered.direction.dot(&i.normal) < 0.0 {
+                            if i.front_facing {
+                                path.media.push(Medium {
+                                    object: i.object,
+                                    ir,
+                                    absorption,
+                                    priority: scene.world.priorities[i.object],
+                                });
+                            } else {
+                                path.media.retain(|m| m.object != i.object);
+                            }
+                        }
+                    }
+                    let throughput = path.throughput;
+                    path.throughput = throughput * attenuation;
+                    let incoming = scattered.ray_color(scene, depth - 1, cache, path);
+                    path.throughput = throughput;
+                    radiance += attenuation * incoming;
+                }
+                radiance
+            } else {
+                path.terminate(Termination::Absorbed);
+                direct
+            }
+        } else {
+            path.terminate(Termination::Escaped(self.direction.normalize()));
+            let radiance = match (
+                path.camera_hits,
+                &scene.backdrop,
+                scene.plate.zip(path.film),
+            ) {
+                (Some(0), _, Some((plate, (u, v)))) => {
+                    scene.color_space.from_linear_srgb(plate.at(u, v))
+                }
+                (Some(0), Some(backdrop), _) => scene
+                    .color_space
+                    .from_linear_srgb(backdrop.radiance(&self.direction)),
+                _ if path.camera_hits.is_some() && path.kind_bounces.diffuse == 0 => {
+                    scene.background(&self.direction) + scene.sun_disk(&self.direction)
+                }
+                _ => scene.background(&self.direction),
+            };
+            if let Some(tally) = &mut path.light_tally {
+                tally.add(scene, scene.lights.len(), path.throughput * radiance);
+            }
+            if let Some(events) = &mut path.events {
+                events.push(PathEvent::Escape {
+                    direction: self.direction.normalize(),
+                    throughput: path.throughput,
+                    radiance,
+                });
+            }
+            radiance
+        }
+    }
+
+    /// `ray_color` for rays leaving the camera, which additionally pass through
+    /// the scene's participating medium, if any. The result is linear sRGB.
+    pub fn camera_ray_color(
+        &self,
+        scene: &Scene,
+        depth: usize,
+        cache: Option<&IrradianceCache>,
+        path: &mut PathInfo,
+    ) -> Vector3 {
+        path.camera_hits = Some(0);
+        path.kind_bounces = BounceCounts::default();
+        path.media.clear();
+        path.throughput = Vector3(1.0, 1.0, 1.0);
+        let mut radiance = self.ray_color(scene, depth, cache, path);
+        if let Sky::Atmosphere(atmosphere) = &scene.sky {
+            radiance = atmosphere.apply(scene, self, radiance);
+        }
+        if let Some(medium) = &scene.medium {
+            radiance = medium.apply(scene, self, radiance);
+        }
+        scene.color_space.to_linear_srgb(radiance)
+    }
+
+    /// An estimate of the irradiance at the first surface the ray hits, in
+    /// linear sRGB: the radiance arriving from one direction, sampled like a
+    /// diffuse bounce, times its cosine over its density, whose mean over
+    /// samples is the light falling on the surface per unit area. Black where
+    /// the ray hits nothing.
+    pub fn irradiance(
+        &self,
+        scene: &Scene,
+        depth: usize,
+        cache: Option<&IrradianceCache>,
+        path: &mut PathInfo,
+    ) -> Vector3 {
+        let i = match scene
+            .world
+            .hit_as(self, 0.001, f64::INFINITY, RayKind::Camera)
+        {
+            Some(i) => i.shaded_with(
+                scene.material_override.unwrap_or(i.material),
+                &self.direction,
+            ),
+            None => return Vector3(0.0, 0.0, 0.0),
+        };
+        // half of the directions go towards the glowing objects, as in
+        // `ray_color`
+        let cosine = CosinePdf { normal: i.normal };
+        let (direction, density) = if scene.emitters.is_empty() {
+            let direction = cosine.generate();
+            (direction, cosine.value(&direction))
+        } else {
+            let pdf = MixturePdf {
+                a: cosine,
+                b: HittablePdf {
+                    world: &scene.world,
+                    ids: &scene.emitters,
+                    origin: i.p,
+                    time: self.time,
+                },
+            };
+            let direction = pdf.generate();
+            (direction, pdf.value(&direction))
+        };
+        let cos = i.normal.dot(&direction.normalize());
+        if cos <= 0.0 || density <= 0.0 {
+            return Vector3(0.0, 0.0, 0.0);
+        }
+        let r = Ray {
+            origin: i.p,
+            direction,
+            time: self.time,
+        };
+        path.camera_hits = None;
+        path.kind_bounces = BounceCounts::default();
+        path.media.clear();
+        path.throughput = Vector3(1.0, 1.0, 1.0);
+        let radiance = r.ray_color(scene, depth.saturating_sub(1), cache, path);
+        scene.color_space.to_linear_srgb(radiance) * (cos / density)
+    }
+
+    /// Visualizes first-hit data for the debug render modes. Depth is returned
+    /// as the raw hit distance (infinite on a miss) and normalized afterwards.
+    pub fn debug_color(&self, scene: &Scene, mode: RenderMode) -> Vector3 {
+        let hit = scene
+            .world
+            .hit_as(self, 0.001, f64::INFINITY, RayKind::Camera);
+        match (mode, hit) {
+            (RenderMode::Depth, None) => Vector3(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            (_, None) => Vector3(0.0, 0.0, 0.0),
+            (RenderMode::Normals, Some(i)) => {
+                // the shading normal, tilted by normal and bump maps
+                let i = i.shaded_with(
+                    scene.material_override.unwrap_or(i.material),
+                    &self.direction,
+                );
+                (i.normal + Vector3(1.0, 1.0, 1.0)) * 0.5
+            }
+            (RenderMode::Depth, Some(i)) => {
+                let d = i.t * self.direction.length();
+                Vector3(d, d, d)
+            }
+            (RenderMode::Albedo, Some(i)) => scene
+                .material_override
+                .unwrap_or(i.material)
+                .albedo(i.u, i.v, &i.p),
+            (RenderMode::Uv, Some(i)) => Vector3(i.u, i.v, 0.0),
+            (RenderMode::Facing, Some(i)) => {
+                if i.front_facing {
+                    Vector3(0.0, 1.0, 0.0)
+                } else {
+                    Vector3(1.0, 0.0, 0.0)
+                }
+            }
+            (RenderMode::Id, Some(i)) => id_color(i.object),
+            (
+                RenderMode::Path | RenderMode::Lights | RenderMode::Motion | RenderMode::Irradiance,
+                Some(_),
+            ) => {
+                unreachable!()
+            }
+        }
+    }
+
+    /// How far the first hit of this camera ray moves across the image of
+    /// `cam` in one unit of time, as offsets of the `(s, t)` that
+    /// `Camera::center_ray` takes. Zero on a miss, for still objects and for
+    /// projections other than perspective.
+    pub(crate) fn motion(&self, scene: &Scene, cam: &Camera) -> (f64, f64) {
+        let hit = scene
+            .world
+            .hit_as(self, 0.001, f64::INFINITY, RayKind::Camera);
+        let i = match hit {
+            Some(i) => i,
+            None => return (0.0, 0.0),
+        };
+        let velocity = scene.world.objects[i.object].velocity(self.time);
+        let now = cam.project(&(i.p - cam.origin));
+        let later = cam.project(&(i.p + velocity - cam.origin));
+        match (now, later) {
+            (Some((s0, t0)), Some((s1, t1))) => (s1 - s0, t1 - t0),
+            _ => (0.0, 0.0),
+        }
+    }
+}
+
+/// Bookkeeping collected while tracing a path with `Ray::ray_color`.
+#[derive(Default, Debug)]
+pub struct PathInfo {
+    pub(crate) bounces: usize,
+    /// Hit points along the path, only recorded when this is `Some`.
+    pub(crate) vertices: Option<Vec<Vector3>>,
+    /// What happened at each vertex, only recorded when this is `Some`.
+    pub(crate) events: Option<Vec<PathEvent>>,
+    pub(crate) termination: Termination,
+    /// ID of the most recently hit object.
+    pub(crate) last_object: Option<usize>,
+    /// Hits so far along the current camera path; `None` for paths that do not
+    /// start at the camera.
+    pub(crate) camera_hits: Option<usize>,
+    /// Scattering events so far along the current path, by kind.
+    pub(crate) kind_bounces: BounceCounts,
+    /// Dielectric volumes the path is currently inside, in the order entered.
+    pub(crate) media: Vec<Medium>,
+    /// Radiance per light, only recorded when this is `Some`.
+    pub(crate) light_tally: Option<LightTally>,
+    /// Product of the attenuations from the camera to the current vertex,
+    /// reset by `Ray::camera_ray_color`.
+    pub(crate) throughput: Vector3,
+    /// `throughput` when the path terminated.
+    pub(crate) final_throughput: Vector3,
+    /// Bounces after which the path faces Russian roulette.
+    pub(crate) roulette: Option<usize>,
+    /// Most continuations bright indirect paths are split into.
+    pub(crate) split: Option<usize>,
+    /// Where a camera path leaves the film, as coordinates on the background
+    /// plate; see `film_position`.
+    pub(crate) film: Option<(f64, f64)>,
+}
+
+impl PathInfo {
+    /// The volume filling the path's current position, see
+    /// `HittableStore::priorities`.
+    fn inside(&self) -> Option<&Medium> {
+        self.media.iter().max_by_key(|m| m.priority)
+    }
+
+    fn terminate(&mut self, termination: Termination) {
+        self.termination = termination;
+        self.final_throughput = self.throughput;
+    }
+}
+
+/// One vertex of a traced path, see `PathInfo::events`. `throughput` is the
+/// product of the attenuations from the camera up to the vertex, so what
+/// the vertex adds to the pixel is `throughput` times its radiance.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum PathEvent {
+    /// A surface hit, adding `direct` radiance from emission and the lights
+    /// and scattering on with `attenuation`, if at all.
+    Hit {
+        object: usize,
+        point: Vector3,
+        material: Material,
+        front_facing: bool,
+        throughput: Vector3,
+        direct: Vector3,
+        attenuation: Option<Vector3>,
+    },
+    /// The ray left the scene towards `direction`, seeing `radiance`.
+    Escape {
+        direction: Vector3,
+        throughput: Vector3,
+        radiance: Vector3,
+    },
+}
+
+/// Radiance that reached the camera from each light, with the sky counted
+/// as the light after the last one and glowing surfaces as the one after
+/// that. Only the direct lighting of diffuse surfaces, emission and escaped
+/// rays are attributed; participating media, fog and the irradiance cache are
+/// not.
+#[derive(Clone, Debug)]
+pub(crate) struct LightTally {
+    luminance: Vec<f64>,
+    /// Linear sRGB, summed over the paths of a pixel.
+    pub(crate) radiance: Vec<Vector3>,
+}
+
+impl LightTally {
+    pub(crate) fn new(scene: &Scene) -> Self {
+        Self {
+            luminance: vec![0.0; scene.lights.len() + 2],
+            radiance: vec![Vector3(0.0, 0.0, 0.0); scene.lights.len() + 2],
+        }
+    }
+
+    /// Counts `radiance` reaching the camera from `light`.
+    fn add(&mut self, scene: &Scene, light: usize, radiance: Vector3) {
+        let c = scene.color_space.to_linear_srgb(radiance);
+        self.luminance[light] += 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+        self.radiance[light] += c;
+    }
+
+    /// The light that contributed the most, if any contributed at all.
+    pub(crate) fn dominant(&self) -> Option<usize> {
+        let (light, luminance) = self
+            .luminance
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))?;
+        (*luminance > 0.0).then_some(light)
+    }
+}
+
+/// A dielectric volume on the stack of `PathInfo::media`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Medium {
+    object: usize,
+    ir: f64,
+    absorption: Vector3,
+    priority: u32,
+}
+
+/// Numbers of bounces per kind of scattering: diffuse off Lambertian
+/// surfaces, glossy off metals and transmission through dielectrics.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BounceCounts {
+    pub(crate) diffuse: usize,
+    pub(crate) glossy: usize,
+    pub(crate) transmission: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Termination {
+    #[default]
+    MaxDepth,
+    Absorbed,
+    Escaped(Vector3),
+    /// Ended at a diffuse hit whose irradiance came from the irradiance cache.
+    Cached,
+}
+
+impl Termination {
+    /// Names of the kinds of termination, in the order of `index`.
+    pub(crate) const NAMES: [&'static str; 4] = ["max depth", "absorbed", "escaped", "cached"];
+
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Termination::MaxDepth => 0,
+            Termination::Absorbed => 1,
+            Termination::Escaped(_) => 2,
+            Termination::Cached => 3,
+        }
+    }
+}
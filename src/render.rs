@@ -0,0 +1,1157 @@
+//! Rendering whole images, pass by pass.
+
+use crate::camera::Camera;
+use crate::color::{id_color, viridis};
+use crate::diagnostics::log_eprintln;
+use crate::hittable::{Hittable, RayKind};
+use crate::image::{write_image, ImageFormat, RGB};
+use crate::irradiance_cache::IrradianceCache;
+use crate::pdf::{CosinePdf, Pdf};
+use crate::ray::{LightTally, PathEvent, PathInfo, Ray, Termination};
+use crate::scene::Scene;
+use crate::vec3::{derive_seed, random, reseed, Vector3};
+use rayon::prelude::*;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum RenderMode {
+    #[default]
+    Path,
+    Normals,
+    Depth,
+    Albedo,
+    Uv,
+    Facing,
+    /// A distinct color per object ID.
+    Id,
+    /// A distinct color per light ID, for the light that delivered the most
+    /// radiance to the pixel.
+    Lights,
+    /// Screen-space motion of the first hit, see `Ray::motion`.
+    Motion,
+    /// Light arriving at the first hit, see `Ray::irradiance`.
+    Irradiance,
+}
+
+impl std::str::FromStr for RenderMode {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(RenderMode::Path),
+            "normals" => Ok(RenderMode::Normals),
+            "depth" => Ok(RenderMode::Depth),
+            "albedo" => Ok(RenderMode::Albedo),
+            "uv" => Ok(RenderMode::Uv),
+            "facing" => Ok(RenderMode::Facing),
+            "id" => Ok(RenderMode::Id),
+            "lights" => Ok(RenderMode::Lights),
+            "motion" => Ok(RenderMode::Motion),
+            "irradiance" => Ok(RenderMode::Irradiance),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What `render` renders besides the scene.
+#[derive(Clone)]
+pub struct Settings {
+    pub camera: Camera,
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: usize,
+    pub max_depth: usize,
+    pub mode: RenderMode,
+    /// Makes renders with the same seed come out identical.
+    pub seed: Option<u64>,
+    /// Ends paths by Russian roulette after this many bounces, see
+    /// `Ray::ray_color`; `max_depth` still applies.
+    pub roulette: Option<usize>,
+    /// Splits bright indirect paths into up to this many continuations, see
+    /// `Ray::ray_color`.
+    pub split: Option<usize>,
+    /// Spreads the samples of each pixel over it; all go through its center
+    /// if `None`.
+    pub sampler: Option<Sampler>,
+}
+
+impl Settings {
+    /// Path tracing with 100 samples per pixel and at most 50 bounces.
+    pub fn new(camera: Camera, width: u32, height: u32) -> Self {
+        Self {
+            camera,
+            width,
+            height,
+            samples_per_pixel: 100,
+            max_depth: 50,
+            mode: RenderMode::Path,
+            seed: None,
+            roulette: None,
+            split: None,
+            sampler: None,
+        }
+    }
+}
+
+/// A rendered image, top row first. Path traced images hold linear radiance,
+/// the other modes display values.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vector3>,
+    /// Whether `write` encodes the pixels with the sRGB transfer function.
+    pub gamma: bool,
+}
+
+impl Image {
+    pub fn write(&self, out: impl Write, format: ImageFormat) -> std::io::Result<()> {
+        let pixels: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|color| color.to_rgb8(1, self.gamma))
+            .collect();
+        write_image(out, format, self.width, self.height, &pixels, &[])
+    }
+}
+
+/// Renders `scene` as seen by `settings.camera`.
+pub fn render(scene: &Scene, settings: &Settings) -> Image {
+    let pass = RenderSettings {
+        width: settings.width,
+        height: settings.height,
+        max_depth: settings.max_depth,
+        mode: settings.mode,
+        crop: None,
+        jitter: None,
+        adaptive: None,
+        light_groups: false,
+        shadow_catcher: None,
+        seed: settings.seed,
+        buckets: None,
+        trace: false,
+        aovs: false,
+        roulette: settings.roulette,
+        split: settings.split,
+        sampler: settings.sampler,
+    };
+    let samples = settings.samples_per_pixel.max(1);
+    let pixels = render_pass(&settings.camera, scene, &pass, samples, None, None, None)
+        .into_iter()
+        .map(|(color, _)| color / samples as f64)
+        .collect();
+    Image {
+        width: settings.width,
+        height: settings.height,
+        pixels,
+        gamma: settings.mode == RenderMode::Path,
+    }
+}
+
+/// Per-render parameters shared by all passes.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RenderSettings {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) max_depth: usize,
+    pub(crate) mode: RenderMode,
+    /// Only pixels in `x0..x1`, `y0..y1` (top row first) are rendered, the
+    /// rest stay black.
+    pub(crate) crop: Option<(u32, u32, u32, u32)>,
+    /// Offset of every camera ray from the pixel center, in pixels.
+    pub(crate) jitter: Option<(f64, f64)>,
+    /// Stop sampling converged pixels early when path tracing.
+    pub(crate) adaptive: Option<Adaptive>,
+    /// Record the radiance from each light in `PixelCost::lights`.
+    pub(crate) light_groups: bool,
+    /// Render the scene over a shadow catcher instead of the sky, recording
+    /// alpha in `PixelCost::coverage`.
+    pub(crate) shadow_catcher: Option<ShadowCatcher>,
+    /// Seed of this pass; each pixel draws its random numbers from a seed
+    /// derived from it, whichever thread renders the pixel.
+    pub(crate) seed: Option<u64>,
+    /// Spread the samples of each path traced pixel over this many
+    /// `PixelCost::buckets`, see `PixelCost::median_of_means`.
+    pub(crate) buckets: Option<usize>,
+    /// Record every path traced in `PixelCost::trace`; meant for a crop of
+    /// a single pixel.
+    pub(crate) trace: bool,
+    /// Record what camera rays hit first in `PixelCost::aov`.
+    pub(crate) aovs: bool,
+    /// Bounces after which paths face Russian roulette, see `Ray::ray_color`.
+    pub(crate) roulette: Option<usize>,
+    /// Most continuations bright indirect paths split into.
+    pub(crate) split: Option<usize>,
+    /// Where in each pixel its camera rays go, on top of `jitter`; every
+    /// pass takes the whole set, so it only pays off in a single pass.
+    pub(crate) sampler: Option<Sampler>,
+}
+
+/// An invisible ground plane at `height` that only shows the ambient
+/// occlusion of the scene above it within `reach`, as black of that alpha,
+/// for dropping renders onto other backgrounds. Camera rays that miss both
+/// are transparent.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ShadowCatcher {
+    pub(crate) height: f64,
+    pub(crate) reach: f64,
+}
+
+impl ShadowCatcher {
+    /// A catcher under the bounds of the scene, occluded within half their
+    /// largest extent; `None` for unbounded scenes.
+    pub(crate) fn under(scene: &Scene) -> Option<Self> {
+        let bounds = scene.world.bounding_box()?;
+        let size = bounds.max - bounds.min;
+        Some(Self {
+            height: bounds.min.y(),
+            reach: 0.5 * size.x().max(size.y()).max(size.z()),
+        })
+    }
+
+    /// The alpha of a sample along camera ray `r`, if it misses the scene or
+    /// reaches the catcher first.
+    fn alpha(&self, r: &Ray, scene: &Scene) -> Option<f64> {
+        let t = (self.height - r.origin.y()) / r.direction.y();
+        let t = if t > 0.001 { t } else { f64::INFINITY };
+        if scene.world.hit_as(r, 0.001, t, RayKind::Camera).is_some() {
+            return None;
+        }
+        if t == f64::INFINITY {
+            return Some(0.0);
+        }
+        let up = Vector3(0.0, 1.0, 0.0);
+        let probe = Ray {
+            origin: r.at(t),
+            direction: CosinePdf { normal: up }.generate().normalize(),
+            time: r.time,
+        };
+        let occluded = scene
+            .world
+            .hit_as(&probe, 0.001, self.reach, RayKind::Shadow)
+            .is_some();
+        Some(if occluded { 1.0 } else { 0.0 })
+    }
+}
+
+/// Adaptive sampling: a pixel stops after `min_samples` once the 95%
+/// confidence interval of its mean luminance is within `threshold` of the
+/// mean (or of 0.01 in the dark), and after all samples otherwise.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Adaptive {
+    pub(crate) threshold: f64,
+    pub(crate) min_samples: usize,
+}
+
+/// Camera coordinates `u`, `v`, which put pixel centers on the edges of the
+/// frame, as texture coordinates of an image stretched over the frame, so a
+/// background plate the size of the render lines up pixel for pixel.
+pub(crate) fn film_position(u: f64, v: f64, width: u32, height: u32) -> (f64, f64) {
+    (
+        (u * (width - 1) as f64 + 0.5) / width as f64,
+        (v * (height - 1) as f64 + 0.5) / height as f64,
+    )
+}
+
+/// Point `n` of the R2 low-discrepancy sequence, a sub-pixel offset in
+/// `[-0.5, 0.5)` along each axis; successive frames shifted by successive
+/// points cover the pixel evenly.
+pub(crate) fn r2_jitter(n: usize) -> (f64, f64) {
+    // the plastic number, the unique real root of x^3 = x + 1
+    let g = 1.324_717_957_244_746;
+    let x = (0.5 + n as f64 / g).fract();
+    let y = (0.5 + n as f64 / (g * g)).fract();
+    (x - 0.5, y - 0.5)
+}
+
+/// A finite set of points spread over a pixel for a sample count known up
+/// front, more evenly than progressive sequences manage for any one count.
+/// Each pixel draws its own randomization, so neighbours do not repeat the
+/// same pattern. Only the position in the pixel is stratified; lens, time
+/// and bounces draw their random numbers as before.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sampler {
+    /// The Hammersley set, `(k + 0.5) / n` across and the base 2 radical
+    /// inverse of `k` up, shifted around the pixel at random.
+    Hammersley,
+    /// One point in each of `n` columns and in each of `n` rows, with the
+    /// rows shuffled.
+    LatinHypercube,
+}
+
+impl std::str::FromStr for Sampler {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hammersley" => Ok(Sampler::Hammersley),
+            "latin" | "latin-hypercube" => Ok(Sampler::LatinHypercube),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Sampler {
+    /// Sub-pixel offsets in `[-0.5, 0.5)` along each axis for the `n`
+    /// samples of a pixel, in the order they are taken.
+    pub(crate) fn offsets(self, n: usize) -> Vec<(f64, f64)> {
+        let n = n.max(1);
+        match self {
+            Sampler::Hammersley => {
+                let (sx, sy) = (random(), random());
+                (0..n)
+                    .map(|k| {
+                        let x = (k as f64 + 0.5) / n as f64;
+                        let y = (k as u32).reverse_bits() as f64 / 2f64.powi(32);
+                        ((x + sx).fract() - 0.5, (y + sy).fract() - 0.5)
+                    })
+                    .collect()
+            }
+            Sampler::LatinHypercube => {
+                let mut rows: Vec<usize> = (0..n).collect();
+                for k in (1..n).rev() {
+                    rows.swap(k, (random() * (k + 1) as f64) as usize % (k + 1));
+                }
+                rows.iter()
+                    .enumerate()
+                    .map(|(k, &row)| {
+                        let x = (k as f64 + random()) / n as f64;
+                        let y = (row as f64 + random()) / n as f64;
+                        (x - 0.5, y - 0.5)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// What it took to render a pixel and how its paths ended, for the cost
+/// heatmap and path statistics, and where its light came from.
+#[derive(Default, Clone, Debug)]
+pub(crate) struct PixelCost {
+    seconds: f64,
+    bounces: usize,
+    /// Traced camera paths, by how they terminated; see `Termination::index`.
+    terminations: [usize; 4],
+    /// Summed luminance of the throughput of each path when it terminated.
+    throughput: f64,
+    /// Summed radiance per light, see `LightTally`; empty unless
+    /// `RenderSettings::light_groups` is set.
+    pub(crate) lights: Vec<Vector3>,
+    /// Summed alpha of the samples over a `ShadowCatcher`.
+    pub(crate) coverage: f64,
+    /// Summed color and number of samples per bucket; empty unless
+    /// `RenderSettings::buckets` is set.
+    buckets: Vec<(Vector3, usize)>,
+    /// Every path traced, in order; empty unless `RenderSettings::trace` is
+    /// set.
+    pub(crate) trace: Vec<SampleTrace>,
+    /// Nothing unless `RenderSettings::aovs` is set.
+    first_hits: FirstHits,
+}
+
+/// What the camera rays of a pixel hit first, summed over them.
+#[derive(Default, Clone, Debug)]
+struct FirstHits {
+    rays: usize,
+    hits: usize,
+    depth: f64,
+    normal: Vector3,
+    albedo: Vector3,
+    /// The object the first camera ray that hit anything hit.
+    object: Option<usize>,
+}
+
+impl FirstHits {
+    fn record(&mut self, r: &Ray, scene: &Scene) {
+        self.rays += 1;
+        if let Some(i) = scene.world.hit_as(r, 0.001, f64::INFINITY, RayKind::Camera) {
+            self.hits += 1;
+            self.object = self.object.or(Some(i.object));
+            self.depth += i.t * r.direction.length();
+            self.normal += i.normal;
+            self.albedo += scene
+                .material_override
+                .unwrap_or(i.material)
+                .albedo(i.u, i.v, &i.p);
+        }
+    }
+}
+
+/// Auxiliary images recorded alongside the render, for compositing and for
+/// denoisers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Aov {
+    /// Distance to the first hit along the camera ray, averaged over the
+    /// rays that hit something; infinite where none did.
+    Depth,
+    /// World-space normal at the first hit, facing the camera.
+    Normal,
+    /// Surface color at the first hit, see `Material::albedo`.
+    Albedo,
+    /// Index of the object the pixel's first camera ray to hit anything hit,
+    /// -1 where none did; unlike the others it is not blended at edges.
+    Id,
+}
+
+impl Aov {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Id => "id",
+        }
+    }
+
+    /// Layer and channel names in a multi-layer EXR, following the usual
+    /// conventions: depth is the `Z` channel, normals are `N.X`, `N.Y` and
+    /// `N.Z`.
+    pub(crate) fn exr_channels(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Aov::Depth => ("", &["Z"]),
+            Aov::Normal => ("N", &["X", "Y", "Z"]),
+            Aov::Albedo => ("albedo", RGB),
+            Aov::Id => ("", &["id"]),
+        }
+    }
+}
+
+impl std::str::FromStr for Aov {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "depth" => Ok(Aov::Depth),
+            "normal" => Ok(Aov::Normal),
+            "albedo" => Ok(Aov::Albedo),
+            "id" => Ok(Aov::Id),
+            _ => Err(()),
+        }
+    }
+}
+
+/// One sample of a pixel traced with `RenderSettings::trace`.
+#[derive(Clone, Debug)]
+pub(crate) struct SampleTrace {
+    pub(crate) radiance: Vector3,
+    pub(crate) termination: Termination,
+    pub(crate) events: Vec<PathEvent>,
+}
+
+impl PixelCost {
+    pub(crate) fn paths(&self) -> usize {
+        self.terminations.iter().sum()
+    }
+
+    /// Bounces per path, on average.
+    pub(crate) fn path_length(&self) -> f64 {
+        self.bounces as f64 / self.paths().max(1) as f64
+    }
+
+    /// The pixel's value of `aov`; normals and albedo are averaged over all
+    /// camera rays, so they blend at edges like the render, and are black
+    /// where rays hit nothing.
+    pub(crate) fn aov(&self, aov: Aov) -> Vector3 {
+        let h = &self.first_hits;
+        match aov {
+            Aov::Depth if h.hits == 0 => Vector3(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            Aov::Depth => {
+                let d = h.depth / h.hits as f64;
+                Vector3(d, d, d)
+            }
+            Aov::Normal => h.normal / h.rays.max(1) as f64,
+            Aov::Albedo => h.albedo / h.rays.max(1) as f64,
+            Aov::Id => {
+                let id = h.object.map_or(-1.0, |id| id as f64);
+                Vector3(id, id, id)
+            }
+        }
+    }
+
+    /// The mean color of the bucket of median luminance (between the middle
+    /// two for an even number), or `None` without buckets. A rare very
+    /// bright sample lifts the mean of one bucket only, so unlike the mean
+    /// over all samples this leaves out fireflies, at the cost of darkening
+    /// a little wherever the radiance is skewed towards bright samples.
+    pub(crate) fn median_of_means(&self) -> Option<Vector3> {
+        let luminance = |c: &Vector3| 0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z();
+        let mut means: Vec<Vector3> = self
+            .buckets
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(sum, count)| *sum / *count as f64)
+            .collect();
+        means.sort_by(|a, b| luminance(a).total_cmp(&luminance(b)));
+        let k = means.len();
+        match k {
+            0 => None,
+            _ if k % 2 == 1 => Some(means[k / 2]),
+            _ => Some((means[k / 2 - 1] + means[k / 2]) * 0.5),
+        }
+    }
+}
+
+impl std::ops::AddAssign for PixelCost {
+    fn add_assign(&mut self, rhs: Self) {
+        self.seconds += rhs.seconds;
+        self.bounces += rhs.bounces;
+        for (a, b) in self.terminations.iter_mut().zip(rhs.terminations) {
+            *a += b;
+        }
+        self.throughput += rhs.throughput;
+        self.coverage += rhs.coverage;
+        self.trace.extend(rhs.trace);
+        self.first_hits.rays += rhs.first_hits.rays;
+        self.first_hits.hits += rhs.first_hits.hits;
+        self.first_hits.depth += rhs.first_hits.depth;
+        self.first_hits.normal += rhs.first_hits.normal;
+        self.first_hits.albedo += rhs.first_hits.albedo;
+        self.first_hits.object = self.first_hits.object.or(rhs.first_hits.object);
+        if self.buckets.is_empty() {
+            self.buckets = rhs.buckets;
+        } else {
+            for (a, b) in self.buckets.iter_mut().zip(rhs.buckets) {
+                a.0 += b.0;
+                a.1 += b.1;
+            }
+        }
+        if self.lights.is_empty() {
+            self.lights = rhs.lights;
+        } else {
+            for (a, b) in self.lights.iter_mut().zip(rhs.lights) {
+                *a += b;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum HeatmapMetric {
+    Time,
+    Bounces,
+    /// Mean bounces per path.
+    Length,
+    /// Mean luminance of the path throughput at termination.
+    Throughput,
+    /// Fractions of the paths ending at the maximum depth (red), absorbed
+    /// (blue), escaping (green) and in the irradiance cache (yellow).
+    Termination,
+    /// Paths traced per pixel, fewer where adaptive sampling stopped early.
+    Samples,
+}
+
+impl std::str::FromStr for HeatmapMetric {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "time" => Ok(HeatmapMetric::Time),
+            "bounces" => Ok(HeatmapMetric::Bounces),
+            "length" => Ok(HeatmapMetric::Length),
+            "throughput" => Ok(HeatmapMetric::Throughput),
+            "termination" => Ok(HeatmapMetric::Termination),
+            "samples" => Ok(HeatmapMetric::Samples),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Scalar quantities rendered in false color, see `post::false_color`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum FalseColor {
+    /// Distance to the first hit.
+    Depth,
+    /// Luminance of the irradiance at the first hit.
+    Irradiance,
+    /// Surfaces hit per path, on average.
+    Hits,
+}
+
+impl FalseColor {
+    /// The mode rendering the quantity.
+    pub(crate) fn mode(self) -> RenderMode {
+        match self {
+            FalseColor::Depth => RenderMode::Depth,
+            FalseColor::Irradiance => RenderMode::Irradiance,
+            FalseColor::Hits => RenderMode::Path,
+        }
+    }
+}
+
+impl std::str::FromStr for FalseColor {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "depth" => Ok(FalseColor::Depth),
+            "irradiance" => Ok(FalseColor::Irradiance),
+            "hits" => Ok(FalseColor::Hits),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Seconds between progress reports.
+const PROGRESS_INTERVAL: f64 = 1.0;
+
+/// How much of a render, over all its passes, is done: reported on stderr
+/// with the time left, at most every `PROGRESS_INTERVAL` and when finished.
+pub(crate) struct Progress {
+    /// Pixel samples to render.
+    total: u64,
+    done: AtomicU64,
+    start: std::time::Instant,
+    /// Milliseconds after `start` of the last report.
+    reported: AtomicU64,
+}
+
+impl Progress {
+    pub(crate) fn new(total: u64) -> Self {
+        Self {
+            total: total.max(1),
+            done: AtomicU64::new(0),
+            start: std::time::Instant::now(),
+            reported: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn add(&self, samples: u64) {
+        let done = self.done.fetch_add(samples, Ordering::Relaxed) + samples;
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let now = (elapsed * 1e3) as u64;
+        let last = self.reported.load(Ordering::Relaxed);
+        let due = now >= last + (PROGRESS_INTERVAL * 1e3) as u64;
+        // one thread reports, unless this finished the render
+        if done < self.total
+            && (!due
+                || self
+                    .reported
+                    .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_err())
+        {
+            return;
+        }
+        let fraction = (done as f64 / self.total as f64).min(1.0);
+        eprint!(
+            "\r{:5.1}%  {:.0}s elapsed, {:.0}s left ",
+            fraction * 100.0,
+            elapsed,
+            elapsed * (1.0 - fraction) / fraction
+        );
+        if done >= self.total {
+            eprintln!();
+        }
+    }
+}
+
+/// Side of the square tiles a pass is divided into.
+const TILE_SIZE: u32 = 32;
+
+/// Tiles are not split below this side.
+const MIN_TILE_SIZE: u32 = 8;
+
+/// The cost prepass traces one sample at every `PREPASS_STRIDE`th pixel in
+/// each direction.
+const PREPASS_STRIDE: u32 = 4;
+
+/// A rectangle of pixels `x0..x1`, `y0..y1` (top row first) rendered as one
+/// unit of work.
+#[derive(Clone, Copy, Debug)]
+struct Tile {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+}
+
+impl Tile {
+    fn pixels(self) -> impl Iterator<Item = (u32, u32)> {
+        (self.y0..self.y1).flat_map(move |y| (self.x0..self.x1).map(move |x| (x, y)))
+    }
+
+    /// Estimated seconds to render the tile, from per-pixel `estimate`s.
+    fn cost(self, estimate: &[f64], width: u32) -> f64 {
+        self.pixels()
+            .map(|(x, y)| estimate[(y * width + x) as usize])
+            .sum()
+    }
+
+    /// The four quadrants of the tile.
+    fn split(self) -> [Tile; 4] {
+        let (xm, ym) = ((self.x0 + self.x1) / 2, (self.y0 + self.y1) / 2);
+        [
+            Tile {
+                x1: xm,
+                y1: ym,
+                ..self
+            },
+            Tile {
+                x0: xm,
+                y1: ym,
+                ..self
+            },
+            Tile {
+                x1: xm,
+                y0: ym,
+                ..self
+            },
+            Tile {
+                x0: xm,
+                y0: ym,
+                ..self
+            },
+        ]
+    }
+}
+
+/// Divides the image into tiles, most expensive first, splitting those
+/// estimated to take more than a fraction of a thread's share of the pass so
+/// that no single tile holds up its end.
+fn schedule_tiles(width: u32, height: u32, estimate: &[f64]) -> Vec<Tile> {
+    let total: f64 = estimate.iter().sum();
+    let limit = total / (4 * rayon::current_num_threads()) as f64;
+    let mut pending: Vec<Tile> = (0..height)
+        .step_by(TILE_SIZE as usize)
+        .flat_map(|y0| {
+            (0..width).step_by(TILE_SIZE as usize).map(move |x0| Tile {
+                x0,
+                y0,
+                x1: (x0 + TILE_SIZE).min(width),
+                y1: (y0 + TILE_SIZE).min(height),
+            })
+        })
+        .collect();
+    let mut tiles = Vec::new();
+    while let Some(tile) = pending.pop() {
+        let cost = tile.cost(estimate, width);
+        if cost > limit
+            && tile.x1 - tile.x0 >= 2 * MIN_TILE_SIZE
+            && tile.y1 - tile.y0 >= 2 * MIN_TILE_SIZE
+        {
+            pending.extend(tile.split());
+        } else {
+            tiles.push((cost, tile));
+        }
+    }
+    tiles.sort_by(|a, b| b.0.total_cmp(&a.0));
+    tiles.into_iter().map(|(_, tile)| tile).collect()
+}
+
+/// Renders `samples` samples for every pixel and returns the per-pixel sums
+/// and costs, top row first. With adaptive sampling, pixels that converge
+/// early return their mean times `samples`. Tiles are scheduled by the cost `estimate` of
+/// each pixel, typically a previous pass, or else by a quick prepass. Every
+/// finished tile counts towards `progress`.
+pub(crate) fn render_pass(
+    cam: &Camera,
+    scene: &Scene,
+    settings: &RenderSettings,
+    samples: usize,
+    cache: Option<&IrradianceCache>,
+    estimate: Option<&[PixelCost]>,
+    progress: Option<&Progress>,
+) -> Vec<(Vector3, PixelCost)> {
+    let RenderSettings {
+        width,
+        height,
+        max_depth,
+        mode,
+        crop,
+        jitter,
+        adaptive,
+        light_groups,
+        shadow_catcher,
+        seed,
+        buckets,
+        trace,
+        aovs,
+        roulette,
+        split,
+        sampler,
+    } = *settings;
+    let buckets = buckets.filter(|_| mode == RenderMode::Path);
+    let adaptive = adaptive.filter(|_| mode == RenderMode::Path);
+    let light_groups = light_groups && mode == RenderMode::Path;
+    let (jitter_x, jitter_y) = jitter.unwrap_or((0.0, 0.0));
+    let nan_reported = AtomicBool::new(false);
+    // `y` counts rows from the top, `j` from the bottom
+    let render_pixel = |i: u32, y: u32, samples: usize| {
+        if let Some((x0, y0, x1, y1)) = crop {
+            if i < x0 || i >= x1 || y < y0 || y >= y1 {
+                return (Vector3(0.0, 0.0, 0.0), PixelCost::default());
+            }
+        }
+        let j = height - 1 - y;
+        let start = std::time::Instant::now();
+        if let Some(seed) = seed {
+            reseed(derive_seed(seed, y as u64 * width as u64 + i as u64));
+        }
+        let mut path = PathInfo {
+            light_tally: (mode == RenderMode::Lights || light_groups)
+                .then(|| LightTally::new(scene)),
+            roulette,
+            split,
+            ..PathInfo::default()
+        };
+        let mut terminations = [0; 4];
+        let mut throughput = 0.0;
+        let mut coverage = 0.0;
+        // running mean and squared deviations of the sample luminance
+        let (mut mean, mut m2) = (0.0, 0.0);
+        let mut color = Vector3(0.0, 0.0, 0.0);
+        let mut sums = vec![(Vector3(0.0, 0.0, 0.0), 0); buckets.unwrap_or(0)];
+        let mut traces = Vec::new();
+        let mut first_hits = FirstHits::default();
+        // samples go round the buckets from a random one, so that passes of
+        // fewer samples than buckets still fill them evenly
+        let first_bucket = buckets.map_or(0, |k| (random() * k as f64) as usize);
+        let offsets = sampler.map(|sampler| sampler.offsets(samples));
+        let mut n = 0;
+        while n < samples {
+            let sample = {
+                let (dx, dy) = offsets.as_ref().map_or((0.0, 0.0), |offsets| offsets[n]);
+                let u = (i as f64 + jitter_x + dx) / (width - 1) as f64;
+                let v = (j as f64 - jitter_y - dy) / (height - 1) as f64;
+                let r = cam.get_ray(u, v);
+                path.film = Some(film_position(u, v, width, height));
+                if aovs {
+                    first_hits.record(&r, scene);
+                }
+                let catcher = match (mode, shadow_catcher) {
+                    (RenderMode::Path, Some(catcher)) => catcher.alpha(&r, scene),
+                    _ => None,
+                };
+                match (mode, catcher) {
+                    (_, Some(alpha)) => {
+                        coverage += alpha;
+                        Vector3(0.0, 0.0, 0.0)
+                    }
+                    (RenderMode::Path, None) | (RenderMode::Lights, None) => {
+                        coverage += 1.0;
+                        path.last_object = None;
+                        path.events = trace.then(Vec::new);
+                        let color = r.camera_ray_color(scene, max_depth, cache, &mut path);
+                        if let Some(events) = path.events.take() {
+                            traces.push(SampleTrace {
+                                radiance: color,
+                                termination: path.termination,
+                                events,
+                            });
+                        }
+                        terminations[path.termination.index()] += 1;
+                        let t = path.final_throughput;
+                        throughput += 0.2126 * t.x() + 0.7152 * t.y() + 0.0722 * t.z();
+                        let nan = color.x().is_nan() || color.y().is_nan() || color.z().is_nan();
+                        if nan && !nan_reported.swap(true, Ordering::Relaxed) {
+                            let object = match path.last_object {
+                                Some(id) => scene.world.describe(id),
+                                None => "the background".to_string(),
+                            };
+                            log_eprintln!(
+                                "NaN produced by object {} at pixel ({}, {})",
+                                object,
+                                i,
+                                y
+                            );
+                        }
+                        color
+                    }
+                    (RenderMode::Motion, _) => {
+                        let (ds, dt) = r.motion(scene, cam);
+                        Vector3(ds * (width - 1) as f64, dt * (height - 1) as f64, 0.0)
+                    }
+                    (RenderMode::Irradiance, _) => r.irradiance(scene, max_depth, cache, &mut path),
+                    _ => r.debug_color(scene, mode),
+                }
+            };
+            color += sample;
+            if !sums.is_empty() {
+                let k = sums.len();
+                let bucket = &mut sums[(first_bucket + n) % k];
+                bucket.0 += sample;
+                bucket.1 += 1;
+            }
+            n += 1;
+            if let Some(Adaptive {
+                threshold,
+                min_samples,
+            }) = adaptive
+            {
+                let l = 0.2126 * sample.x() + 0.7152 * sample.y() + 0.0722 * sample.z();
+                let delta = l - mean;
+                mean += delta / n as f64;
+                m2 += delta * (l - mean);
+                if n >= min_samples.max(2) && n < samples {
+                    let error = 1.96 * (m2 / ((n - 1) * n) as f64).sqrt();
+                    if error <= threshold * mean.max(0.01) {
+                        break;
+                    }
+                }
+            }
+        }
+        let color = color * (samples as f64 / n as f64);
+        let color = match &path.light_tally {
+            Some(tally) if mode == RenderMode::Lights => {
+                tally.dominant().map_or(Vector3(0.0, 0.0, 0.0), |light| {
+                    id_color(light) * samples as f64
+                })
+            }
+            _ => color,
+        };
+        let lights = match path.light_tally {
+            Some(tally) if light_groups => tally
+                .radiance
+                .into_iter()
+                .map(|c| c * (samples as f64 / n as f64))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let cost = PixelCost {
+            seconds: start.elapsed().as_secs_f64(),
+            bounces: path.bounces,
+            terminations,
+            throughput,
+            lights,
+            coverage: coverage * (samples as f64 / n as f64),
+            buckets: sums,
+            trace: traces,
+            first_hits,
+        };
+        (color, cost)
+    };
+
+    let estimate: Vec<f64> = match estimate {
+        Some(costs) => costs.iter().map(|cost| cost.seconds).collect(),
+        None => {
+            let stride = PREPASS_STRIDE as usize;
+            let probes: Vec<Vec<f64>> = (0..height)
+                .into_par_iter()
+                .step_by(stride)
+                .map(|y| {
+                    (0..width)
+                        .step_by(stride)
+                        .map(|i| render_pixel(i, y, 1).1.seconds)
+                        .collect()
+                })
+                .collect();
+            (0..height as usize)
+                .flat_map(|y| {
+                    let row = &probes[y / stride];
+                    (0..width as usize).map(move |x| row[x / stride])
+                })
+                .collect()
+        }
+    };
+    let tiles = schedule_tiles(width, height, &estimate);
+
+    // every worker takes the next tile in order, so the expensive ones start
+    // first and the cheap ones fill in at the end
+    let next = AtomicUsize::new(0);
+    let done: Vec<(Tile, Vec<(Vector3, PixelCost)>)> = (0..rayon::current_num_threads())
+        .into_par_iter()
+        .flat_map_iter(|_| {
+            let mut done = Vec::new();
+            while let Some(&tile) = tiles.get(next.fetch_add(1, Ordering::Relaxed)) {
+                let pixels = tile.pixels().map(|(i, y)| render_pixel(i, y, samples));
+                done.push((tile, pixels.collect()));
+                if let Some(progress) = progress {
+                    // pixels outside the crop take no time
+                    let (x0, y0, x1, y1) = crop.unwrap_or((0, 0, width, height));
+                    let columns = tile.x1.min(x1).saturating_sub(tile.x0.max(x0));
+                    let rows = tile.y1.min(y1).saturating_sub(tile.y0.max(y0));
+                    progress.add(columns as u64 * rows as u64 * samples as u64);
+                }
+            }
+            done
+        })
+        .collect();
+    let mut image = vec![(Vector3(0.0, 0.0, 0.0), PixelCost::default()); (width * height) as usize];
+    for (tile, pixels) in done {
+        for ((x, y), pixel) in tile.pixels().zip(pixels) {
+            image[(y * width + x) as usize] = pixel;
+        }
+    }
+    image
+}
+
+/// A traced path of one camera sample, for offline inspection.
+pub(crate) struct PathDump {
+    pixel: (u32, u32),
+    sample: usize,
+    radiance: Vector3,
+    vertices: Vec<Vector3>,
+    termination: Termination,
+}
+
+/// Traces all samples of the pixels in `region` (x0, y0, x1, y1 in image
+/// coordinates, exclusive upper bounds) single-threaded, recording every path.
+pub(crate) fn dump_paths(
+    cam: &Camera,
+    scene: &Scene,
+    settings: &RenderSettings,
+    samples: usize,
+    region: (u32, u32, u32, u32),
+) -> Vec<PathDump> {
+    let RenderSettings {
+        width,
+        height,
+        max_depth,
+        ..
+    } = *settings;
+    let mut dumps = Vec::new();
+    for y in region.1..region.3.min(height) {
+        for x in region.0..region.2.min(width) {
+            for sample in 0..samples {
+                let u = x as f64 / (width - 1) as f64;
+                let v = (height - 1 - y) as f64 / (height - 1) as f64;
+                let r = cam.get_ray(u, v);
+                let mut path = PathInfo {
+                    vertices: Some(vec![r.origin]),
+                    ..PathInfo::default()
+                };
+                let radiance = r.camera_ray_color(scene, max_depth, None, &mut path);
+                dumps.push(PathDump {
+                    pixel: (x, y),
+                    sample,
+                    radiance,
+                    vertices: path.vertices.unwrap_or_default(),
+                    termination: path.termination,
+                });
+            }
+        }
+    }
+    dumps
+}
+
+/// Writes path dumps as JSON, or as a PLY edge list if `path` ends in `.ply`.
+/// Escaping rays get a final segment of length `escape_length` in the PLY.
+pub(crate) fn write_path_dump(
+    path: &str,
+    dumps: &[PathDump],
+    escape_length: f64,
+) -> std::io::Result<()> {
+    let mut f = std::io::BufWriter::new(std::fs::File::create(path)?);
+    let v = |v: &Vector3| format!("[{}, {}, {}]", v.x(), v.y(), v.z());
+    if path.ends_with(".ply") {
+        let mut points = Vec::new();
+        let mut edges = Vec::new();
+        for dump in dumps {
+            let first = points.len();
+            points.extend(dump.vertices.iter().copied());
+            if let (Termination::Escaped(dir), Some(last)) =
+                (dump.termination, dump.vertices.last())
+            {
+                points.push(*last + dir * escape_length);
+            }
+            edges.extend((first + 1..points.len()).map(|i| (i - 1, i)));
+        }
+        writeln!(f, "ply\nformat ascii 1.0")?;
+        writeln!(f, "element vertex {}", points.len())?;
+        writeln!(f, "property float x\nproperty float y\nproperty float z")?;
+        writeln!(f, "element edge {}", edges.len())?;
+        writeln!(f, "property int vertex1\nproperty int vertex2\nend_header")?;
+        for p in &points {
+            writeln!(f, "{} {} {}", p.x(), p.y(), p.z())?;
+        }
+        for (a, b) in &edges {
+            writeln!(f, "{} {}", a, b)?;
+        }
+    } else {
+        writeln!(f, "[")?;
+        for (n, dump) in dumps.iter().enumerate() {
+            let termination = match dump.termination {
+                Termination::MaxDepth => "\"max_depth\"".to_string(),
+                Termination::Absorbed => "\"absorbed\"".to_string(),
+                Termination::Cached => "\"cached\"".to_string(),
+                Termination::Escaped(dir) => format!("{{\"escaped\": {}}}", v(&dir)),
+            };
+            let vertices: Vec<String> = dump.vertices.iter().map(v).collect();
+            writeln!(
+                f,
+                "  {{\"pixel\": [{}, {}], \"sample\": {}, \"radiance\": {}, \"termination\": {}, \"vertices\": [{}]}}{}",
+                dump.pixel.0,
+                dump.pixel.1,
+                dump.sample,
+                v(&dump.radiance),
+                termination,
+                vertices.join(", "),
+                if n + 1 < dumps.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(f, "]")?;
+    }
+    f.flush()
+}
+
+/// Maps per-pixel costs through viridis, scaled so the 99th percentile is the
+/// top of the colormap. Returns the image and the cost at the top.
+pub(crate) fn cost_heatmap(costs: &[PixelCost], metric: HeatmapMetric) -> (Vec<Vector3>, f64) {
+    if metric == HeatmapMetric::Termination {
+        let colors = [
+            Vector3(1.0, 0.0, 0.0),
+            Vector3(0.0, 0.0, 1.0),
+            Vector3(0.0, 1.0, 0.0),
+            Vector3(1.0, 1.0, 0.0),
+        ];
+        let mix = |c: &PixelCost| {
+            let paths = c.paths().max(1) as f64;
+            c.terminations
+                .iter()
+                .zip(colors)
+                .fold(Vector3(0.0, 0.0, 0.0), |acc, (n, color)| {
+                    acc + color * (*n as f64 / paths)
+                })
+        };
+        return (costs.iter().map(mix).collect(), 1.0);
+    }
+    let values: Vec<f64> = costs
+        .iter()
+        .map(|c| match metric {
+            HeatmapMetric::Time => c.seconds,
+            HeatmapMetric::Bounces => c.bounces as f64,
+            HeatmapMetric::Length => c.path_length(),
+            HeatmapMetric::Throughput => c.throughput / c.paths().max(1) as f64,
+            HeatmapMetric::Samples => c.paths() as f64,
+            HeatmapMetric::Termination => unreachable!(),
+        })
+        .collect();
+    let mut sorted = values.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let top = sorted[(sorted.len() - 1) * 99 / 100].max(f64::MIN_POSITIVE);
+    (values.iter().map(|v| viridis(v / top)).collect(), top)
+}
+
+/// Prints the mean path length and throughput at termination over all
+/// pixels, and how the paths terminated.
+pub(crate) fn print_path_stats(costs: &[PixelCost]) {
+    let mut total = PixelCost::default();
+    for cost in costs {
+        total += cost.clone();
+    }
+    let paths = total.paths().max(1) as f64;
+    eprintln!(
+        "paths: {} traced, {:.2} bounces and final throughput {:.4} on average",
+        total.paths(),
+        total.bounces as f64 / paths,
+        total.throughput / paths
+    );
+    for (name, n) in Termination::NAMES.iter().zip(total.terminations) {
+        eprintln!("  {:<10} {:>6.2}%", name, 100.0 * n as f64 / paths);
+    }
+}
+
+/// RMSE and relative MSE of the current estimate against a reference image.
+pub(crate) fn error_metrics(
+    image: &[Vector3],
+    samples: usize,
+    reference: &[Vector3],
+) -> (f64, f64) {
+    let mut se = 0.0;
+    let mut rel_se = 0.0;
+    for (sum, r) in image.iter().zip(reference) {
+        let d = *sum / samples as f64 - *r;
+        for (d, r) in [(d.x(), r.x()), (d.y(), r.y()), (d.z(), r.z())] {
+            se += d * d;
+            rel_se += d * d / (r * r + 0.01);
+        }
+    }
+    let n = (image.len() * 3) as f64;
+    ((se / n).sqrt(), rel_se / n)
+}
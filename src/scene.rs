@@ -0,0 +1,1008 @@
+//! Scenes: the objects, lights, sky and media, and the built-in presets.
+
+use crate::cli::Options;
+use crate::color::ColorSpace;
+use crate::hittable::mesh::{smooth_normals, Mesh};
+use crate::hittable::quad::Cuboid;
+use crate::hittable::terrain::{lattice_hash, Terrain};
+use crate::hittable::transform::{Matrix4, Transform};
+use crate::hittable::water::WaterSurface;
+use crate::hittable::{HittableStore, RayKind, Sphere, Visibility};
+use crate::light::Light;
+use crate::material::{reflectance, Material};
+use crate::medium::HomogeneousMedium;
+use crate::ray::{BounceCounts, Ray};
+use crate::scene_file::{load_scene, parse_scene, SceneSettings};
+use crate::sky::{Atmosphere, Fog, Sky};
+use crate::texture::{ImageTexture, Texture};
+use crate::vec3::{random, random_in_range, Vector3};
+use rayon::prelude::*;
+use std::sync::Arc;
+
+impl HittableStore {
+    fn random() -> Self {
+        let mut world = Self::default();
+        let ground_material = Material::Lambertian {
+            albedo: Vector3(0.5, 0.5, 0.5).into(),
+        };
+        world.add_named(
+            "ground",
+            Sphere {
+                center: Vector3(0.0, -1000.0, 0.0),
+                radius: 1000.0,
+                material: ground_material,
+            },
+        );
+
+        for a in -11..11 {
+            for b in -11..11 {
+                let choose_mat = random();
+                let center = Vector3(a as f64 + 0.9 * random(), 0.2, b as f64 + 0.9 * random());
+
+                if (center - Vector3(4.0, 0.2, 0.0)).length() > 0.9 {
+                    if choose_mat < 0.8 {
+                        //diffuse
+                        let albedo = Vector3::random() * Vector3::random();
+                        let material = Material::Lambertian {
+                            albedo: albedo.into(),
+                        };
+                        world.add(Sphere {
+                            center,
+                            radius: 0.2,
+                            material,
+                        })
+                    } else if choose_mat < 0.95 {
+                        //metal
+                        let albedo = Vector3::random_in_range(0.5, 1.0);
+                        let fuzz = random_in_range(0.0, 0.5);
+                        let material = Material::Metal {
+                            albedo: albedo.into(),
+                            fuzz,
+                        };
+                        world.add(Sphere {
+                            center,
+                            radius: 0.2,
+                            material,
+                        })
+                    } else {
+                        let material = Material::Dielectric {
+                            ir: 1.5,
+                            roughness: 0.0,
+                            absorption: Vector3(0.0, 0.0, 0.0),
+                        };
+                        world.add(Sphere {
+                            center,
+                            radius: 0.2,
+                            material,
+                        })
+                    }
+                }
+            }
+        }
+
+        let m1 = Material::Dielectric {
+            ir: 1.5,
+            roughness: 0.0,
+            absorption: Vector3(0.0, 0.0, 0.0),
+        };
+        world.add_named(
+            "glass_ball",
+            Sphere {
+                center: Vector3(0.0, 1.0, 0.0),
+                radius: 1.0,
+                material: m1,
+            },
+        );
+        let m2 = Material::Lambertian {
+            albedo: Vector3(0.2, 0.2, 0.5).into(),
+        };
+        world.add_named(
+            "diffuse_ball",
+            Sphere {
+                center: Vector3(-4.0, 1.0, 0.0),
+                radius: 1.0,
+                material: m2,
+            },
+        );
+        let m3 = Material::Metal {
+            albedo: Vector3(0.7, 0.6, 0.5).into(),
+            fuzz: 0.2,
+        };
+        world.add_named(
+            "metal_ball",
+            Sphere {
+                center: Vector3(4.0, 1.0, 0.0),
+                radius: 1.0,
+                material: m3,
+            },
+        );
+        world
+    }
+
+    /// A stretch of choppy sea over a sandy floor with a few floating balls.
+    fn ocean(time: f64) -> Self {
+        let mut world = Self::default();
+        world.add_named(
+            "sea_floor",
+            Sphere {
+                center: Vector3(0.0, -1003.0, 0.0),
+                radius: 1000.0,
+                material: Material::Lambertian {
+                    albedo: Vector3(0.76, 0.7, 0.5).into(),
+                },
+            },
+        );
+        world.add_named(
+            "water",
+            WaterSurface::new(
+                0.0,
+                60.0,
+                (1.0, 0.3),
+                0.15,
+                time,
+                Material::Dielectric {
+                    ir: 1.33,
+                    roughness: 0.02,
+                    absorption: Vector3(0.0, 0.0, 0.0),
+                },
+            ),
+        );
+        world.add_named(
+            "red_ball",
+            Sphere {
+                center: Vector3(0.0, 0.4, 0.0),
+                radius: 1.0,
+                material: Material::Lambertian {
+                    albedo: Vector3(0.8, 0.2, 0.1).into(),
+                },
+            },
+        );
+        world.add_named(
+            "chrome_ball",
+            Sphere {
+                center: Vector3(-4.0, 0.1, 1.5),
+                radius: 0.7,
+                material: Material::Metal {
+                    albedo: Vector3(0.8, 0.8, 0.8).into(),
+                    fuzz: 0.1,
+                },
+            },
+        );
+        world.add_named(
+            "sunken_ball",
+            Sphere {
+                center: Vector3(3.0, -1.5, -1.5),
+                radius: 1.0,
+                material: Material::Lambertian {
+                    albedo: Vector3(0.2, 0.6, 0.3).into(),
+                },
+            },
+        );
+        world
+    }
+}
+
+impl HittableStore {
+    /// An 18% gray and a chrome ball on gray ground, for judging the light.
+    fn daylight() -> Self {
+        let mut world = Self::default();
+        world.add_named(
+            "ground",
+            Sphere {
+                center: Vector3(0.0, -1000.0, 0.0),
+                radius: 1000.0,
+                material: Material::Lambertian {
+                    albedo: Vector3(0.2, 0.2, 0.2).into(),
+                },
+            },
+        );
+        world.add_named(
+            "gray_ball",
+            Sphere {
+                center: Vector3(0.0, 1.0, -1.2),
+                radius: 1.0,
+                material: Material::Lambertian {
+                    albedo: Vector3(0.18, 0.18, 0.18).into(),
+                },
+            },
+        );
+        world.add_named(
+            "chrome_ball",
+            Sphere {
+                center: Vector3(0.0, 1.0, 1.2),
+                radius: 1.0,
+                material: Material::Metal {
+                    albedo: Vector3(0.9, 0.9, 0.9).into(),
+                    fuzz: 0.0,
+                },
+            },
+        );
+        world
+    }
+}
+
+/// The shader ball's material unless the options give another.
+const BALL_MATERIAL: Material = Material::Lambertian {
+    albedo: Texture::Solid(Vector3(0.8, 0.35, 0.2)),
+};
+
+impl HittableStore {
+    /// A ball of `material` on a dark pedestal over a checkered floor, framed
+    /// by the default camera.
+    fn material_ball(material: Material) -> Self {
+        let mut world = Self::default();
+        world.add_named(
+            "floor",
+            Sphere {
+                center: Vector3(0.0, -1001.25, 0.0),
+                radius: 1000.0,
+                material: Material::Lambertian {
+                    albedo: Texture::Checker {
+                        even: Vector3(0.6, 0.6, 0.6),
+                        odd: Vector3(0.15, 0.15, 0.15),
+                        scale: 2.0,
+                    },
+                },
+            },
+        );
+        // a frustum turned around the y axis, its top hidden inside the ball
+        let segments = 64;
+        let (bottom, top) = ((0.75, -1.25), (0.45, -0.8));
+        let mut positions = Vec::new();
+        for k in 0..segments {
+            let phi = 2.0 * std::f64::consts::PI * k as f64 / segments as f64;
+            let (sin, cos) = phi.sin_cos();
+            for (r, y) in [bottom, top] {
+                positions.push(Vector3(r * cos, y, r * sin));
+            }
+        }
+        let faces: Vec<[usize; 3]> = (0..segments)
+            .flat_map(|k| {
+                let (a, b) = (2 * k, 2 * ((k + 1) % segments));
+                [[a, a + 1, b], [b, a + 1, b + 1]]
+            })
+            .collect();
+        let normals = smooth_normals(&positions, faces.iter().copied());
+        let pedestal = Material::Lambertian {
+            albedo: Vector3(0.05, 0.05, 0.05).into(),
+        };
+        world.add_named(
+            "pedestal",
+            Mesh::new(positions, normals, Vec::new(), faces, pedestal),
+        );
+        world.add_named(
+            "ball",
+            Sphere {
+                center: Vector3(0.0, 0.0, 0.0),
+                radius: 1.0,
+                material,
+            },
+        );
+        world
+    }
+}
+
+/// A closed cylinder around the vertical axis from `bottom` to `top`, with
+/// smooth sides and flat caps.
+fn cylinder(radius: f64, bottom: f64, top: f64, material: Material) -> Mesh {
+    let segments = 48;
+    let ring = |y: f64| {
+        (0..segments).map(move |k| {
+            let phi = 2.0 * std::f64::consts::PI * k as f64 / segments as f64;
+            Vector3(radius * phi.cos(), y, radius * phi.sin())
+        })
+    };
+    // the sides, then each cap around its own center so that the caps stay
+    // flat when the normals are smoothed
+    let mut positions: Vec<Vector3> = ring(bottom)
+        .zip(ring(top))
+        .flat_map(|(b, t)| [b, t])
+        .collect();
+    let mut faces: Vec<[usize; 3]> = (0..segments)
+        .flat_map(|k| {
+            let (a, b) = (2 * k, 2 * ((k + 1) % segments));
+            [[a, a + 1, b], [b, a + 1, b + 1]]
+        })
+        .collect();
+    for (y, up) in [(bottom, false), (top, true)] {
+        let center = positions.len();
+        positions.push(Vector3(0.0, y, 0.0));
+        positions.extend(ring(y));
+        faces.extend((0..segments).map(|k| {
+            let (a, b) = (center + 1 + k, center + 1 + (k + 1) % segments);
+            if up {
+                [center, b, a]
+            } else {
+                [center, a, b]
+            }
+        }));
+    }
+    let normals = smooth_normals(&positions, faces.iter().copied());
+    Mesh::new(positions, normals, Vec::new(), faces, material)
+}
+
+impl HittableStore {
+    /// A glass of water with ice cubes and a straw on a checkered table, all
+    /// overlapping dielectrics told apart by nesting priority: the glass is a
+    /// solid cylinder hollowed out by a cylinder of air reaching above its
+    /// rim, the water fills the bottom of the hollow and a little of the
+    /// glass, and the ice and the straw sit in both.
+    fn glass_of_water() -> Self {
+        let mut world = Self::default();
+        world.add_named(
+            "table",
+            Sphere {
+                center: Vector3(0.0, -1001.2, 0.0),
+                radius: 1000.0,
+                material: Material::Lambertian {
+                    albedo: Texture::Checker {
+                        even: Vector3(0.8, 0.8, 0.75),
+                        odd: Vector3(0.2, 0.3, 0.5),
+                        scale: 1.5,
+                    },
+                },
+            },
+        );
+        let glass = Material::Dielectric {
+            ir: 1.5,
+            roughness: 0.0,
+            absorption: Vector3(0.2, 0.02, 0.1),
+        };
+        let air = Material::Dielectric {
+            ir: 1.0,
+            roughness: 0.0,
+            absorption: Vector3(0.0, 0.0, 0.0),
+        };
+        let water = Material::Dielectric {
+            ir: 1.33,
+            roughness: 0.0,
+            absorption: Vector3(0.45, 0.09, 0.06),
+        };
+        let ice = Material::Dielectric {
+            ir: 1.31,
+            roughness: 0.0,
+            absorption: Vector3(0.1, 0.02, 0.01),
+        };
+        let straw = Material::Dielectric {
+            ir: 1.45,
+            roughness: 0.0,
+            absorption: Vector3(0.0, 8.0, 6.0),
+        };
+        world.add_named("glass", cylinder(0.8, -1.2, 1.0, glass));
+        world.add_named("hollow", cylinder(0.72, -1.05, 1.6, air));
+        world.add_named("water", cylinder(0.73, -1.06, 0.4, water));
+        for (k, &(x, z, turn)) in [(-0.2, 0.15, 20.0), (0.25, -0.1, -35.0)].iter().enumerate() {
+            let cube = Cuboid::new(Vector3(-0.2, -0.2, -0.2), Vector3(0.2, 0.2, 0.2), ice);
+            let matrix = Matrix4::translation(Vector3(x, 0.42, z))
+                .then_after(&Matrix4::rotation(Vector3(1.0, 0.3, 0.2), turn));
+            let cube = Transform::new(Arc::new(cube), matrix).unwrap();
+            world.add_named(&format!("ice{}", k), cube);
+        }
+        let matrix = Matrix4::translation(Vector3(0.2, 0.0, 0.1))
+            .then_after(&Matrix4::rotation(Vector3(1.0, 0.0, -1.0), 15.0));
+        let tube = Arc::new(cylinder(0.05, -1.0, 1.8, straw));
+        world.add_named("straw", Transform::new(tube, matrix).unwrap());
+        // table, glass, hollow, water, ice, ice, straw
+        world.priorities = vec![0, 1, 2, 3, 4, 4, 5];
+        world
+    }
+
+    /// A valley of procedural terrain with scattered rocks and trees.
+    fn terrain() -> Self {
+        let seed = 7;
+        let mut world = Self::default();
+        let terrain = Terrain::new(60.0, seed);
+        let bark = Material::Lambertian {
+            albedo: Vector3(0.3, 0.2, 0.1).into(),
+        };
+        let leaves = Material::Lambertian {
+            albedo: Vector3(0.1, 0.3, 0.08).into(),
+        };
+        let stone = Material::Lambertian {
+            albedo: Vector3(0.45, 0.43, 0.4).into(),
+        };
+        let mut placed = 0;
+        let mut n: i64 = 0;
+        while placed < 120 && n < 10_000 {
+            n += 1;
+            let x = lattice_hash(n, 0, seed) * 40.0;
+            let z = lattice_hash(n, 1, seed) * 40.0;
+            let h = terrain.height(x, z);
+            let (dx, dz) = terrain.gradient(x, z);
+            // keep the view from the camera free and skip cliffs and snow
+            if (x - 13.0).hypot(z - 3.0) < 4.0 || dx.hypot(dz) > 0.5 || h > 4.0 {
+                continue;
+            }
+            let size = 0.75 + 0.25 * lattice_hash(n, 2, seed);
+            if placed % 3 == 0 {
+                world.add(Sphere {
+                    center: Vector3(x, h, z),
+                    radius: 0.3 * size,
+                    material: stone,
+                });
+            } else {
+                // a trunk of stacked spheres topped by two crowns
+                for k in 0..3 {
+                    world.add(Sphere {
+                        center: Vector3(x, h + 0.25 * k as f64 * size, z),
+                        radius: 0.12 * size,
+                        material: bark,
+                    });
+                }
+                world.add(Sphere {
+                    center: Vector3(x, h + 1.0 * size, z),
+                    radius: 0.45 * size,
+                    material: leaves,
+                });
+                world.add(Sphere {
+                    center: Vector3(x, h + 1.5 * size, z),
+                    radius: 0.3 * size,
+                    material: leaves,
+                });
+            }
+            placed += 1;
+        }
+        world.add_named("terrain", terrain);
+        world
+    }
+}
+
+/// The built-in scenes selectable from the command line.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum ScenePreset {
+    /// The final scene of the book with randomly placed balls.
+    #[default]
+    Random,
+    Ocean,
+    Terrain,
+    /// Gray and chrome reference balls under a validated clear sky, see
+    /// `Atmosphere::daylight`.
+    Daylight,
+    /// A shader ball on a pedestal and a checkered floor in a studio, for
+    /// look development.
+    MaterialBall,
+    /// A glass of water with ice and a straw, for the nested dielectrics.
+    GlassOfWater,
+}
+
+impl std::str::FromStr for ScenePreset {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(ScenePreset::Random),
+            "ocean" => Ok(ScenePreset::Ocean),
+            "terrain" => Ok(ScenePreset::Terrain),
+            "daylight" => Ok(ScenePreset::Daylight),
+            "material-ball" => Ok(ScenePreset::MaterialBall),
+            "glass-of-water" => Ok(ScenePreset::GlassOfWater),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Everything a ray can interact with: geometry, explicit lights, the sky and
+/// the medium filling the space between objects.
+#[derive(Debug)]
+pub struct Scene {
+    pub(crate) world: HittableStore,
+    pub(crate) lights: Vec<Light>,
+    pub(crate) sky: Sky,
+    /// Rotation of the sky around the y axis in radians.
+    sky_rotation: f64,
+    sky_intensity: f64,
+    /// What camera rays that hit nothing see instead of the sky, which then
+    /// only lights the scene.
+    pub(crate) backdrop: Option<Sky>,
+    /// A photograph camera rays that hit nothing see at their place in the
+    /// frame, lined up with the render, instead of the sky.
+    pub(crate) plate: Option<&'static ImageTexture>,
+    pub(crate) fog: Option<Fog>,
+    pub(crate) medium: Option<HomogeneousMedium>,
+    /// Draft previews only refract through the first dielectric along a path;
+    /// dielectrics hit after that just show the sky behind them.
+    pub(crate) draft: bool,
+    /// Working space of all radiance below `camera_ray_color`.
+    pub(crate) color_space: ColorSpace,
+    /// Bounce limits per kind, on top of the overall maximum depth.
+    pub(crate) max_bounces: BounceCounts,
+    /// Material every object is shaded with instead of its own.
+    pub(crate) material_override: Option<Material>,
+    /// IDs of the glowing objects diffuse bounces are aimed at, those that
+    /// can be sampled.
+    pub(crate) emitters: Vec<usize>,
+    /// Per light ID, which object IDs it lights; every object if `None`.
+    pub(crate) light_links: Vec<Option<Vec<bool>>>,
+    /// How many dielectric surfaces shadow rays to the lights pass through,
+    /// tinted by the glass, before they count as blocked.
+    pub(crate) transparent_shadows: usize,
+}
+
+/// The first surface along a ray, as returned by `Scene::intersect`.
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    /// Ray parameter of the hit; the distance for unit-length directions.
+    pub t: f64,
+    pub point: Vector3,
+    /// Unit normal facing against the ray.
+    pub normal: Vector3,
+    pub u: f64,
+    pub v: f64,
+    pub front_facing: bool,
+    /// ID of the hit object, see `HittableStore::find`.
+    pub object: usize,
+    pub material: Material,
+}
+
+impl Scene {
+    /// The scene around `world` lit by `lights` and the default sky.
+    pub fn new(world: HittableStore, lights: Vec<Light>) -> Self {
+        let options = Options {
+            lights,
+            ..Options::default()
+        };
+        Self::with_world(&options, world)
+    }
+
+    /// Builds the scene selected by the command line options.
+    pub(crate) fn from_options(options: &Options) -> Self {
+        let world = match options.scene {
+            ScenePreset::Random => HittableStore::random(),
+            ScenePreset::Ocean => HittableStore::ocean(options.time),
+            ScenePreset::Terrain => HittableStore::terrain(),
+            ScenePreset::Daylight => HittableStore::daylight(),
+            ScenePreset::MaterialBall => {
+                HittableStore::material_ball(options.ball_material.unwrap_or(BALL_MATERIAL))
+            }
+            ScenePreset::GlassOfWater => HittableStore::glass_of_water(),
+        };
+        Self::with_world(options, world)
+    }
+
+    /// The material ball scene with `material` on the ball and everything else
+    /// taken from the options.
+    pub(crate) fn material_ball(options: &Options, material: Material) -> Self {
+        Self::with_world(options, HittableStore::material_ball(material))
+    }
+
+    /// Loads the objects from a JSON, TOML or RON scene description, chosen by
+    /// the file extension; lights, sky and media still come from the options.
+    /// See `parse_scene` for the format.
+    pub(crate) fn from_file(
+        path: &str,
+        options: &Options,
+    ) -> Result<(Self, SceneSettings), String> {
+        let value = load_scene(std::path::Path::new(path), &options.asset_paths)?;
+        let (world, settings) = parse_scene(&value, options.time, options.units)?;
+        let mut scene = Self::with_world(options, world);
+        if options.units.is_none() {
+            // point lights were given in the file's units, not meters
+            scene.rescale_lights(settings.units.meters());
+        }
+        // the command line overrides the scene file
+        if let (None, Some(background)) = (options.sky, settings.background) {
+            scene.sky = background;
+        }
+        Ok((scene, settings))
+    }
+
+    /// The scene around `world` with everything else taken from the options.
+    fn with_world(options: &Options, mut world: HittableStore) -> Self {
+        world.build_bvh();
+        let mut lights = options.lights.clone();
+        let mut sky = options.sky.unwrap_or_default();
+        if options.scene == ScenePreset::Daylight && options.sky.is_none() {
+            // the sun 45 degrees high behind the default camera, unless a sun
+            // light gives another direction
+            let atmosphere = Atmosphere::daylight(Vector3(0.5, 1.0, 0.866));
+            if !lights
+                .iter()
+                .any(|l| matches!(l, Light::Directional { .. }))
+            {
+                lights.push(Light::Directional {
+                    direction: atmosphere.sun_direction,
+                    color: atmosphere.sun_color * atmosphere.sun_intensity,
+                });
+            }
+            sky = Sky::Atmosphere(atmosphere);
+        }
+        if options.scene == ScenePreset::MaterialBall && options.sky.is_none() {
+            sky = Sky::Studio;
+        }
+        if let Sky::Atmosphere(atmosphere) = &mut sky {
+            // the sun light sets the sun position in the sky, and the atmosphere
+            // dims and reddens the sun light
+            let sun = lights.iter().find_map(|light| match light {
+                Light::Directional { direction, .. } => Some(direction.normalize()),
+                _ => None,
+            });
+            if let Some(direction) = sun {
+                atmosphere.sun_direction = direction;
+            }
+            atmosphere.meters_per_unit = options.aerial_perspective;
+            for light in lights.iter_mut() {
+                if let Light::Directional { direction, color } = light {
+                    *color = *color * atmosphere.transmittance(direction);
+                }
+            }
+        }
+        if let Sky::Night(night) = &mut sky {
+            // the sun light is the moonlight, and the moon shows where it
+            // comes from
+            night.moon = lights.iter().find_map(|light| match light {
+                Light::Directional { direction, color } => Some((direction.normalize(), *color)),
+                _ => None,
+            });
+        }
+        let color_space = options.color_space.unwrap_or_default();
+        for light in lights.iter_mut() {
+            match light {
+                Light::Directional { color, .. } | Light::Point { color, .. } => {
+                    *color = color_space.from_linear_srgb(*color)
+                }
+            }
+        }
+        let emitters = (0..world.objects.len())
+            .filter(|&id| {
+                let object = &world.objects[id];
+                object
+                    .materials()
+                    .iter()
+                    .any(|m| m.emitted().length_squared() > 0.0)
+                    && object.random_direction(&Vector3(0.0, 0.0, 0.0)).is_some()
+            })
+            .collect();
+        let mut scene = Scene {
+            world,
+            emitters,
+            lights,
+            sky,
+            sky_rotation: options.sky_rotation.to_radians(),
+            sky_intensity: options.sky_intensity.unwrap_or(1.0),
+            backdrop: options.backdrop,
+            plate: options.plate,
+            fog: options.fog.map(|fog| Fog {
+                color: color_space.from_linear_srgb(options.fog_color.unwrap_or(fog.color)),
+                ..fog
+            }),
+            medium: options.medium.map(|medium| HomogeneousMedium {
+                phase: options.phase.unwrap_or_default(),
+                ..medium
+            }),
+            draft: options.draft,
+            color_space,
+            max_bounces: BounceCounts {
+                diffuse: options.max_diffuse.unwrap_or(usize::MAX),
+                glossy: options.max_glossy.unwrap_or(usize::MAX),
+                transmission: options.max_transmission.unwrap_or(usize::MAX),
+            },
+            material_override: None,
+            light_links: Vec::new(),
+            transparent_shadows: options.transparent_shadows.unwrap_or(0),
+        };
+        scene.rescale_lights(options.units.unwrap_or_default().meters());
+        scene
+    }
+
+    /// Rescales the point lights of a scene measured in units of `meters`,
+    /// whose falloff is computed over scene units, to their intensity per
+    /// meter.
+    fn rescale_lights(&mut self, meters: f64) {
+        for light in self.lights.iter_mut() {
+            if let Light::Point { color, falloff, .. } = light {
+                *color /= meters.powf(*falloff);
+            }
+        }
+    }
+
+    /// `direction` turned into the frame of the rotated sky.
+    fn sky_direction(&self, direction: &Vector3) -> Vector3 {
+        let (sin, cos) = (-self.sky_rotation).sin_cos();
+        Vector3(
+            direction.x() * cos + direction.z() * sin,
+            direction.y(),
+            direction.z() * cos - direction.x() * sin,
+        )
+    }
+
+    /// Radiance arriving from the sky for rays that hit nothing.
+    pub fn background(&self, direction: &Vector3) -> Vector3 {
+        let rotated = self.sky_direction(direction);
+        self.color_space
+            .from_linear_srgb(self.sky.radiance(&rotated) * self.sky_intensity)
+    }
+
+    /// Radiance of the visible sun disk in `direction`, or the stars and
+    /// moon of a night sky.
+    /// Only specular paths from the camera see it: diffuse surfaces already
+    /// receive the sun as a light.
+    pub fn sun_disk(&self, direction: &Vector3) -> Vector3 {
+        match &self.sky {
+            Sky::Atmosphere(atmosphere) => self.color_space.from_linear_srgb(
+                atmosphere.sun_disk(&self.sky_direction(direction)) * self.sky_intensity,
+            ),
+            Sky::Night(night) => self
+                .color_space
+                .from_linear_srgb(night.disks(&self.sky_direction(direction)) * self.sky_intensity),
+            _ => Vector3(0.0, 0.0, 0.0),
+        }
+    }
+
+    /// Whether nothing blocks the way from `p` along `direction` for `distance`.
+    pub fn visible(&self, p: &Vector3, direction: &Vector3, distance: f64) -> bool {
+        self.visible_at(p, direction, distance, 0.0)
+    }
+
+    /// `visible` at `time`, for scenes with moving objects.
+    pub(crate) fn visible_at(
+        &self,
+        p: &Vector3,
+        direction: &Vector3,
+        distance: f64,
+        time: f64,
+    ) -> bool {
+        let r = Ray {
+            origin: *p,
+            direction: *direction,
+            time,
+        };
+        self.world
+            .hit_as(&r, 0.001, distance, RayKind::Shadow)
+            .is_none()
+    }
+
+    /// The share of light that gets from `p` along `direction` for
+    /// `distance` at `time`: none past an opaque surface, or past more than
+    /// `transparent_shadows` dielectric ones. Glass lets through what it
+    /// does not reflect at its surfaces, less what its volume absorbs, the
+    /// volume of highest priority where several overlap.
+    pub(crate) fn transmittance_at(
+        &self,
+        p: &Vector3,
+        direction: &Vector3,
+        distance: f64,
+        time: f64,
+    ) -> Vector3 {
+        let mut r = Ray {
+            origin: *p,
+            direction: *direction,
+            time,
+        };
+        let mut left = distance;
+        let mut transmittance = Vector3(1.0, 1.0, 1.0);
+        // the dielectric volumes the ray is in: object, absorption, priority
+        let mut media: Vec<(usize, Vector3, u32)> = Vec::new();
+        let mut surfaces = 0;
+        while let Some(i) = self.world.hit_as(&r, 0.001, left, RayKind::Shadow) {
+            let material = self
+                .color_space
+                .material(self.material_override.unwrap_or(i.material));
+            let (ir, absorption) = match material {
+                Material::Dielectric { ir, absorption, .. }
+                    if surfaces < self.transparent_shadows =>
+                {
+                    (ir, absorption)
+                }
+                _ => return Vector3(0.0, 0.0, 0.0),
+            };
+            surfaces += 1;
+            let priority = self.world.priorities[i.object];
+            // a ray that leaves a volume it never entered started in it
+            let inside = match media.iter().max_by_key(|m| m.2) {
+                Some(m) => Some(m.1),
+                None => (!i.front_facing).then_some(absorption),
+            };
+            if let Some(a) = inside {
+                let d = i.t * direction.length();
+                transmittance = transmittance
+                    * Vector3((-a.x() * d).exp(), (-a.y() * d).exp(), (-a.z() * d).exp());
+            }
+            // surfaces inside a volume of higher priority only bound its own
+            let outside = media.iter().filter(|m| m.0 != i.object).map(|m| m.2).max();
+            if outside.is_none_or(|o| o <= priority) {
+                let cos = direction.normalize().dot(&i.normal).abs();
+                transmittance *= 1.0 - reflectance(cos, ir);
+            }
+            if i.front_facing {
+                media.push((i.object, absorption, priority));
+            } else {
+                media.retain(|m| m.0 != i.object);
+            }
+            r.origin = i.p;
+            left -= i.t;
+        }
+        transmittance
+    }
+
+    /// Shows only the objects of `layer` and applies its material override.
+    pub fn apply_layer(&mut self, layer: &RenderLayer) -> Result<(), String> {
+        let world = &mut self.world;
+        match &layer.objects {
+            Some(objects) => {
+                world.hidden = vec![true; world.objects.len()];
+                for name in objects {
+                    let id = world
+                        .find(name)
+                        .ok_or_else(|| format!("there is no object {}", name))?;
+                    world.hidden[id] = false;
+                }
+            }
+            None => world.hidden = vec![false; world.objects.len()],
+        }
+        self.material_override = layer.material;
+        Ok(())
+    }
+
+    /// Sets which rays see the object with the given name or ID.
+    pub fn set_visibility(&mut self, object: &str, visibility: Visibility) -> Result<(), String> {
+        let id = self
+            .world
+            .find(object)
+            .ok_or_else(|| format!("there is no object {}", object))?;
+        self.world.visibility[id] = visibility;
+        Ok(())
+    }
+
+    /// Makes the object with the given name or ID `weight` times as likely as
+    /// others to be aimed at among the glowing objects.
+    pub fn set_importance(&mut self, object: &str, weight: f64) -> Result<(), String> {
+        let id = self
+            .world
+            .find(object)
+            .ok_or_else(|| format!("there is no object {}", object))?;
+        self.world.importance[id] = weight;
+        Ok(())
+    }
+
+    /// Whether any light or glowing object darkens, or any glows through a
+    /// gel; photon mapping handles neither.
+    pub(crate) fn has_light_modifiers(&self) -> bool {
+        let negative = |c: Vector3| c.x() < 0.0 || c.y() < 0.0 || c.z() < 0.0;
+        self.lights.iter().any(|light| match light {
+            Light::Directional { color, .. } | Light::Point { color, .. } => negative(*color),
+        }) || self
+            .world
+            .objects
+            .iter()
+            .flat_map(|object| object.materials())
+            .any(|m| negative(m.emitted()) || m.gelled())
+    }
+
+    /// Lets the light with the given ID light only the listed objects, given
+    /// by name or ID, or with `exclude` every object but those. Links of the
+    /// same light add up.
+    pub fn link_light(
+        &mut self,
+        light: usize,
+        objects: &[&str],
+        exclude: bool,
+    ) -> Result<(), String> {
+        if light >= self.lights.len() {
+            return Err(format!("there is no light {}", light));
+        }
+        let ids = objects
+            .iter()
+            .map(|o| {
+                self.world
+                    .find(o)
+                    .ok_or_else(|| format!("there is no object {}", o))
+            })
+            .collect::<Result<Vec<usize>, String>>()?;
+        self.light_links.resize(self.lights.len(), None);
+        let count = self.world.objects.len();
+        let links = self.light_links[light].get_or_insert_with(|| vec![exclude; count]);
+        for id in ids {
+            links[id] = !exclude;
+        }
+        Ok(())
+    }
+
+    /// The first surface the camera sees along `ray`, if any.
+    pub fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        self.world
+            .hit_as(ray, 0.001, f64::INFINITY, RayKind::Camera)
+            .map(|i| Hit {
+                t: i.t,
+                point: i.p,
+                normal: i.normal,
+                u: i.u,
+                v: i.v,
+                front_facing: i.front_facing,
+                object: i.object,
+                material: i.material,
+            })
+    }
+
+    /// Whether any surface lies on the segment between `a` and `b`.
+    pub fn occluded(&self, a: &Vector3, b: &Vector3) -> bool {
+        let direction = *b - *a;
+        let length = direction.length();
+        !self.visible(a, &(direction / length), length - 0.001)
+    }
+
+    /// `intersect` for many rays at once, in parallel.
+    pub fn intersect_batch(&self, rays: &[Ray]) -> Vec<Option<Hit>> {
+        rays.par_iter().map(|r| self.intersect(r)).collect()
+    }
+
+    /// `occluded` for many segments at once, in parallel.
+    pub fn occluded_batch(&self, segments: &[(Vector3, Vector3)]) -> Vec<bool> {
+        segments
+            .par_iter()
+            .map(|(a, b)| self.occluded(a, b))
+            .collect()
+    }
+
+    /// Irradiance from the explicit lights onto a surface at `p` facing `normal`.
+    pub fn direct_irradiance(&self, p: &Vector3, normal: &Vector3) -> Vector3 {
+        self.light_irradiance(p, Some(normal), None, 0.0)
+            .fold(Vector3(0.0, 0.0, 0.0), |acc, (_, irradiance)| {
+                acc + irradiance
+            })
+    }
+
+    /// The irradiance each light delivers at `p`, by light ID, skipping the
+    /// lights that are behind the surface or occluded at `time` and those not
+    /// linked to `object`, the ID of the object `p` lies on; glass in the way
+    /// tints the light with `transparent_shadows`. Without a surface
+    /// `normal`, for points in a volume, it is the irradiance on a plane
+    /// facing the light.
+    pub(crate) fn light_irradiance<'a>(
+        &'a self,
+        p: &'a Vector3,
+        normal: Option<&'a Vector3>,
+        object: Option<usize>,
+        time: f64,
+    ) -> impl Iterator<Item = (usize, Vector3)> + 'a {
+        self.lights
+            .iter()
+            .enumerate()
+            .filter(move |(id, _)| {
+                match (self.light_links.get(*id).and_then(Option::as_ref), object) {
+                    (Some(links), Some(object)) => links[object],
+                    _ => true,
+                }
+            })
+            .filter_map(move |(id, light)| {
+                let (to_light, distance, irradiance) = light.illuminate(p);
+                let cos = normal.map_or(1.0, |n| to_light.dot(n));
+                if cos <= 0.0 {
+                    return None;
+                }
+                if self.transparent_shadows == 0 {
+                    return self
+                        .visible_at(p, &to_light, distance, time)
+                        .then(|| (id, irradiance * cos));
+                }
+                let shadow = self.transmittance_at(p, &to_light, distance, time);
+                (shadow.length_squared() > 0.0).then(|| (id, irradiance * shadow * cos))
+            })
+    }
+}
+
+/// A named subset of the scene rendered as its own image, for compositing.
+#[derive(Clone, Debug)]
+pub struct RenderLayer {
+    pub(crate) name: String,
+    /// Names or IDs of the visible objects; all of them if `None`.
+    objects: Option<Vec<String>>,
+    material: Option<Material>,
+}
+
+impl std::str::FromStr for RenderLayer {
+    type Err = ();
+    /// Parses `name[:objects[:material]]`, where objects is `all` or a comma
+    /// separated list of names and IDs; see `Material` for the material.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let name = parts.next().filter(|n| !n.is_empty()).ok_or(())?;
+        let objects = match parts.next() {
+            None | Some("all") => None,
+            Some(list) => Some(list.split(',').map(String::from).collect()),
+        };
+        Ok(RenderLayer {
+            name: name.to_string(),
+            objects,
+            material: parts.next().map(str::parse).transpose()?,
+        })
+    }
+}
@@ -0,0 +1,1342 @@
+//! Scene description files.
+
+use crate::camera::{CameraView, ShutterCurve};
+use crate::color::ColorSpace;
+use crate::embed;
+use crate::hittable::displacement::{DisplacedMesh, Displacement};
+use crate::hittable::lod::{decimate, LodMesh};
+use crate::hittable::mesh::{Mesh, ObjFile, SubdivisionSurface, Triangle};
+use crate::hittable::portal::{Portal, Window};
+use crate::hittable::quad::{Cuboid, Quad};
+use crate::hittable::scatter::Scatter;
+use crate::hittable::terrain::Terrain;
+use crate::hittable::transform::{Matrix4, Transform};
+use crate::hittable::water::WaterSurface;
+use crate::hittable::{
+    ConstantMedium, Cull, HeterogeneousMedium, Hittable, HittableStore, MovingSphere, Sphere,
+    Visibility, VolumeEmission,
+};
+use crate::light::SceneUnit;
+use crate::material::{Material, PbrMaterial, SurfaceMap};
+use crate::render::Aov;
+use crate::sky::Sky;
+use crate::texture::{ImageTexture, Node, Texture, VoxelGrid};
+use crate::vec3::Vector3;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Camera and render settings from a scene file, replacing the defaults.
+#[derive(Debug, Default)]
+pub(crate) struct SceneSettings {
+    pub(crate) camera: Option<CameraView>,
+    pub(crate) width: Option<u32>,
+    pub(crate) samples: Option<usize>,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) roulette: Option<usize>,
+    pub(crate) background: Option<Sky>,
+    pub(crate) shutter: Option<(f64, f64)>,
+    pub(crate) shutter_curve: Option<ShutterCurve>,
+    pub(crate) rolling_shutter: Option<f64>,
+    pub(crate) motion_blur: Option<bool>,
+    pub(crate) aovs: Option<Vec<Aov>>,
+    /// The length of one scene unit, from the command line or the file.
+    pub(crate) units: SceneUnit,
+}
+
+/// Reads a JSON, TOML or RON scene file, chosen by the extension, with the
+/// files it includes and references, each asset path replaced by where it
+/// was found: next to the file naming it, else in its `search_path`
+/// directories and then the `search` ones, in order.
+pub(crate) fn load_scene(path: &Path, search: &[PathBuf]) -> Result<Value, String> {
+    load(path, search, &mut Vec::new())
+}
+
+fn read(path: &Path) -> Result<Value, String> {
+    let text = embed::read_to_string(path).map_err(|e| e.to_string())?;
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&text).map_err(|e| e.to_string()),
+        Some("toml") => toml::from_str(&text).map_err(|e| e.to_string()),
+        Some("ron") => ron::from_str(&text).map_err(|e| e.to_string()),
+        _ => Err("expected a .json, .toml or .ron file".to_string()),
+    }
+}
+
+/// Where `file`, named by a scene in `dir`, is found. Backslashes separate
+/// directories too, for scenes written on Windows; a file found nowhere
+/// stays next to the scene, for the error to name.
+fn resolve(file: &str, dir: &Path, search: &[PathBuf]) -> PathBuf {
+    let file = if cfg!(windows) {
+        file.to_string()
+    } else {
+        file.replace('\\', "/")
+    };
+    let local = dir.join(&file);
+    if embed::exists(&local) || Path::new(&file).is_absolute() {
+        return local;
+    }
+    search
+        .iter()
+        .map(|d| d.join(&file))
+        .find(|p| embed::exists(p))
+        .unwrap_or(local)
+}
+
+/// Replaces the asset paths in `value`: every `file`, the `map` of displaced
+/// meshes and a `background` naming a file.
+fn resolve_assets(value: &mut Value, dir: &Path, search: &[PathBuf]) {
+    match value {
+        Value::Object(map) => {
+            let displaced = map.get("type").and_then(Value::as_str) == Some("displaced");
+            for (key, v) in map.iter_mut() {
+                match (key.as_str(), &v) {
+                    ("file", Value::String(file)) => {
+                        *v = Value::String(resolve(file, dir, search).to_string_lossy().into())
+                    }
+                    ("map", Value::String(file)) if displaced => {
+                        *v = Value::String(resolve(file, dir, search).to_string_lossy().into())
+                    }
+                    ("background", Value::String(sky)) => {
+                        // or else the name of a sky
+                        let path = resolve(sky, dir, search);
+                        if embed::exists(&path) {
+                            *v = Value::String(path.to_string_lossy().into());
+                        }
+                    }
+                    _ => resolve_assets(v, dir, search),
+                }
+            }
+        }
+        Value::Array(list) => list.iter_mut().for_each(|v| resolve_assets(v, dir, search)),
+        _ => {}
+    }
+}
+
+/// The files `value`, a scene read by `load_scene`, names: every `file`,
+/// the `map` of displaced meshes and a `background` naming a file.
+pub(crate) fn asset_files(value: &Value) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = vec![value];
+    while let Some(value) = pending.pop() {
+        match value {
+            Value::Object(map) => {
+                let displaced = map.get("type").and_then(Value::as_str) == Some("displaced");
+                for (key, v) in map {
+                    match (key.as_str(), v) {
+                        ("file", Value::String(file)) => files.push(PathBuf::from(file)),
+                        ("map", Value::String(file)) if displaced => {
+                            files.push(PathBuf::from(file))
+                        }
+                        ("background", Value::String(sky)) if embed::exists(Path::new(sky)) => {
+                            files.push(PathBuf::from(sky))
+                        }
+                        _ => pending.push(v),
+                    }
+                }
+            }
+            Value::Array(list) => pending.extend(list),
+            _ => {}
+        }
+    }
+    files
+}
+
+/// Replaces `material` names in `object` and its children and prototype by
+/// the entries of `materials`, so they keep their meaning in another file.
+fn inline_materials(object: &mut Value, materials: &Value) {
+    if let Some(name) = object["material"].as_str() {
+        if let Some(m) = materials.get(name) {
+            object["material"] = m.clone();
+        }
+    }
+    if let Some(prototype) = object.get_mut("prototype") {
+        inline_materials(prototype, materials);
+    }
+    if let Some(children) = object.get_mut("children").and_then(Value::as_array_mut) {
+        children
+            .iter_mut()
+            .for_each(|c| inline_materials(c, materials));
+    }
+}
+
+/// Reads `path` with everything it brings in; `stack` holds the files being
+/// read, to catch a file that includes itself.
+fn load(path: &Path, search: &[PathBuf], stack: &mut Vec<PathBuf>) -> Result<Value, String> {
+    // files built in are told apart by their paths alone
+    let canonical = match embed::find(path) {
+        Some(_) => embed::normalize(path),
+        None => path.canonicalize().map_err(|e| e.to_string())?,
+    };
+    if stack.contains(&canonical) {
+        return Err(format!("{} includes itself", path.display()));
+    }
+    stack.push(canonical);
+    let mut value = read(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut search_path = match &value["search_path"] {
+        Value::Null => Vec::new(),
+        list => list
+            .as_array()
+            .and_then(|l| l.iter().map(|d| d.as_str().map(|d| dir.join(d))).collect())
+            .ok_or("'search_path' must be a list of directories")?,
+    };
+    search_path.extend_from_slice(search);
+    let search = &search_path[..];
+    resolve_assets(&mut value, dir, search);
+
+    // references become groups of the objects of the file they name
+    let mut pending: Vec<(String, &mut Value)> = match value["objects"].as_array_mut() {
+        Some(objects) => objects
+            .iter_mut()
+            .enumerate()
+            .map(|(i, o)| (format!("objects[{}]", i), o))
+            .collect(),
+        None => Vec::new(),
+    };
+    while let Some((at, object)) = pending.pop() {
+        if object["type"].as_str() == Some("reference") {
+            // already resolved with the other files
+            let file = object["file"]
+                .as_str()
+                .ok_or_else(|| format!("{}: 'file' must be a path", at))?;
+            let mut referenced = load(Path::new(file), &[], stack)
+                .map_err(|e| format!("{}: {}: {}", at, file, e))?;
+            let materials = referenced["materials"].take();
+            let mut children = match referenced["objects"].take() {
+                Value::Array(children) => children,
+                _ => return Err(format!("{}: {} has no 'objects'", at, file)),
+            };
+            children
+                .iter_mut()
+                .for_each(|c| inline_materials(c, &materials));
+            object["type"] = "group".into();
+            object["children"] = Value::Array(children);
+            // a file modeled in other units than the scene is scaled into it
+            if object.get("units").is_none() {
+                if let Some(units) = referenced.get("units").cloned() {
+                    object["units"] = units;
+                }
+            }
+        } else if let Some(children) = object.get_mut("children").and_then(Value::as_array_mut) {
+            pending.extend(
+                children
+                    .iter_mut()
+                    .enumerate()
+                    .map(|(i, c)| (format!("{}.children[{}]", at, i), c)),
+            );
+        }
+    }
+
+    // included files come first, the including one overriding their
+    // materials, camera and render settings
+    let includes = match value["include"].take() {
+        Value::Null => Vec::new(),
+        Value::String(file) => vec![Value::String(file)],
+        Value::Array(files) => files,
+        _ => return Err("'include' must be a file or a list of files".to_string()),
+    };
+    for (i, file) in includes.iter().enumerate() {
+        let at = format!("include[{}]", i);
+        let file = file
+            .as_str()
+            .ok_or_else(|| format!("{}: must be a path", at))?;
+        let mut included = load(&resolve(file, dir, search), &[], stack)
+            .map_err(|e| format!("{}: {}: {}", at, file, e))?;
+        let mut objects = match included["objects"].take() {
+            Value::Array(objects) => objects,
+            _ => Vec::new(),
+        };
+        if let Some(own) = value["objects"].as_array_mut() {
+            objects.append(own);
+        }
+        value["objects"] = Value::Array(objects);
+        for key in ["materials", "camera", "render"].iter() {
+            let (theirs, ours) = (included[*key].take(), &mut value[*key]);
+            match (theirs, ours) {
+                (Value::Object(theirs), Value::Object(ours)) if *key != "camera" => {
+                    for (k, v) in theirs {
+                        ours.entry(k).or_insert(v);
+                    }
+                }
+                (theirs, ours @ Value::Null) => *ours = theirs,
+                _ => {}
+            }
+        }
+    }
+    stack.pop();
+    Ok(value)
+}
+
+/// Reads a scene description:
+///
+/// - `include`: scene files, or one, whose objects come before this file's
+///   and whose materials, camera and render settings apply unless given
+///   here
+/// - `search_path`: directories to look for assets in when they are not
+///   next to the file naming them
+/// - `units`: what one scene unit is, `m` (the default), `cm`, `mm`, `in`
+///   or `ft`, unless `--units` says otherwise
+/// - `camera`: `from` and `at` points, optional `vfov` (degrees), `aperture`,
+///   `focus` distance, `shutter` open and close times, a `shutter_curve`
+///   (the fractions of that time the shutter takes to open and to close,
+///   default a box), the `rolling_shutter` readout time (the fraction of it
+///   the shutter takes from the top row to the bottom one, default 0) and
+///   `motion_blur = false` rendering one moment, the middle of the exposure
+/// - `render`: optional `width`, `samples`, `max_depth`, the bounces after
+///   which paths face Russian `roulette`, `aovs` to write
+///   (a list of `depth`, `normal`, `albedo` and `id`) and a `background`
+///   replacing the sky: a uniform color, a sky name like the command line's
+///   `--sky` or an equirectangular .hdr, .exr or .ppm environment map
+/// - `materials`: named materials, each with a `type` of `diffuse`
+///   (`albedo`), `metal` (`albedo`, `fuzz`), `glass` (`ir`, `roughness`,
+///   `absorption` per unit length as a color), `light` (`emit`, masked by the
+///   texture `gel`, and with `negative = true` taking light away, which is
+///   not physical but handy for darkening by hand), `pbr` (glTF's
+///   metallic-roughness material: an `albedo`, `metallic` and `roughness`
+///   from 0 to 1, default 1, scaled by the blue and green of the `file` of a
+///   `metallic_roughness_map`, an `occlusion_map` whose `file` darkens the
+///   albedo by its red, as much as its `strength` says, and an `emission`
+///   color masked by the texture `emission_map`) or `mix`
+///   (material `a` where the luminance of the texture `factor` is 0 and `b`
+///   where it is 1, each inline or by name), any of them with a `bump` map (bumps as high as the luminance of the texture
+///   `height` times `strength`, default 1) and a tangent-space `normal_map`
+///   (a `file` whose red, green and blue hold the normal along u, along v
+///   and out of the surface, its tilt scaled by `strength`)
+/// - `objects`: a list of `sphere` (`center`, `radius`), `moving_sphere`
+///   (from `center0` at `time0`, default 0, to `center1` at `time1`, default
+///   1, with a `radius`), `triangle`
+///   (`vertices`, three points), `quad` (a parallelogram from `corner`
+///   spanned by `u` and `v`, facing along u × v), `box` (axis-aligned from
+///   `min` to `max`), `mesh` (an OBJ `file` relative to the scene
+///   file, optional `scale`, `position` offset and `smooth` normals, `true`
+///   or a smoothing angle in degrees keeping sharper edges creased,
+///   `decimate` to simplify it to at most that many triangles on import and
+///   `lod`, a table of `levels`, default 4, each with half the triangles of
+///   the one before, rendering the coarsest whose edges span at most
+///   `edge_pixels`, default 1, pixels where it stands; instances of it pick
+///   their own level, objects in groups keep the finest),
+///   `subdivision` (a Catmull-Clark surface over the polygons of an OBJ
+///   `file`, with `scale` and `position` like meshes, subdivided `levels`
+///   times or, given `edge_pixels`, as often as the view needs), `displaced`
+///   (a smooth OBJ mesh placed like `mesh`, displaced by `height` times a
+///   grayscale PPM `map` over its texture coordinates or else fBm noise with
+///   `frequency` and `seed`; diced lazily `subdivisions` times per edge or by
+///   `edge_pixels`, keeping at most `cache_mb` megabytes of patches), `water`
+///   (`level`, `size`, `wind` as [x, z], `amplitude`), `medium` (smoke of
+///   constant `density` and `albedo`, default white, filling a `boundary`
+///   `sphere` with `center` and `radius` or `box` from `min` to `max`; with
+///   a `density_map` texture the density varies as `density` times its
+///   luminance, clamped to [0, 1], and an `emission` color or texture, or a
+///   `blackbody` of a `temperature` texture mapped from `min` to `max`
+///   kelvin with an `intensity`, makes it glow where it absorbs),
+///   `portal` (windows `a` and `b`, parallelograms from `corner` spanned by
+///   `u` and `v`, each leading through the other), `scatter` (copies of a
+///   `prototype` sphere with a `radius` or OBJ mesh `file`, standing on the
+///   earlier object named `target`: `count` candidates at random within
+///   `region` [x0, z0, x1, z1], default the target's bounds, each kept with
+///   a chance given by the luminance of `density`, a number or texture, sized
+///   at random within `scale` [min, max] times the luminance of an optional
+///   `scale_map` texture and turned up to `rotation` degrees around the
+///   vertical, repeatably for a `seed`; the copies form one object with its
+///   own BVH), `instance` (the earlier object named `of` shown again without
+///   copying it, resized by `scale`, turned by `rotate` [x, y, z] degrees
+///   around the axes in that order, both about the scene origin, and moved
+///   by `translate`), `group` (the objects listed in `children`, which may
+///   be groups again, placed together by the group's `scale`, `rotate` and
+///   `translate` like an instance's, so turning a group turns everything in
+///   it; the group's `visibility` and `priority` stand for all its
+///   children's, instances and scatters refer to earlier objects of the same
+///   group and lights inside groups are only found by chance), `reference`
+///   (the objects of another scene `file` as a group, with its materials
+///   and placed like one) and `terrain`
+///   (`size`, `seed`), each with an optional
+///   `name`, a nesting `priority` for overlapping dielectrics, an
+///   `importance` making a glowing object likelier to be aimed at, a `cull`
+///   table leaving it out beyond a `distance` from the camera, faded out at
+///   random over the `fade` before it, and below a size in `pixels` on
+///   screen (for a scatter, each copy by itself), an
+///   `opacity` number or texture cutting it out where its luminance is below
+///   1 (rays pass through at random, as often as it is transparent), a
+///   `visibility` table turning `camera`, `shadow` or `indirect` rays off,
+///   for meshes, subdivision surfaces, displaced meshes, mesh prototypes,
+///   instances, groups and references (by default those of the file they
+///   name) the `units` it was modeled in, scaling it into the scene's
+///   and, except terrain, media, portals, instances, groups and scatters (whose
+///   prototype takes it), a `material` given by name or inline
+///
+/// Points and colors are [x, y, z] arrays. Instead of a color, `albedo` can
+/// be a texture: `checker` (`even` and `odd` colors, `scale`), `noise`
+/// (marble with `scale`), `turbulence` (billows with `scale`), `ramp` (color
+/// `from` at point `start` to `to` at `end`), `grid` (a Mitsuba .vol `file`
+/// of voxels) or `image` (a PPM, .hdr or .exr `file` over the texture
+/// coordinates), or a gray number. Textures combine in nodes: `math` (`op`
+/// `add`, `subtract`, `multiply`, `divide`, `power`, `minimum` or `maximum`
+/// of the textures `a` and `b`, channel by channel), `fresnel` (the share a
+/// dielectric of index `ir` reflects at the angle the surface is seen from)
+/// and `mix` (texture `a` blending into `b` by `factor`). Asset files are relative to the file naming them, as
+/// `load_scene` resolves them. Errors name the offending entry.
+pub(crate) fn parse_scene(
+    value: &Value,
+    time: f64,
+    units: Option<SceneUnit>,
+) -> Result<(HittableStore, SceneSettings), String> {
+    let parse_units = |v: &Value, at: &str| {
+        v.as_str()
+            .and_then(|u| u.parse::<SceneUnit>().ok())
+            .ok_or_else(|| format!("{}'units' must be m, cm, mm, in or ft", at))
+    };
+    // the command line overrides the scene file
+    let scene_units = match (units, value.get("units")) {
+        (Some(units), _) => units,
+        (None, Some(v)) => parse_units(v, "")?,
+        (None, None) => SceneUnit::default(),
+    };
+    // the size of one of an object's `units` in the scene's
+    let unit_scale = |object: &Value, at: &str| -> Result<f64, String> {
+        match object.get("units") {
+            Some(v) => {
+                let units = parse_units(v, &format!("{}: ", at))?;
+                Ok(units.meters() / scene_units.meters())
+            }
+            None => Ok(1.0),
+        }
+    };
+    let scale_of = |object: &Value, at: &str| -> Result<f64, String> {
+        Ok(optional(object, "scale", at, 1.0)? * unit_scale(object, at)?)
+    };
+    let mut materials = std::collections::HashMap::new();
+    if let Some(table) = value.get("materials") {
+        let table = table
+            .as_object()
+            .ok_or("'materials' must map names to materials")?;
+        // mixes may name materials that come after them, which they wait
+        // for until a later round
+        let mut pending: Vec<_> = table.iter().collect();
+        while !pending.is_empty() {
+            let left = pending.len();
+            let mut error = None;
+            pending.retain(|(name, m)| {
+                match material(m, &format!("materials.{}", name), &materials) {
+                    Ok(m) => {
+                        materials.insert((*name).clone(), m);
+                        false
+                    }
+                    Err(e) => {
+                        error.get_or_insert(e);
+                        true
+                    }
+                }
+            });
+            if pending.len() == left {
+                return Err(error.unwrap());
+            }
+        }
+    }
+
+    let placement = |object: &Value, at: &str| -> Result<Matrix4, String> {
+        let scale = scale_of(object, at)?;
+        let rotate = match object.get("rotate") {
+            Some(_) => vector(object, "rotate", at)?,
+            None => Vector3(0.0, 0.0, 0.0),
+        };
+        let translate = match object.get("translate") {
+            Some(_) => vector(object, "translate", at)?,
+            None => Vector3(0.0, 0.0, 0.0),
+        };
+        Ok(Matrix4::translation(translate)
+            .then_after(&Matrix4::rotation(Vector3(0.0, 0.0, 1.0), rotate.z()))
+            .then_after(&Matrix4::rotation_y(rotate.y()))
+            .then_after(&Matrix4::rotation(Vector3(1.0, 0.0, 0.0), rotate.x()))
+            .then_after(&Matrix4::scaling(Vector3(scale, scale, scale))))
+    };
+    // name, priority, opacity and visibility of the object added last
+    let annotate = |world: &mut HittableStore, object: &Value, at: &str| -> Result<(), String> {
+        let n = world.objects.len() - 1;
+        world.names[n] = object["name"].as_str().map(String::from);
+        world.priorities[n] = match object.get("priority") {
+            Some(p) => p
+                .as_u64()
+                .filter(|&p| p <= u32::MAX as u64)
+                .map(|p| p as u32)
+                .ok_or_else(|| format!("{}: 'priority' must be a non-negative integer", at))?,
+            None => 0,
+        };
+        world.importance[n] = match object.get("importance") {
+            Some(w) => w
+                .as_f64()
+                .filter(|&w| w > 0.0)
+                .ok_or_else(|| format!("{}: 'importance' must be a positive number", at))?,
+            None => 1.0,
+        };
+        world.opacity[n] = match object.get("opacity") {
+            Some(_) => Some(texture(object, "opacity", at)?),
+            None => None,
+        };
+        // a scatter culls its copies one by one instead
+        if object["type"].as_str() != Some("scatter") {
+            world.cull[n] = cull(object, at)?;
+        }
+        if let Some(visibility) = object.get("visibility") {
+            let ray = |kind: &str| match visibility.get(kind) {
+                Some(v) => v
+                    .as_bool()
+                    .ok_or_else(|| format!("{}: 'visibility.{}' must be true or false", at, kind)),
+                None => Ok(true),
+            };
+            world.visibility[n] = Visibility {
+                camera: ray("camera")?,
+                shadow: ray("shadow")?,
+                indirect: ray("indirect")?,
+            };
+        }
+        Ok(())
+    };
+
+    /// A group whose children are being read, with the store they go into;
+    /// the scene itself is the outermost.
+    struct Group<'a> {
+        object: Option<&'a Value>,
+        at: String,
+        children: &'a [Value],
+        next: usize,
+        world: HittableStore,
+        shared: std::collections::HashMap<usize, Arc<dyn Hittable>>,
+        /// Meshes with levels of detail, for their instances.
+        lods: std::collections::HashMap<usize, LodMesh>,
+    }
+    let objects = value["objects"]
+        .as_array()
+        .ok_or("expected a list of 'objects'")?;
+    let mut groups = vec![Group {
+        object: None,
+        at: "objects".to_string(),
+        children: objects,
+        next: 0,
+        world: HittableStore::default(),
+        shared: Default::default(),
+        lods: Default::default(),
+    }];
+    let world = loop {
+        let group = groups.last_mut().unwrap();
+        if group.next == group.children.len() {
+            // a finished group goes into its parent as one object, its own
+            // BVH placed by the group's transform
+            let done = groups.pop().unwrap();
+            let (parent, object) = match (groups.last_mut(), done.object) {
+                (Some(parent), Some(object)) => (parent, object),
+                _ => break done.world,
+            };
+            let at = done.at.trim_end_matches(".children");
+            let mut members = done.world;
+            members.build_bvh();
+            let matrix = placement(object, at)?;
+            parent.world.add_boxed(Box::new(
+                Transform::new(Arc::new(members), matrix)
+                    .ok_or_else(|| format!("{}: 'scale' must not be zero", at))?,
+            ));
+            annotate(&mut parent.world, object, at)?;
+            continue;
+        }
+        let children = group.children;
+        let object = &children[group.next];
+        let at = format!("{}[{}]", group.at, group.next);
+        group.next += 1;
+        if object["type"].as_str() == Some("group") {
+            let children = object["children"]
+                .as_array()
+                .ok_or_else(|| format!("{}: 'children' must be a list of objects", at))?;
+            groups.push(Group {
+                object: Some(object),
+                at: format!("{}.children", at),
+                children,
+                next: 0,
+                world: HittableStore::default(),
+                shared: Default::default(),
+                lods: Default::default(),
+            });
+            continue;
+        }
+        let (world, shared, lods) = (&mut group.world, &mut group.shared, &mut group.lods);
+        let material_of = |v: &Value, at: &str| match &v["material"] {
+            Value::String(name) => materials
+                .get(name)
+                .copied()
+                .ok_or_else(|| format!("{}: unknown material '{}'", at, name)),
+            Value::Null => Err(format!("{}: missing 'material'", at)),
+            inline => material(inline, &format!("{}.material", at), &materials),
+        };
+        let object_material = || material_of(object, &at);
+        let hittable: Box<dyn Hittable> = match object["type"].as_str() {
+            Some("sphere") => Box::new(Sphere {
+                center: vector(object, "center", &at)?,
+                radius: number(object, "radius", &at)?,
+                material: object_material()?,
+            }),
+            Some("moving_sphere") => Box::new(MovingSphere {
+                center0: vector(object, "center0", &at)?,
+                center1: vector(object, "center1", &at)?,
+                time0: optional(object, "time0", &at, 0.0)?,
+                time1: optional(object, "time1", &at, 1.0)?,
+                radius: number(object, "radius", &at)?,
+                material: object_material()?,
+            }),
+            Some("triangle") => {
+                let point = |p: &Value| match p.as_array()?[..] {
+                    [ref x, ref y, ref z] => Some(Vector3(x.as_f64()?, y.as_f64()?, z.as_f64()?)),
+                    _ => None,
+                };
+                let corners: Option<Vec<Vector3>> = object["vertices"]
+                    .as_array()
+                    .and_then(|c| c.iter().map(point).collect());
+                match corners.as_deref() {
+                    Some(&[a, b, c]) => Box::new(Triangle {
+                        vertices: [a, b, c],
+                        material: object_material()?,
+                    }),
+                    _ => return Err(format!("{}: 'vertices' must be three [x, y, z] points", at)),
+                }
+            }
+            Some("mesh") => {
+                let file = object["file"]
+                    .as_str()
+                    .ok_or_else(|| format!("{}: 'file' must be a path", at))?;
+                let path = Path::new(file);
+                let smooth = smoothing(object, &at)?;
+                let obj =
+                    ObjFile::read(&path.to_string_lossy()).map_err(|e| format!("{}: {}", at, e))?;
+                let mesh = Mesh::from_obj(&obj, object_material()?, smooth);
+                let position = match object.get("position") {
+                    Some(_) => vector(object, "position", &at)?,
+                    None => Vector3(0.0, 0.0, 0.0),
+                };
+                let mut mesh = mesh.place(scale_of(object, &at)?, position);
+                if object.get("decimate").is_some() {
+                    let target = number(object, "decimate", &at)?;
+                    if target < 1.0 {
+                        return Err(format!("{}: 'decimate' must be at least 1 triangle", at));
+                    }
+                    mesh = decimate(&mesh, target as usize);
+                }
+                match object.get("lod") {
+                    Some(lod) => {
+                        let levels = optional(lod, "levels", &at, 4.0)?;
+                        let edge_pixels = optional(lod, "edge_pixels", &at, 1.0)?;
+                        if levels < 1.0 || edge_pixels <= 0.0 {
+                            return Err(format!(
+                                "{}: 'lod' must have at least 1 level and positive 'edge_pixels'",
+                                at
+                            ));
+                        }
+                        let lod = LodMesh::new(mesh, levels as usize, edge_pixels);
+                        lods.insert(world.objects.len(), lod.clone());
+                        Box::new(lod)
+                    }
+                    None => Box::new(mesh),
+                }
+            }
+            Some("subdivision") => {
+                let file = object["file"]
+                    .as_str()
+                    .ok_or_else(|| format!("{}: 'file' must be a path", at))?;
+                let obj = ObjFile::read(file).map_err(|e| format!("{}: {}", at, e))?;
+                let scale = scale_of(object, &at)?;
+                let position = match object.get("position") {
+                    Some(_) => vector(object, "position", &at)?,
+                    None => Vector3(0.0, 0.0, 0.0),
+                };
+                let levels = optional(object, "levels", &at, 2.0)?;
+                if !(0.0..=SubdivisionSurface::MAX_LEVELS as f64).contains(&levels) {
+                    return Err(format!(
+                        "{}: 'levels' must be between 0 and {}",
+                        at,
+                        SubdivisionSurface::MAX_LEVELS
+                    ));
+                }
+                let edge_pixels = match object.get("edge_pixels") {
+                    Some(_) => Some(number(object, "edge_pixels", &at)?),
+                    None => None,
+                };
+                Box::new(SubdivisionSurface::new(
+                    obj.positions
+                        .iter()
+                        .map(|&p| p * scale + position)
+                        .collect(),
+                    obj.polygons
+                        .iter()
+                        .map(|p| p.iter().map(|c| c.0).collect())
+                        .collect(),
+                    levels as u32,
+                    edge_pixels,
+                    object_material()?,
+                ))
+            }
+            Some("displaced") => {
+                let file = object["file"]
+                    .as_str()
+                    .ok_or_else(|| format!("{}: 'file' must be a path", at))?;
+                let obj = ObjFile::read(file).map_err(|e| format!("{}: {}", at, e))?;
+                let position = match object.get("position") {
+                    Some(_) => vector(object, "position", &at)?,
+                    None => Vector3(0.0, 0.0, 0.0),
+                };
+                let base = Mesh::from_obj(&obj, object_material()?, Some(180.0))
+                    .place(scale_of(object, &at)?, position);
+                let displacement = match object["map"].as_str() {
+                    Some(map) => {
+                        let path = Path::new(map);
+                        Displacement::load_map(&path.to_string_lossy())
+                            .map_err(|e| format!("{}: {}: {}", at, path.display(), e))?
+                    }
+                    None => Displacement::Noise {
+                        frequency: optional(object, "frequency", &at, 4.0)?,
+                        seed: optional(object, "seed", &at, 7.0)? as u32,
+                    },
+                };
+                let subdivisions = optional(object, "subdivisions", &at, 16.0)?;
+                if !(1.0..=DisplacedMesh::MAX_SUBDIVISIONS as f64).contains(&subdivisions) {
+                    return Err(format!(
+                        "{}: 'subdivisions' must be between 1 and {}",
+                        at,
+                        DisplacedMesh::MAX_SUBDIVISIONS
+                    ));
+                }
+                let edge_pixels = match object.get("edge_pixels") {
+                    Some(_) => Some(number(object, "edge_pixels", &at)?),
+                    None => None,
+                };
+                let cache_mb = optional(object, "cache_mb", &at, 256.0)?;
+                Box::new(DisplacedMesh::new(
+                    base,
+                    displacement,
+                    optional(object, "height", &at, 0.1)?,
+                    subdivisions as u32,
+                    edge_pixels,
+                    (cache_mb * 1048576.0) as usize,
+                ))
+            }
+            Some("water") => {
+                let wind = match object["wind"].as_array().map(Vec::as_slice) {
+                    Some([x, z]) => (x.as_f64().unwrap_or(1.0), z.as_f64().unwrap_or(0.0)),
+                    None => (1.0, 0.0),
+                    Some(_) => return Err(format!("{}: 'wind' must be an [x, z] array", at)),
+                };
+                Box::new(WaterSurface::new(
+                    optional(object, "level", &at, 0.0)?,
+                    number(object, "size", &at)?,
+                    wind,
+                    optional(object, "amplitude", &at, 0.15)?,
+                    time,
+                    object_material()?,
+                ))
+            }
+            Some("quad") => Box::new(Quad {
+                corner: vector(object, "corner", &at)?,
+                u: vector(object, "u", &at)?,
+                v: vector(object, "v", &at)?,
+                material: object_material()?,
+            }),
+            Some("box") => Box::new(Cuboid::new(
+                vector(object, "min", &at)?,
+                vector(object, "max", &at)?,
+                object_material()?,
+            )),
+            Some("medium") => {
+                let boundary = &object["boundary"];
+                let boundary_at = format!("{}.boundary", at);
+                // rays never see the boundary's own surface
+                let material = Material::Lambertian {
+                    albedo: Vector3(1.0, 1.0, 1.0).into(),
+                };
+                let shape: Box<dyn Hittable> = match boundary["type"].as_str() {
+                    Some("sphere") => Box::new(Sphere {
+                        center: vector(boundary, "center", &boundary_at)?,
+                        radius: number(boundary, "radius", &boundary_at)?,
+                        material,
+                    }),
+                    Some("box") => Box::new(Cuboid::new(
+                        vector(boundary, "min", &boundary_at)?,
+                        vector(boundary, "max", &boundary_at)?,
+                        material,
+                    )),
+                    Some(other) => {
+                        return Err(format!(
+                            "{}: unknown boundary type '{}', expected sphere or box",
+                            boundary_at, other
+                        ))
+                    }
+                    None => return Err(format!("{}: missing boundary 'type'", boundary_at)),
+                };
+                let density = number(object, "density", &at)?;
+                let albedo = match object.get("albedo") {
+                    Some(_) => texture(object, "albedo", &at)?,
+                    None => Vector3(1.0, 1.0, 1.0).into(),
+                };
+                let emission = match object.get("emission") {
+                    Some(e) if e["type"].as_str() == Some("blackbody") => {
+                        let at = format!("{}.emission", at);
+                        let (min, max) = (number(e, "min", &at)?, number(e, "max", &at)?);
+                        if min <= 0.0 || max <= min {
+                            return Err(format!("{}: need 0 < 'min' < 'max' kelvin", at));
+                        }
+                        Some(VolumeEmission::Blackbody {
+                            temperature: texture(e, "temperature", &at)?,
+                            min,
+                            max,
+                            intensity: optional(e, "intensity", &at, 1.0)?,
+                        })
+                    }
+                    Some(_) => Some(VolumeEmission::Color(texture(object, "emission", &at)?)),
+                    None => None,
+                };
+                match (object.get("density_map"), emission) {
+                    (None, None) => Box::new(ConstantMedium {
+                        boundary: shape,
+                        density,
+                        albedo,
+                    }),
+                    (map, emission) => Box::new(HeterogeneousMedium {
+                        boundary: shape,
+                        density,
+                        density_map: match map {
+                            Some(_) => texture(object, "density_map", &at)?,
+                            None => Vector3(1.0, 1.0, 1.0).into(),
+                        },
+                        albedo,
+                        emission,
+                    }),
+                }
+            }
+            Some("portal") => {
+                let window = |key: &str| -> Result<Window, String> {
+                    let at = format!("{}.{}", at, key);
+                    let w = &object[key];
+                    Ok(Window {
+                        corner: vector(w, "corner", &at)?,
+                        u: vector(w, "u", &at)?,
+                        v: vector(w, "v", &at)?,
+                    })
+                };
+                Box::new(Portal {
+                    a: window("a")?,
+                    b: window("b")?,
+                })
+            }
+            Some("scatter") => {
+                let target = object["target"]
+                    .as_str()
+                    .and_then(|name| world.find(name))
+                    .ok_or_else(|| format!("{}: 'target' must name an earlier object", at))?;
+                let target = world.objects[target].as_ref();
+                let region = match object.get("region") {
+                    Some(r) => match r.as_array().map(|r| r.iter().map(Value::as_f64).collect()) {
+                        Some(Some::<Vec<f64>>(r)) if r.len() == 4 => (r[0], r[1], r[2], r[3]),
+                        _ => return Err(format!("{}: 'region' must be [x0, z0, x1, z1]", at)),
+                    },
+                    None => match target.bounding_box() {
+                        Some(b) => (b.min.x(), b.min.z(), b.max.x(), b.max.z()),
+                        None => return Err(format!("{}: unbounded targets need a 'region'", at)),
+                    },
+                };
+                let scale = match object.get("scale") {
+                    Some(s) => match s.as_array().map(|s| s.iter().map(Value::as_f64).collect()) {
+                        Some(Some::<Vec<f64>>(s)) if s.len() == 2 => (s[0], s[1]),
+                        _ => return Err(format!("{}: 'scale' must be [min, max]", at)),
+                    },
+                    None => (1.0, 1.0),
+                };
+                let scatter = Scatter {
+                    region,
+                    count: number(object, "count", &at)? as usize,
+                    density: match object.get("density") {
+                        Some(Value::Number(d)) => {
+                            let d = d.as_f64().unwrap_or(1.0);
+                            Vector3(d, d, d).into()
+                        }
+                        Some(_) => texture(object, "density", &at)?,
+                        None => Vector3(1.0, 1.0, 1.0).into(),
+                    },
+                    scale,
+                    scale_map: match object.get("scale_map") {
+                        Some(_) => Some(texture(object, "scale_map", &at)?),
+                        None => None,
+                    },
+                    rotation: optional(object, "rotation", &at, 360.0)?,
+                    seed: optional(object, "seed", &at, 1.0)? as u64,
+                };
+                let prototype = &object["prototype"];
+                let at = format!("{}.prototype", at);
+                let material = material_of(prototype, &at)?;
+                let mut instances = HittableStore::default();
+                match prototype["type"].as_str() {
+                    Some("sphere") => {
+                        let radius = number(prototype, "radius", &at)?;
+                        for p in scatter.place(target) {
+                            instances.add(Sphere {
+                                center: p.position + p.normal * (radius * p.scale),
+                                radius: radius * p.scale,
+                                material,
+                            });
+                        }
+                    }
+                    Some("mesh") => {
+                        let file = prototype["file"]
+                            .as_str()
+                            .ok_or_else(|| format!("{}: 'file' must be a path", at))?;
+                        let obj = ObjFile::read(file).map_err(|e| format!("{}: {}", at, e))?;
+                        let smooth = smoothing(prototype, &at)?;
+                        let mesh: Arc<dyn Hittable> =
+                            Arc::new(Mesh::from_obj(&obj, material, smooth));
+                        let unit = unit_scale(prototype, &at)?;
+                        for p in scatter.place(target) {
+                            let s = p.scale * unit;
+                            let matrix = Matrix4::translation(p.position)
+                                .then_after(&Matrix4::scaling(Vector3(s, s, s)))
+                                .then_after(&Matrix4::rotation_y(p.rotation));
+                            instances.add(
+                                Transform::new(mesh.clone(), matrix)
+                                    .ok_or_else(|| format!("{}: scales must not be zero", at))?,
+                            );
+                        }
+                    }
+                    Some(other) => {
+                        return Err(format!(
+                            "{}: unknown prototype type '{}', expected sphere or mesh",
+                            at, other
+                        ))
+                    }
+                    None => return Err(format!("{}: missing prototype 'type'", at)),
+                }
+                let cull = cull(object, &at)?;
+                instances.cull.iter_mut().for_each(|c| *c = cull);
+                instances.build_bvh();
+                Box::new(instances)
+            }
+            Some("instance") => {
+                let id = object["of"]
+                    .as_str()
+                    .and_then(|name| world.find(name))
+                    .ok_or_else(|| format!("{}: 'of' must name an earlier object", at))?;
+                if let Some(lod) = lods.get(&id) {
+                    let matrix = placement(object, &at)?;
+                    let instance = lod
+                        .instance(matrix)
+                        .ok_or_else(|| format!("{}: 'scale' must not be zero", at))?;
+                    world.add_boxed(Box::new(instance));
+                    annotate(world, object, &at)?;
+                    continue;
+                }
+                // the first instance moves the original into shared ownership,
+                // leaving it in place behind an identity transform
+                let original = shared
+                    .entry(id)
+                    .or_insert_with(|| {
+                        let placeholder = Box::new(HittableStore::default());
+                        let original: Arc<dyn Hittable> =
+                            std::mem::replace(&mut world.objects[id], placeholder).into();
+                        world.objects[id] =
+                            Box::new(Transform::new(original.clone(), Matrix4::IDENTITY).unwrap());
+                        original
+                    })
+                    .clone();
+                let matrix = placement(object, &at)?;
+                Box::new(
+                    Transform::new(original, matrix)
+                        .ok_or_else(|| format!("{}: 'scale' must not be zero", at))?,
+                )
+            }
+            Some("terrain") => Box::new(Terrain::new(
+                number(object, "size", &at)?,
+                optional(object, "seed", &at, 7.0)? as u32,
+            )),
+            Some(other) => {
+                return Err(format!(
+                    "{}: unknown object type '{}', expected sphere, moving_sphere, \
+                     triangle, quad, box, mesh, subdivision, displaced, water, medium, \
+                     portal, scatter, instance, group or terrain",
+                    at, other
+                ))
+            }
+            None => return Err(format!("{}: missing object 'type'", at)),
+        };
+        world.add_boxed(hittable);
+        annotate(world, object, &at)?;
+    };
+
+    let mut settings = SceneSettings {
+        units: scene_units,
+        ..Default::default()
+    };
+    if let Some(camera) = value.get("camera") {
+        let (from, at) = (
+            vector(camera, "from", "camera")?,
+            vector(camera, "at", "camera")?,
+        );
+        settings.camera = Some(CameraView {
+            name: None,
+            from,
+            at,
+            vfov: optional(camera, "vfov", "camera", 20.0)?,
+            aperture: optional(camera, "aperture", "camera", 0.1)?,
+            focus: optional(camera, "focus", "camera", (at - from).length())?,
+        });
+        if camera.get("shutter").is_some() {
+            let shutter: Option<Vec<f64>> = camera["shutter"]
+                .as_array()
+                .and_then(|s| s.iter().map(Value::as_f64).collect());
+            settings.shutter = match shutter.as_deref() {
+                Some(&[open, close]) if open <= close => Some((open, close)),
+                _ => return Err("camera: 'shutter' must be [open, close] times".to_string()),
+            };
+        }
+        if camera.get("shutter_curve").is_some() {
+            let curve: Option<Vec<f64>> = camera["shutter_curve"]
+                .as_array()
+                .and_then(|s| s.iter().map(Value::as_f64).collect());
+            settings.shutter_curve = match curve.as_deref() {
+                Some(&[opening, closing]) => ShutterCurve::new(opening, closing),
+                _ => None,
+            };
+            if settings.shutter_curve.is_none() {
+                return Err(
+                    "camera: 'shutter_curve' must be [opening, closing] fractions \
+                            adding up to at most 1"
+                        .to_string(),
+                );
+            }
+        }
+        if let Some(readout) = camera.get("rolling_shutter") {
+            settings.rolling_shutter = Some(
+                readout
+                    .as_f64()
+                    .filter(|r| (0.0..=1.0).contains(r))
+                    .ok_or("camera: 'rolling_shutter' must be a fraction from 0 to 1")?,
+            );
+        }
+        if let Some(blur) = camera.get("motion_blur") {
+            settings.motion_blur = Some(
+                blur.as_bool()
+                    .ok_or("camera: 'motion_blur' must be true or false")?,
+            );
+        }
+    }
+    if let Some(render) = value.get("render") {
+        let count = |key: &str| -> Result<Option<u64>, String> {
+            match render.get(key) {
+                None => Ok(None),
+                Some(v) => v
+                    .as_u64()
+                    .filter(|n| *n > 0)
+                    .map(Some)
+                    .ok_or_else(|| format!("render: '{}' must be a positive integer", key)),
+            }
+        };
+        settings.width = count("width")?.map(|n| n as u32);
+        settings.samples = count("samples")?.map(|n| n as usize);
+        settings.max_depth = count("max_depth")?.map(|n| n as usize);
+        settings.roulette = count("roulette")?.map(|n| n as usize);
+        if let Some(aovs) = render.get("aovs") {
+            let parsed = aovs.as_array().and_then(|list| {
+                list.iter()
+                    .map(|aov| aov.as_str()?.parse().ok())
+                    .collect::<Option<Vec<Aov>>>()
+            });
+            settings.aovs = Some(parsed.ok_or_else(|| {
+                "render: 'aovs' must be a list of depth, normal, albedo and id".to_string()
+            })?);
+        }
+        match &render["background"] {
+            Value::Null => {}
+            Value::String(sky) => {
+                settings.background = Some(
+                    Sky::load(sky)?
+                        .ok_or_else(|| format!("render: unknown background '{}'", sky))?,
+                );
+            }
+            _ => settings.background = Some(Sky::Color(vector(render, "background", "render")?)),
+        }
+    }
+    Ok((world, settings))
+}
+
+/// `v[key]` as a number; `at` names `v` in errors, as for the helpers below.
+fn number(v: &Value, key: &str, at: &str) -> Result<f64, String> {
+    v[key]
+        .as_f64()
+        .ok_or_else(|| format!("{}: '{}' must be a number", at, key))
+}
+
+/// `v[key]` as a number, `default` if it is missing.
+fn optional(v: &Value, key: &str, at: &str, default: f64) -> Result<f64, String> {
+    match v.get(key) {
+        Some(_) => number(v, key, at),
+        None => Ok(default),
+    }
+}
+
+/// The smoothing angle of a mesh's `smooth`: `true` smooths across every
+/// edge, a number only across edges flatter than that many degrees.
+fn smoothing(v: &Value, at: &str) -> Result<Option<f64>, String> {
+    match v.get("smooth") {
+        None | Some(Value::Bool(false)) => Ok(None),
+        Some(Value::Bool(true)) => Ok(Some(180.0)),
+        Some(angle) => match angle.as_f64() {
+            Some(angle) if (0.0..=180.0).contains(&angle) => Ok(Some(angle)),
+            _ => Err(format!(
+                "{}: 'smooth' must be true, false or an angle from 0 to 180 degrees",
+                at
+            )),
+        },
+    }
+}
+
+/// `v[key]` as an [x, y, z] array.
+fn vector(v: &Value, key: &str, at: &str) -> Result<Vector3, String> {
+    let c: Option<Vec<f64>> = v[key]
+        .as_array()
+        .map(|c| c.iter().map(Value::as_f64).collect())
+        .unwrap_or(None);
+    match c.as_deref() {
+        Some(&[x, y, z]) => Ok(Vector3(x, y, z)),
+        _ => Err(format!("{}: '{}' must be an [x, y, z] array", at, key)),
+    }
+}
+
+/// The culling in `v`'s `cull` table, if it has one.
+fn cull(v: &Value, at: &str) -> Result<Option<Cull>, String> {
+    let table = match v.get("cull") {
+        Some(table) => table,
+        None => return Ok(None),
+    };
+    let at = format!("{}.cull", at);
+    let positive = |key: &str| match table.get(key) {
+        Some(value) => value
+            .as_f64()
+            .filter(|&x| x > 0.0)
+            .map(Some)
+            .ok_or_else(|| format!("{}: '{}' must be a positive number", at, key)),
+        None => Ok(None),
+    };
+    Ok(Some(Cull {
+        distance: positive("distance")?,
+        fade: positive("fade")?.unwrap_or(0.0),
+        pixels: positive("pixels")?,
+    }))
+}
+
+/// `v[key]` as a color, a gray number or a texture table, which may be a
+/// node combining further textures.
+fn texture(v: &Value, key: &str, at: &str) -> Result<Texture, String> {
+    let t = &v[key];
+    if let Some(gray) = t.as_f64() {
+        return Ok(Texture::Solid(Vector3(gray, gray, gray)));
+    }
+    if !t.is_object() {
+        return vector(v, key, at).map(Texture::Solid);
+    }
+    let at = format!("{}.{}", at, key);
+    let node = |node: Node| Texture::Node(Box::leak(Box::new(node)));
+    match t["type"].as_str() {
+        Some("checker") => Ok(Texture::Checker {
+            even: vector(t, "even", &at)?,
+            odd: vector(t, "odd", &at)?,
+            scale: optional(t, "scale", &at, 1.0)?,
+        }),
+        Some("noise") => Ok(Texture::Noise {
+            scale: optional(t, "scale", &at, 1.0)?,
+        }),
+        Some("turbulence") => Ok(Texture::Turbulence {
+            scale: optional(t, "scale", &at, 1.0)?,
+        }),
+        Some("ramp") => Ok(Texture::Ramp {
+            start: vector(t, "start", &at)?,
+            end: vector(t, "end", &at)?,
+            from: vector(t, "from", &at)?,
+            to: vector(t, "to", &at)?,
+        }),
+        Some("grid") => {
+            let file = t["file"]
+                .as_str()
+                .ok_or_else(|| format!("{}: 'file' must be a path", at))?;
+            let path = Path::new(file);
+            let grid = VoxelGrid::load(&path.to_string_lossy())
+                .map_err(|e| format!("{}: {}: {}", at, path.display(), e))?;
+            Ok(Texture::Grid(grid))
+        }
+        Some("image") => {
+            let file = t["file"]
+                .as_str()
+                .ok_or_else(|| format!("{}: 'file' must be a path", at))?;
+            let path = Path::new(file);
+            let image = ImageTexture::load(&path.to_string_lossy())
+                .map_err(|e| format!("{}: {}: {}", at, path.display(), e))?;
+            Ok(Texture::Image {
+                image,
+                space: ColorSpace::default(),
+            })
+        }
+        Some("vertex_color") => Ok(Texture::VertexColor {
+            space: ColorSpace::default(),
+        }),
+        Some("math") => Ok(node(Node::Math {
+            op: t["op"]
+                .as_str()
+                .and_then(|op| op.parse().ok())
+                .ok_or_else(|| {
+                    format!(
+                        "{}: 'op' must be add, subtract, multiply, divide, power, minimum \
+                         or maximum",
+                        at
+                    )
+                })?,
+            a: texture(t, "a", &at)?,
+            b: texture(t, "b", &at)?,
+        })),
+        Some("fresnel") => Ok(node(Node::Fresnel {
+            ir: optional(t, "ir", &at, 1.5)?,
+        })),
+        Some("mix") => Ok(node(Node::Mix {
+            factor: texture(t, "factor", &at)?,
+            a: texture(t, "a", &at)?,
+            b: texture(t, "b", &at)?,
+        })),
+        Some(other) => Err(format!(
+            "{}: unknown texture type '{}', expected checker, noise, turbulence, ramp, \
+             grid, image, vertex_color, math, fresnel or mix",
+            at, other
+        )),
+        None => Err(format!("{}: missing texture 'type'", at)),
+    }
+}
+
+/// The material `v`; mixes may name the materials in `named`.
+fn material(
+    v: &Value,
+    at: &str,
+    named: &std::collections::HashMap<String, Material>,
+) -> Result<Material, String> {
+    let base = match v["type"].as_str() {
+        Some("diffuse") => Ok(Material::Lambertian {
+            albedo: texture(v, "albedo", at)?,
+        }),
+        Some("metal") => Ok(Material::Metal {
+            albedo: texture(v, "albedo", at)?,
+            fuzz: optional(v, "fuzz", at, 0.0)?,
+        }),
+        Some("glass") => Ok(Material::Dielectric {
+            ir: optional(v, "ir", at, 1.5)?,
+            roughness: optional(v, "roughness", at, 0.0)?,
+            absorption: match v.get("absorption") {
+                Some(_) => vector(v, "absorption", at)?,
+                None => Vector3(0.0, 0.0, 0.0),
+            },
+        }),
+        Some("light") => Ok(Material::DiffuseLight {
+            // negative lights have to say so
+            emit: match (
+                vector(v, "emit", at)?,
+                v.get("negative").map(Value::as_bool),
+            ) {
+                (e, _) if e.x() < 0.0 || e.y() < 0.0 || e.z() < 0.0 => {
+                    return Err(format!(
+                        "{}: 'emit' must not be negative, lights that darken take \
+                         negative = true",
+                        at
+                    ))
+                }
+                (e, None | Some(Some(false))) => e,
+                (e, Some(Some(true))) => e * -1.0,
+                (_, Some(None)) => return Err(format!("{}: 'negative' must be true or false", at)),
+            },
+            gel: match v.get("gel") {
+                Some(_) => Some(texture(v, "gel", at)?),
+                None => None,
+            },
+        }),
+        Some("pbr") => {
+            let fraction = |v: &Value, key: &str, at: &str| match optional(v, key, at, 1.0)? {
+                f if (0.0..=1.0).contains(&f) => Ok(f),
+                _ => Err(format!("{}: '{}' must be from 0 to 1", at, key)),
+            };
+            // linear data rather than sRGB colors, as glTF stores them
+            let map = |key: &str| -> Result<Option<(&'static ImageTexture, f64)>, String> {
+                let map = match v.get(key) {
+                    Some(map) => map,
+                    None => return Ok(None),
+                };
+                let at = format!("{}.{}", at, key);
+                let file = map["file"]
+                    .as_str()
+                    .ok_or_else(|| format!("{}: 'file' must be a path", at))?;
+                let image = ImageTexture::load_data(file)
+                    .map_err(|e| format!("{}: {}: {}", at, file, e))?;
+                Ok(Some((image, fraction(map, "strength", &at)?)))
+            };
+            let occlusion = map("occlusion_map")?;
+            Ok(Material::Pbr(Box::leak(Box::new(PbrMaterial {
+                albedo: texture(v, "albedo", at)?,
+                metallic: fraction(v, "metallic", at)?,
+                roughness: fraction(v, "roughness", at)?,
+                metallic_roughness_map: map("metallic_roughness_map")?.map(|(image, _)| image),
+                occlusion_map: occlusion.map(|(image, _)| image),
+                occlusion_strength: occlusion.map_or(1.0, |(_, strength)| strength),
+                emission: match v.get("emission") {
+                    Some(_) => vector(v, "emission", at)?,
+                    None => Vector3(0.0, 0.0, 0.0),
+                },
+                emission_map: match v.get("emission_map") {
+                    Some(_) => Some(texture(v, "emission_map", at)?),
+                    None => None,
+                },
+            }))))
+        }
+        Some("mix") => {
+            let part = |key: &str| -> Result<&'static Material, String> {
+                let m = match &v[key] {
+                    Value::String(name) => named
+                        .get(name)
+                        .copied()
+                        .ok_or_else(|| format!("{}: unknown material '{}'", at, name))?,
+                    Value::Null => return Err(format!("{}: missing '{}'", at, key)),
+                    inline => material(inline, &format!("{}.{}", at, key), named)?,
+                };
+                Ok(Box::leak(Box::new(m)))
+            };
+            Ok(Material::Mix {
+                factor: texture(v, "factor", at)?,
+                a: part("a")?,
+                b: part("b")?,
+            })
+        }
+        Some(other) => Err(format!(
+            "{}: unknown material type '{}', expected diffuse, metal, glass, light, pbr or \
+             mix",
+            at, other
+        )),
+        None => Err(format!("{}: missing material 'type'", at)),
+    }?;
+    let mut maps = Vec::new();
+    if let Some(bump) = v.get("bump") {
+        let at = format!("{}.bump", at);
+        maps.push(SurfaceMap::Bump {
+            height: texture(bump, "height", &at)?,
+            strength: optional(bump, "strength", &at, 1.0)?,
+        });
+    }
+    if let Some(normal) = v.get("normal_map") {
+        let at = format!("{}.normal_map", at);
+        let file = normal["file"]
+            .as_str()
+            .ok_or_else(|| format!("{}: 'file' must be a path", at))?;
+        let path = Path::new(file);
+        maps.push(SurfaceMap::Normal {
+            image: ImageTexture::load_data(&path.to_string_lossy())
+                .map_err(|e| format!("{}: {}: {}", at, path.display(), e))?,
+            strength: optional(normal, "strength", &at, 1.0)?,
+        });
+    }
+    // the normal map tilts the normal the bumps leave
+    Ok(maps.into_iter().fold(base, |base, map| Material::Mapped {
+        base: Box::leak(Box::new(base)),
+        map,
+    }))
+}
@@ -0,0 +1,428 @@
+//! `raytracer serve`: a long-running render server taking jobs over HTTP.
+//!
+//! Jobs render one after another (or `--jobs N` at a time) in child
+//! processes of the same binary, each in a directory of its own holding the
+//! scene, the preview, the image and the log. The API speaks JSON:
+//!
+//! - `POST /jobs` with `{"scene": text, "format": "toml", "args": [...]}`
+//!   queues a render of the scene text (JSON, TOML or RON) with the command
+//!   line options in `args`; either may be left out. Answers `{"id": n}`.
+//! - `GET /jobs` lists the jobs, `GET /jobs/<id>` gives one's `state`
+//!   (`queued`, `rendering`, `done` or `failed`), `progress` from 0 to 1 and,
+//!   once failed, the `error`.
+//! - `GET /jobs/<id>/preview` is the latest preview as PPM, refined after
+//!   every pass, and `GET /jobs/<id>/image` the finished image as PNG.
+//!
+//! Bodies over 64 MiB are refused with 413, and at most 64 connections,
+//! each given 30 seconds to send and read, are served at once.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+/// Largest request body taken, which is mostly scene text.
+const MAX_BODY: u64 = 64 << 20;
+
+/// Longest request or header line taken.
+const MAX_LINE: u64 = 16 << 10;
+
+/// Connections served at once; more are turned away.
+const MAX_CONNECTIONS: usize = 64;
+
+/// How long a connection may wait for the client to send or take data.
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Queued,
+    Rendering,
+    Done,
+    Failed,
+}
+
+#[derive(Debug)]
+struct Job {
+    /// Command line options for the child process.
+    args: Vec<String>,
+    dir: PathBuf,
+    state: State,
+    progress: f64,
+    error: Option<String>,
+}
+
+impl Job {
+    fn json(&self, id: usize) -> serde_json::Value {
+        let state = match self.state {
+            State::Queued => "queued",
+            State::Rendering => "rendering",
+            State::Done => "done",
+            State::Failed => "failed",
+        };
+        let mut json = serde_json::json!({
+            "id": id,
+            "state": state,
+            "progress": self.progress,
+        });
+        if let Some(error) = &self.error {
+            json["error"] = error.clone().into();
+        }
+        json
+    }
+}
+
+/// The jobs by ID, and a signal for the workers waiting for queued ones.
+type Queue = Arc<(Mutex<Vec<Job>>, Condvar)>;
+
+/// `raytracer serve [--listen ADDR] [--jobs N] [--dir DIR] [--asset-path
+/// DIRS]`: answers on ADDR, 127.0.0.1:8080 by default, and keeps the jobs
+/// under DIR, by default a new directory in the temporary one. The server
+/// trusts its clients, which can pass any options to the renders.
+pub(crate) fn serve(args: &[String]) {
+    let mut listen = "127.0.0.1:8080".to_string();
+    let mut workers = 1;
+    let mut dir = std::env::temp_dir().join(format!("raytracer-serve-{}", std::process::id()));
+    let mut asset_paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next().cloned().unwrap_or_else(|| {
+                eprintln!("missing value for {}", arg);
+                std::process::exit(1);
+            })
+        };
+        match arg.as_str() {
+            "--listen" => listen = value(),
+            "--jobs" => {
+                workers = value().parse::<usize>().unwrap_or_else(|_| {
+                    eprintln!("--jobs expects a number");
+                    std::process::exit(1);
+                })
+            }
+            "--dir" => dir = PathBuf::from(value()),
+            "--asset-path" => asset_paths.extend(std::env::split_paths(&value())),
+            _ => {
+                eprintln!(
+                    "usage: raytracer serve [--listen ADDR] [--jobs N] [--dir DIR] \
+                     [--asset-path DIRS]"
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    let exe = std::env::current_exe().unwrap_or_else(|e| {
+        eprintln!("could not locate the raytracer executable: {}", e);
+        std::process::exit(1);
+    });
+    // scenes are written into the job directories, so their assets are
+    // looked for where the server was started as well
+    let cwd = std::env::current_dir().unwrap_or_default();
+    asset_paths = asset_paths.into_iter().map(|p| cwd.join(p)).collect();
+    asset_paths.push(cwd);
+    let asset_path = std::env::join_paths(&asset_paths).unwrap_or_default();
+    let listener = std::net::TcpListener::bind(&listen).unwrap_or_else(|e| {
+        eprintln!("could not listen on {}: {}", listen, e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        eprintln!("could not create {}: {}", dir.display(), e);
+        std::process::exit(1);
+    }
+    eprintln!("listening on {}, jobs in {}", listen, dir.display());
+
+    let queue: Queue = Default::default();
+    for _ in 0..workers.max(1) {
+        let queue = queue.clone();
+        let exe = exe.clone();
+        std::thread::spawn(move || loop {
+            let (id, args, dir) = {
+                let (jobs, queued) = &*queue;
+                let mut jobs = queued
+                    .wait_while(jobs.lock().unwrap(), |jobs| {
+                        jobs.iter().all(|job| job.state != State::Queued)
+                    })
+                    .unwrap();
+                let id = jobs.iter().position(|job| job.state == State::Queued);
+                let job = &mut jobs[id.unwrap()];
+                job.state = State::Rendering;
+                (id.unwrap(), job.args.clone(), job.dir.clone())
+            };
+            let result = run(&exe, &args, &dir, |progress| {
+                queue.0.lock().unwrap()[id].progress = progress;
+            });
+            let mut jobs = queue.0.lock().unwrap();
+            let job = &mut jobs[id];
+            match result {
+                Ok(()) => {
+                    job.state = State::Done;
+                    job.progress = 1.0;
+                }
+                Err(e) => {
+                    job.state = State::Failed;
+                    job.error = Some(e);
+                }
+            }
+        });
+    }
+
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        // a client that stops sending or reading gives up its thread
+        let _ = stream.set_read_timeout(Some(TIMEOUT));
+        let _ = stream.set_write_timeout(Some(TIMEOUT));
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            answer(&mut stream, 503, "text/plain", b"too many connections");
+            continue;
+        }
+        let queue = queue.clone();
+        let (dir, asset_path) = (dir.clone(), asset_path.clone());
+        let connections = connections.clone();
+        std::thread::spawn(move || {
+            let (status, content_type, body) = match read_request(&mut stream) {
+                Ok((method, path, body)) => {
+                    respond(&queue, &dir, &asset_path, &method, &path, &body)
+                }
+                Err((status, e)) => (status, "text/plain", e.into_bytes()),
+            };
+            answer(&mut stream, status, content_type, &body);
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Writes the response and closes the connection.
+fn answer(stream: &mut std::net::TcpStream, status: u16, content_type: &str, body: &[u8]) {
+    let reason = match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        404 => "Not Found",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        content_type,
+        body.len()
+    );
+    let _ = stream
+        .write_all(head.as_bytes())
+        .and_then(|_| stream.write_all(body));
+}
+
+/// Method, path and body of an HTTP request.
+type Request = (String, String, Vec<u8>);
+
+/// The HTTP request on `stream`, or the status and message to answer with.
+fn read_request(stream: &mut std::net::TcpStream) -> Result<Request, (u16, String)> {
+    let bad = |e: String| (400, e);
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    let read_line = |reader: &mut BufReader<_>, line: &mut String| {
+        line.clear();
+        match reader.by_ref().take(MAX_LINE).read_line(line) {
+            Ok(n) if n as u64 == MAX_LINE => Err(bad("line too long".to_string())),
+            Ok(_) => Ok(()),
+            Err(e) => Err(bad(e.to_string())),
+        }
+    };
+    read_line(&mut reader, &mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(bad("expected a request line".to_string())),
+    };
+    let mut length: u64 = 0;
+    loop {
+        read_line(&mut reader, &mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| bad("invalid Content-Length".to_string()))?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err((413, format!("bodies are at most {} bytes", MAX_BODY)));
+    }
+    let mut body = Vec::new();
+    reader
+        .take(length)
+        .read_to_end(&mut body)
+        .map_err(|e| bad(e.to_string()))?;
+    if body.len() as u64 != length {
+        return Err(bad("body shorter than its Content-Length".to_string()));
+    }
+    Ok((method, path, body))
+}
+
+/// Status, content type and body answering a request.
+fn respond(
+    queue: &Queue,
+    dir: &std::path::Path,
+    asset_path: &std::ffi::OsStr,
+    method: &str,
+    path: &str,
+    body: &[u8],
+) -> (u16, &'static str, Vec<u8>) {
+    let json = |status, value: serde_json::Value| {
+        (status, "application/json", value.to_string().into_bytes())
+    };
+    let error = |status, message: String| json(status, serde_json::json!({ "error": message }));
+    let segments: Vec<&str> = path
+        .split('?')
+        .next()
+        .unwrap_or("")
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .collect();
+    let (jobs, queued) = &**queue;
+    match (method, &segments[..]) {
+        ("POST", ["jobs"]) => {
+            let request: serde_json::Value = match serde_json::from_slice(body) {
+                Ok(request) => request,
+                Err(e) => return error(400, format!("invalid JSON: {}", e)),
+            };
+            let mut args = vec![
+                "--asset-path".to_string(),
+                asset_path.to_string_lossy().into_owned(),
+            ];
+            match request.get("args").map(|a| a.as_array()) {
+                None => {}
+                Some(Some(list)) if list.iter().all(|a| a.is_string()) => {
+                    args.extend(list.iter().map(|a| a.as_str().unwrap().to_string()))
+                }
+                Some(_) => return error(400, "'args' must be a list of strings".to_string()),
+            }
+            let mut jobs = jobs.lock().unwrap();
+            let id = jobs.len();
+            let job_dir = dir.join(id.to_string());
+            if let Err(e) = std::fs::create_dir_all(&job_dir) {
+                return error(500, e.to_string());
+            }
+            if let Some(scene) = request.get("scene") {
+                let format = request["format"].as_str().unwrap_or("toml");
+                let (scene, format) = match (scene.as_str(), format) {
+                    (Some(scene), "json" | "toml" | "ron") => (scene, format),
+                    _ => {
+                        return error(
+                            400,
+                            "'scene' must be text and 'format' json, toml or ron".to_string(),
+                        )
+                    }
+                };
+                let file = job_dir.join(format!("scene.{}", format));
+                if let Err(e) = std::fs::write(&file, scene) {
+                    return error(500, e.to_string());
+                }
+                args.push("--scene-file".to_string());
+                args.push(file.to_string_lossy().into_owned());
+            }
+            jobs.push(Job {
+                args,
+                dir: job_dir,
+                state: State::Queued,
+                progress: 0.0,
+                error: None,
+            });
+            queued.notify_one();
+            json(201, serde_json::json!({ "id": id }))
+        }
+        ("GET", ["jobs"]) => {
+            let jobs = jobs.lock().unwrap();
+            json(
+                200,
+                jobs.iter()
+                    .enumerate()
+                    .map(|(id, job)| job.json(id))
+                    .collect(),
+            )
+        }
+        ("GET", ["jobs", id, rest @ ..]) => {
+            let jobs = jobs.lock().unwrap();
+            let (id, job) = match id
+                .parse::<usize>()
+                .ok()
+                .and_then(|id| Some((id, jobs.get(id)?)))
+            {
+                Some(found) => found,
+                None => return error(404, format!("there is no job {}", id)),
+            };
+            let file = |name: &str, content_type| match std::fs::read(job.dir.join(name)) {
+                Ok(data) => (200, content_type, data),
+                Err(_) => error(409, format!("job {} has no {} yet", id, name)),
+            };
+            match rest {
+                [] => json(200, job.json(id)),
+                ["preview"] => file("preview.ppm", "image/x-portable-pixmap"),
+                ["image"] if job.state == State::Done => file("image.png", "image/png"),
+                ["image"] => error(409, format!("job {} is not done", id)),
+                _ => error(404, format!("no such resource {}", path)),
+            }
+        }
+        _ => error(404, format!("no such resource {} {}", method, path)),
+    }
+}
+
+/// Renders a job in `dir` with the binary `exe`, telling `progress` how far
+/// it got; fails with the last line the render logged.
+fn run(
+    exe: &std::path::Path,
+    args: &[String],
+    dir: &std::path::Path,
+    progress: impl Fn(f64),
+) -> Result<(), String> {
+    let mut child = std::process::Command::new(exe)
+        .args(args)
+        .args([
+            "--progress",
+            "--progressive",
+            "--preview-file",
+            "preview.ppm",
+        ])
+        .args(["--output", "image.png"])
+        .current_dir(dir)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let mut log = std::fs::File::create(dir.join("render.log")).map_err(|e| e.to_string())?;
+    let mut last = String::new();
+    // progress reports end in carriage returns, everything else in newlines
+    for line in BufReader::new(child.stderr.take().unwrap()).split(b'\r') {
+        let line = line.map_err(|e| e.to_string())?;
+        let _ = log.write_all(&line).and_then(|_| log.write_all(b"\r"));
+        let text = String::from_utf8_lossy(&line);
+        for part in text.split('\n') {
+            let part = part.trim();
+            match part.split_once('%').map(|(p, _)| p.trim().parse::<f64>()) {
+                Some(Ok(percent)) => progress(percent / 100.0),
+                _ if !part.is_empty() => last = part.to_string(),
+                _ => {}
+            }
+        }
+    }
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else if last.is_empty() {
+        Err(format!("the render failed with {}", status))
+    } else {
+        Err(last)
+    }
+}
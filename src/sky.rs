@@ -0,0 +1,530 @@
+//! Skies, the physically based atmosphere and fog.
+
+use crate::hittable::terrain::lattice_hash;
+use crate::hittable::RayKind;
+use crate::light::{blackbody, LUMENS_PER_WATT};
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::texture::{turbulence, ImageTexture};
+use crate::vec3::Vector3;
+
+/// Single-scattering Rayleigh and Mie model of an earth-like atmosphere,
+/// after Nishita et al., "Display of the Earth Taking into Account
+/// Atmospheric Scattering" (1993). Lengths are in meters, the scene sits on
+/// top of the planet with y pointing up.
+#[derive(Clone, Copy, Debug)]
+pub struct Atmosphere {
+    pub(crate) sun_direction: Vector3,
+    /// Irradiance of the sun above the atmosphere, tinted by `sun_color`.
+    pub(crate) sun_intensity: f64,
+    pub(crate) sun_color: Vector3,
+    /// Angular radius of the visible solar disk, if it is drawn.
+    sun_radius: Option<f64>,
+    earth_radius: f64,
+    atmosphere_radius: f64,
+    rayleigh_height: f64,
+    mie_height: f64,
+    beta_rayleigh: Vector3,
+    beta_mie: f64,
+    mie_g: f64,
+    /// Height of the scene origin above the ground.
+    altitude: f64,
+    /// Size of a scene unit for aerial perspective; `None` leaves surfaces
+    /// unaffected by the atmosphere.
+    pub(crate) meters_per_unit: Option<f64>,
+}
+
+impl Atmosphere {
+    pub fn new(sun_direction: Vector3) -> Self {
+        Self {
+            sun_direction: sun_direction.normalize(),
+            sun_intensity: 20.0,
+            sun_color: Vector3(1.0, 1.0, 1.0),
+            sun_radius: None,
+            earth_radius: 6360e3,
+            atmosphere_radius: 6420e3,
+            rayleigh_height: 7994.0,
+            mie_height: 1200.0,
+            beta_rayleigh: Vector3(5.8e-6, 13.5e-6, 33.1e-6),
+            beta_mie: 21e-6,
+            mie_g: 0.76,
+            altitude: 1.0,
+            meters_per_unit: None,
+        }
+    }
+
+    /// A clear day for the `daylight` scene: the sun is a 5778 K black body
+    /// delivering the luminous solar constant of 133.8 klx (Darula et al. 2005)
+    /// above the atmosphere, seen as a disk 0.533 degrees across.
+    pub fn daylight(sun_direction: Vector3) -> Self {
+        Self {
+            sun_intensity: 133.8e3 / LUMENS_PER_WATT,
+            sun_color: blackbody(5778.0),
+            sun_radius: Some(0.2665f64.to_radians()),
+            ..Self::new(sun_direction)
+        }
+    }
+
+    fn origin(&self) -> Vector3 {
+        Vector3(0.0, self.earth_radius + self.altitude, 0.0)
+    }
+
+    /// Distances along the ray to the two intersections with a sphere of
+    /// `radius` around the planet center.
+    fn intersect(origin: &Vector3, direction: &Vector3, radius: f64) -> Option<(f64, f64)> {
+        let b = origin.dot(direction);
+        let c = origin.length_squared() - radius * radius;
+        let discriminant = b * b - c;
+        (discriminant >= 0.0).then(|| {
+            let sd = discriminant.sqrt();
+            (-b - sd, -b + sd)
+        })
+    }
+
+    /// Distance along the ray to the ground, if it hits the planet.
+    fn ground_distance(&self, origin: &Vector3, direction: &Vector3) -> Option<f64> {
+        Self::intersect(origin, direction, self.earth_radius)
+            .map(|(near, _)| near)
+            .filter(|&near| near > 0.0)
+    }
+
+    /// Distance from `origin` along `direction` to the ground or to space.
+    fn path_length(&self, origin: &Vector3, direction: &Vector3) -> f64 {
+        self.ground_distance(origin, direction).unwrap_or_else(|| {
+            Self::intersect(origin, direction, self.atmosphere_radius)
+                .map_or(0.0, |(_, far)| far.max(0.0))
+        })
+    }
+
+    fn extinction(&self, rayleigh_depth: f64, mie_depth: f64) -> Vector3 {
+        let tau = self.beta_rayleigh * rayleigh_depth
+            + Vector3(1.0, 1.0, 1.0) * (self.beta_mie * 1.1 * mie_depth);
+        Vector3((-tau.x()).exp(), (-tau.y()).exp(), (-tau.z()).exp())
+    }
+
+    /// Rayleigh and Mie optical depth from `p` towards the sun, `None` if the
+    /// planet is in the way.
+    fn sun_optical_depth(&self, p: &Vector3) -> Option<(f64, f64)> {
+        if self.ground_distance(p, &self.sun_direction).is_some() {
+            return None;
+        }
+        let steps = 8;
+        let length = self.path_length(p, &self.sun_direction);
+        let segment = length / steps as f64;
+        Some((0..steps).fold((0.0, 0.0), |(r, m), i| {
+            let h = (*p + self.sun_direction * ((i as f64 + 0.5) * segment)).length()
+                - self.earth_radius;
+            (
+                r + (-h / self.rayleigh_height).exp() * segment,
+                m + (-h / self.mie_height).exp() * segment,
+            )
+        }))
+    }
+
+    /// Transmittance and in-scattered sun light along `distance` meters from
+    /// the scene origin in `direction`.
+    fn integrate(&self, direction: &Vector3, distance: f64) -> (Vector3, Vector3) {
+        let steps = 16;
+        let origin = self.origin();
+        let segment = distance / steps as f64;
+        let mu = direction.dot(&self.sun_direction);
+        let g = self.mie_g;
+        let phase_rayleigh = 3.0 / (16.0 * std::f64::consts::PI) * (1.0 + mu * mu);
+        let phase_mie = 3.0 / (8.0 * std::f64::consts::PI) * ((1.0 - g * g) * (1.0 + mu * mu))
+            / ((2.0 + g * g) * (1.0 + g * g - 2.0 * g * mu).powf(1.5));
+
+        let (mut rayleigh_depth, mut mie_depth) = (0.0, 0.0);
+        let mut rayleigh = Vector3(0.0, 0.0, 0.0);
+        let mut mie = Vector3(0.0, 0.0, 0.0);
+        for i in 0..steps {
+            let p = origin + *direction * ((i as f64 + 0.5) * segment);
+            let h = p.length() - self.earth_radius;
+            let hr = (-h / self.rayleigh_height).exp() * segment;
+            let hm = (-h / self.mie_height).exp() * segment;
+            rayleigh_depth += hr;
+            mie_depth += hm;
+            if let Some((light_rayleigh, light_mie)) = self.sun_optical_depth(&p) {
+                let attenuation =
+                    self.extinction(rayleigh_depth + light_rayleigh, mie_depth + light_mie);
+                rayleigh += attenuation * hr;
+                mie += attenuation * hm;
+            }
+        }
+        let inscattered = (rayleigh * self.beta_rayleigh * phase_rayleigh
+            + mie * (self.beta_mie * phase_mie))
+            * self.sun_color
+            * self.sun_intensity;
+        (self.extinction(rayleigh_depth, mie_depth), inscattered)
+    }
+
+    /// Sky radiance seen from the scene origin.
+    pub fn sky(&self, direction: &Vector3) -> Vector3 {
+        let direction = direction.normalize();
+        self.integrate(&direction, self.path_length(&self.origin(), &direction))
+            .1
+    }
+
+    /// Radiance of the solar disk seen in `direction`, after passing through the
+    /// atmosphere. Zero outside the disk or if the sun is not drawn.
+    pub fn sun_disk(&self, direction: &Vector3) -> Vector3 {
+        let radius = match self.sun_radius {
+            Some(radius) => radius,
+            None => return Vector3(0.0, 0.0, 0.0),
+        };
+        let angle = direction
+            .normalize()
+            .dot(&self.sun_direction)
+            .clamp(-1.0, 1.0)
+            .acos();
+        if angle >= radius {
+            return Vector3(0.0, 0.0, 0.0);
+        }
+        // linear limb darkening, normalized so the whole disk still delivers
+        // the sun's irradiance
+        let limb = 0.6;
+        let mu = (1.0 - (angle / radius).powi(2)).sqrt();
+        let solid_angle = 2.0 * std::f64::consts::PI * (1.0 - radius.cos());
+        let radiance =
+            self.sun_intensity * (1.0 - limb * (1.0 - mu)) / (solid_angle * (1.0 - limb / 3.0));
+        self.transmittance(&self.sun_direction) * self.sun_color * radiance
+    }
+
+    /// How much of the sun light arriving from `direction` makes it through
+    /// the atmosphere to the scene origin.
+    pub fn transmittance(&self, direction: &Vector3) -> Vector3 {
+        let towards = Atmosphere {
+            sun_direction: direction.normalize(),
+            ..*self
+        };
+        towards
+            .sun_optical_depth(&self.origin())
+            .map_or(Vector3(0.0, 0.0, 0.0), |(r, m)| self.extinction(r, m))
+    }
+
+    /// Aerial perspective: attenuates the `radiance` arriving along the camera
+    /// ray `r` and adds the light the air scatters into it.
+    pub(crate) fn apply(&self, scene: &Scene, r: &Ray, radiance: Vector3) -> Vector3 {
+        let meters_per_unit = match self.meters_per_unit {
+            Some(m) => m,
+            None => return radiance,
+        };
+        let direction = r.direction.normalize();
+        let hit = scene.world.hit_as(
+            &Ray {
+                origin: r.origin,
+                direction,
+                time: r.time,
+            },
+            0.001,
+            f64::INFINITY,
+            RayKind::Camera,
+        );
+        match hit {
+            Some(i) => {
+                let (transmittance, inscattered) =
+                    self.integrate(&direction, i.t * meters_per_unit);
+                radiance * transmittance + scene.color_space.from_linear_srgb(inscattered)
+            }
+            // the sky already contains the whole atmosphere
+            None => radiance,
+        }
+    }
+}
+
+/// Cheap fog blending everything towards `color` with distance, without
+/// lighting it. The density falls off exponentially with height above y = 0 at
+/// rate `falloff`, or is uniform if that is zero.
+#[derive(Clone, Copy, Debug)]
+pub struct Fog {
+    pub(crate) color: Vector3,
+    pub(crate) density: f64,
+    pub(crate) falloff: f64,
+}
+
+impl Fog {
+    /// Blends `radiance` arriving from `t` along the ray towards the fog color.
+    pub fn apply(&self, r: &Ray, t: f64, radiance: Vector3) -> Vector3 {
+        let length = r.direction.length();
+        let distance = t * length;
+        let at_origin = self.density * (-self.falloff * r.origin.y()).exp();
+        let k = self.falloff * r.direction.y() / length;
+        // density integrated along the ray
+        let optical_depth = if k.abs() < 1e-9 {
+            at_origin * distance
+        } else if distance.is_infinite() {
+            if k > 0.0 {
+                at_origin / k
+            } else {
+                f64::INFINITY
+            }
+        } else {
+            at_origin * (1.0 - (-k * distance).exp()) / k
+        };
+        let transmittance = (-optical_depth).exp();
+        if transmittance == 0.0 {
+            // avoids infinity times zero for radiance arriving from infinitely far
+            return self.color;
+        }
+        radiance * transmittance + self.color * (1.0 - transmittance)
+    }
+}
+
+impl std::str::FromStr for Fog {
+    type Err = ();
+    /// Parses `density[,falloff]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let c: Vec<f64> = s
+            .split(',')
+            .map(|c| c.parse().map_err(|_| ()))
+            .collect::<Result<_, _>>()?;
+        let (density, falloff) = match c[..] {
+            [density] => (density, 0.0),
+            [density, falloff] => (density, falloff),
+            _ => return Err(()),
+        };
+        Ok(Fog {
+            color: Vector3(0.7, 0.75, 0.8),
+            density,
+            falloff,
+        })
+    }
+}
+
+/// Angular radius of the disks stars are drawn as, about a pixel of an 800
+/// pixel wide image with a 30 degree field of view.
+const STAR_RADIUS: f64 = 0.0006;
+
+/// Radiance of the faintest stars.
+const FAINTEST_STAR: f64 = 0.2;
+
+/// Angular radius of the moon disk, 0.52 degrees across.
+const MOON_RADIUS: f64 = 0.0045;
+
+/// A clear night: procedural stars, a band of Milky Way and, if the scene
+/// has a sun light, a moon disk where that light comes from.
+#[derive(Clone, Copy, Debug)]
+pub struct NightSky {
+    /// Number of stars over the whole sky.
+    pub(crate) stars: f64,
+    /// Stars brighter than `b` times the faintest make up `b^-slope` of
+    /// them, so a higher slope leaves fewer bright stars.
+    pub(crate) slope: f64,
+    /// Radiance of the Milky Way at its brightest, 0 for none.
+    pub(crate) milky_way: f64,
+    /// Direction and irradiance of the moonlight, from the sun light.
+    pub(crate) moon: Option<(Vector3, Vector3)>,
+}
+
+impl Default for NightSky {
+    fn default() -> Self {
+        Self {
+            stars: 6000.0,
+            slope: 1.5,
+            milky_way: 0.08,
+            moon: None,
+        }
+    }
+}
+
+impl NightSky {
+    pub fn sky(&self, direction: &Vector3) -> Vector3 {
+        let d = direction.normalize();
+        // faint airglow, stronger towards the horizon
+        let glow = Vector3(0.6, 0.8, 1.2) * (0.002 + 0.004 * (1.0 - d.y().abs()).powi(4));
+        glow + self.milky_way(&d)
+    }
+
+    /// Stars seen in unit direction `d`. Each face of a cube around the
+    /// viewer is split into cells with at most one star each, kept with
+    /// a chance proportional to the solid angle of the cell so the stars
+    /// spread evenly over the sky.
+    fn stars(&self, d: &Vector3) -> Vector3 {
+        let a = [d.x(), d.y(), d.z()];
+        let axis = (0..3)
+            .max_by(|&i, &j| a[i].abs().total_cmp(&a[j].abs()))
+            .unwrap();
+        let (i, j) = ((axis + 1) % 3, (axis + 2) % 3);
+        let face = 2 * axis as i64 + (a[axis] < 0.0) as i64;
+        // cells make up pi / 6 of a face's solid angle on average
+        let n = (self.stars / std::f64::consts::PI).sqrt().ceil().max(1.0) as i64;
+        let cell = |x: f64| ((x + 1.0) * 0.5 * n as f64).floor() as i64;
+        let (ci, cj) = (cell(a[i] / a[axis].abs()), cell(a[j] / a[axis].abs()));
+        let mut radiance = Vector3(0.0, 0.0, 0.0);
+        for (di, dj) in (0..9).map(|k| (k % 3 - 1, k / 3 - 1)) {
+            let (x, y) = (ci + di, cj + dj);
+            if x < 0 || y < 0 || x >= n || y >= n {
+                continue;
+            }
+            let hash = |k: u32| (lattice_hash(face * n + x, y, k) + 1.0) * 0.5;
+            let u = (x as f64 + hash(0)) / n as f64 * 2.0 - 1.0;
+            let v = (y as f64 + hash(1)) / n as f64 * 2.0 - 1.0;
+            if hash(2) > (1.0 + u * u + v * v).powf(-1.5) {
+                continue;
+            }
+            let mut s = [0.0; 3];
+            s[axis] = a[axis].signum();
+            s[i] = u;
+            s[j] = v;
+            let star = Vector3(s[0], s[1], s[2]).normalize();
+            if (star - *d).length() < STAR_RADIUS {
+                let brightness = hash(3).max(1e-6).powf(-1.0 / self.slope).min(500.0);
+                let kelvin = 3000.0 + 9000.0 * hash(4).powi(2);
+                radiance += blackbody(kelvin) * (FAINTEST_STAR * brightness);
+            }
+        }
+        radiance
+    }
+
+    /// The Milky Way in unit direction `d`: a mottled band along a tilted
+    /// great circle, brightest towards the galactic center.
+    fn milky_way(&self, d: &Vector3) -> Vector3 {
+        if self.milky_way <= 0.0 {
+            return Vector3(0.0, 0.0, 0.0);
+        }
+        let pole = Vector3(0.4, 0.6, 0.7).normalize();
+        let center = Vector3(0.0, 0.7, -0.6).cross(&pole).normalize();
+        let latitude = d.dot(&pole);
+        let band = (-(latitude / 0.15).powi(2)).exp();
+        let core = 0.3 + 0.7 * d.dot(&center).max(0.0).powi(2);
+        let clouds = (1.5 * turbulence(*d * 6.0, 5)).min(1.0);
+        Vector3(1.0, 0.95, 0.85) * (self.milky_way * band * core * (0.3 + 0.7 * clouds))
+    }
+
+    /// Radiance of the stars and the moon disk seen in `direction`, the moon
+    /// lit evenly and delivering the irradiance of the moonlight. Like the
+    /// sun disk of the atmosphere, too small and bright to be found by
+    /// diffuse bounces.
+    pub fn disks(&self, direction: &Vector3) -> Vector3 {
+        let d = direction.normalize();
+        let moon = match self.moon {
+            Some((moon, irradiance)) if d.dot(&moon) > MOON_RADIUS.cos() => {
+                irradiance / (2.0 * std::f64::consts::PI * (1.0 - MOON_RADIUS.cos()))
+            }
+            _ => Vector3(0.0, 0.0, 0.0),
+        };
+        moon + self.stars(&d)
+    }
+}
+
+impl std::str::FromStr for NightSky {
+    type Err = ();
+    /// Parses `night[:stars[,slope[,milky_way]]]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let night = NightSky::default();
+        let c: Vec<f64> = match s.strip_prefix("night") {
+            Some("") => return Ok(night),
+            Some(rest) => rest
+                .strip_prefix(':')
+                .ok_or(())?
+                .split(',')
+                .map(|c| c.parse().map_err(|_| ()))
+                .collect::<Result<_, _>>()?,
+            None => return Err(()),
+        };
+        let night = match c[..] {
+            [stars] => NightSky { stars, ..night },
+            [stars, slope] => NightSky {
+                stars,
+                slope,
+                ..night
+            },
+            [stars, slope, milky_way] => NightSky {
+                stars,
+                slope,
+                milky_way,
+                ..night
+            },
+            _ => return Err(()),
+        };
+        if night.stars < 0.0 || night.slope <= 0.0 || night.milky_way < 0.0 {
+            return Err(());
+        }
+        Ok(night)
+    }
+}
+
+/// What rays that hit nothing see.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Sky {
+    /// The white to light blue gradient of the book.
+    #[default]
+    Gradient,
+    Atmosphere(Atmosphere),
+    /// The same radiance from every direction.
+    Color(Vector3),
+    /// A dark photo studio with a large softbox above front left, a fill
+    /// light on the right and a rim light behind, as seen from the default
+    /// camera; for judging materials.
+    Studio,
+    /// An equirectangular image of the surroundings in linear sRGB, its
+    /// center looking along -z and its top straight up.
+    Environment(&'static ImageTexture),
+    Night(NightSky),
+}
+
+impl Sky {
+    /// Loads the path of an equirectangular .hdr, .exr or .ppm image as an
+    /// environment map, and parses anything else as usual. `None` means `s`
+    /// is not a sky.
+    pub(crate) fn load(s: &str) -> Result<Option<Self>, String> {
+        let lower = s.to_ascii_lowercase();
+        if [".hdr", ".exr", ".ppm"].iter().any(|e| lower.ends_with(e)) {
+            return ImageTexture::load(s)
+                .map(|image| Some(Sky::Environment(image)))
+                .map_err(|e| format!("could not read environment map {}: {}", s, e));
+        }
+        Ok(s.parse().ok())
+    }
+
+    pub fn radiance(&self, direction: &Vector3) -> Vector3 {
+        match self {
+            Sky::Gradient => {
+                let unit_direction = direction.normalize();
+                let t = (unit_direction.y() + 1.0) * 0.5;
+                Vector3(1.0, 1.0, 1.0) * (1.0 - t) + Vector3(0.5, 0.7, 1.0) * t
+            }
+            Sky::Atmosphere(atmosphere) => atmosphere.sky(direction),
+            Sky::Night(night) => night.sky(direction),
+            Sky::Color(color) => *color,
+            Sky::Studio => {
+                let d = direction.normalize();
+                let wall = 0.04 + 0.1 * d.y().max(0.0);
+                // (direction, angular radius, radiance) of each light
+                let softboxes = [
+                    (Vector3(0.45, 0.89, 0.71), 0.6, 2.0),
+                    (Vector3(0.9, 0.2, -0.5), 0.7, 0.4),
+                    (Vector3(-0.83, 0.25, -0.8), 0.3, 3.0),
+                ];
+                softboxes
+                    .iter()
+                    .filter(|(center, radius, _)| d.dot(&center.normalize()) > f64::cos(*radius))
+                    .fold(Vector3(wall, wall, wall), |acc, (_, _, radiance)| {
+                        acc + Vector3(*radiance, *radiance, *radiance)
+                    })
+            }
+            Sky::Environment(image) => {
+                let d = direction.normalize();
+                let u = 0.5 + d.x().atan2(-d.z()) / (2.0 * std::f64::consts::PI);
+                let v = 0.5 + d.y().clamp(-1.0, 1.0).asin() / std::f64::consts::PI;
+                image.at(u, v)
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for Sky {
+    type Err = ();
+    /// Parses `gradient`, `atmosphere`, `studio`, `black`, a night sky (see
+    /// `NightSky`) or a color `r,g,b`; see `Sky::load` for environment maps.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gradient" => Ok(Sky::Gradient),
+            // the sun direction is taken from the scene's sun light, if any
+            "atmosphere" => Ok(Sky::Atmosphere(Atmosphere::new(Vector3(0.0, 0.5, -1.0)))),
+            "studio" => Ok(Sky::Studio),
+            "black" => Ok(Sky::Color(Vector3(0.0, 0.0, 0.0))),
+            _ if s.starts_with("night") => Ok(Sky::Night(s.parse()?)),
+            _ => Ok(Sky::Color(s.parse()?)),
+        }
+    }
+}
@@ -0,0 +1,620 @@
+//! Textures giving materials colors that vary over their surfaces, and
+//! volumes densities and glow that vary through space.
+
+use crate::color::{srgb_encode, ColorSpace};
+use crate::image::{read_exr, read_hdr, read_ppm};
+use crate::material::reflectance;
+use crate::vec3::{random, Vector3};
+
+/// A color as a function of the texture coordinates and position of a hit.
+#[derive(Clone, Copy, Debug)]
+pub enum Texture {
+    Solid(Vector3),
+    /// A 3D checkerboard of cubes `1 / scale` wide.
+    Checker {
+        even: Vector3,
+        odd: Vector3,
+        scale: f64,
+    },
+    /// Gray marble veins from Perlin turbulence, `scale` setting their
+    /// frequency.
+    Noise {
+        scale: f64,
+    },
+    /// Billowing gray from Perlin turbulence at `scale` times the point, from
+    /// 0 to 1, for smoke and clouds.
+    Turbulence {
+        scale: f64,
+    },
+    /// `from` at point `start` blending into `to` at `end`, along the line
+    /// between them and constant beyond.
+    Ramp {
+        start: Vector3,
+        end: Vector3,
+        from: Vector3,
+        to: Vector3,
+    },
+    /// Values sampled on a 3D grid, black outside it.
+    Grid(&'static VoxelGrid),
+    /// An image over the texture coordinates, stored in linear sRGB and
+    /// converted into `space` on lookup.
+    Image {
+        image: &'static ImageTexture,
+        space: ColorSpace,
+    },
+    /// A node of a shading network, computing its color from other textures.
+    Node(&'static Node),
+    /// The colors painted on the vertices of a mesh, blended across its
+    /// faces and converted into `space`; white on other surfaces.
+    VertexColor {
+        space: ColorSpace,
+    },
+}
+
+/// Nodes of shading networks, built in scene files to combine textures
+/// without a new texture type for each combination.
+#[derive(Clone, Copy, Debug)]
+pub enum Node {
+    /// `op` applied to `a` and `b`, channel by channel.
+    Math { op: MathOp, a: Texture, b: Texture },
+    /// How much of the light a dielectric of index `ir` reflects at the angle
+    /// it is seen from, as gray; Schlick's approximation.
+    Fresnel { ir: f64 },
+    /// `a` blending into `b` as `factor` goes from 0 to 1, channel by channel.
+    Mix {
+        factor: Texture,
+        a: Texture,
+        b: Texture,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MathOp {
+    Add,
+    Subtract,
+    Multiply,
+    /// Division, 0 where `b` is.
+    Divide,
+    Power,
+    Minimum,
+    Maximum,
+}
+
+impl std::str::FromStr for MathOp {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "add" => Ok(MathOp::Add),
+            "subtract" => Ok(MathOp::Subtract),
+            "multiply" => Ok(MathOp::Multiply),
+            "divide" => Ok(MathOp::Divide),
+            "power" => Ok(MathOp::Power),
+            "minimum" => Ok(MathOp::Minimum),
+            "maximum" => Ok(MathOp::Maximum),
+            _ => Err(()),
+        }
+    }
+}
+
+impl MathOp {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            MathOp::Add => a + b,
+            MathOp::Subtract => a - b,
+            MathOp::Multiply => a * b,
+            MathOp::Divide if b == 0.0 => 0.0,
+            MathOp::Divide => a / b,
+            MathOp::Power => a.powf(b),
+            MathOp::Minimum => a.min(b),
+            MathOp::Maximum => a.max(b),
+        }
+    }
+}
+
+impl Node {
+    /// The color at texture coordinates `u`, `v` and point `p` of a surface
+    /// seen at an angle with cosine `cos` to its normal and painted `color`.
+    fn value(&self, u: f64, v: f64, p: &Vector3, cos: f64, color: Vector3) -> Vector3 {
+        let channels = |a: Vector3, b: Vector3, f: &dyn Fn(f64, f64) -> f64| {
+            Vector3(f(a.x(), b.x()), f(a.y(), b.y()), f(a.z(), b.z()))
+        };
+        match self {
+            Node::Math { op, a, b } => channels(
+                a.value_facing(u, v, p, cos, color),
+                b.value_facing(u, v, p, cos, color),
+                &|a, b| op.apply(a, b),
+            ),
+            Node::Fresnel { ir } => {
+                let r = reflectance(cos, *ir);
+                Vector3(r, r, r)
+            }
+            Node::Mix { factor, a, b } => {
+                let f = factor.value_facing(u, v, p, cos, color);
+                let (a, b) = (
+                    a.value_facing(u, v, p, cos, color),
+                    b.value_facing(u, v, p, cos, color),
+                );
+                a + Vector3(
+                    f.x() * (b.x() - a.x()),
+                    f.y() * (b.y() - a.y()),
+                    f.z() * (b.z() - a.z()),
+                )
+            }
+        }
+    }
+}
+
+impl From<Vector3> for Texture {
+    fn from(color: Vector3) -> Self {
+        Texture::Solid(color)
+    }
+}
+
+impl Texture {
+    /// The color at texture coordinates `u`, `v` and point `p`, as seen
+    /// head-on and without vertex colors.
+    pub fn value(&self, u: f64, v: f64, p: &Vector3) -> Vector3 {
+        self.value_facing(u, v, p, 1.0, Vector3(1.0, 1.0, 1.0))
+    }
+
+    /// The color at `u`, `v` and `p` of a surface seen at an angle with
+    /// cosine `cos` to its normal, which only nodes depend on, and painted
+    /// `color` there, which only vertex colors do.
+    pub(crate) fn value_facing(
+        &self,
+        u: f64,
+        v: f64,
+        p: &Vector3,
+        cos: f64,
+        color: Vector3,
+    ) -> Vector3 {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Checker { even, odd, scale } => {
+                let cell =
+                    (p.x() * scale).floor() + (p.y() * scale).floor() + (p.z() * scale).floor();
+                if cell.rem_euclid(2.0) == 0.0 {
+                    *even
+                } else {
+                    *odd
+                }
+            }
+            Texture::Noise { scale } => {
+                let gray = 0.5 * (1.0 + (scale * p.z() + 10.0 * turbulence(*p, 7)).sin());
+                Vector3(gray, gray, gray)
+            }
+            Texture::Turbulence { scale } => {
+                let gray = turbulence(*p * *scale, 7).min(1.0);
+                Vector3(gray, gray, gray)
+            }
+            Texture::Ramp {
+                start,
+                end,
+                from,
+                to,
+            } => {
+                let axis = *end - *start;
+                let t = ((*p - *start).dot(&axis) / axis.length_squared()).clamp(0.0, 1.0);
+                *from * (1.0 - t) + *to * t
+            }
+            Texture::Grid(grid) => grid.at(p),
+            Texture::Image { image, space } => space.from_linear_srgb(image.at(u, v)),
+            Texture::Node(node) => node.value(u, v, p, cos, color),
+            Texture::VertexColor { space } => space.from_linear_srgb(color),
+        }
+    }
+}
+
+/// The image textures whose pixels are in memory, and how many bytes those
+/// take. Once they take more than the budget, the least recently used are
+/// dropped until they fit into three quarters of it, to be read again when
+/// next looked up; whole images, as the formats read hold no tiles or mips.
+struct TextureCache {
+    budget: std::sync::atomic::AtomicUsize,
+    /// Ticks at each image read, and textures note the tick of their last
+    /// lookup, so lookups need not write to anything shared.
+    clock: std::sync::atomic::AtomicU64,
+    loaded: std::sync::Mutex<(Vec<&'static ImageTexture>, usize)>,
+}
+
+static CACHE: TextureCache = TextureCache {
+    budget: std::sync::atomic::AtomicUsize::new(usize::MAX),
+    clock: std::sync::atomic::AtomicU64::new(0),
+    loaded: std::sync::Mutex::new((Vec::new(), 0)),
+};
+
+/// An image texture, in linear sRGB. Its pixels are read when it is first
+/// looked up and kept in a cache under a memory budget, see
+/// `ImageTexture::set_cache_budget`.
+pub struct ImageTexture {
+    path: String,
+    /// Whether PPM values are sRGB colors to decode rather than data.
+    srgb: bool,
+    width: usize,
+    height: usize,
+    /// The tick of the cache clock at the last lookup.
+    used: std::sync::atomic::AtomicU64,
+    pixels: std::sync::RwLock<Option<Vec<Vector3>>>,
+    texels: std::sync::OnceLock<TexelDistribution>,
+}
+
+impl ImageTexture {
+    /// Reads a PPM, Radiance HDR (.hdr) or uncompressed OpenEXR (.exr) image,
+    /// by its extension. Textures live as long as the program so that
+    /// materials and skies can keep referring to them.
+    pub fn load(path: &str) -> std::io::Result<&'static Self> {
+        Self::read(path, true)
+    }
+
+    /// Like `load`, but keeps the values PPM images store instead of decoding
+    /// them as sRGB colors, for data such as normal maps.
+    pub fn load_data(path: &str) -> std::io::Result<&'static Self> {
+        Self::read(path, false)
+    }
+
+    /// Limits the pixels of image textures held in memory to about `bytes`;
+    /// unlimited by default.
+    pub fn set_cache_budget(bytes: usize) {
+        CACHE
+            .budget
+            .store(bytes, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    /// Reads the file once to check it, keeping the pixels in the cache.
+    fn read(path: &str, srgb: bool) -> std::io::Result<&'static Self> {
+        let (width, height, pixels) = Self::read_pixels(path, srgb)?;
+        let texture: &'static Self = Box::leak(Box::new(Self {
+            path: path.to_string(),
+            srgb,
+            width,
+            height,
+            used: Default::default(),
+            pixels: Default::default(),
+            texels: Default::default(),
+        }));
+        texture.keep(pixels);
+        Ok(texture)
+    }
+
+    fn read_pixels(path: &str, srgb: bool) -> std::io::Result<(usize, usize, Vec<Vector3>)> {
+        let extension = std::path::Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        let (width, height, pixels) = match extension.as_deref() {
+            Some("hdr") => read_hdr(path)?,
+            Some("exr") => read_exr(path)?,
+            _ if srgb => read_ppm(path)?,
+            _ => {
+                // undo the decoding of read_ppm
+                let (width, height, pixels) = read_ppm(path)?;
+                let encode = |c: &Vector3| {
+                    Vector3(srgb_encode(c.x()), srgb_encode(c.y()), srgb_encode(c.z()))
+                };
+                (width, height, pixels.iter().map(encode).collect())
+            }
+        };
+        // lookups wrap around the size
+        if width == 0 || height == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "empty image",
+            ));
+        }
+        Ok((width, height, pixels))
+    }
+
+    /// Puts `pixels` in the cache as those of the texture, dropping the least
+    /// recently used others if they no longer fit.
+    fn keep(&'static self, pixels: Vec<Vector3>) {
+        use std::sync::atomic::Ordering::Relaxed;
+        let size = |t: &ImageTexture| t.width * t.height * std::mem::size_of::<Vector3>();
+        // textures only take their pixels' write locks while holding the
+        // cache's lock, so no two can wait for each other
+        let mut loaded = CACHE.loaded.lock().unwrap();
+        let mut slot = self.pixels.write().unwrap();
+        if slot.is_some() {
+            // another thread read it in the meantime
+            return;
+        }
+        *slot = Some(pixels);
+        drop(slot);
+        self.used
+            .store(CACHE.clock.fetch_add(1, Relaxed) + 1, Relaxed);
+        let (textures, bytes) = &mut *loaded;
+        textures.push(self);
+        *bytes += size(self);
+        let budget = CACHE.budget.load(Relaxed);
+        if *bytes > budget {
+            textures.sort_by_key(|t| std::cmp::Reverse(t.used.load(Relaxed)));
+            while *bytes > budget / 4 * 3 && textures.len() > 1 {
+                let old = textures.pop().unwrap();
+                *old.pixels.write().unwrap() = None;
+                *bytes -= size(old);
+            }
+        }
+    }
+
+    /// Bilinearly filtered color at `u`, `v`, repeating outside [0, 1] and
+    /// with v = 0 at the bottom of the image.
+    pub(crate) fn at(&'static self, u: f64, v: f64) -> Vector3 {
+        self.with_pixels(|pixels| self.filtered(pixels, u, v))
+    }
+
+    /// Where the image is bright, for sampling lights glowing through it.
+    pub(crate) fn texels(&'static self) -> &'static TexelDistribution {
+        self.texels.get_or_init(|| {
+            self.with_pixels(|pixels| TexelDistribution::new(self.width, self.height, pixels))
+        })
+    }
+
+    /// `f` of the pixels, read into the cache first if they are not there.
+    fn with_pixels<T>(&'static self, f: impl Fn(&[Vector3]) -> T) -> T {
+        use std::sync::atomic::Ordering::Relaxed;
+        let now = CACHE.clock.load(Relaxed);
+        if self.used.load(Relaxed) != now {
+            self.used.store(now, Relaxed);
+        }
+        loop {
+            if let Some(pixels) = &*self.pixels.read().unwrap() {
+                return f(pixels);
+            }
+            let (_, _, pixels) = Self::read_pixels(&self.path, self.srgb)
+                .unwrap_or_else(|e| panic!("could not read texture {} again: {}", self.path, e));
+            self.keep(pixels);
+        }
+    }
+
+    fn filtered(&self, pixels: &[Vector3], u: f64, v: f64) -> Vector3 {
+        let x = u.rem_euclid(1.0) * self.width as f64 - 0.5;
+        let y = (1.0 - v.rem_euclid(1.0)) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let texel = |x: f64, y: f64| {
+            let x = (x as i64).rem_euclid(self.width as i64) as usize;
+            let y = (y as i64).rem_euclid(self.height as i64) as usize;
+            pixels[y * self.width + x]
+        };
+        let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1.0, y0) * fx;
+        let bottom = texel(x0, y0 + 1.0) * (1.0 - fx) + texel(x0 + 1.0, y0 + 1.0) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+// the file stands for its pixels, which are too many to print
+impl std::fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ImageTexture")
+            .field("path", &self.path)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The texels of an image drawn in proportion to their luminance, as
+/// points on its texture coordinates.
+pub(crate) struct TexelDistribution {
+    width: usize,
+    height: usize,
+    /// Cumulative share of the rows, from the top, ending at 1.
+    rows: Vec<f64>,
+    /// Cumulative share of each texel within its row, ending at 1 in each.
+    columns: Vec<f64>,
+}
+
+impl TexelDistribution {
+    fn new(width: usize, height: usize, pixels: &[Vector3]) -> Self {
+        let luminance = |c: &Vector3| (0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()).max(0.0);
+        let mean = pixels.iter().map(luminance).sum::<f64>() / pixels.len() as f64;
+        // dark texels keep a small chance, as filtering blends them with
+        // bright neighbours
+        let weights: Vec<f64> = match mean {
+            m if m > 0.0 => pixels.iter().map(|c| luminance(c) + 0.01 * m).collect(),
+            _ => vec![1.0; pixels.len()],
+        };
+        let cumulative = |values: &mut [f64]| {
+            let mut sum = 0.0;
+            for value in values.iter_mut() {
+                sum += *value;
+                *value = sum;
+            }
+            values.iter_mut().for_each(|value| *value /= sum);
+        };
+        let mut rows: Vec<f64> = weights.chunks(width).map(|row| row.iter().sum()).collect();
+        cumulative(&mut rows);
+        let mut columns = weights;
+        columns.chunks_mut(width).for_each(cumulative);
+        Self {
+            width,
+            height,
+            rows,
+            columns,
+        }
+    }
+
+    /// Texture coordinates drawn likelier where the image is brighter.
+    pub(crate) fn sample(&self) -> (f64, f64) {
+        let pick = |cumulative: &[f64]| {
+            let r = random();
+            cumulative
+                .partition_point(|&c| c <= r)
+                .min(cumulative.len() - 1)
+        };
+        let y = pick(&self.rows);
+        let x = pick(&self.columns[y * self.width..(y + 1) * self.width]);
+        (
+            (x as f64 + random()) / self.width as f64,
+            1.0 - (y as f64 + random()) / self.height as f64,
+        )
+    }
+
+    /// Density with which `sample` draws `u`, `v`, over the unit square.
+    pub(crate) fn pdf(&self, u: f64, v: f64) -> f64 {
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = (((1.0 - v) * self.height as f64) as usize).min(self.height - 1);
+        let share = |cumulative: &[f64], k: usize| match k {
+            0 => cumulative[0],
+            k => cumulative[k] - cumulative[k - 1],
+        };
+        let row = &self.columns[y * self.width..(y + 1) * self.width];
+        share(&self.rows, y) * share(row, x) * (self.width * self.height) as f64
+    }
+}
+
+/// Values on a regular 3D grid of voxels spanning the box from `min` to
+/// `max`, such as the density or temperature of a simulated fire.
+pub struct VoxelGrid {
+    path: String,
+    resolution: [usize; 3],
+    /// 1 for gray values, 3 for colors.
+    channels: usize,
+    min: Vector3,
+    max: Vector3,
+    /// x varying fastest, then y, then z.
+    values: Vec<f32>,
+}
+
+impl std::fmt::Debug for VoxelGrid {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("VoxelGrid")
+            .field("path", &self.path)
+            .field("resolution", &self.resolution)
+            .field("channels", &self.channels)
+            .field("min", &self.min)
+            .field("max", &self.max)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VoxelGrid {
+    /// Reads a Mitsuba .vol file of 32-bit floats, with one or three
+    /// channels; it lives as long as the program, like image textures.
+    pub fn load(path: &str) -> std::io::Result<&'static Self> {
+        let invalid =
+            |msg: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string());
+        let data = crate::embed::read(path)?;
+        if data.len() < 48 || &data[..4] != b"VOL\x03" {
+            return Err(invalid("not a version 3 .vol file"));
+        }
+        let bytes = |at: usize| [data[at], data[at + 1], data[at + 2], data[at + 3]];
+        let int = |k: usize| i32::from_le_bytes(bytes(4 + 4 * k));
+        let float = |at: usize| f32::from_le_bytes(bytes(at));
+        if int(0) != 1 {
+            return Err(invalid("only 32-bit float grids are supported"));
+        }
+        let (nx, ny, nz, channels) = (int(1), int(2), int(3), int(4));
+        if nx < 1 || ny < 1 || nz < 1 || !(channels == 1 || channels == 3) {
+            return Err(invalid("bad resolution or channel count"));
+        }
+        let resolution = [nx as usize, ny as usize, nz as usize];
+        let count = resolution.iter().product::<usize>() * channels as usize;
+        if data.len() != 48 + 4 * count {
+            return Err(invalid("file size does not match the resolution"));
+        }
+        let corner = |offset: usize| {
+            Vector3(
+                float(offset) as f64,
+                float(offset + 4) as f64,
+                float(offset + 8) as f64,
+            )
+        };
+        Ok(Box::leak(Box::new(Self {
+            path: path.to_string(),
+            resolution,
+            channels: channels as usize,
+            min: corner(24),
+            max: corner(36),
+            values: (0..count).map(|k| float(48 + 4 * k)).collect(),
+        })))
+    }
+
+    /// Trilinearly interpolated value at `p`, with the samples at the voxel
+    /// centers.
+    pub(crate) fn at(&self, p: &Vector3) -> Vector3 {
+        let size = self.max - self.min;
+        let local = *p - self.min;
+        let coordinate = |t: f64, size: f64, n: usize| t / size * n as f64 - 0.5;
+        let c = [
+            coordinate(local.x(), size.x(), self.resolution[0]),
+            coordinate(local.y(), size.y(), self.resolution[1]),
+            coordinate(local.z(), size.z(), self.resolution[2]),
+        ];
+        if (0..3).any(|k| c[k] < -0.5 || c[k] > self.resolution[k] as f64 - 0.5) {
+            return Vector3(0.0, 0.0, 0.0);
+        }
+        let voxel = |x: usize, y: usize, z: usize| {
+            let [nx, ny, _] = self.resolution;
+            let at = ((z * ny + y) * nx + x) * self.channels;
+            match self.channels {
+                1 => {
+                    let v = self.values[at] as f64;
+                    Vector3(v, v, v)
+                }
+                _ => Vector3(
+                    self.values[at] as f64,
+                    self.values[at + 1] as f64,
+                    self.values[at + 2] as f64,
+                ),
+            }
+        };
+        let mut sum = Vector3(0.0, 0.0, 0.0);
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0; 3];
+            for k in 0..3 {
+                let low = c[k].floor().max(0.0);
+                let f = (c[k] - low).clamp(0.0, 1.0);
+                let high = corner >> k & 1 == 1;
+                index[k] = (low as usize + high as usize).min(self.resolution[k] - 1);
+                weight *= if high { f } else { 1.0 - f };
+            }
+            sum += voxel(index[0], index[1], index[2]) * weight;
+        }
+        sum
+    }
+}
+
+/// Deterministic pseudo-random unit vector for a lattice point.
+fn gradient(x: i64, y: i64, z: i64) -> Vector3 {
+    let mut h = (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+        ^ (z as u64).wrapping_mul(0x1656_67B1_9E37_79F9);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+    h ^= h >> 33;
+    // a uniformly distributed direction from two 32-bit halves
+    let cos_theta = (h >> 32) as f64 / (1u64 << 32) as f64 * 2.0 - 1.0;
+    let phi = (h & 0xFFFF_FFFF) as f64 / (1u64 << 32) as f64 * 2.0 * std::f64::consts::PI;
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    Vector3(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+/// Perlin gradient noise in about [-1, 1].
+fn perlin(p: Vector3) -> f64 {
+    let (x0, y0, z0) = (p.x().floor(), p.y().floor(), p.z().floor());
+    let f = Vector3(p.x() - x0, p.y() - y0, p.z() - z0);
+    let smooth = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+    let (sx, sy, sz) = (smooth(f.x()), smooth(f.y()), smooth(f.z()));
+    let mut sum = 0.0;
+    for (dx, dy, dz) in (0..8).map(|k| (k & 1, (k >> 1) & 1, k >> 2)) {
+        let corner = Vector3(dx as f64, dy as f64, dz as f64);
+        let weight = (if dx == 1 { sx } else { 1.0 - sx })
+            * (if dy == 1 { sy } else { 1.0 - sy })
+            * (if dz == 1 { sz } else { 1.0 - sz });
+        let g = gradient(x0 as i64 + dx, y0 as i64 + dy, z0 as i64 + dz);
+        sum += weight * g.dot(&(f - corner));
+    }
+    sum
+}
+
+/// Absolute value of `octaves` layers of Perlin noise, each at twice the
+/// frequency and half the amplitude of the previous one.
+pub(crate) fn turbulence(p: Vector3, octaves: u32) -> f64 {
+    (0..octaves)
+        .fold((0.0, p, 1.0), |(sum, p, weight), _| {
+            (sum + weight * perlin(p), p * 2.0, weight * 0.5)
+        })
+        .0
+        .abs()
+}
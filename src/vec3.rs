@@ -0,0 +1,225 @@
+//! Vectors, points and colors.
+
+use crate::color::srgb_encode;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::cell::RefCell;
+use std::io::Write;
+
+#[derive(Default, Copy, Clone, Debug)]
+pub struct Vector3(pub f64, pub f64, pub f64);
+
+impl Vector3 {
+    pub fn random() -> Self {
+        Vector3(random(), random(), random())
+    }
+
+    pub fn random_in_range(min: f64, max: f64) -> Self {
+        Vector3(
+            random_in_range(min, max),
+            random_in_range(min, max),
+            random_in_range(min, max),
+        )
+    }
+
+    pub fn random_in_unit_sphere() -> Self {
+        loop {
+            let p = Vector3::random_in_range(-1.0, 1.0);
+            if p.length_squared() < 1.0 {
+                return p;
+            }
+        }
+    }
+
+    pub fn random_unit_vector() -> Self {
+        Self::random_in_unit_sphere().normalize()
+    }
+
+    pub fn random_in_unit_disk() -> Self {
+        loop {
+            let p = Vector3(random_in_range(-1.0, 1.0), random_in_range(-1.0, 1.0), 0.0);
+            if p.length_squared() < 1.0 {
+                return p;
+            }
+        }
+    }
+
+    pub fn near_zero(&self) -> bool {
+        let s = 1e-8;
+        self.x().abs() < s && self.y().abs() < s && self.z().abs() < s
+    }
+
+    pub fn reflect(&self, n: &Vector3) -> Vector3 {
+        *self - (*n * self.dot(n) * 2.0)
+    }
+
+    pub fn refract(&self, n: &Vector3, etai_over_etat: f64) -> Vector3 {
+        let cos_theta = (*self * -1.0).dot(n).min(1.0);
+        let r_out_perp = (*self + *n * cos_theta) * etai_over_etat;
+        let r_out_parallel = *n * (1.0 - r_out_perp.length_squared()).abs().sqrt() * -1.0;
+        r_out_perp + r_out_parallel
+    }
+
+    pub fn x(&self) -> f64 {
+        self.0
+    }
+    pub fn y(&self) -> f64 {
+        self.1
+    }
+    pub fn z(&self) -> f64 {
+        self.2
+    }
+
+    pub fn length_squared(&self) -> f64 {
+        self.x() * self.x() + self.y() * self.y() + self.z() * self.z()
+    }
+
+    pub fn length(&self) -> f64 {
+        self.length_squared().sqrt()
+    }
+
+    pub fn dot(&self, rhs: &Self) -> f64 {
+        self.0 * rhs.0 + self.1 * rhs.1 + self.2 * rhs.2
+    }
+
+    pub fn cross(&self, rhs: &Self) -> Self {
+        Self(
+            self.1 * rhs.2 - self.2 * rhs.1,
+            self.2 * rhs.0 - self.0 * rhs.2,
+            self.0 * rhs.1 - self.1 * rhs.0,
+        )
+    }
+
+    pub fn normalize(self) -> Self {
+        let len = self.length();
+        self / len
+    }
+
+    pub(crate) fn write_color(
+        &self,
+        mut f: impl Write,
+        samples_per_pixel: usize,
+        gamma: bool,
+    ) -> std::io::Result<()> {
+        let [r, g, b] = self.to_rgb8(samples_per_pixel, gamma);
+        writeln!(f, "{} {} {}", r, g, b)
+    }
+
+    /// The 8-bit display value of an accumulated color, sRGB encoded if
+    /// `gamma` is set.
+    pub(crate) fn to_rgb8(self, samples_per_pixel: usize, gamma: bool) -> [u8; 3] {
+        let scale = 1.0 / samples_per_pixel as f64;
+        let encode = |c: f64| {
+            let c = if gamma {
+                srgb_encode((c * scale).max(0.0))
+            } else {
+                c * scale
+            };
+            (256.0 * c.clamp(0.0, 0.999)) as u8
+        };
+        [encode(self.0), encode(self.1), encode(self.2)]
+    }
+}
+
+impl std::str::FromStr for Vector3 {
+    type Err = ();
+    /// Parses `x,y,z`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let c: Vec<f64> = s
+            .split(',')
+            .map(|c| c.trim().parse().map_err(|_| ()))
+            .collect::<Result<_, _>>()?;
+        match c[..] {
+            [x, y, z] => Ok(Vector3(x, y, z)),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::ops::Add for Vector3 {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(self.0 + rhs.0, self.1 + rhs.1, self.2 + rhs.2)
+    }
+}
+
+impl std::ops::AddAssign for Vector3 {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = Self(self.0 + rhs.0, self.1 + rhs.1, self.2 + rhs.2)
+    }
+}
+
+impl std::ops::Sub for Vector3 {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self(self.0 - rhs.0, self.1 - rhs.1, self.2 - rhs.2)
+    }
+}
+
+impl std::ops::SubAssign for Vector3 {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = Self(self.0 - rhs.0, self.1 - rhs.1, self.2 - rhs.2)
+    }
+}
+
+impl std::ops::Mul for Vector3 {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self(self.0 * rhs.0, self.1 * rhs.1, self.2 * rhs.2)
+    }
+}
+
+impl std::ops::Mul<f64> for Vector3 {
+    type Output = Self;
+    fn mul(self, rhs: f64) -> Self::Output {
+        Self(self.0 * rhs, self.1 * rhs, self.2 * rhs)
+    }
+}
+
+impl std::ops::MulAssign<f64> for Vector3 {
+    fn mul_assign(&mut self, rhs: f64) {
+        *self = Self(self.0 * rhs, self.1 * rhs, self.2 * rhs)
+    }
+}
+
+impl std::ops::Div<f64> for Vector3 {
+    type Output = Self;
+    fn div(self, rhs: f64) -> Self::Output {
+        Self(self.0 / rhs, self.1 / rhs, self.2 / rhs)
+    }
+}
+
+impl std::ops::DivAssign<f64> for Vector3 {
+    fn div_assign(&mut self, rhs: f64) {
+        *self = Self(self.0 / rhs, self.1 / rhs, self.2 / rhs)
+    }
+}
+
+thread_local! {
+    // every random number comes from here, seeded from the OS until `reseed`
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+/// Restarts the random numbers of the current thread from `seed`, so the
+/// same work done after it comes out the same on any thread.
+pub fn reseed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+/// A seed for the `index`th piece of work under `seed`, such as one pixel of
+/// a pass; nearby indices give unrelated seeds.
+pub fn derive_seed(seed: u64, index: u64) -> u64 {
+    // SplitMix64
+    let mut z = seed ^ index.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+pub fn random() -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
+
+pub fn random_in_range(min: f64, max: f64) -> f64 {
+    min + (max - min) * random()
+}
@@ -0,0 +1,231 @@
+//! A window showing the image while it renders, with the `window` feature.
+
+use crate::vec3::Vector3;
+
+/// Largest initial window size; bigger images are shown scaled down.
+#[cfg(feature = "window")]
+const MAX_WINDOW_SIZE: (usize, usize) = (1280, 800);
+
+#[cfg(feature = "window")]
+pub(crate) struct PreviewWindow {
+    window: minifb::Window,
+    width: usize,
+    height: usize,
+    buffer: Vec<u32>,
+    /// The rectangle last dragged over the image, see `focus`.
+    focus: Option<(u32, u32, u32, u32)>,
+    depth_of_field: Option<DepthOfField>,
+}
+
+/// The overlay of what is in focus, see `PreviewWindow::set_depth_of_field`.
+#[cfg(feature = "window")]
+struct DepthOfField {
+    /// Blur diameter of each pixel, see `focus_blur`.
+    blur: Vec<f64>,
+    /// Largest blur, in pixels, that counts as sharp.
+    limit: f64,
+    /// Nearest and farthest sharp distances along the view axis.
+    range: (f64, f64),
+    shown: bool,
+}
+
+#[cfg(feature = "window")]
+impl PreviewWindow {
+    pub(crate) fn open(width: u32, height: u32) -> Result<Self, String> {
+        let (width, height) = (width as usize, height as usize);
+        let scale = f64::min(
+            1.0,
+            f64::min(
+                MAX_WINDOW_SIZE.0 as f64 / width as f64,
+                MAX_WINDOW_SIZE.1 as f64 / height as f64,
+            ),
+        );
+        let options = minifb::WindowOptions {
+            resize: true,
+            scale_mode: minifb::ScaleMode::AspectRatioStretch,
+            ..minifb::WindowOptions::default()
+        };
+        let window = minifb::Window::new(
+            "raytracer",
+            ((width as f64 * scale) as usize).max(1),
+            ((height as f64 * scale) as usize).max(1),
+            options,
+        )
+        .map_err(|e| e.to_string())?;
+        Ok(Self {
+            window,
+            width,
+            height,
+            buffer: vec![0; width * height],
+            focus: None,
+            depth_of_field: None,
+        })
+    }
+
+    /// Sets up an overlay tinting pixels blurred less than `limit` pixels
+    /// green and the focal plane yellow, from the `blur` of each pixel and
+    /// the `range` of sharp distances. F turns it on and off; `shown` is
+    /// whether it starts on.
+    pub(crate) fn set_depth_of_field(
+        &mut self,
+        blur: Vec<f64>,
+        limit: f64,
+        range: (f64, f64),
+        shown: bool,
+    ) {
+        self.depth_of_field = Some(DepthOfField {
+            blur,
+            limit,
+            range,
+            shown,
+        });
+    }
+
+    /// Shows `pixels`, sums of `samples` samples, top row first. Returns
+    /// whether the window is still open; closing it or pressing Escape asks
+    /// to stop rendering.
+    ///
+    /// Holding the left mouse button pauses to drag a rectangle over the
+    /// image, which becomes the `focus`; the right button clears it.
+    pub(crate) fn show(&mut self, pixels: &[Vector3], samples: usize, gamma: bool) -> bool {
+        for (out, pixel) in self.buffer.iter_mut().zip(pixels) {
+            let [r, g, b] = pixel.to_rgb8(samples, gamma);
+            *out = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        }
+        let mut title = format!("raytracer: {} samples per pixel", samples);
+        if let Some(DepthOfField {
+            range: (near, far),
+            shown: true,
+            ..
+        }) = self.depth_of_field
+        {
+            title += &format!(", sharp from {:.2} to {:.2}", near, far);
+        }
+        self.window.set_title(&title);
+        if !self.update(self.focus) {
+            return false;
+        }
+        if self
+            .window
+            .is_key_pressed(minifb::Key::F, minifb::KeyRepeat::No)
+        {
+            if let Some(depth_of_field) = &mut self.depth_of_field {
+                depth_of_field.shown = !depth_of_field.shown;
+                self.update(self.focus);
+            }
+        }
+        if self.window.get_mouse_down(minifb::MouseButton::Right) {
+            self.focus = None;
+        }
+        if let Some(start) = self.mouse() {
+            let mut end = start;
+            while self.window.get_mouse_down(minifb::MouseButton::Left) {
+                end = self.mouse().unwrap_or(end);
+                if !self.update(Some(rectangle(start, end))) {
+                    return false;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(16));
+            }
+            let (x0, y0, x1, y1) = rectangle(start, end);
+            self.focus = (x1 > x0 + 1 && y1 > y0 + 1).then_some((x0, y0, x1, y1));
+            self.update(self.focus);
+        }
+        self.window.is_open() && !self.window.is_key_down(minifb::Key::Escape)
+    }
+
+    /// The rectangle, x0, y0, x1, y1 in image pixels with exclusive upper
+    /// bounds, to sample more than the rest.
+    pub(crate) fn focus(&self) -> Option<(u32, u32, u32, u32)> {
+        self.focus
+    }
+
+    /// Shows the buffer with the outline of `rectangle` over it.
+    fn update(&mut self, rectangle: Option<(u32, u32, u32, u32)>) -> bool {
+        let mut shown = self.buffer.clone();
+        if let Some(dof) = self.depth_of_field.as_ref().filter(|dof| dof.shown) {
+            // halfway to the tint
+            let tint =
+                |pixel: u32, color: u32| ((pixel >> 1) & 0x7f7f7f) + ((color >> 1) & 0x7f7f7f);
+            for (pixel, blur) in shown.iter_mut().zip(&dof.blur) {
+                if blur.abs() < dof.limit / 4.0 {
+                    *pixel = tint(*pixel, 0xffff00);
+                } else if blur.abs() < dof.limit {
+                    *pixel = tint(*pixel, 0x00ff00);
+                }
+            }
+        }
+        if let Some((x0, y0, x1, y1)) = rectangle {
+            let (x0, y0, x1, y1) = (x0 as usize, y0 as usize, x1 as usize - 1, y1 as usize - 1);
+            for x in x0..=x1 {
+                shown[y0 * self.width + x] = 0xffff00;
+                shown[y1 * self.width + x] = 0xffff00;
+            }
+            for y in y0..=y1 {
+                shown[y * self.width + x0] = 0xffff00;
+                shown[y * self.width + x1] = 0xffff00;
+            }
+        }
+        self.window
+            .update_with_buffer(&shown, self.width, self.height)
+            .is_ok()
+    }
+
+    /// The image pixel under the mouse if the left button is down; the image
+    /// is fitted into the window keeping its aspect ratio, centered.
+    fn mouse(&self) -> Option<(u32, u32)> {
+        if !self.window.get_mouse_down(minifb::MouseButton::Left) {
+            return None;
+        }
+        let (mx, my) = self.window.get_mouse_pos(minifb::MouseMode::Clamp)?;
+        let (ww, wh) = self.window.get_size();
+        let scale = f64::min(
+            ww as f64 / self.width as f64,
+            wh as f64 / self.height as f64,
+        );
+        let x = (mx as f64 - (ww as f64 - self.width as f64 * scale) / 2.0) / scale;
+        let y = (my as f64 - (wh as f64 - self.height as f64 * scale) / 2.0) / scale;
+        let clamp = |v: f64, size: usize| v.max(0.0).min(size as f64 - 1.0) as u32;
+        Some((clamp(x, self.width), clamp(y, self.height)))
+    }
+}
+
+/// The pixels between two corners, both included.
+#[cfg(feature = "window")]
+fn rectangle(a: (u32, u32), b: (u32, u32)) -> (u32, u32, u32, u32) {
+    (
+        a.0.min(b.0),
+        a.1.min(b.1),
+        a.0.max(b.0) + 1,
+        a.1.max(b.1) + 1,
+    )
+}
+
+/// Stands in for the window in builds without the `window` feature; it can
+/// never be opened.
+#[cfg(not(feature = "window"))]
+pub(crate) enum PreviewWindow {}
+
+#[cfg(not(feature = "window"))]
+impl PreviewWindow {
+    pub(crate) fn open(_width: u32, _height: u32) -> Result<Self, String> {
+        Err("this build has no window support, rebuild with --features window".to_string())
+    }
+
+    pub(crate) fn show(&mut self, _pixels: &[Vector3], _samples: usize, _gamma: bool) -> bool {
+        match *self {}
+    }
+
+    pub(crate) fn focus(&self) -> Option<(u32, u32, u32, u32)> {
+        match *self {}
+    }
+
+    pub(crate) fn set_depth_of_field(
+        &mut self,
+        _blur: Vec<f64>,
+        _limit: f64,
+        _range: (f64, f64),
+        _shown: bool,
+    ) {
+        match *self {}
+    }
+}
@@ -0,0 +1,26 @@
+//! Frames rendered at a frame rate show the scene at their own time.
+
+mod common;
+
+use common::{parse_ppm, render, run, TempDir};
+
+#[test]
+fn frames_advance_the_time() {
+    let dir = TempDir::new("animation");
+    let args = [
+        "--scene", "ocean", "--width", "48", "--height", "27", "--spp", "2", "--seed", "5",
+    ];
+    let output = dir.path("wave.ppm");
+    let frames: Vec<&str> = args
+        .iter()
+        .copied()
+        .chain(["--fps", "4", "--frames", "0,1", "-o", &output])
+        .collect();
+    run(&frames);
+    let frame = |k: u32| {
+        parse_ppm(&std::fs::read_to_string(dir.path(&format!("wave_{:04}.ppm", k))).unwrap())
+    };
+    let at_quarter: Vec<&str> = args.iter().copied().chain(["--time", "0.25"]).collect();
+    assert_eq!(frame(1), render(&at_quarter));
+    assert_ne!(frame(0), frame(1), "the waves stood still");
+}
@@ -0,0 +1,58 @@
+//! Checkpoints of the same scene and settings rendered with different seeds,
+//! as on a render farm, must merge and resume into the image, whether or not
+//! previews were rendered first.
+
+mod common;
+
+use common::{render, run, TempDir};
+
+#[test]
+fn seeds_merge_and_resume() {
+    let dir = TempDir::new("checkpoint");
+    // a displaced mesh, whose patches are built lazily while rendering
+    let scene = dir.write(
+        "displaced.toml",
+        &format!(
+            r#"
+[[objects]]
+type = "displaced"
+file = "{}/scenes/models/cube.obj"
+height = 0.1
+frequency = 4.0
+subdivisions = 4
+cache_mb = 1
+material = {{ type = "diffuse", albedo = [0.6, 0.45, 0.3] }}
+"#,
+            env!("CARGO_MANIFEST_DIR")
+        ),
+    );
+    let preview = dir.path("preview.ppm");
+    let (a, b, merged) = (
+        dir.path("a.ckpt"),
+        dir.path("b.ckpt"),
+        dir.path("merged.ckpt"),
+    );
+    let render_to = |checkpoint: &str, samples: &str, extra: &[&str]| {
+        let mut args = vec![
+            "--scene-file",
+            &scene,
+            "--width",
+            "32",
+            "--height",
+            "18",
+            "--preview-file",
+            &preview,
+            "--checkpoint",
+            checkpoint,
+            "--spp",
+            samples,
+        ];
+        args.extend_from_slice(extra);
+        render(&args)
+    };
+    render_to(&a, "2", &["--seed", "1", "--progressive"]);
+    render_to(&b, "2", &["--seed", "2"]);
+    run(&["merge", &a, &b, "-o", &merged]);
+    let (width, height, _) = render_to(&merged, "4", &["--resume", "--progressive"]);
+    assert_eq!((width, height), (32, 18));
+}
@@ -0,0 +1,79 @@
+//! Helpers shared by the tests that run the binary.
+
+// each test file uses some of them
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+/// A directory for the scene files of one test, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("raytracer-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// The path of `file` in the directory.
+    pub fn path(&self, file: &str) -> String {
+        self.0.join(file).to_str().unwrap().to_string()
+    }
+
+    /// Writes `contents` to `file` in the directory and returns its path.
+    pub fn write(&self, file: &str, contents: &str) -> String {
+        let path = self.path(file);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs the binary, which must succeed, and returns what it wrote to stdout.
+pub fn run(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_raytracer"))
+        .args(args)
+        .output()
+        .expect("failed to run the raytracer");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Runs the binary, which must fail with an error rather than a panic, and
+/// returns what it wrote to stderr.
+pub fn fail(args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_raytracer"))
+        .args(args)
+        .output()
+        .expect("failed to run the raytracer");
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    assert_eq!(output.status.code(), Some(1), "{}", stderr);
+    stderr
+}
+
+/// Renders with the binary and returns width, height and 8-bit RGB values.
+pub fn render(args: &[&str]) -> (usize, usize, Vec<u8>) {
+    parse_ppm(&run(args))
+}
+
+pub fn parse_ppm(text: &str) -> (usize, usize, Vec<u8>) {
+    let mut values = text
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(str::split_whitespace)
+        .skip(1);
+    let mut next = || values.next().unwrap().parse::<usize>().unwrap();
+    let (width, height, _max) = (next(), next(), next());
+    let pixels = (0..width * height * 3).map(|_| next() as u8).collect();
+    (width, height, pixels)
+}
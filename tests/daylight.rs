@@ -0,0 +1,133 @@
+//! Checks the sky of the `daylight` scene against published clear-sky data,
+//! through the `query` subcommand.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Direction towards the sun in the `daylight` scene, 45 degrees high.
+const SUN: [f64; 3] = [0.5, 1.0, 0.866];
+
+/// Angular radius of the solar disk.
+const SUN_RADIUS: f64 = 0.2665 * std::f64::consts::PI / 180.0;
+
+/// Luminance in cd/m² a camera ray sees in each of `directions`.
+fn sky_luminance(directions: &[[f64; 3]]) -> Vec<f64> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_raytracer"))
+        .args(["query", "--scene", "daylight"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run the raytracer");
+    let mut stdin = child.stdin.take().unwrap();
+    for [x, y, z] in directions {
+        writeln!(stdin, "sky {},{},{}", x, y, z).unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| line.split_whitespace().nth(1).unwrap().parse().unwrap())
+        .collect()
+}
+
+fn normalize([x, y, z]: [f64; 3]) -> [f64; 3] {
+    let length = (x * x + y * y + z * z).sqrt();
+    [x / length, y / length, z / length]
+}
+
+/// The direction `angle` radians away from the sun, turned by `around`
+/// radians about it.
+fn around_sun(angle: f64, around: f64) -> [f64; 3] {
+    let [sx, sy, sz] = normalize(SUN);
+    // two unit vectors perpendicular to the sun direction
+    let [ux, uy, uz] = normalize([sz, 0.0, -sx]);
+    let (vx, vy, vz) = (sy * uz - sz * uy, sz * ux - sx * uz, sx * uy - sy * ux);
+    let (sin, cos) = angle.sin_cos();
+    let (a, b) = (sin * around.cos(), sin * around.sin());
+    [
+        sx * cos + ux * a + vx * b,
+        sy * cos + uy * a + vy * b,
+        sz * cos + uz * a + vz * b,
+    ]
+}
+
+#[test]
+fn solar_disk_matches_published_luminance() {
+    // average over rings of equal area
+    let rings = 20;
+    let directions: Vec<[f64; 3]> = (0..rings)
+        .flat_map(|i| {
+            let r = SUN_RADIUS * ((i as f64 + 0.5) / rings as f64).sqrt();
+            (0..12).map(move |k| around_sun(r, k as f64 * std::f64::consts::PI / 6.0))
+        })
+        .collect();
+    let luminance = sky_luminance(&directions);
+    let mean = luminance.iter().sum::<f64>() / luminance.len() as f64;
+    // about 1.6e9 cd/m² for the sun high in a clear sky
+    assert!(
+        (mean / 1.6e9 - 1.0).abs() < 0.25,
+        "mean solar luminance {}",
+        mean
+    );
+
+    // limb darkening halfway to the rim in cos(angle on the solar surface);
+    // Pierce and Slaughter (1977) give 0.72 at 550 nm
+    let ratio = sky_luminance(&[around_sun(SUN_RADIUS * 0.75f64.sqrt(), 0.0)])[0]
+        / sky_luminance(&[normalize(SUN)])[0];
+    assert!((ratio - 0.72).abs() < 0.05, "limb darkening {}", ratio);
+
+    let outside = sky_luminance(&[around_sun(SUN_RADIUS * 1.1, 0.0)])[0];
+    assert!(outside < 1e6, "sky next to the sun {}", outside);
+}
+
+/// Relative luminance of the CIE standard clear sky (ISO 15469, type 12) at
+/// zenith angle `z` and azimuth `azimuth` from the sun, with the sun at zenith
+/// angle `zs`, normalized to the zenith.
+fn cie_clear_sky(z: f64, azimuth: f64, zs: f64) -> f64 {
+    let (a, b, c, d, e) = (-1.0, -0.32, 10.0, -3.0, 0.45);
+    let gradation = |z: f64| 1.0 + a * (b / z.cos()).exp();
+    let indicatrix = |chi: f64| {
+        1.0 + c * ((d * chi).exp() - (d * std::f64::consts::FRAC_PI_2).exp())
+            + e * chi.cos().powi(2)
+    };
+    let chi = (zs.cos() * z.cos() + zs.sin() * z.sin() * azimuth.cos())
+        .clamp(-1.0, 1.0)
+        .acos();
+    indicatrix(chi) * gradation(z) / (indicatrix(zs) * gradation(0.0))
+}
+
+#[test]
+fn sky_distribution_matches_cie_clear_sky() {
+    let [sx, sy, sz] = normalize(SUN);
+    let zs = sy.acos();
+    let sun_azimuth = sx.atan2(sz);
+    let mut samples = vec![(0.0, 0.0)];
+    for z in [15.0f64, 30.0, 45.0, 60.0] {
+        for azimuth in (0..360).step_by(30) {
+            samples.push((z.to_radians(), (azimuth as f64).to_radians()));
+        }
+    }
+    let directions: Vec<[f64; 3]> = samples
+        .iter()
+        .map(|&(z, azimuth)| {
+            let azimuth = sun_azimuth + azimuth;
+            [z.sin() * azimuth.sin(), z.cos(), z.sin() * azimuth.cos()]
+        })
+        .collect();
+    let luminance = sky_luminance(&directions);
+    let zenith = luminance[0];
+    // compare on a log scale, skipping the solar disk
+    let errors: Vec<f64> = samples
+        .iter()
+        .zip(&luminance)
+        .filter(|((z, azimuth), _)| (z - zs).abs() > 0.01 || *azimuth != 0.0)
+        .map(|(&(z, azimuth), l)| (l / zenith / cie_clear_sky(z, azimuth, zs)).ln())
+        .collect();
+    let rms = (errors.iter().map(|e| e * e).sum::<f64>() / errors.len() as f64).sqrt();
+    let max = errors.iter().fold(0.0f64, |m, e| m.max(e.abs()));
+    // single scattering brightens the sky opposite the sun somewhat too much
+    assert!(rms < 0.2, "RMS log deviation {}", rms);
+    assert!(max < 0.4, "largest log deviation {}", max);
+}
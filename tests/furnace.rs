@@ -0,0 +1,60 @@
+//! White furnace test: white objects under a uniform sky send on all the
+//! light they receive, so they must vanish against it. A material that loses
+//! energy, like a rough metal absorbing what it scatters below its surface,
+//! shows as a darker shape.
+
+mod common;
+
+use common::TempDir;
+
+/// Renders a scene file with the binary and returns width, height and 8-bit
+/// RGB values.
+fn render_scene(scene: &str) -> (usize, usize, Vec<u8>) {
+    let dir = TempDir::new("furnace");
+    let path = dir.write("furnace.toml", scene);
+    common::render(&["--scene-file", &path, "--seed", "1"])
+}
+
+#[test]
+fn white_objects_vanish_in_the_furnace() {
+    let (width, height, pixels) = render_scene(
+        r#"
+[camera]
+from = [0.0, 0.0, 10.0]
+at = [0.0, 0.0, 0.0]
+vfov = 30.0
+aperture = 0.0
+
+[render]
+width = 96
+samples = 16
+background = [0.5, 0.5, 0.5]
+
+[[objects]]
+type = "sphere"
+center = [-2.2, 0.0, 0.0]
+radius = 1.0
+material = { type = "diffuse", albedo = [1.0, 1.0, 1.0] }
+
+[[objects]]
+type = "sphere"
+center = [0.0, 0.0, 0.0]
+radius = 1.0
+material = { type = "metal", albedo = [1.0, 1.0, 1.0], fuzz = 0.5 }
+
+[[objects]]
+type = "sphere"
+center = [2.2, 0.0, 0.0]
+radius = 1.0
+material = { type = "metal", albedo = [1.0, 1.0, 1.0], fuzz = 1.0 }
+"#,
+    );
+    let background = pixels[0];
+    let darkest = (0..width * height).map(|i| pixels[3 * i]).min().unwrap();
+    assert!(
+        background - darkest <= 2,
+        "background {}, darkest pixel {}",
+        background,
+        darkest
+    );
+}
@@ -0,0 +1,139 @@
+//! Regression tests for the dielectrics: refraction, absorption and nested
+//! volumes resolved by priority, which are easy to break without noticing.
+
+mod common;
+
+use common::{parse_ppm, render, TempDir};
+use std::path::Path;
+
+/// Side of the square blocks of pixels compared between renders, large
+/// enough to average out the noise of a few samples per pixel.
+const BLOCK: usize = 8;
+
+/// The largest difference of a block's mean color between two images, in
+/// 8-bit steps.
+fn block_difference(a: &(usize, usize, Vec<u8>), b: &(usize, usize, Vec<u8>)) -> f64 {
+    assert_eq!((a.0, a.1), (b.0, b.1), "image sizes differ");
+    let (width, height) = (a.0, a.1);
+    let mut largest = 0.0f64;
+    for y0 in (0..height - BLOCK + 1).step_by(BLOCK) {
+        for x0 in (0..width - BLOCK + 1).step_by(BLOCK) {
+            for c in 0..3 {
+                let sum = |pixels: &[u8]| -> f64 {
+                    (y0..y0 + BLOCK)
+                        .flat_map(|y| (x0..x0 + BLOCK).map(move |x| (y * width + x) * 3 + c))
+                        .map(|i| pixels[i] as f64)
+                        .sum()
+                };
+                let difference = (sum(&a.2) - sum(&b.2)).abs() / (BLOCK * BLOCK) as f64;
+                largest = largest.max(difference);
+            }
+        }
+    }
+    largest
+}
+
+/// Renders of the same scene with different seeds stay within this of each
+/// other block by block; losing the absorption, the priorities or the
+/// relative indices of refraction moves blocks of the glass of water by 10
+/// to 40.
+const TOLERANCE: f64 = 6.0;
+
+#[test]
+fn glass_of_water_matches_reference() {
+    let image = render(&[
+        "--scene",
+        "glass-of-water",
+        "--seed",
+        "1",
+        "--width",
+        "96",
+        "--spp",
+        "32",
+    ]);
+    let reference =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/reference/glass_of_water.ppm");
+    // after an intended change to how glass looks, write a new reference
+    // with UPDATE_REFERENCE=1 cargo test
+    if std::env::var_os("UPDATE_REFERENCE").is_some() {
+        let (width, height, pixels) = &image;
+        let mut text = format!("P3\n{} {}\n255\n", width, height);
+        for rgb in pixels.chunks(3) {
+            text += &format!("{} {} {}\n", rgb[0], rgb[1], rgb[2]);
+        }
+        std::fs::write(&reference, text).unwrap();
+    }
+    let expected = parse_ppm(&std::fs::read_to_string(&reference).unwrap());
+    let difference = block_difference(&image, &expected);
+    assert!(
+        difference < TOLERANCE,
+        "largest block difference {}",
+        difference
+    );
+}
+
+#[test]
+fn equal_indices_hide_the_surface_between_nested_volumes() {
+    // a ball of the same glass inside a glass ball, filling part of it by
+    // priority, must not show
+    let dir = TempDir::new("glass");
+    let scene = |inner: &str| {
+        format!(
+            r#"
+[camera]
+from = [0.0, 1.0, 5.0]
+at = [0.0, 1.0, 0.0]
+vfov = 30.0
+aperture = 0.0
+focus = 5.0
+
+[materials]
+glass = {{ type = "glass", ir = 1.5, absorption = [0.3, 0.1, 0.2] }}
+
+[[objects]]
+type = "sphere"
+center = [0.0, -1000.0, 0.0]
+radius = 1000.0
+material = {{ type = "diffuse", albedo = {{ type = "checker", even = [0.9, 0.9, 0.9], odd = [0.1, 0.1, 0.1], scale = 4.0 }} }}
+
+[[objects]]
+type = "sphere"
+center = [0.0, 1.0, 0.0]
+radius = 1.0
+material = "glass"
+priority = 1
+{}"#,
+            inner
+        )
+    };
+    let single = dir.write("single.toml", &scene(""));
+    let inner = r#"
+[[objects]]
+type = "sphere"
+center = [0.3, 1.2, 0.0]
+radius = 0.6
+material = "glass"
+priority = 2
+"#;
+    let nested = dir.write("nested.toml", &scene(inner));
+    let args = |path: &str| {
+        render(&[
+            "--scene-file",
+            path,
+            "--seed",
+            "1",
+            "--width",
+            "64",
+            "--height",
+            "64",
+            "--spp",
+            "32",
+        ])
+    };
+    let difference = block_difference(&args(&single), &args(&nested));
+    assert!(
+        difference < TOLERANCE,
+        "largest block difference {}",
+        difference
+    );
+}
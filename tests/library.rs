@@ -0,0 +1,192 @@
+//! Renders a scene built through the library API rather than the binary.
+
+use raytracer::camera::Camera;
+use raytracer::hittable::lod::decimate;
+use raytracer::hittable::mesh::{Mesh, ObjFile, Triangle};
+use raytracer::hittable::{Hittable, HittableStore, Sphere};
+use raytracer::material::Material;
+use raytracer::ray::Ray;
+use raytracer::render::{render, Settings};
+use raytracer::scene::Scene;
+use raytracer::vec3::Vector3;
+
+#[test]
+fn black_ball_against_the_sky() {
+    let mut world = HittableStore::default();
+    world.add(Sphere {
+        center: Vector3(0.0, 0.0, 0.0),
+        radius: 1.0,
+        material: Material::Lambertian {
+            albedo: Vector3(0.0, 0.0, 0.0).into(),
+        },
+    });
+    let scene = Scene::new(world, Vec::new());
+    let camera = Camera::new(
+        Vector3(0.0, 0.0, 5.0),
+        Vector3(0.0, 0.0, 0.0),
+        Vector3(0.0, 1.0, 0.0),
+        40.0,
+        1.0,
+        0.0,
+        5.0,
+    );
+    let settings = Settings {
+        samples_per_pixel: 4,
+        ..Settings::new(camera, 32, 32)
+    };
+    let image = render(&scene, &settings);
+    assert_eq!(image.pixels.len(), 32 * 32);
+    let center = image.pixels[16 * 32 + 16];
+    assert_eq!(center.x() + center.y() + center.z(), 0.0);
+    let corner = image.pixels[0];
+    assert!(corner.x() > 0.1 && corner.y() > 0.1 && corner.z() > 0.1);
+
+    let mut png = Vec::new();
+    image
+        .write(&mut png, raytracer::image::ImageFormat::Png)
+        .unwrap();
+    assert!(png.starts_with(b"\x89PNG"));
+}
+
+#[test]
+fn same_seed_same_image() {
+    let mut world = HittableStore::default();
+    world.add(Sphere {
+        center: Vector3(0.0, 0.0, 0.0),
+        radius: 1.0,
+        material: Material::Lambertian {
+            albedo: Vector3(0.5, 0.5, 0.5).into(),
+        },
+    });
+    let scene = Scene::new(world, Vec::new());
+    let camera = Camera::new(
+        Vector3(0.0, 0.0, 5.0),
+        Vector3(0.0, 0.0, 0.0),
+        Vector3(0.0, 1.0, 0.0),
+        40.0,
+        1.0,
+        0.1,
+        5.0,
+    );
+    let settings = |seed| Settings {
+        samples_per_pixel: 2,
+        seed: Some(seed),
+        ..Settings::new(camera.clone(), 16, 16)
+    };
+    let pixels = |seed| -> Vec<[u64; 3]> {
+        render(&scene, &settings(seed))
+            .pixels
+            .iter()
+            .map(|c| [c.x().to_bits(), c.y().to_bits(), c.z().to_bits()])
+            .collect()
+    };
+    assert_eq!(pixels(1), pixels(1));
+    assert_ne!(pixels(1), pixels(2));
+}
+
+#[test]
+fn rays_through_a_shared_edge_hit_one_of_the_triangles() {
+    // a tilted parallelogram split along its diagonal, struck on the
+    // diagonal from all over
+    let material = Material::Lambertian {
+        albedo: Vector3(0.5, 0.5, 0.5).into(),
+    };
+    let (a, b, c) = (
+        Vector3(0.1, 0.2, 0.3),
+        Vector3(1.7, 0.4, -0.9),
+        Vector3(2.3, 1.9, 0.1),
+    );
+    let d = a + c - b;
+    let halves = [
+        Triangle {
+            vertices: [a, b, c],
+            material,
+        },
+        Triangle {
+            vertices: [a, c, d],
+            material,
+        },
+    ];
+    let mut misses = 0;
+    for k in 0..10_000 {
+        // away from the corners, where rounding may take rays outside
+        let s = 0.05 + 0.9 * (k as f64 * 0.618_033_988_749_895).fract();
+        let target = a + (c - a) * s;
+        let angle = k as f64 * 2.399_963_229_728_653;
+        let origin = Vector3(
+            angle.cos() * 3.0,
+            (k % 7) as f64 - 3.0,
+            angle.sin() * 3.0 + 0.5,
+        );
+        let r = Ray {
+            origin,
+            direction: target - origin,
+            time: 0.0,
+        };
+        if halves
+            .iter()
+            .all(|t| t.hit(&r, 1e-9, f64::INFINITY).is_none())
+        {
+            misses += 1;
+        }
+    }
+    assert_eq!(misses, 0, "rays slipped through the shared edge");
+}
+
+/// How far along `r` it first hits `object`, narrowed down by the
+/// distances it is hit within.
+fn distance(object: &dyn Hittable, r: &Ray) -> Option<f64> {
+    let (mut near, mut far) = (0.0, 100.0);
+    object.hit(r, 1e-9, far)?;
+    for _ in 0..40 {
+        let mid = (near + far) / 2.0;
+        match object.hit(r, 1e-9, mid) {
+            Some(_) => far = mid,
+            None => near = mid,
+        }
+    }
+    Some(far)
+}
+
+#[test]
+fn decimated_meshes_keep_their_shape() {
+    let material = Material::Lambertian {
+        albedo: Vector3(0.5, 0.5, 0.5).into(),
+    };
+    let obj = ObjFile::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/scenes/models/torus.obj"
+    ))
+    .unwrap();
+    let mesh = Mesh::from_obj(&obj, material, Some(180.0));
+    let coarse = decimate(&mesh, 300);
+    let triangles = coarse.triangles().len();
+    assert!(
+        (150..=300).contains(&triangles),
+        "{} triangles left",
+        triangles
+    );
+    // rays from all around hit both where the surface was
+    let (mut both, mut apart) = (0, 0);
+    for k in 0..2_000 {
+        let z = 1.0 - 2.0 * (k as f64 + 0.5) / 2_000.0;
+        let angle = k as f64 * 2.399_963_229_728_653;
+        let around = Vector3(
+            angle.cos() * (1.0 - z * z).sqrt(),
+            z,
+            angle.sin() * (1.0 - z * z).sqrt(),
+        );
+        let r = Ray {
+            origin: around * 10.0,
+            direction: around * -1.0 + Vector3(0.3, 0.1, 0.2) * angle.sin(),
+            time: 0.0,
+        };
+        match (distance(&mesh, &r), distance(&coarse, &r)) {
+            (Some(a), Some(b)) if (a - b).abs() < 0.1 => both += 1,
+            (None, None) => {}
+            _ => apart += 1,
+        }
+    }
+    assert!(both > 300, "only {} rays hit", both);
+    assert!(apart * 20 < both, "{} of {} hits moved", apart, both);
+}
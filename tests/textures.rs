@@ -0,0 +1,98 @@
+//! Image textures dropped from a texture cache too small for them must read
+//! back the same pixels, and malformed images must be turned down.
+
+mod common;
+
+use common::{fail, render, TempDir};
+
+#[test]
+fn evicted_textures_render_the_same() {
+    let dir = TempDir::new("textures");
+    let sphere = |x: f64, image: &str| {
+        format!(
+            r#"
+[[objects]]
+type = "sphere"
+center = [{}, 0.0, -1.0]
+radius = 0.5
+material = {{ type = "diffuse", albedo = {{ type = "image", file = "{}/scenes/models/{}" }} }}
+"#,
+            x,
+            env!("CARGO_MANIFEST_DIR"),
+            image
+        )
+    };
+    let scene = dir.write(
+        "two_images.toml",
+        &(sphere(-0.6, "bands.ppm") + &sphere(0.6, "tiles_normal.ppm")),
+    );
+    let args = [
+        "--scene-file",
+        &scene,
+        "--width",
+        "64",
+        "--height",
+        "36",
+        "--spp",
+        "4",
+        "--seed",
+        "3",
+    ];
+    // smaller than either image, so each lookup of the other reads it again
+    let small: Vec<&str> = args
+        .iter()
+        .copied()
+        .chain(["--texture-cache-mb", "0.0001"])
+        .collect();
+    assert_eq!(render(&args), render(&small));
+}
+
+#[test]
+fn malformed_images_are_errors() {
+    let dir = TempDir::new("malformed");
+    // an uncompressed OpenEXR header whose data window ends before it starts
+    let mut exr = vec![0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0];
+    let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+        exr.extend(name.bytes().chain([0]).chain(kind.bytes()).chain([0]));
+        exr.extend((value.len() as i32).to_le_bytes());
+        exr.extend(value);
+    };
+    let mut channels = Vec::new();
+    for name in ["B", "G", "R"] {
+        channels.extend(name.bytes().chain([0]));
+        channels.extend([1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0]);
+    }
+    channels.push(0);
+    attribute("channels", "chlist", &channels);
+    attribute("compression", "compression", &[0]);
+    let window: Vec<u8> = [0i32, 0, -5, -5]
+        .iter()
+        .flat_map(|c| c.to_le_bytes())
+        .collect();
+    attribute("dataWindow", "box2i", &window);
+    exr.push(0);
+    let images = [
+        ("inverted.exr", exr),
+        ("empty.hdr", b"#?RADIANCE\n\n-Y 0 +X 0\n".to_vec()),
+        ("empty.ppm", b"P3\n0 0\n255\n".to_vec()),
+    ];
+    for (name, bytes) in images {
+        let image = dir.path(name);
+        std::fs::write(&image, bytes).unwrap();
+        let scene = dir.write(
+            "scene.toml",
+            &format!(
+                r#"
+[[objects]]
+type = "sphere"
+center = [0.0, 0.0, -1.0]
+radius = 0.5
+material = {{ type = "diffuse", albedo = {{ type = "image", file = "{}" }} }}
+"#,
+                image
+            ),
+        );
+        let stderr = fail(&["--scene-file", &scene, "--width", "8", "--height", "8"]);
+        assert!(stderr.contains(name), "{}: {}", name, stderr);
+    }
+}